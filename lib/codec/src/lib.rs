@@ -1,6 +1,10 @@
 #[macro_use]
 extern crate tracing;
 
+mod octet_counting;
+
+pub use octet_counting::OctetCountingCodec;
+
 use bytes::{BufMut, Bytes, BytesMut};
 use std::{cmp, io, usize};
 use tokio_codec::{Decoder, Encoder};
@@ -0,0 +1,119 @@
+use bytes::{Bytes, BytesMut};
+use std::{cmp, io, usize};
+use tokio_codec::Decoder;
+
+/// The longest length prefix accepted, which is enough for any `usize`.
+const MAX_DIGITS: usize = 20;
+
+/// Decodes frames prefixed with their length in ASCII digits and a space, the
+/// octet counting framing described in RFC 6587, section 3.4.1.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct OctetCountingCodec {
+    max_length: usize,
+    /// How much of a frame larger than `max_length` is left to discard.
+    discarding: usize,
+}
+
+impl OctetCountingCodec {
+    /// Returns an `OctetCountingCodec` without a maximum frame length.
+    pub fn new() -> Self {
+        OctetCountingCodec::new_with_max_length(usize::MAX)
+    }
+
+    /// Returns an `OctetCountingCodec` with a maximum frame length limit.
+    pub fn new_with_max_length(max_length: usize) -> Self {
+        OctetCountingCodec {
+            max_length,
+            discarding: 0,
+        }
+    }
+
+    /// Returns the maximum frame length when decoding.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+}
+
+impl Default for OctetCountingCodec {
+    fn default() -> Self {
+        OctetCountingCodec::new()
+    }
+}
+
+impl Decoder for OctetCountingCodec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Bytes>, io::Error> {
+        loop {
+            if self.discarding > 0 {
+                let discard = cmp::min(self.discarding, buf.len());
+                buf.advance(discard);
+                self.discarding -= discard;
+                if self.discarding > 0 {
+                    return Ok(None);
+                }
+            }
+
+            // Some senders terminate each frame with a newline as well.
+            let separators = buf
+                .iter()
+                .take_while(|&&b| b == b'\n' || b == b'\r')
+                .count();
+            buf.advance(separators);
+
+            let space = match buf.iter().take(MAX_DIGITS + 1).position(|&b| b == b' ') {
+                Some(space) => space,
+                None if buf.len() > MAX_DIGITS => return Err(invalid_length(&buf[..MAX_DIGITS])),
+                None => return Ok(None),
+            };
+            let length = parse_length(&buf[..space])?;
+
+            if length > self.max_length {
+                warn!(
+                    message = "discarding frame larger than max_length",
+                    frame_len = length,
+                    max_len = self.max_length,
+                );
+                buf.advance(space + 1);
+                self.discarding = length;
+                continue;
+            }
+
+            let frame_end = match (space + 1).checked_add(length) {
+                Some(frame_end) => frame_end,
+                None => return Err(invalid_length(&buf[..space])),
+            };
+            if buf.len() < frame_end {
+                buf.reserve(frame_end - buf.len());
+                return Ok(None);
+            }
+
+            trace!(message = "decoding the frame.", bytes_proccesed = frame_end);
+
+            buf.advance(space + 1);
+            return Ok(Some(buf.split_to(length).freeze()));
+        }
+    }
+}
+
+fn parse_length(digits: &[u8]) -> Result<usize, io::Error> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(invalid_length(digits));
+    }
+    // Only ASCII digits, so this is valid UTF-8.
+    std::str::from_utf8(digits)
+        .unwrap()
+        .parse()
+        .map_err(|_| invalid_length(digits))
+}
+
+fn invalid_length(prefix: &[u8]) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "invalid octet counting frame length {:?}",
+            String::from_utf8_lossy(prefix)
+        ),
+    )
+}
@@ -0,0 +1,59 @@
+use bytes::BytesMut;
+use codec::OctetCountingCodec;
+use tokio_codec::Decoder;
+
+#[test]
+fn octet_counting_decode() {
+    let mut codec = OctetCountingCodec::new();
+    let buf = &mut BytesMut::new();
+    buf.extend_from_slice(b"3 abc11 hello\nworld5 ab");
+
+    assert_eq!(Some("abc".into()), codec.decode(buf).unwrap());
+    assert_eq!(Some("hello\nworld".into()), codec.decode(buf).unwrap());
+    assert!(codec.decode(buf).unwrap().is_none());
+
+    buf.extend_from_slice(b"cde\n");
+    assert_eq!(Some("abcde".into()), codec.decode(buf).unwrap());
+    assert!(codec.decode_eof(buf).unwrap().is_none());
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn octet_counting_decode_max_length() {
+    let mut codec = OctetCountingCodec::new_with_max_length(5);
+    let buf = &mut BytesMut::new();
+    buf.extend_from_slice(b"8 too long4 fits7 t");
+
+    assert_eq!(Some("fits".into()), codec.decode(buf).unwrap());
+    assert!(codec.decode(buf).unwrap().is_none());
+
+    buf.extend_from_slice(b"oo big2 ok");
+    assert_eq!(Some("ok".into()), codec.decode(buf).unwrap());
+}
+
+#[test]
+fn octet_counting_decode_invalid_length() {
+    let mut codec = OctetCountingCodec::new();
+
+    let buf = &mut BytesMut::new();
+    buf.extend_from_slice(b"<13>Oct 11 22:14:15 host message");
+    assert!(codec.decode(buf).is_err());
+
+    let buf = &mut BytesMut::new();
+    buf.extend_from_slice(b"123456789012345678901234567890");
+    assert!(codec.decode(buf).is_err());
+
+    let buf = &mut BytesMut::new();
+    buf.extend_from_slice(b"18446744073709551615 x");
+    assert!(codec.decode(buf).is_err());
+}
+
+#[test]
+fn octet_counting_decode_eof_truncated() {
+    let mut codec = OctetCountingCodec::new();
+    let buf = &mut BytesMut::new();
+    buf.extend_from_slice(b"10 short");
+
+    assert!(codec.decode(buf).unwrap().is_none());
+    assert!(codec.decode_eof(buf).is_err());
+}
@@ -0,0 +1,348 @@
+use crate::{FileFingerprint, FilePosition};
+use glob::glob;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time;
+
+const STATE_FILE: &str = "checkpoints.json";
+const TMP_FILE: &str = "checkpoints.new.json";
+/// How long to remember the checkpoint of a file that is no longer watched,
+/// in case it shows up again (e.g. compressed after rotation).
+const CHECKPOINT_EXPIRY: time::Duration = time::Duration::from_secs(60 * 60);
+
+/// The on-disk format of the checkpoint file. New versions are added as new
+/// variants, so that older files can still be read and upgraded.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "version")]
+enum State {
+    #[serde(rename = "1")]
+    V1 { checkpoints: Vec<Checkpoint> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Checkpoint {
+    fingerprint: FileFingerprint,
+    position: FilePosition,
+    /// Unknown for checkpoints migrated from the legacy format until the file
+    /// is read again.
+    path: Option<PathBuf>,
+    /// The modification time of the file when it was last read. For
+    /// checkpoints migrated from the legacy format, when the checkpoint was
+    /// written instead.
+    modified: time::SystemTime,
+    /// When the file was last watched, to expire the checkpoints of files that
+    /// are gone.
+    #[serde(default = "time::SystemTime::now")]
+    last_seen: time::SystemTime,
+}
+
+/// `Checkpointer` keeps track of how far each file has been read, keyed by its
+/// fingerprint, and persists that to a single checkpoint file in the data
+/// directory. The file is replaced atomically, so a crash mid-write leaves the
+/// previous checkpoints intact.
+pub struct Checkpointer {
+    directory: PathBuf,
+    tmp_file_path: PathBuf,
+    stable_file_path: PathBuf,
+    legacy_directory: PathBuf,
+    checkpoints: HashMap<FileFingerprint, Checkpoint>,
+    dirty: bool,
+}
+
+impl Checkpointer {
+    pub fn new(data_dir: &Path) -> Checkpointer {
+        Checkpointer {
+            directory: data_dir.to_path_buf(),
+            tmp_file_path: data_dir.join(TMP_FILE),
+            stable_file_path: data_dir.join(STATE_FILE),
+            legacy_directory: data_dir.join("checkpoints"),
+            checkpoints: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn set_checkpoint(&mut self, fng: FileFingerprint, pos: FilePosition, path: &Path) {
+        let now = time::SystemTime::now();
+        // The file may already have been moved away, in which case the
+        // modification time last seen is kept.
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+        let checkpoint = self.checkpoints.entry(fng).or_insert_with(|| Checkpoint {
+            fingerprint: fng,
+            position: pos,
+            path: None,
+            modified: now,
+            last_seen: now,
+        });
+        checkpoint.position = pos;
+        if let Ok(modified) = modified {
+            checkpoint.modified = modified;
+        }
+        checkpoint.last_seen = now;
+        if checkpoint.path.as_ref().map(PathBuf::as_path) != Some(path) {
+            checkpoint.path = Some(path.to_path_buf());
+        }
+        self.dirty = true;
+    }
+
+    pub fn get_checkpoint(&self, fng: FileFingerprint) -> Option<FilePosition> {
+        self.checkpoints
+            .get(&fng)
+            .map(|checkpoint| checkpoint.position)
+    }
+
+    /// Forget the checkpoints of files that have not been watched for
+    /// `CHECKPOINT_EXPIRY`, as determined by `watched`.
+    pub fn expire(&mut self, watched: impl Fn(&FileFingerprint) -> bool) {
+        let now = time::SystemTime::now();
+        let mut expired = false;
+        self.checkpoints.retain(|fng, checkpoint| {
+            if watched(fng) {
+                checkpoint.last_seen = now;
+                return true;
+            }
+            let keep = now
+                .duration_since(checkpoint.last_seen)
+                .map(|age| age < CHECKPOINT_EXPIRY)
+                .unwrap_or(true);
+            if !keep {
+                debug!(
+                    message = "Expiring checkpoint of file no longer watched.",
+                    path = ?checkpoint.path,
+                );
+                expired = true;
+            }
+            keep
+        });
+        self.dirty |= expired;
+    }
+
+    /// Write the checkpoints to a temporary file, sync it to disk, and then
+    /// rename it over the previous checkpoint file.
+    pub fn write_checkpoints(&mut self) -> Result<(), io::Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let state = State::V1 {
+            checkpoints: self.checkpoints.values().cloned().collect(),
+        };
+
+        fs::create_dir_all(&self.directory)?;
+        let mut file = io::BufWriter::new(fs::File::create(&self.tmp_file_path)?);
+        serde_json::to_writer(&mut file, &state)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        fs::rename(&self.tmp_file_path, &self.stable_file_path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Read the checkpoint file, or migrate the legacy checkpoints if there is
+    /// none yet. Checkpoints of files last modified before `ignore_before` are
+    /// dropped.
+    pub fn read_checkpoints(&mut self, ignore_before: Option<time::SystemTime>) {
+        match self.read_state() {
+            Ok(Some(State::V1 { checkpoints })) => {
+                for checkpoint in checkpoints {
+                    self.checkpoints.insert(checkpoint.fingerprint, checkpoint);
+                }
+            }
+            Ok(None) => self.migrate_legacy_checkpoints(),
+            Err(error) => {
+                error!(message = "Unable to read checkpoints, starting without them.", %error, path = ?self.stable_file_path);
+            }
+        }
+
+        if let Some(ignore_before) = ignore_before {
+            let len = self.checkpoints.len();
+            self.checkpoints
+                .retain(|_fng, checkpoint| checkpoint.modified >= ignore_before);
+            self.dirty |= self.checkpoints.len() != len;
+        }
+    }
+
+    fn read_state(&self) -> Result<Option<State>, io::Error> {
+        match fs::File::open(&self.stable_file_path) {
+            Ok(file) => Ok(Some(serde_json::from_reader(io::BufReader::new(file))?)),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Checkpoints used to be stored as empty files named after the
+    /// fingerprint (in hex) and position, in a `checkpoints` directory. These
+    /// are converted to the new format, which is written before the legacy
+    /// directory is removed.
+    fn migrate_legacy_checkpoints(&mut self) {
+        let glob_string = self
+            .legacy_directory
+            .join("*")
+            .to_string_lossy()
+            .into_owned();
+        let paths = match glob(&glob_string) {
+            Ok(paths) => paths,
+            Err(_) => return,
+        };
+
+        for path in paths.flatten() {
+            let (fingerprint, position) = match decode_legacy(&path) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or_else(|_| time::SystemTime::now());
+            self.checkpoints.insert(
+                fingerprint,
+                Checkpoint {
+                    fingerprint,
+                    position,
+                    path: None,
+                    modified,
+                    last_seen: time::SystemTime::now(),
+                },
+            );
+        }
+
+        if !self.legacy_directory.exists() {
+            return;
+        }
+        info!(
+            message = "Migrating legacy checkpoints.",
+            count = self.checkpoints.len()
+        );
+        self.dirty = true;
+        match self.write_checkpoints() {
+            Ok(()) => {
+                fs::remove_dir_all(&self.legacy_directory).ok();
+            }
+            Err(error) => error!(message = "Unable to write migrated checkpoints.", %error),
+        }
+    }
+}
+
+fn decode_legacy(path: &Path) -> Option<(FileFingerprint, FilePosition)> {
+    let file_name = &path.file_name()?.to_string_lossy();
+    scan_fmt!(file_name, "{x}.{}", [hex FileFingerprint], FilePosition).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_legacy, Checkpointer, FileFingerprint, FilePosition, CHECKPOINT_EXPIRY};
+    use std::fs;
+    use std::path::Path;
+    use std::time;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checkpointer_basics() {
+        let fingerprint: FileFingerprint = 0x1234567890abcdef;
+        let position: FilePosition = 1234;
+        let data_dir = tempdir().unwrap();
+        let mut chkptr = Checkpointer::new(&data_dir.path());
+        assert_eq!(chkptr.get_checkpoint(fingerprint), None);
+        chkptr.set_checkpoint(fingerprint, position, Path::new("/var/log/a.log"));
+        assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
+    }
+
+    #[test]
+    fn test_checkpointer_restart() {
+        let fingerprint: FileFingerprint = 0x1234567890abcdef;
+        let position: FilePosition = 1234;
+        let data_dir = tempdir().unwrap();
+        {
+            let mut chkptr = Checkpointer::new(&data_dir.path());
+            chkptr.set_checkpoint(fingerprint, position, Path::new("/var/log/a.log"));
+            assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
+            chkptr.write_checkpoints().unwrap();
+        }
+        assert!(data_dir.path().join("checkpoints.json").exists());
+        assert!(!data_dir.path().join("checkpoints.new.json").exists());
+        {
+            let mut chkptr = Checkpointer::new(&data_dir.path());
+            assert_eq!(chkptr.get_checkpoint(fingerprint), None);
+            chkptr.read_checkpoints(None);
+            assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
+            assert_eq!(
+                chkptr.checkpoints[&fingerprint].path.as_ref().unwrap(),
+                Path::new("/var/log/a.log")
+            );
+        }
+    }
+
+    #[test]
+    fn test_checkpointer_migrate_legacy() {
+        let fingerprint: FileFingerprint = 0x1234567890abcdef;
+        let position: FilePosition = 1234;
+        let data_dir = tempdir().unwrap();
+        let legacy_dir = data_dir.path().join("checkpoints");
+        fs::create_dir_all(&legacy_dir).unwrap();
+        fs::File::create(legacy_dir.join(format!("{:x}.{}", fingerprint, position))).unwrap();
+        assert_eq!(
+            decode_legacy(&legacy_dir.join("1234567890abcdef.1234")),
+            Some((fingerprint, position))
+        );
+
+        let mut chkptr = Checkpointer::new(&data_dir.path());
+        chkptr.read_checkpoints(None);
+        assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
+        assert!(!legacy_dir.exists());
+
+        let mut chkptr = Checkpointer::new(&data_dir.path());
+        chkptr.read_checkpoints(None);
+        assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
+    }
+
+    #[test]
+    fn test_checkpointer_expiry() {
+        let data_dir = tempdir().unwrap();
+        let mut chkptr = Checkpointer::new(&data_dir.path());
+        chkptr.set_checkpoint(1, 10, Path::new("/var/log/watched.log"));
+        chkptr.set_checkpoint(2, 20, Path::new("/var/log/recent.log"));
+        chkptr.set_checkpoint(3, 30, Path::new("/var/log/gone.log"));
+        chkptr.checkpoints.get_mut(&3).unwrap().last_seen =
+            time::SystemTime::now() - CHECKPOINT_EXPIRY;
+        chkptr.checkpoints.get_mut(&1).unwrap().last_seen =
+            time::SystemTime::now() - CHECKPOINT_EXPIRY;
+
+        chkptr.expire(|fng| *fng == 1);
+        assert_eq!(chkptr.get_checkpoint(1), Some(10));
+        assert_eq!(chkptr.get_checkpoint(2), Some(20));
+        assert_eq!(chkptr.get_checkpoint(3), None);
+    }
+
+    #[test]
+    fn test_checkpointer_records_file_modification_time() {
+        let data_dir = tempdir().unwrap();
+        let path = data_dir.path().join("a.log");
+        fs::write(&path, "first line\n").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+        let mut chkptr = Checkpointer::new(&data_dir.path());
+        chkptr.set_checkpoint(1, 11, &path);
+        assert_eq!(chkptr.checkpoints[&1].modified, modified);
+
+        // Once the file is gone, the last known time is kept.
+        fs::remove_file(&path).unwrap();
+        chkptr.set_checkpoint(1, 22, &path);
+        assert_eq!(chkptr.checkpoints[&1].modified, modified);
+        assert_eq!(chkptr.get_checkpoint(1), Some(22));
+    }
+
+    #[test]
+    fn test_checkpointer_ignore_before() {
+        let data_dir = tempdir().unwrap();
+        {
+            let mut chkptr = Checkpointer::new(&data_dir.path());
+            chkptr.set_checkpoint(1, 10, Path::new("/var/log/a.log"));
+            chkptr.write_checkpoints().unwrap();
+        }
+        let mut chkptr = Checkpointer::new(&data_dir.path());
+        chkptr.read_checkpoints(Some(time::SystemTime::now() + time::Duration::from_secs(1)));
+        assert_eq!(chkptr.get_checkpoint(1), None);
+    }
+}
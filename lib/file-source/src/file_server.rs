@@ -1,22 +1,33 @@
-use crate::{file_watcher::FileWatcher, FileFingerprint, FilePosition};
+use crate::{
+    checkpointer::Checkpointer,
+    file_watcher::{open_decompressed, FileWatcher},
+    notifier::{Changes, NotificationMode, Notifier},
+    FileFingerprint,
+};
 use bytes::Bytes;
 use futures::{stream, Future, Sink, Stream};
 use glob::{glob, Pattern};
 use indexmap::IndexMap;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::fs;
-use std::io::{self, Read, Seek, Write};
+use std::io::{self, Read, Write};
 use std::os::unix::fs::MetadataExt;
-use std::path::{Path, PathBuf};
-use std::sync::mpsc::RecvTimeoutError;
+use std::path::PathBuf;
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
 use std::time;
 use tracing::field;
 
+/// How often to glob for files as a fallback when relying on notifications.
+const FALLBACK_GLOB_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// How long to block waiting for notifications before checking for shutdown.
+const NOTIFICATION_WAIT: time::Duration = time::Duration::from_millis(100);
+
 /// `FileServer` is a Source which cooperatively schedules reads over files,
 /// converting the lines of said files into `LogLine` structures. As
 /// `FileServer` is intended to be useful across multiple operating systems with
-/// POSIX filesystem semantics `FileServer` must poll for changes. That is, no
-/// event notification is used by `FileServer`.
+/// POSIX filesystem semantics `FileServer` polls for changes by default. Where
+/// available, the `Inotify` notification mode instead wakes `FileServer` on
+/// file system events, so that it only globs and reads when something changed.
 ///
 /// `FileServer` is configured on a path to watch. The files do _not_ need to
 /// exist at startup. `FileServer` will discover new files which match
@@ -32,6 +43,7 @@ pub struct FileServer {
     pub glob_minimum_cooldown: time::Duration,
     pub fingerprinter: Fingerprinter,
     pub oldest_first: bool,
+    pub notification_mode: NotificationMode,
 }
 
 /// `FileServer` as Source
@@ -72,6 +84,12 @@ impl FileServer {
 
         let mut known_small_files = HashSet::new();
 
+        let mut notifier = Notifier::new(self.notification_mode);
+        notifier.watch(&self.include);
+        let mut changes = Changes::default();
+        // Files with more left to read, when reads are driven by notifications.
+        let mut pending: HashSet<FileFingerprint> = HashSet::new();
+
         let mut existing_files = Vec::new();
         for include_pattern in &self.include {
             for path in glob(include_pattern.to_str().expect("no ability to glob"))
@@ -120,19 +138,45 @@ impl FileServer {
         // exponential fashion to some hard-coded cap. To reduce time using glob,
         // we do not re-scan for major file changes (new files, moves, deletes),
         // or write new checkpoints, on every iteration.
+        //
+        // When notifications are available we don't sleep at all, but block
+        // waiting for them instead. Files are only read once notified that
+        // they were modified, and only globbed for once notified that files
+        // were created, moved or deleted, or as a fallback every
+        // `FALLBACK_GLOB_INTERVAL`.
         let mut next_glob_time = time::Instant::now();
+        let mut next_checkpoint_time = next_glob_time;
+        let mut next_fallback_glob_time = next_glob_time;
         loop {
-            // Glob find files to follow, but not too often.
             let now_time = time::Instant::now();
-            if next_glob_time <= now_time {
-                // Schedule the next glob time.
-                next_glob_time = now_time.checked_add(self.glob_minimum_cooldown).unwrap();
+            if next_checkpoint_time <= now_time {
+                next_checkpoint_time = now_time.checked_add(self.glob_minimum_cooldown).unwrap();
 
                 // Write any stored checkpoints (uses glob to find old checkpoints).
                 checkpointer
                     .write_checkpoints()
                     .map_err(|e| warn!("Problem writing checkpoints: {:?}", e))
                     .ok();
+            }
+
+            // Files too small to fingerprint when last globbed may not be now.
+            if changes
+                .modified
+                .iter()
+                .any(|path| known_small_files.contains(path))
+            {
+                changes.rescan = true;
+            }
+
+            // Glob find files to follow, but not too often.
+            let glob_due =
+                notifier.is_polling() || changes.rescan || next_fallback_glob_time <= now_time;
+            let globbed = next_glob_time <= now_time && glob_due;
+            if globbed {
+                // Schedule the next glob time.
+                next_glob_time = now_time.checked_add(self.glob_minimum_cooldown).unwrap();
+                next_fallback_glob_time = now_time.checked_add(FALLBACK_GLOB_INTERVAL).unwrap();
+                changes.rescan = false;
 
                 // Search (glob) for files to detect major file changes.
                 for (_file_id, watcher) in &mut fp_map {
@@ -210,10 +254,29 @@ impl FileServer {
                 }
             }
 
-            // Collect lines by polling files.
+            if globbed {
+                // Watch any directories that have appeared since.
+                notifier.watch(&self.include);
+                // Forget files that have been gone for a while.
+                checkpointer.expire(|file_id| fp_map.contains_key(file_id));
+            }
+
+            // Collect lines by polling files. When notified of changes, only
+            // files that were modified or have more left to read are polled.
+            let read_all = globbed || notifier.is_polling();
+            for (&file_id, watcher) in &fp_map {
+                if changes.modified.contains(&watcher.path) {
+                    pending.insert(file_id);
+                }
+            }
+            changes.modified.clear();
+
             let mut global_bytes_read: usize = 0;
             let mut maxed_out_reading_single_file = false;
             for (&file_id, watcher) in &mut fp_map {
+                if !read_all && !pending.contains(&file_id) {
+                    continue;
+                }
                 let mut bytes_read: usize = 0;
                 while let Ok(sz) = watcher.read_line(&mut line_buffer, self.max_line_bytes) {
                     if sz > 0 {
@@ -242,7 +305,14 @@ impl FileServer {
                 }
                 if bytes_read > 0 {
                     global_bytes_read = global_bytes_read.saturating_add(bytes_read);
-                    checkpointer.set_checkpoint(file_id, watcher.get_file_position());
+                    checkpointer.set_checkpoint(
+                        file_id,
+                        watcher.get_file_position(),
+                        &watcher.path,
+                    );
+                    pending.insert(file_id);
+                } else {
+                    pending.remove(&file_id);
                 }
                 // Do not move on to newer files if we are behind on an older file
                 if self.oldest_first && maxed_out_reading_single_file {
@@ -253,6 +323,7 @@ impl FileServer {
             // A FileWatcher is dead when the underlying file has disappeared.
             // If the FileWatcher is dead we don't retain it; it will be deallocated.
             fp_map.retain(|_file_id, watcher| !watcher.dead());
+            pending.retain(|file_id| fp_map.contains_key(file_id));
 
             match stream::iter_ok::<_, ()>(lines.drain(..))
                 .forward(chans)
@@ -264,6 +335,34 @@ impl FileServer {
                     return;
                 }
             }
+
+            if !notifier.is_polling() {
+                match shutdown.try_recv() {
+                    Ok(()) => unreachable!(), // The sender should never actually send
+                    Err(TryRecvError::Empty) => {}
+                    Err(TryRecvError::Disconnected) => return,
+                }
+
+                // Don't block while there is more to read, or while a glob is
+                // held back by the cooldown.
+                let timeout = if !pending.is_empty() {
+                    time::Duration::from_millis(0)
+                } else if changes.rescan {
+                    let now_time = time::Instant::now();
+                    if next_glob_time > now_time {
+                        (next_glob_time - now_time).min(NOTIFICATION_WAIT)
+                    } else {
+                        time::Duration::from_millis(0)
+                    }
+                } else {
+                    NOTIFICATION_WAIT
+                };
+                let new_changes = notifier.wait(timeout);
+                changes.rescan |= new_changes.rescan;
+                changes.modified.extend(new_changes.modified);
+                continue;
+            }
+
             // When no lines have been read we kick the backup_cap up by twice,
             // limited by the hard-coded cap. Else, we set the backup_cap to its
             // minimum on the assumption that next time through there will be
@@ -316,64 +415,6 @@ impl FileServer {
     }
 }
 
-pub struct Checkpointer {
-    directory: PathBuf,
-    glob_string: String,
-    checkpoints: HashMap<FileFingerprint, FilePosition>,
-}
-
-impl Checkpointer {
-    pub fn new(data_dir: &Path) -> Checkpointer {
-        let directory = data_dir.join("checkpoints");
-        let glob_string = directory.join("*").to_string_lossy().into_owned();
-        Checkpointer {
-            directory: directory,
-            glob_string: glob_string,
-            checkpoints: HashMap::new(),
-        }
-    }
-
-    fn encode(&self, fng: FileFingerprint, pos: FilePosition) -> PathBuf {
-        self.directory.join(format!("{:x}.{}", fng, pos))
-    }
-    fn decode(&self, path: &Path) -> (FileFingerprint, FilePosition) {
-        let file_name = &path.file_name().unwrap().to_string_lossy();
-        scan_fmt!(file_name, "{x}.{}", [hex FileFingerprint], FilePosition).unwrap()
-    }
-
-    pub fn set_checkpoint(&mut self, fng: FileFingerprint, pos: FilePosition) {
-        self.checkpoints.insert(fng, pos);
-    }
-
-    pub fn get_checkpoint(&self, fng: FileFingerprint) -> Option<FilePosition> {
-        self.checkpoints.get(&fng).cloned()
-    }
-
-    pub fn write_checkpoints(&mut self) -> Result<(), io::Error> {
-        fs::remove_dir_all(&self.directory).ok();
-        fs::create_dir_all(&self.directory)?;
-        for (&fng, &pos) in self.checkpoints.iter() {
-            fs::File::create(self.encode(fng, pos))?;
-        }
-        Ok(())
-    }
-
-    pub fn read_checkpoints(&mut self, ignore_before: Option<time::SystemTime>) {
-        for path in glob(&self.glob_string).unwrap().flatten() {
-            if let Some(ignore_before) = ignore_before {
-                if let Ok(Ok(modified)) = fs::metadata(&path).map(|metadata| metadata.modified()) {
-                    if modified < ignore_before {
-                        fs::remove_file(path).ok();
-                        continue;
-                    }
-                }
-            }
-            let (fng, pos) = self.decode(&path);
-            self.checkpoints.insert(fng, pos);
-        }
-    }
-}
-
 #[derive(Clone)]
 pub enum Fingerprinter {
     Checksum {
@@ -405,8 +446,11 @@ impl Fingerprinter {
                 let i = ignored_header_bytes as u64;
                 let b = fingerprint_bytes;
                 buffer.resize(b, 0u8);
-                let mut fp = fs::File::open(path)?;
-                fp.seek(io::SeekFrom::Start(i))?;
+                // Compressed files are fingerprinted by their decompressed
+                // contents, so a file compressed on rotation keeps its
+                // checkpoint and is not read again from the start.
+                let mut fp = open_decompressed(path)?;
+                io::copy(&mut (&mut fp).take(i), &mut io::sink())?;
                 fp.read_exact(&mut buffer[..b])?;
             }
         }
@@ -420,7 +464,8 @@ impl Fingerprinter {
         buffer: &mut Vec<u8>,
         known_small_files: &mut HashSet<PathBuf>,
     ) -> Option<FileFingerprint> {
-        self.get_fingerprint_of_file(path, buffer)
+        let fingerprint = self
+            .get_fingerprint_of_file(path, buffer)
             .map_err(|err| {
                 if err.kind() == io::ErrorKind::UnexpectedEof {
                     if !known_small_files.contains(path) {
@@ -431,13 +476,17 @@ impl Fingerprinter {
                     error!(message = "Error reading file for fingerprinting", %err, file = ?path);
                 }
             })
-            .ok()
+            .ok();
+        if fingerprint.is_some() {
+            known_small_files.remove(path);
+        }
+        fingerprint
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Checkpointer, FileFingerprint, FilePosition, Fingerprinter};
+    use super::Fingerprinter;
     use std::fs;
     use tempfile::tempdir;
 
@@ -512,37 +561,4 @@ mod test {
                 .unwrap()
         );
     }
-
-    #[test]
-    fn test_checkpointer_basics() {
-        let fingerprint: FileFingerprint = 0x1234567890abcdef;
-        let position: FilePosition = 1234;
-        let data_dir = tempdir().unwrap();
-        let mut chkptr = Checkpointer::new(&data_dir.path());
-        assert_eq!(
-            chkptr.decode(&chkptr.encode(fingerprint, position)),
-            (fingerprint, position)
-        );
-        chkptr.set_checkpoint(fingerprint, position);
-        assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
-    }
-
-    #[test]
-    fn test_checkpointer_restart() {
-        let fingerprint: FileFingerprint = 0x1234567890abcdef;
-        let position: FilePosition = 1234;
-        let data_dir = tempdir().unwrap();
-        {
-            let mut chkptr = Checkpointer::new(&data_dir.path());
-            chkptr.set_checkpoint(fingerprint, position);
-            assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
-            chkptr.write_checkpoints().ok();
-        }
-        {
-            let mut chkptr = Checkpointer::new(&data_dir.path());
-            assert_eq!(chkptr.get_checkpoint(fingerprint), None);
-            chkptr.read_checkpoints(None);
-            assert_eq!(chkptr.get_checkpoint(fingerprint), Some(position));
-        }
-    }
 }
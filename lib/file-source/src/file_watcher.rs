@@ -1,25 +1,32 @@
 use crate::FilePosition;
+use flate2::read::MultiGzDecoder;
 use std::fs;
-use std::io::{self, BufRead, Seek};
+use std::io::{self, BufRead, Read, Seek};
 use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time;
+use tracing::field;
 
 /// The `FileWatcher` struct defines the polling based state machine which reads
 /// from a file path, transparently updating the underlying file descriptor when
 /// the file has been rolled over, as is common for logs.
 ///
+/// Compressed files are decompressed as they are read. They are assumed to be
+/// immutable, so they are read once to the end and never tailed.
+///
 /// The `FileWatcher` is expected to live for the lifetime of the file
 /// path. `FileServer` is responsible for clearing away `FileWatchers` which no
 /// longer exist.
 pub struct FileWatcher {
     pub path: PathBuf,
     findable: bool,
-    reader: io::BufReader<fs::File>,
+    reader: Box<dyn BufRead + Send>,
     file_position: FilePosition,
     devno: u64,
     inode: u64,
     is_dead: bool,
+    compressed: bool,
+    finished: bool,
 }
 
 impl FileWatcher {
@@ -35,7 +42,6 @@ impl FileWatcher {
     ) -> Result<FileWatcher, io::Error> {
         let f = fs::File::open(&path)?;
         let metadata = f.metadata()?;
-        let mut rdr = io::BufReader::new(f);
 
         let too_old = if let (Some(ignore_before), Ok(modified_time)) =
             (ignore_before, metadata.modified())
@@ -45,29 +51,31 @@ impl FileWatcher {
             false
         };
 
-        let file_position = if too_old {
-            rdr.seek(io::SeekFrom::End(0)).unwrap()
-        } else {
-            rdr.seek(io::SeekFrom::Start(file_position)).unwrap()
-        };
+        let start = if too_old { None } else { Some(file_position) };
+        let (reader, compressed, file_position) = open_reader(f, start)?;
 
         Ok(FileWatcher {
             path: path,
             findable: true,
-            reader: rdr,
+            reader: reader,
             file_position: file_position,
             devno: metadata.dev(),
             inode: metadata.ino(),
             is_dead: false,
+            compressed: compressed,
+            finished: false,
         })
     }
 
     pub fn update_path(&mut self, path: PathBuf) -> io::Result<()> {
         let metadata = fs::metadata(&path)?;
         if (metadata.dev(), metadata.ino()) != (self.devno, self.inode) {
-            let mut new_reader = io::BufReader::new(fs::File::open(&path)?);
-            new_reader.seek(io::SeekFrom::Start(self.file_position))?;
-            self.reader = new_reader;
+            // The file may have been compressed as it was rotated, in which
+            // case its fingerprint is unchanged but it now needs decompressing.
+            let (reader, compressed, _) =
+                open_reader(fs::File::open(&path)?, Some(self.file_position))?;
+            self.reader = reader;
+            self.compressed = compressed;
             self.devno = metadata.dev();
             self.inode = metadata.ino();
         }
@@ -103,10 +111,23 @@ impl FileWatcher {
     pub fn read_line(&mut self, mut buffer: &mut Vec<u8>, max_size: usize) -> io::Result<usize> {
         //ensure buffer is re-initialized
         buffer.clear();
+        if self.finished {
+            if !self.file_findable() {
+                self.set_dead();
+            }
+            return Ok(0);
+        }
         let reader = &mut self.reader;
         let file_position = &mut self.file_position;
         match read_until_with_max_size(reader, file_position, b'\n', &mut buffer, max_size) {
             Ok(sz) => {
+                if sz == 0 && self.compressed {
+                    info!(
+                        message = "Finished reading compressed file.",
+                        path = field::debug(&self.path)
+                    );
+                    self.finished = true;
+                }
                 if sz == 0 && !self.file_findable() {
                     self.set_dead();
                 }
@@ -122,6 +143,81 @@ impl FileWatcher {
     }
 }
 
+/// Compression formats recognized by their magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// Detect whether `file` is compressed by looking at its first few bytes,
+/// leaving it positioned at the start.
+pub fn detect_compression(file: &mut fs::File) -> io::Result<Option<Compression>> {
+    let mut magic = [0u8; 4];
+    let mut len = 0;
+    while len < magic.len() {
+        match file.read(&mut magic[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    file.seek(io::SeekFrom::Start(0))?;
+
+    let compression = if len >= 2 && magic[..2] == [0x1f, 0x8b] {
+        Some(Compression::Gzip)
+    } else if len == 4 && magic == [0x28, 0xb5, 0x2f, 0xfd] {
+        Some(Compression::Zstd)
+    } else {
+        None
+    };
+    Ok(compression)
+}
+
+/// Open `path` for reading, decompressing it if needed.
+pub fn open_decompressed(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let mut file = fs::File::open(path)?;
+    Ok(match detect_compression(&mut file)? {
+        None => Box::new(file),
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(io::BufReader::new(file))),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::new(file)?),
+    })
+}
+
+/// Build a reader for `file` positioned at `start`, or at the end of the file
+/// if `start` is `None`. Compressed streams can't be seeked, so for those the
+/// already read part is decompressed and skipped over instead.
+fn open_reader(
+    mut file: fs::File,
+    start: Option<FilePosition>,
+) -> io::Result<(Box<dyn BufRead + Send>, bool, FilePosition)> {
+    let compression = detect_compression(&mut file)?;
+
+    let mut reader: Box<dyn BufRead + Send> = match compression {
+        None => {
+            let mut reader = io::BufReader::new(file);
+            let position = match start {
+                Some(position) => reader.seek(io::SeekFrom::Start(position))?,
+                None => reader.seek(io::SeekFrom::End(0))?,
+            };
+            return Ok((Box::new(reader), false, position));
+        }
+        Some(Compression::Gzip) => Box::new(io::BufReader::new(MultiGzDecoder::new(
+            io::BufReader::new(file),
+        ))),
+        Some(Compression::Zstd) => {
+            Box::new(io::BufReader::new(zstd::stream::read::Decoder::new(file)?))
+        }
+    };
+
+    let position = match start {
+        Some(position) => io::copy(&mut (&mut reader).take(position), &mut io::sink())?,
+        None => io::copy(&mut reader, &mut io::sink())?,
+    };
+    Ok((reader, true, position))
+}
+
 // Tweak of https://github.com/rust-lang/rust/blob/bf843eb9c2d48a80a5992a5d60858e27269f9575/src/libstd/io/mod.rs#L1471
 // After more than max_size bytes are read as part of a single line, this discard the remaining bytes
 // in that line, and then starts again on the next line.
@@ -178,8 +274,51 @@ fn read_until_with_max_size<R: BufRead + ?Sized>(
 
 #[cfg(test)]
 mod test {
-    use super::read_until_with_max_size;
-    use std::io::Cursor;
+    use super::{read_until_with_max_size, FileWatcher};
+    use flate2::{write::GzEncoder, Compression};
+    use std::fs;
+    use std::io::{Cursor, Write};
+    use tempfile::tempdir;
+
+    fn read_all_lines(watcher: &mut FileWatcher) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut buffer = Vec::new();
+        while watcher.read_line(&mut buffer, 1000).unwrap() > 0 {
+            lines.push(String::from_utf8(buffer.clone()).unwrap());
+        }
+        lines
+    }
+
+    #[test]
+    fn test_read_gzip_compressed_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("app.log.1.gz");
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(b"first line\nsecond line\nthird line\n")
+            .unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let mut watcher = FileWatcher::new(path.clone(), 0, None).unwrap();
+        assert_eq!(
+            read_all_lines(&mut watcher),
+            vec!["first line", "second line", "third line"]
+        );
+        assert_eq!(watcher.get_file_position(), 34);
+
+        // Starting from a checkpoint skips the decompressed bytes already read.
+        let mut watcher = FileWatcher::new(path.clone(), 11, None).unwrap();
+        assert_eq!(
+            read_all_lines(&mut watcher),
+            vec!["second line", "third line"]
+        );
+
+        // Anything appended to a compressed file is never read.
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"appended\n").unwrap();
+        assert!(read_all_lines(&mut watcher).is_empty());
+    }
 
     #[test]
     fn test_read_until_with_max_size() {
@@ -3,10 +3,13 @@ extern crate scan_fmt;
 #[macro_use]
 extern crate tracing;
 
+mod checkpointer;
 mod file_server;
 mod file_watcher;
+mod notifier;
 
 pub use self::file_server::{FileServer, Fingerprinter};
+pub use self::notifier::NotificationMode;
 
 type FileFingerprint = u64;
 type FilePosition = u64;
@@ -14,6 +17,8 @@ type FilePosition = u64;
 #[cfg(test)]
 mod test {
     use self::file_watcher::FileWatcher;
+    #[cfg(target_os = "linux")]
+    use self::notifier::Notifier;
     use super::*;
     use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
     use std::fs;
@@ -25,7 +30,8 @@ mod test {
     // This suite of tests is structured as an interpreter of file system
     // actions. You'll find two interpreters here, `experiment` and
     // `experiment_no_truncations`. These differ in one key respect: the later
-    // does not interpret the 'truncation' instruction.
+    // does not interpret the 'truncation' instruction. A third,
+    // `experiment_with_notifications`, only reads when told to by inotify.
     //
     // What do I mean by all this? Well, what we're trying to do is validate the
     // behaviour of the file_watcher in the presence of arbitrary file-system
@@ -370,6 +376,142 @@ mod test {
             .quickcheck(inner as fn(Vec<FWAction>) -> TestResult);
     }
 
+    // Interpret all FWActions, reading only when notified
+    //
+    // When reads are driven by inotify rather than by polling, file_watcher is
+    // only read once the notifier reports that its file was modified, and then
+    // until it runs dry. A missed notification is missed data, so whenever the
+    // SUT has not been notified the model must agree that there is nothing new
+    // to read. Without truncations every read must also match the model
+    // exactly, as in `experiment_no_truncations`; with them we can only bound
+    // the reads, as in `experiment`. Rotations and deletions must be reported
+    // as needing a rescan, truncations as modifications.
+    #[cfg(target_os = "linux")]
+    fn experiment_with_notifications(actions: Vec<FWAction>, truncations: bool) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("a_file.log");
+        let mut fp = fs::File::create(&path).expect("could not create");
+        let mut fw = FileWatcher::new(path.clone(), 0, None).expect("must be able to create");
+
+        let mut notifier = Notifier::new(NotificationMode::Inotify);
+        assert!(!notifier.is_polling());
+        notifier.watch(&[dir.path().join("*.log")]);
+        let no_wait = std::time::Duration::from_millis(0);
+
+        let mut writes = 0;
+        let mut sut_reads = 0;
+        let mut notified = false;
+
+        let mut fwfiles: Vec<FWFile> = vec![];
+        fwfiles.push(FWFile::new());
+        let mut read_index = 0;
+        for action in actions.iter() {
+            match *action {
+                FWAction::DeleteFile => {
+                    let _ = fs::remove_file(&path);
+                    assert!(!path.exists());
+                    fwfiles[0].reset();
+                    assert!(notifier.wait(no_wait).rescan);
+                    break;
+                }
+                FWAction::TruncateFile => {
+                    if !truncations {
+                        continue;
+                    }
+                    fwfiles[0].truncate();
+                    fp = fs::OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .truncate(true)
+                        .open(&path)
+                        .unwrap();
+                    assert_eq!(fp.metadata().unwrap().size(), 0);
+                    let changes = notifier.wait(no_wait);
+                    assert!(changes.modified.contains(&path));
+                    notified = true;
+                }
+                FWAction::Pause(ps) => delay(ps),
+                FWAction::Exit => break,
+                FWAction::WriteLine(ref s) => {
+                    fwfiles[0].write_line(s);
+                    assert!(fp.write(s.as_bytes()).is_ok());
+                    assert!(fp.write("\n".as_bytes()).is_ok());
+                    assert!(fp.flush().is_ok());
+                    writes += 1;
+                }
+                FWAction::RotateFile => {
+                    let mut new_path = path.clone();
+                    new_path.set_extension("log.1");
+                    fs::rename(&path, &new_path).expect("could not rotate");
+                    fp = fs::File::create(&path).expect("could not create");
+                    fwfiles.insert(0, FWFile::new());
+                    read_index += 1;
+                    let changes = notifier.wait(no_wait);
+                    assert!(changes.rescan);
+                    notified |= changes.modified.contains(&path);
+                }
+                FWAction::Read => {
+                    let changes = notifier.wait(no_wait);
+                    notified |= changes.modified.contains(&path);
+                    if !notified {
+                        if !truncations {
+                            assert!(fwfiles[read_index].read_line().is_none());
+                        }
+                        continue;
+                    }
+
+                    let mut buf = Vec::new();
+                    match fw.read_line(&mut buf, 100_000) {
+                        Err(_) => {
+                            unreachable!();
+                        }
+                        Ok(0) => {
+                            notified = false;
+                            if !truncations {
+                                assert!(fwfiles[read_index].read_line().is_none());
+                            }
+                        }
+                        Ok(sz) => {
+                            sut_reads += 1;
+                            if !truncations {
+                                let exp = fwfiles[read_index].read_line().unwrap();
+                                assert_eq!(exp.into_bytes(), buf);
+                                assert_eq!(sz, buf.len() + 1);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert!(writes >= sut_reads);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn file_watcher_notified_no_truncation() {
+        fn inner(actions: Vec<FWAction>) -> TestResult {
+            experiment_with_notifications(actions, false);
+            TestResult::passed()
+        }
+        QuickCheck::new()
+            .tests(10000)
+            .max_tests(100000)
+            .quickcheck(inner as fn(Vec<FWAction>) -> TestResult);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn file_watcher_notified_with_truncation() {
+        fn inner(actions: Vec<FWAction>) -> TestResult {
+            experiment_with_notifications(actions, true);
+            TestResult::passed()
+        }
+        QuickCheck::new()
+            .tests(10000)
+            .max_tests(100000)
+            .quickcheck(inner as fn(Vec<FWAction>) -> TestResult);
+    }
+
     #[inline]
     pub fn delay(attempts: u32) {
         let delay = match attempts {
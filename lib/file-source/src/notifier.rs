@@ -0,0 +1,275 @@
+use glob::glob;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::time;
+
+/// How `FileServer` finds out about new, changed and removed files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotificationMode {
+    /// Glob for files every `glob_minimum_cooldown` and read from all of them
+    /// in turn.
+    Poll,
+    /// Wake up on inotify events, only globbing and reading where something
+    /// has changed. Falls back to `Poll` wherever inotify is unavailable.
+    Inotify,
+}
+
+/// The changes a `Notifier` has seen since it was last waited on.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Files may have been created, moved or deleted, so the include patterns
+    /// need to be globbed again.
+    pub rescan: bool,
+    /// Files that have been written to or truncated.
+    pub modified: HashSet<PathBuf>,
+}
+
+pub enum Notifier {
+    Poll,
+    #[cfg(target_os = "linux")]
+    Inotify(linux::InotifyNotifier),
+}
+
+impl Notifier {
+    pub fn new(mode: NotificationMode) -> Notifier {
+        match mode {
+            NotificationMode::Poll => Notifier::Poll,
+            #[cfg(target_os = "linux")]
+            NotificationMode::Inotify => match linux::InotifyNotifier::new() {
+                Ok(notifier) => Notifier::Inotify(notifier),
+                Err(error) => {
+                    warn!(message = "Unable to initialize inotify, falling back to polling.", %error);
+                    Notifier::Poll
+                }
+            },
+            #[cfg(not(target_os = "linux"))]
+            NotificationMode::Inotify => {
+                warn!("inotify is only available on Linux, falling back to polling.");
+                Notifier::Poll
+            }
+        }
+    }
+
+    pub fn is_polling(&self) -> bool {
+        match *self {
+            Notifier::Poll => true,
+            #[cfg(target_os = "linux")]
+            Notifier::Inotify(_) => false,
+        }
+    }
+
+    /// Watch every directory that files matching the `include` patterns may
+    /// appear in. This is expected to be called again after each glob, so
+    /// that newly created directories are watched too.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    pub fn watch(&mut self, include: &[PathBuf]) {
+        #[cfg(target_os = "linux")]
+        {
+            let result = match *self {
+                Notifier::Inotify(ref mut notifier) => notifier.watch(include),
+                Notifier::Poll => Ok(()),
+            };
+            if let Err(error) = result {
+                warn!(message = "Unable to watch directory, falling back to polling.", %error);
+                *self = Notifier::Poll;
+            }
+        }
+    }
+
+    /// Block for up to `timeout` waiting for changes. When polling, this
+    /// returns immediately without any changes.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    pub fn wait(&mut self, timeout: time::Duration) -> Changes {
+        #[cfg(target_os = "linux")]
+        {
+            let result = match *self {
+                Notifier::Inotify(ref mut notifier) => notifier.wait(timeout),
+                Notifier::Poll => Ok(Changes::default()),
+            };
+            match result {
+                Ok(changes) => return changes,
+                Err(error) => {
+                    warn!(message = "Unable to read inotify events, falling back to polling.", %error);
+                    *self = Notifier::Poll;
+                    // Events may have been missed, so make sure nothing is.
+                    return Changes {
+                        rescan: true,
+                        ..Changes::default()
+                    };
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        Changes::default()
+    }
+}
+
+/// The directories to watch for files matching `pattern`: every directory
+/// its parent matches, plus the deepest directory without any glob
+/// metacharacters so that new subdirectories are noticed.
+fn directories(pattern: &Path) -> Vec<PathBuf> {
+    let parent = match pattern.parent() {
+        Some(parent) => parent,
+        None => return vec![],
+    };
+
+    let mut base = parent
+        .components()
+        .take_while(|component| !is_glob(component))
+        .collect::<PathBuf>();
+    if base.as_os_str().is_empty() {
+        base = PathBuf::from(".");
+    }
+
+    let mut directories = vec![base];
+    if let Some(paths) = parent.to_str().and_then(|parent| glob(parent).ok()) {
+        directories.extend(paths.filter_map(Result::ok));
+    }
+    directories.retain(|path| path.is_dir());
+    directories
+}
+
+fn is_glob(component: &Component) -> bool {
+    component
+        .as_os_str()
+        .to_string_lossy()
+        .contains(|c: char| c == '*' || c == '?' || c == '[')
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{directories, Changes};
+    use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+    use std::collections::{HashMap, HashSet};
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::PathBuf;
+    use std::time;
+
+    pub struct InotifyNotifier {
+        inotify: Inotify,
+        buffer: Vec<u8>,
+        directories: HashMap<WatchDescriptor, PathBuf>,
+        watched: HashSet<PathBuf>,
+    }
+
+    impl InotifyNotifier {
+        pub fn new() -> io::Result<InotifyNotifier> {
+            Ok(InotifyNotifier {
+                inotify: Inotify::init()?,
+                buffer: vec![0; 4096],
+                directories: HashMap::new(),
+                watched: HashSet::new(),
+            })
+        }
+
+        pub fn watch(&mut self, include: &[PathBuf]) -> io::Result<()> {
+            let mask = WatchMask::CREATE
+                | WatchMask::MODIFY
+                | WatchMask::DELETE
+                | WatchMask::MOVE
+                | WatchMask::DELETE_SELF
+                | WatchMask::MOVE_SELF;
+
+            for pattern in include {
+                for directory in directories(pattern) {
+                    if self.watched.contains(&directory) {
+                        continue;
+                    }
+                    match self.inotify.add_watch(&directory, mask) {
+                        Ok(wd) => {
+                            trace!(message = "Watching directory.", ?directory);
+                            self.watched.insert(directory.clone());
+                            self.directories.insert(wd, directory);
+                        }
+                        // Removed since globbing, a later glob will sort it out.
+                        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        pub fn wait(&mut self, timeout: time::Duration) -> io::Result<Changes> {
+            let mut changes = Changes::default();
+
+            let mut pollfd = libc::pollfd {
+                fd: self.inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = timeout.as_millis().min(libc::c_int::max_value() as u128) as libc::c_int;
+            if unsafe { libc::poll(&mut pollfd, 1, timeout) } < 0 {
+                let error = io::Error::last_os_error();
+                return match error.kind() {
+                    io::ErrorKind::Interrupted => Ok(changes),
+                    _ => Err(error),
+                };
+            }
+
+            loop {
+                let events = match self.inotify.read_events(&mut self.buffer) {
+                    Ok(events) => events,
+                    Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(error) => return Err(error),
+                };
+
+                let mut empty = true;
+                for event in events {
+                    empty = false;
+
+                    if event.mask.contains(EventMask::Q_OVERFLOW) {
+                        warn!("Too many inotify events, some were dropped.");
+                        changes.rescan = true;
+                        continue;
+                    }
+
+                    if event.mask.contains(EventMask::IGNORED) {
+                        // The directory is gone, so will be its watch.
+                        if let Some(directory) = self.directories.remove(&event.wd) {
+                            self.watched.remove(&directory);
+                        }
+                        changes.rescan = true;
+                        continue;
+                    }
+
+                    if event.mask.contains(EventMask::MOVE_SELF) {
+                        // The watch follows the directory to wherever it was
+                        // moved, which is no longer the path we know it by.
+                        if let Some(directory) = self.directories.remove(&event.wd) {
+                            self.watched.remove(&directory);
+                        }
+                        self.inotify.rm_watch(event.wd.clone()).ok();
+                        changes.rescan = true;
+                        continue;
+                    }
+
+                    if event.mask.intersects(
+                        EventMask::CREATE
+                            | EventMask::DELETE
+                            | EventMask::MOVED_FROM
+                            | EventMask::MOVED_TO
+                            | EventMask::DELETE_SELF,
+                    ) {
+                        changes.rescan = true;
+                    }
+
+                    if event.mask.contains(EventMask::MODIFY) {
+                        if let (Some(directory), Some(name)) =
+                            (self.directories.get(&event.wd), event.name)
+                        {
+                            changes.modified.insert(directory.join(name));
+                        }
+                    }
+                }
+
+                if empty {
+                    break;
+                }
+            }
+
+            Ok(changes)
+        }
+    }
+}
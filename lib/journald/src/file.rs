@@ -0,0 +1,363 @@
+//! Reading of the journal file format, as documented at
+//! <https://www.freedesktop.org/wiki/Software/systemd/journal-files/>.
+//!
+//! Only what is needed to walk the entries of a file in order, and to look
+//! up data objects to match entries against, is implemented.
+
+use crate::hash::{jenkins_hash64, siphash24};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+const SIGNATURE: &[u8; 8] = b"LPKSHHRH";
+/// The fields of the header up to and including `tail_entry_monotonic`,
+/// which every journal file has.
+const HEADER_MIN_SIZE: usize = 208;
+/// Entries have a few dozen fields at most, so anything much larger than
+/// this means the file is corrupt.
+const ENTRY_MAX_SIZE: u64 = 1024 * 1024;
+/// journald doesn't store fields larger than this (`DATA_SIZE_MAX` in
+/// systemd), compressed or not.
+const DATA_MAX_SIZE: u64 = 768 * 1024 * 1024;
+
+const HEADER_INCOMPATIBLE_COMPRESSED_XZ: u32 = 1 << 0;
+const HEADER_INCOMPATIBLE_COMPRESSED_LZ4: u32 = 1 << 1;
+const HEADER_INCOMPATIBLE_KEYED_HASH: u32 = 1 << 2;
+const HEADER_INCOMPATIBLE_COMPRESSED_ZSTD: u32 = 1 << 3;
+const HEADER_INCOMPATIBLE_COMPACT: u32 = 1 << 4;
+const HEADER_INCOMPATIBLE_SUPPORTED: u32 = HEADER_INCOMPATIBLE_COMPRESSED_XZ
+    | HEADER_INCOMPATIBLE_COMPRESSED_LZ4
+    | HEADER_INCOMPATIBLE_KEYED_HASH
+    | HEADER_INCOMPATIBLE_COMPRESSED_ZSTD
+    | HEADER_INCOMPATIBLE_COMPACT;
+
+const OBJECT_DATA: u8 = 1;
+const OBJECT_ENTRY: u8 = 3;
+const OBJECT_ENTRY_ARRAY: u8 = 6;
+
+const OBJECT_COMPRESSED_XZ: u8 = 1 << 0;
+const OBJECT_COMPRESSED_LZ4: u8 = 1 << 1;
+const OBJECT_COMPRESSED_ZSTD: u8 = 1 << 2;
+
+const OBJECT_HEADER_SIZE: u64 = 16;
+const DATA_OBJECT_SIZE: u64 = 64;
+const COMPACT_DATA_OBJECT_SIZE: u64 = 72;
+const ENTRY_OBJECT_SIZE: u64 = 64;
+const ENTRY_ARRAY_OBJECT_SIZE: u64 = 24;
+
+pub type Id128 = [u8; 16];
+
+#[derive(Debug)]
+struct Header {
+    incompatible_flags: u32,
+    file_id: Id128,
+    seqnum_id: Id128,
+    data_hash_table_offset: u64,
+    data_hash_table_size: u64,
+    entry_array_offset: u64,
+    n_entries: u64,
+}
+
+/// The fields of an entry that identify it and order it against others.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryHeader {
+    pub offset: u64,
+    pub seqnum: u64,
+    pub realtime: u64,
+    pub monotonic: u64,
+    pub boot_id: Id128,
+    pub xor_hash: u64,
+    /// The offsets of the data objects holding the fields of the entry.
+    pub items: Vec<u64>,
+}
+
+/// How far the entries of a file have been walked, following the chain of
+/// entry arrays that starts in the header.
+#[derive(Clone, Debug, Default)]
+pub struct Position {
+    array_offset: u64,
+    index: u64,
+    read: u64,
+}
+
+pub struct JournalFile {
+    file: File,
+    header: Header,
+    /// The size of the file once the header was read, which the objects of
+    /// the entries it counts fit in.
+    size: u64,
+}
+
+impl JournalFile {
+    pub fn open(path: &Path) -> io::Result<JournalFile> {
+        let file = File::open(path)?;
+        let header = read_header(&file)?;
+        let size = file.metadata()?.len();
+        Ok(JournalFile { file, header, size })
+    }
+
+    /// Re-read the header, to pick up entries appended since the file was
+    /// opened.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        self.header = read_header(&self.file)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    pub fn file_id(&self) -> Id128 {
+        self.header.file_id
+    }
+
+    pub fn seqnum_id(&self) -> Id128 {
+        self.header.seqnum_id
+    }
+
+    fn compact(&self) -> bool {
+        self.header.incompatible_flags & HEADER_INCOMPATIBLE_COMPACT != 0
+    }
+
+    /// Advance `position` to the next entry of the file, returning `None`
+    /// if no more have been written yet.
+    pub fn next_entry(&self, position: &mut Position) -> io::Result<Option<EntryHeader>> {
+        if position.read >= self.header.n_entries {
+            return Ok(None);
+        }
+        if position.array_offset == 0 {
+            if self.header.entry_array_offset == 0 {
+                return Ok(None);
+            }
+            position.array_offset = self.header.entry_array_offset;
+            position.index = 0;
+        }
+
+        let item_size = if self.compact() { 4 } else { 8 };
+        loop {
+            let size = self.object_size(position.array_offset, OBJECT_ENTRY_ARRAY)?;
+            let n_items = size.saturating_sub(ENTRY_ARRAY_OBJECT_SIZE) / item_size;
+            if position.index < n_items {
+                let offset = self.read_offset(
+                    position.array_offset + ENTRY_ARRAY_OBJECT_SIZE + position.index * item_size,
+                    item_size,
+                )?;
+                if offset == 0 {
+                    return Ok(None);
+                }
+                position.index += 1;
+                position.read += 1;
+                return self.entry(offset).map(Some);
+            }
+
+            let next = self.read_u64(position.array_offset + OBJECT_HEADER_SIZE)?;
+            if next == 0 {
+                return Ok(None);
+            }
+            position.array_offset = next;
+            position.index = 0;
+        }
+    }
+
+    fn entry(&self, offset: u64) -> io::Result<EntryHeader> {
+        let size = self.object_size(offset, OBJECT_ENTRY)?;
+        if size < ENTRY_OBJECT_SIZE || size > ENTRY_MAX_SIZE {
+            return Err(invalid_data(format!(
+                "invalid entry object size {} at offset {}",
+                size, offset
+            )));
+        }
+        let mut buf = vec![0; (size - OBJECT_HEADER_SIZE) as usize];
+        self.file
+            .read_exact_at(&mut buf, offset + OBJECT_HEADER_SIZE)?;
+
+        // Compact entries only store 32-bit offsets, regular ones also store
+        // the hash of each data object.
+        let items = &buf[(ENTRY_OBJECT_SIZE - OBJECT_HEADER_SIZE) as usize..];
+        let items = if self.compact() {
+            items
+                .chunks_exact(4)
+                .map(|item| u64::from(le32(item)))
+                .collect()
+        } else {
+            items.chunks_exact(16).map(le64).collect()
+        };
+
+        let mut boot_id = [0; 16];
+        boot_id.copy_from_slice(&buf[24..40]);
+        Ok(EntryHeader {
+            offset,
+            seqnum: le64(&buf[0..]),
+            realtime: le64(&buf[8..]),
+            monotonic: le64(&buf[16..]),
+            boot_id,
+            xor_hash: le64(&buf[40..]),
+            items,
+        })
+    }
+
+    /// Read the fields of `entry`, as `FIELD=value` payloads.
+    pub fn entry_data(&self, entry: &EntryHeader) -> io::Result<Vec<Vec<u8>>> {
+        entry
+            .items
+            .iter()
+            .map(|&offset| self.data(offset))
+            .collect()
+    }
+
+    /// Look up the data object holding `payload` in the data hash table.
+    /// As data objects are never duplicated within a file, entries with
+    /// that field can be recognized by the offset alone.
+    pub fn find_data(&self, payload: &[u8]) -> io::Result<Option<u64>> {
+        let n_buckets = self.header.data_hash_table_size / 16;
+        if n_buckets == 0 {
+            return Ok(None);
+        }
+        let hash = if self.header.incompatible_flags & HEADER_INCOMPATIBLE_KEYED_HASH != 0 {
+            siphash24(payload, &self.header.file_id)
+        } else {
+            jenkins_hash64(payload)
+        };
+
+        let bucket = self.header.data_hash_table_offset + (hash % n_buckets) * 16;
+        let mut offset = self.read_u64(bucket)?;
+        while offset != 0 {
+            self.object_header(offset, OBJECT_DATA)?;
+            if self.read_u64(offset + OBJECT_HEADER_SIZE)? == hash && self.data(offset)? == payload
+            {
+                return Ok(Some(offset));
+            }
+            offset = self.read_u64(offset + OBJECT_HEADER_SIZE + 8)?;
+        }
+        Ok(None)
+    }
+
+    fn data(&self, offset: u64) -> io::Result<Vec<u8>> {
+        let (flags, size) = self.object_header(offset, OBJECT_DATA)?;
+        let start = if self.compact() {
+            COMPACT_DATA_OBJECT_SIZE
+        } else {
+            DATA_OBJECT_SIZE
+        };
+        let end = offset.checked_add(size);
+        if size < start || size > DATA_MAX_SIZE || end.map_or(true, |end| end > self.size) {
+            return Err(invalid_data(format!(
+                "data object at offset {} has invalid size {}",
+                offset, size
+            )));
+        }
+        let mut payload = vec![0; (size - start) as usize];
+        self.file.read_exact_at(&mut payload, offset + start)?;
+
+        if flags & OBJECT_COMPRESSED_XZ != 0 {
+            read_bounded(xz2::read::XzDecoder::new(&payload[..]))
+        } else if flags & OBJECT_COMPRESSED_LZ4 != 0 {
+            // The block is prefixed with its uncompressed size.
+            if payload.len() < 8 {
+                return Err(invalid_data("truncated LZ4 data object"));
+            }
+            let size = le64(&payload);
+            if size > DATA_MAX_SIZE {
+                return Err(invalid_data(format!(
+                    "LZ4 data object at offset {} has invalid uncompressed size {}",
+                    offset, size
+                )));
+            }
+            lz4::block::decompress(&payload[8..], Some(size as i32))
+        } else if flags & OBJECT_COMPRESSED_ZSTD != 0 {
+            read_bounded(zstd::stream::Decoder::new(&payload[..])?)
+        } else {
+            Ok(payload)
+        }
+    }
+
+    fn object_header(&self, offset: u64, expected: u8) -> io::Result<(u8, u64)> {
+        let mut buf = [0; OBJECT_HEADER_SIZE as usize];
+        self.file.read_exact_at(&mut buf, offset)?;
+        if buf[0] != expected {
+            return Err(invalid_data(format!(
+                "expected object of type {} at offset {}, found {}",
+                expected, offset, buf[0]
+            )));
+        }
+        Ok((buf[1], le64(&buf[8..])))
+    }
+
+    fn object_size(&self, offset: u64, expected: u8) -> io::Result<u64> {
+        self.object_header(offset, expected)
+            .map(|(_flags, size)| size)
+    }
+
+    fn read_offset(&self, offset: u64, size: u64) -> io::Result<u64> {
+        if size == 4 {
+            let mut buf = [0; 4];
+            self.file.read_exact_at(&mut buf, offset)?;
+            Ok(u64::from(u32::from_le_bytes(buf)))
+        } else {
+            self.read_u64(offset)
+        }
+    }
+
+    fn read_u64(&self, offset: u64) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.file.read_exact_at(&mut buf, offset)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+fn read_header(file: &File) -> io::Result<Header> {
+    let mut buf = [0; HEADER_MIN_SIZE];
+    file.read_exact_at(&mut buf, 0)?;
+    if &buf[..8] != SIGNATURE {
+        return Err(invalid_data("not a journal file"));
+    }
+
+    let incompatible_flags = le32(&buf[12..]);
+    if incompatible_flags & !HEADER_INCOMPATIBLE_SUPPORTED != 0 {
+        return Err(invalid_data(format!(
+            "unsupported journal file features {:#x}",
+            incompatible_flags & !HEADER_INCOMPATIBLE_SUPPORTED
+        )));
+    }
+
+    let mut file_id = [0; 16];
+    file_id.copy_from_slice(&buf[24..40]);
+    let mut seqnum_id = [0; 16];
+    seqnum_id.copy_from_slice(&buf[72..88]);
+    Ok(Header {
+        incompatible_flags,
+        file_id,
+        seqnum_id,
+        data_hash_table_offset: le64(&buf[104..]),
+        data_hash_table_size: le64(&buf[112..]),
+        entry_array_offset: le64(&buf[176..]),
+        n_entries: le64(&buf[152..]),
+    })
+}
+
+/// Decompress a data object, failing rather than going past `DATA_MAX_SIZE`.
+fn read_bounded(decoder: impl Read) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    decoder.take(DATA_MAX_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > DATA_MAX_SIZE {
+        return Err(invalid_data("compressed data object is too large"));
+    }
+    Ok(data)
+}
+
+fn le32(buf: &[u8]) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[..4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn le64(buf: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buf[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::io;
+
+/// Matches on the fields of entries, combined the way `sd_journal_add_match`
+/// and friends do: terms for the same field are ORed, terms for different
+/// fields are ANDed, and those groups can in turn be ORed and ANDed with
+/// `add_disjunction` and `add_conjunction`. Entries with any of the
+/// excluded terms never match.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// All of these must match, which they do if any of their groups does.
+    conjunctions: Vec<Vec<Group>>,
+    exclude: Vec<String>,
+}
+
+/// Terms by field, of which every field must match one.
+type Group = BTreeMap<String, Vec<String>>;
+
+impl Filter {
+    pub fn add_match(&mut self, term: &str) -> io::Result<()> {
+        let field = field(term)?;
+        if self.conjunctions.is_empty() {
+            self.conjunctions.push(vec![Group::new()]);
+        }
+        let disjunction = self.conjunctions.last_mut().unwrap();
+        disjunction
+            .last_mut()
+            .unwrap()
+            .entry(field.into())
+            .or_insert_with(Vec::new)
+            .push(term.into());
+        Ok(())
+    }
+
+    pub fn add_disjunction(&mut self) {
+        if let Some(disjunction) = self.conjunctions.last_mut() {
+            if !disjunction.last().unwrap().is_empty() {
+                disjunction.push(Group::new());
+            }
+        }
+    }
+
+    pub fn add_conjunction(&mut self) {
+        let is_empty = self
+            .conjunctions
+            .last()
+            .map_or(true, |disjunction| disjunction.iter().all(Group::is_empty));
+        if !is_empty {
+            self.conjunctions.push(vec![Group::new()]);
+        }
+    }
+
+    pub fn add_exclude(&mut self, term: &str) -> io::Result<()> {
+        field(term)?;
+        self.exclude.push(term.into());
+        Ok(())
+    }
+
+    /// Whether an entry matches, given a way to tell if it contains a term.
+    pub fn matches<F>(&self, mut contains: F) -> io::Result<bool>
+    where
+        F: FnMut(&str) -> io::Result<bool>,
+    {
+        for term in &self.exclude {
+            if contains(term)? {
+                return Ok(false);
+            }
+        }
+
+        for disjunction in &self.conjunctions {
+            let groups = disjunction.iter().filter(|group| !group.is_empty());
+            let mut matched = disjunction.iter().all(Group::is_empty);
+            for group in groups {
+                if group_matches(group, &mut contains)? {
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn group_matches<F>(group: &Group, contains: &mut F) -> io::Result<bool>
+where
+    F: FnMut(&str) -> io::Result<bool>,
+{
+    for terms in group.values() {
+        let mut matched = false;
+        for term in terms {
+            if contains(term)? {
+                matched = true;
+                break;
+            }
+        }
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn field(term: &str) -> io::Result<&str> {
+    match term.find('=') {
+        Some(eq) if eq > 0 => Ok(&term[..eq]),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid journal match {:?}, expected FIELD=value", term),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Filter;
+
+    fn matches(filter: &Filter, fields: &[&str]) -> bool {
+        filter.matches(|term| Ok(fields.contains(&term))).unwrap()
+    }
+
+    #[test]
+    fn filter_combines_matches() {
+        let mut filter = Filter::default();
+        assert!(matches(&filter, &["PRIORITY=6"]));
+
+        filter.add_match("_SYSTEMD_UNIT=sshd.service").unwrap();
+        filter.add_match("_SYSTEMD_UNIT=ntpd.service").unwrap();
+        filter.add_match("PRIORITY=3").unwrap();
+        filter.add_disjunction();
+        filter.add_match("_TRANSPORT=kernel").unwrap();
+        filter.add_disjunction();
+        filter.add_conjunction();
+        filter.add_match("_HOSTNAME=web").unwrap();
+        filter.add_exclude("SYSLOG_IDENTIFIER=audit").unwrap();
+
+        assert!(matches(
+            &filter,
+            &["_SYSTEMD_UNIT=ntpd.service", "PRIORITY=3", "_HOSTNAME=web"]
+        ));
+        assert!(matches(&filter, &["_TRANSPORT=kernel", "_HOSTNAME=web"]));
+        assert!(!matches(
+            &filter,
+            &["_SYSTEMD_UNIT=ntpd.service", "_HOSTNAME=web"]
+        ));
+        assert!(!matches(&filter, &["_TRANSPORT=kernel", "_HOSTNAME=db"]));
+        assert!(!matches(
+            &filter,
+            &[
+                "_TRANSPORT=kernel",
+                "_HOSTNAME=web",
+                "SYSLOG_IDENTIFIER=audit"
+            ]
+        ));
+
+        assert!(filter.add_match("no equals sign").is_err());
+        assert!(filter.add_exclude("=value").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! The hash functions used by the data hash table of journal files: Bob
+//! Jenkins' lookup3 for regular files, and SipHash-2-4 keyed with the file
+//! id for those with the keyed hash flag set.
+
+use std::hash::Hasher;
+
+pub fn jenkins_hash64(data: &[u8]) -> u64 {
+    let (c, b) = hashlittle2(data);
+    (u64::from(c) << 32) | u64::from(b)
+}
+
+#[allow(deprecated)]
+pub fn siphash24(data: &[u8], key: &[u8; 16]) -> u64 {
+    let (mut k0, mut k1) = ([0; 8], [0; 8]);
+    k0.copy_from_slice(&key[..8]);
+    k1.copy_from_slice(&key[8..]);
+    let mut hasher =
+        std::hash::SipHasher::new_with_keys(u64::from_le_bytes(k0), u64::from_le_bytes(k1));
+    hasher.write(data);
+    hasher.finish()
+}
+
+fn le32(buf: &[u8]) -> u32 {
+    u32::from(buf[0]) | u32::from(buf[1]) << 8 | u32::from(buf[2]) << 16 | u32::from(buf[3]) << 24
+}
+
+/// `hashlittle2` with both initial values zero, returning `(c, b)`.
+fn hashlittle2(mut key: &[u8]) -> (u32, u32) {
+    let mut a = 0xdead_beef_u32.wrapping_add(key.len() as u32);
+    let mut b = a;
+    let mut c = a;
+
+    while key.len() > 12 {
+        a = a.wrapping_add(le32(&key[0..]));
+        b = b.wrapping_add(le32(&key[4..]));
+        c = c.wrapping_add(le32(&key[8..]));
+        mix(&mut a, &mut b, &mut c);
+        key = &key[12..];
+    }
+    if key.is_empty() {
+        return (c, b);
+    }
+
+    let mut tail = [0; 12];
+    tail[..key.len()].copy_from_slice(key);
+    a = a.wrapping_add(le32(&tail[0..]));
+    b = b.wrapping_add(le32(&tail[4..]));
+    c = c.wrapping_add(le32(&tail[8..]));
+    final_mix(&mut a, &mut b, &mut c);
+    (c, b)
+}
+
+fn mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+    *a = a.wrapping_sub(*c) ^ c.rotate_left(4);
+    *c = c.wrapping_add(*b);
+    *b = b.wrapping_sub(*a) ^ a.rotate_left(6);
+    *a = a.wrapping_add(*c);
+    *c = c.wrapping_sub(*b) ^ b.rotate_left(8);
+    *b = b.wrapping_add(*a);
+    *a = a.wrapping_sub(*c) ^ c.rotate_left(16);
+    *c = c.wrapping_add(*b);
+    *b = b.wrapping_sub(*a) ^ a.rotate_left(19);
+    *a = a.wrapping_add(*c);
+    *c = c.wrapping_sub(*b) ^ b.rotate_left(4);
+    *b = b.wrapping_add(*a);
+}
+
+fn final_mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+    *c = (*c ^ *b).wrapping_sub(b.rotate_left(14));
+    *a = (*a ^ *c).wrapping_sub(c.rotate_left(11));
+    *b = (*b ^ *a).wrapping_sub(a.rotate_left(25));
+    *c = (*c ^ *b).wrapping_sub(b.rotate_left(16));
+    *a = (*a ^ *c).wrapping_sub(c.rotate_left(4));
+    *b = (*b ^ *a).wrapping_sub(a.rotate_left(14));
+    *c = (*c ^ *b).wrapping_sub(b.rotate_left(24));
+}
@@ -1,62 +1,58 @@
-#[macro_use]
-extern crate dlopen_derive;
+mod file;
+mod filter;
+mod hash;
 
-use dlopen::wrapper::{Container, WrapperApi};
-use libc::{free, size_t, strlen};
+use crate::file::{EntryHeader, Id128, JournalFile, Position};
+use crate::filter::Filter;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::fmt;
+use std::fs;
 use std::io;
-use std::iter;
-use std::os::raw::{c_char, c_int, c_void};
-use std::ptr::null_mut;
-
-const SD_JOURNAL_LOCAL_ONLY: c_int = 1;
-const SD_JOURNAL_RUNTIME_ONLY: c_int = 2;
-
-#[allow(non_camel_case_types)]
-enum sd_journal {}
-
-#[derive(WrapperApi)]
-struct LibSystemd {
-    sd_journal_open: extern "C" fn(ret: *mut *mut sd_journal, flags: c_int) -> c_int,
-    sd_journal_close: extern "C" fn(j: *mut sd_journal),
-    sd_journal_next: extern "C" fn(j: *mut sd_journal) -> c_int,
-    sd_journal_seek_head: extern "C" fn(j: *mut sd_journal) -> c_int,
-    sd_journal_restart_data: extern "C" fn(j: *mut sd_journal),
-    sd_journal_enumerate_data:
-        extern "C" fn(j: *mut sd_journal, data: *const *mut u8, l: *mut size_t) -> c_int,
-    sd_journal_seek_cursor: extern "C" fn(j: *mut sd_journal, cursor: *const c_char) -> c_int,
-    sd_journal_get_cursor: extern "C" fn(j: *mut sd_journal, cursor: *const *mut c_char) -> c_int,
-    sd_journal_test_cursor: extern "C" fn(j: *mut sd_journal, cursor: *const c_char) -> c_int,
-}
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-fn load_lib() -> Result<Container<LibSystemd>, dlopen::Error> {
-    unsafe { Container::load("libsystemd.so") }
-}
+const RUNTIME_DIRECTORY: &str = "/run/log/journal";
+const SYSTEM_DIRECTORY: &str = "/var/log/journal";
+const MACHINE_ID_FILE: &str = "/etc/machine-id";
 
-/// A minimal systemd journald reader.
+/// A minimal systemd journal reader.
 ///
-/// Supports only the features that Vector requires: open and read next
-/// (iterator).
+/// Supports only the features that Vector requires: open, match, read
+/// next (iterator) and seek to a cursor.
 ///
-/// This was implemented to support loading the `libsystemd.so` library
-/// at run time to prevent adding it as a hard dependency for the
-/// static-linked target.
+/// The journal files are read directly, rather than through
+/// `libsystemd`, so that this works wherever the files can be read.
+/// Entries are returned in order across all of the files in the
+/// journal directories, which are scanned again for new files whenever
+/// the reader runs out of entries.
+///
+/// Entries are matched against the data objects they reference, so those
+/// that don't match are skipped without reading any of their fields.
 pub struct Journal {
-    lib: Container<LibSystemd>,
-    journal: *mut sd_journal,
+    directories: Vec<PathBuf>,
+    files: Vec<OpenFile>,
+    filter: Filter,
+    /// The entry last returned.
+    current: Option<Cursor>,
+    /// Skip entries up to and including this one in files found later.
+    seek: Option<Cursor>,
+    rescan: bool,
 }
 
-unsafe impl Send for Journal {}
-
 pub type Record = HashMap<String, String>;
 
-fn bool_flag<T: Default>(flag: bool, tvalue: T) -> T {
-    if flag {
-        tvalue
-    } else {
-        T::default()
-    }
+struct OpenFile {
+    path: PathBuf,
+    file: JournalFile,
+    position: Position,
+    next: Option<EntryHeader>,
+    seek: Option<Cursor>,
+    /// The offsets of the data objects of match terms, once looked up.
+    terms: HashMap<String, Option<u64>>,
+    /// Whether the file was found by the last scan of the directories.
+    seen: bool,
+    failed: bool,
 }
 
 impl Journal {
@@ -70,60 +66,236 @@ impl Journal {
     /// * runtime_only: If `true`, include only journal entries from
     ///   volatile journal files, excluding those stored on persistent
     ///   storage. Otherwise, include persistent records.
-    pub fn open(local_only: bool, runtime_only: bool) -> Result<Journal, Error> {
-        // Each Journal structure gets their own handle to the library,
-        // but I couldn't figure out how to make lazy_static work.
-        let lib = load_lib()?;
-
-        let flags = bool_flag(local_only, SD_JOURNAL_LOCAL_ONLY)
-            | bool_flag(runtime_only, SD_JOURNAL_RUNTIME_ONLY);
-        let mut journal = null_mut();
-        sd_result(lib.sd_journal_open(&mut journal, flags))?;
-        sd_result(lib.sd_journal_seek_head(journal))?;
-        Ok(Journal { lib, journal })
-    }
-
-    /// Fetch the current record structure. `libsystemd` reads journal
-    /// records in two steps -- first advance to the next record and
-    /// then fetch the fields of the current record. This accomplishes
-    /// the second part of that process.
-    pub fn current_record(&mut self) -> io::Result<Record> {
-        self.lib.sd_journal_restart_data(self.journal);
-
-        iter::from_fn(|| {
-            let mut size: size_t = 0;
-            let data: *mut u8 = null_mut();
-
-            match sd_result(
-                self.lib
-                    .sd_journal_enumerate_data(self.journal, &data, &mut size),
-            ) {
-                Err(err) => Some(Err(err)),
-                Ok(0) => None,
-
-                Ok(_) => {
-                    let b = unsafe { std::slice::from_raw_parts(data, size as usize) };
-                    let field = String::from_utf8_lossy(b);
-                    let eq = field.find('=').unwrap();
-                    Some(Ok((field[..eq].into(), field[eq + 1..].into())))
+    pub fn open(local_only: bool, runtime_only: bool) -> io::Result<Journal> {
+        let mut roots = vec![PathBuf::from(RUNTIME_DIRECTORY)];
+        if !runtime_only {
+            roots.push(PathBuf::from(SYSTEM_DIRECTORY));
+        }
+
+        // Each host writes to a directory named after its machine id.
+        let directories = if local_only {
+            let machine_id = fs::read_to_string(MACHINE_ID_FILE)?;
+            roots
+                .iter()
+                .map(|root| root.join(machine_id.trim()))
+                .collect()
+        } else {
+            let mut directories = Vec::new();
+            for root in &roots {
+                match fs::read_dir(root) {
+                    Ok(entries) => {
+                        for entry in entries {
+                            let path = entry?.path();
+                            if path.is_dir() {
+                                directories.push(path);
+                            }
+                        }
+                    }
+                    Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+                    Err(error) => return Err(error),
                 }
             }
-        })
-        .collect::<io::Result<Record>>()
+            directories
+        };
+
+        Journal::open_directories(directories)
+    }
+
+    /// Open the journal files found in the given directories for reading.
+    pub fn open_directories(directories: Vec<PathBuf>) -> io::Result<Journal> {
+        let mut journal = Journal {
+            directories,
+            files: Vec::new(),
+            filter: Filter::default(),
+            current: None,
+            seek: None,
+            rescan: false,
+        };
+        journal.scan_directories()?;
+        Ok(journal)
+    }
+
+    /// Look for journal files that are not open yet, and refresh those
+    /// that are to pick up new entries. Files are identified by their
+    /// file id, so those renamed on rotation are not read twice.
+    fn scan_directories(&mut self) -> io::Result<()> {
+        // Keep going after errors, so that one bad file doesn't keep the
+        // others from being read, but report the first.
+        let mut result = Ok(());
+        for file in &mut self.files {
+            file.seen = false;
+            // Terms that were not found may have been added since.
+            file.terms.clear();
+            if !file.failed {
+                if let Err(error) = file.file.refresh() {
+                    file.failed = true;
+                    result = result.and(Err(with_path(error, &file.path)));
+                }
+            }
+        }
+
+        for directory in &self.directories {
+            let entries = match fs::read_dir(directory) {
+                Ok(entries) => entries,
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => {
+                    result = result.and(Err(with_path(error, directory)));
+                    continue;
+                }
+            };
+            for path in entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+            {
+                if path.extension().map_or(true, |ext| ext != "journal") {
+                    continue;
+                }
+                let file = match JournalFile::open(&path) {
+                    Ok(file) => file,
+                    // The file was removed since the directory was read.
+                    Err(ref error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => {
+                        result = result.and(Err(with_path(error, &path)));
+                        continue;
+                    }
+                };
+                match self
+                    .files
+                    .iter_mut()
+                    .find(|open| open.file.file_id() == file.file_id())
+                {
+                    Some(open) => open.seen = true,
+                    None => self.files.push(OpenFile {
+                        path,
+                        file,
+                        position: Position::default(),
+                        next: None,
+                        seek: self.seek.clone(),
+                        terms: HashMap::new(),
+                        seen: true,
+                        failed: false,
+                    }),
+                }
+            }
+        }
+        result
+    }
+
+    /// Find the next entry across all files, which is the earliest of
+    /// those each file would return next.
+    fn next_entry(&mut self) -> io::Result<Option<usize>> {
+        for file in &mut self.files {
+            if file.failed {
+                continue;
+            }
+            if let Err(error) = file.peek(&self.filter) {
+                file.failed = true;
+                return Err(with_path(error, &file.path));
+            }
+        }
+        // Forget files that were removed once they have been read.
+        self.files.retain(|file| file.seen || file.next.is_some());
+
+        let mut earliest: Option<(usize, Cursor)> = None;
+        for (index, file) in self.files.iter().enumerate() {
+            if let Some(ref entry) = file.next {
+                let cursor = Cursor::new(file.file.seqnum_id(), entry);
+                let is_earlier = earliest.as_ref().map_or(true, |(_, earliest)| {
+                    cursor.compare(earliest) == Ordering::Less
+                });
+                if is_earlier {
+                    earliest = Some((index, cursor));
+                }
+            }
+        }
+
+        Ok(earliest.map(|(index, cursor)| {
+            self.current = Some(cursor);
+            index
+        }))
+    }
+
+    /// Only return entries with the field `term`, given as `FIELD=value`.
+    /// Matches should be added before reading, and are combined just
+    /// like with `sd_journal_add_match`.
+    pub fn add_match(&mut self, term: &str) -> io::Result<()> {
+        self.filter.add_match(term)
+    }
+
+    /// OR the matches added since the last disjunction or conjunction with
+    /// those added after.
+    pub fn add_disjunction(&mut self) {
+        self.filter.add_disjunction()
+    }
+
+    /// AND the matches added since the last conjunction with those added
+    /// after.
+    pub fn add_conjunction(&mut self) {
+        self.filter.add_conjunction()
     }
 
+    /// Never return entries with the field `term`, given as `FIELD=value`.
+    pub fn add_exclude(&mut self, term: &str) -> io::Result<()> {
+        self.filter.add_exclude(term)
+    }
+
+    /// Return the cursor of the entry last returned, which can be passed to
+    /// `seek_cursor` to continue after it. The format is that of
+    /// `sd_journal_get_cursor`.
     pub fn cursor(&self) -> io::Result<String> {
-        let mut cursor: *mut c_char = null_mut();
-        sd_result(self.lib.sd_journal_get_cursor(self.journal, &mut cursor))?;
-        Ok(into_string(cursor).unwrap())
+        self.current
+            .as_ref()
+            .map(Cursor::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no journal entry read yet"))
+    }
+
+    /// Continue reading after the entry identified by `cursor`, as returned
+    /// by `cursor` or `sd_journal_get_cursor`.
+    pub fn seek_cursor(&mut self, cursor: &str) -> io::Result<()> {
+        let cursor = cursor.parse::<Cursor>()?;
+        for file in &mut self.files {
+            file.position = Position::default();
+            file.next = None;
+            file.seek = Some(cursor.clone());
+        }
+        self.current = Some(cursor.clone());
+        self.seek = Some(cursor);
+        Ok(())
     }
+}
+
+impl OpenFile {
+    /// Load the next entry of the file into `next`, skipping those up to
+    /// the cursor sought to and those not matching `filter`.
+    fn peek(&mut self, filter: &Filter) -> io::Result<()> {
+        while self.next.is_none() {
+            let entry = match self.file.next_entry(&mut self.position)? {
+                Some(entry) => entry,
+                None => break,
+            };
+            if let Some(ref seek) = self.seek {
+                if Cursor::new(self.file.seqnum_id(), &entry).compare(seek) != Ordering::Greater {
+                    continue;
+                }
+            }
+            self.seek = None;
 
-    pub fn seek_cursor(&self, cursor: &str) -> io::Result<()> {
-        let cursor = CString::new(cursor)?;
-        sd_result(
-            self.lib
-                .sd_journal_seek_cursor(self.journal, cursor.as_ptr()),
-        )?;
+            let (file, terms) = (&self.file, &mut self.terms);
+            let matches = filter.matches(|term| {
+                let offset = match terms.get(term) {
+                    Some(offset) => *offset,
+                    None => {
+                        let offset = file.find_data(term.as_bytes())?;
+                        terms.insert(term.into(), offset);
+                        offset
+                    }
+                };
+                Ok(offset.map_or(false, |offset| entry.items.contains(&offset)))
+            })?;
+            if matches {
+                self.next = Some(entry);
+            }
+        }
         Ok(())
     }
 }
@@ -132,63 +304,317 @@ impl Iterator for Journal {
     type Item = io::Result<Record>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match sd_result(self.lib.sd_journal_next(self.journal)) {
-            Err(err) => Some(Err(err)),
-            Ok(0) => None,
-            _ => Some(self.current_record()),
+        if self.rescan {
+            self.rescan = false;
+            if let Err(error) = self.scan_directories() {
+                return Some(Err(error));
+            }
         }
+
+        let index = match self.next_entry() {
+            Ok(Some(index)) => index,
+            Ok(None) => {
+                self.rescan = true;
+                return None;
+            }
+            Err(error) => return Some(Err(error)),
+        };
+
+        let file = &mut self.files[index];
+        let entry = file.next.take().unwrap();
+        let data = match file.file.entry_data(&entry) {
+            Ok(data) => data,
+            Err(error) => return Some(Err(with_path(error, &file.path))),
+        };
+        Some(Ok(data
+            .iter()
+            .filter_map(|data| {
+                let field = String::from_utf8_lossy(data);
+                let eq = field.find('=')?;
+                Some((field[..eq].into(), field[eq + 1..].into()))
+            })
+            .collect()))
     }
 }
 
-fn sd_result(code: c_int) -> io::Result<c_int> {
-    match code {
-        _ if code < 0 => Err(io::Error::from_raw_os_error(-code)),
-        _ => Ok(code),
+/// The position of an entry across journal files, formatted as
+/// `s=<seqnum id>;i=<seqnum>;b=<boot id>;m=<monotonic>;t=<realtime>;x=<xor hash>`
+/// just like `libsystemd` does.
+#[derive(Clone, Debug, PartialEq)]
+struct Cursor {
+    seqnum_id: Id128,
+    seqnum: u64,
+    boot_id: Id128,
+    monotonic: u64,
+    realtime: u64,
+    xor_hash: u64,
+}
+
+impl Cursor {
+    fn new(seqnum_id: Id128, entry: &EntryHeader) -> Cursor {
+        Cursor {
+            seqnum_id,
+            seqnum: entry.seqnum,
+            boot_id: entry.boot_id,
+            monotonic: entry.monotonic,
+            realtime: entry.realtime,
+            xor_hash: entry.xor_hash,
+        }
+    }
+
+    /// Order entries the way `libsystemd` does: by sequence number if they
+    /// were written by the same journald, by monotonic time if they are
+    /// from the same boot, and by wall clock time otherwise.
+    fn compare(&self, other: &Cursor) -> Ordering {
+        if self.seqnum_id == other.seqnum_id {
+            return self.seqnum.cmp(&other.seqnum);
+        }
+        if self.boot_id == other.boot_id {
+            let ordering = self.monotonic.cmp(&other.monotonic);
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        self.realtime
+            .cmp(&other.realtime)
+            .then(self.xor_hash.cmp(&other.xor_hash))
     }
 }
 
-/// Turn a C `char*` into a String, and free the source
-fn into_string(ptr: *mut c_char) -> Option<String> {
-    if ptr.is_null() {
-        None
-    } else {
-        Some(unsafe {
-            let result =
-                String::from_utf8_lossy(&std::slice::from_raw_parts(ptr as *mut u8, strlen(ptr)))
-                    .into_owned();
-            free(ptr as *mut c_void);
-            result
-        })
+impl fmt::Display for Cursor {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "s={};i={:x};b={};m={:x};t={:x};x={:x}",
+            Hex(&self.seqnum_id),
+            self.seqnum,
+            Hex(&self.boot_id),
+            self.monotonic,
+            self.realtime,
+            self.xor_hash
+        )
     }
 }
 
-/// Error type for functions that return more than just
-/// `std::io::Error`.
-#[derive(Debug)]
-pub enum Error {
-    IOError(io::Error),
-    DLOpenError(dlopen::Error),
+impl FromStr for Cursor {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Cursor> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid journal cursor");
+        let (mut seqnum_id, mut seqnum, mut boot_id) = (None, None, None);
+        let (mut monotonic, mut realtime, mut xor_hash) = (None, None, None);
+        for part in s.split(';') {
+            let eq = part.find('=').ok_or_else(invalid)?;
+            let value = &part[eq + 1..];
+            match &part[..eq] {
+                "s" => seqnum_id = Some(parse_id128(value).ok_or_else(invalid)?),
+                "i" => seqnum = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?),
+                "b" => boot_id = Some(parse_id128(value).ok_or_else(invalid)?),
+                "m" => monotonic = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?),
+                "t" => realtime = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?),
+                "x" => xor_hash = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?),
+                _ => {}
+            }
+        }
+
+        Ok(Cursor {
+            seqnum_id: seqnum_id.ok_or_else(invalid)?,
+            seqnum: seqnum.ok_or_else(invalid)?,
+            boot_id: boot_id.ok_or_else(invalid)?,
+            monotonic: monotonic.ok_or_else(invalid)?,
+            realtime: realtime.ok_or_else(invalid)?,
+            xor_hash: xor_hash.unwrap_or(0),
+        })
+    }
 }
 
-impl From<dlopen::Error> for Error {
-    fn from(err: dlopen::Error) -> Error {
-        Error::DLOpenError(err)
+struct Hex<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(fmt, "{:02x}", byte)?;
+        }
+        Ok(())
     }
 }
 
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Error {
-        Error::IOError(err)
+fn parse_id128(s: &str) -> Option<Id128> {
+    if s.len() != 32 || !s.is_ascii() {
+        return None;
     }
+    let mut id = [0; 16];
+    for (index, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(id)
+}
+
+fn with_path(error: io::Error, path: &Path) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
 }
 
-impl std::error::Error for Error {}
+#[cfg(test)]
+mod test {
+    use super::{Cursor, Journal, Record};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use tempfile::{tempdir, TempDir};
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        match self {
-            Error::IOError(err) => write!(fmt, "I/O Error: {}", err),
-            Error::DLOpenError(err) => write!(fmt, "dlopen Error: {}", err),
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name)
+    }
+
+    fn open_fixture(name: &str) -> (TempDir, Journal) {
+        let dir = tempdir().unwrap();
+        fs::copy(fixture(name), dir.path().join("system.journal")).unwrap();
+        let journal = Journal::open_directories(vec![dir.path().into()]).unwrap();
+        (dir, journal)
+    }
+
+    fn messages(records: &[Record]) -> Vec<&str> {
+        records
+            .iter()
+            .filter(|record| record.contains_key("_SYSTEMD_UNIT"))
+            .map(|record| record["MESSAGE"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn journal_reads_compressed_files() {
+        let big = (0..200)
+            .map(|i| format!("{}:{} ", i, "x".repeat(i % 7)))
+            .collect::<String>();
+        for name in &["xz.journal", "lz4.journal", "zstd.journal"] {
+            let (_dir, journal) = open_fixture(name);
+            let records = journal.collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(records.len(), 9, "{}", name);
+            assert_eq!(
+                messages(&records),
+                vec![
+                    "Server listening on 0.0.0.0 port 22.",
+                    "Accepted publickey for vector from 10.0.0.2 port 51234",
+                    "reply from 192.168.1.2: offset -0.001791 delay 0.000176, next query 1500s",
+                    &big,
+                    "multi\nline message",
+                    "Received disconnect from 10.0.0.2 port 51234",
+                ],
+                "{}",
+                name
+            );
+            assert_eq!(records[2]["_SYSTEMD_UNIT"], "sshd.service");
+            assert_eq!(records[2]["SYSLOG_IDENTIFIER"], "sshd");
+            assert_eq!(records[2]["_TRANSPORT"], "journal");
         }
     }
+
+    #[test]
+    fn journal_cursor_matches_libsystemd() {
+        let (_dir, mut journal) = open_fixture("zstd.journal");
+        assert!(journal.cursor().is_err());
+        journal.next().unwrap().unwrap();
+        // As printed by `journalctl --file zstd.journal -o export`.
+        let cursor = "s=7e90f8b58af242178d7bcaccb5042ab6;i=1;b=56cbcbe6ab224dd8a55e92b3d4ad37ae;\
+                      m=aa909215;t=65e20828fcde2;x=ca73b4b287fb3e88";
+        assert_eq!(journal.cursor().unwrap(), cursor);
+        assert_eq!(cursor.parse::<Cursor>().unwrap().to_string(), cursor);
+        assert!("s=7e90f8b58af242178d7bcaccb5042ab6;i=1"
+            .parse::<Cursor>()
+            .is_err());
+    }
+
+    #[test]
+    fn journal_seeks_cursor() {
+        let (_dir, mut journal) = open_fixture("lz4.journal");
+        for _ in 0..4 {
+            journal.next().unwrap().unwrap();
+        }
+        let cursor = journal.cursor().unwrap();
+
+        let (_dir, mut journal) = open_fixture("xz.journal");
+        journal.seek_cursor(&cursor).unwrap();
+        assert_eq!(journal.cursor().unwrap(), cursor);
+        let records = journal.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 5);
+        assert_eq!(
+            records[0]["MESSAGE"],
+            "reply from 192.168.1.2: offset -0.001791 delay 0.000176, next query 1500s"
+        );
+    }
+
+    #[test]
+    fn journal_matches_fields() {
+        for name in &["xz.journal", "lz4.journal", "zstd.journal"] {
+            let (_dir, mut journal) = open_fixture(name);
+            journal.add_match("_SYSTEMD_UNIT=ntpd.service").unwrap();
+            journal.add_match("_TRANSPORT=journal").unwrap();
+            journal.add_disjunction();
+            journal.add_match("MESSAGE=Journal started").unwrap();
+            journal.add_conjunction();
+            journal.add_match("PRIORITY=6").unwrap();
+            journal.add_exclude("MESSAGE=multi\nline message").unwrap();
+
+            let records = journal.collect::<Result<Vec<_>, _>>().unwrap();
+            let messages = records
+                .iter()
+                .map(|record| &record["MESSAGE"][..8])
+                .collect::<Vec<_>>();
+            assert_eq!(
+                messages,
+                vec!["Journal ", "reply fr", "0: 1:x 2"],
+                "{}",
+                name
+            );
+        }
+
+        let (_dir, mut journal) = open_fixture("zstd.journal");
+        journal
+            .add_match("_SYSTEMD_UNIT=nonexistent.service")
+            .unwrap();
+        assert!(journal.next().is_none());
+    }
+
+    #[test]
+    fn journal_rejects_oversized_data_objects() {
+        let mut data = fs::read(fixture("xz.journal")).unwrap();
+        let payload = data
+            .windows(24)
+            .position(|window| window == b"MESSAGE=Server listening")
+            .unwrap();
+        // The object header precedes the payload, with its size at offset 8.
+        let object = payload - 64;
+        assert_eq!(data[object], 1);
+        data[object + 8..object + 16].copy_from_slice(&u64::max_value().to_le_bytes());
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("system.journal"), data).unwrap();
+        let journal = Journal::open_directories(vec![dir.path().into()]).unwrap();
+        let error = journal.collect::<Result<Vec<_>, _>>().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn journal_finds_new_files() {
+        let dir = tempdir().unwrap();
+        let mut journal = Journal::open_directories(vec![dir.path().into()]).unwrap();
+        assert!(journal.next().is_none());
+
+        fs::copy(fixture("zstd.journal"), dir.path().join("system.journal")).unwrap();
+        assert_eq!(journal.by_ref().count(), 9);
+        assert!(journal.next().is_none());
+
+        // A rotated file is not read again.
+        fs::rename(
+            dir.path().join("system.journal"),
+            dir.path().join("system@rotated.journal"),
+        )
+        .unwrap();
+        assert!(journal.next().is_none());
+        assert!(journal.next().is_none());
+
+        fs::copy(fixture("lz4.journal"), dir.path().join("system.journal")).unwrap();
+        assert_eq!(journal.by_ref().count(), 9);
+    }
 }
@@ -0,0 +1,111 @@
+//! Connections to AMQP 0-9-1 brokers, such as RabbitMQ, shared by the `amqp`
+//! source and sink.
+
+use crate::sinks::{
+    tcp::{connect_socket, Socket, SocketError, TcpSinkTls, TlsConfig},
+    util::load_balance::resolve_address,
+};
+use futures::Future;
+use lapin_futures::client::{Client, ConnectionOptions};
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::{fmt::Display, net::SocketAddr};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    Connect { source: SocketError },
+    #[snafu(display("AMQP error: {}", message))]
+    Amqp { message: String },
+    #[snafu(display("AMQP broker did not confirm a message"))]
+    Unconfirmed,
+}
+
+/// The errors of the AMQP client don't implement `std::error::Error`, so only
+/// their message is kept.
+pub fn amqp_error(error: impl Display) -> Error {
+    Error::Amqp {
+        message: error.to_string(),
+    }
+}
+
+/// The options shared by the `amqp` source and sink to reach the broker.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConnectionConfig {
+    /// The `host:port` of the broker.
+    pub address: String,
+    #[serde(default = "default_vhost")]
+    pub vhost: String,
+    /// Left empty, the broker's default `guest` user is used.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: Option<TlsConfig>,
+}
+
+fn default_vhost() -> String {
+    "/".into()
+}
+
+impl ConnectionConfig {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            vhost: default_vhost(),
+            username: None,
+            password: None,
+            tls: None,
+        }
+    }
+
+    pub fn build(&self) -> Result<Connector, crate::Error> {
+        let (hostname, addr) = resolve_address(&self.address)?;
+
+        let defaults = ConnectionOptions::default();
+        Ok(Connector {
+            hostname,
+            addr,
+            tls: TcpSinkTls::build(&self.tls)?,
+            options: ConnectionOptions {
+                username: self.username.clone().unwrap_or(defaults.username),
+                password: self.password.clone().unwrap_or(defaults.password),
+                vhost: self.vhost.clone(),
+                ..ConnectionOptions::default()
+            },
+        })
+    }
+}
+
+pub type Transport = Box<dyn Socket>;
+
+/// Opens connections to the broker, over TLS if enabled.
+pub struct Connector {
+    hostname: String,
+    addr: SocketAddr,
+    tls: Option<TcpSinkTls>,
+    options: ConnectionOptions,
+}
+
+impl Connector {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connect to the broker, resolving once the connection is open. Its
+    /// heartbeats are sent in the background for as long as it stays open.
+    pub fn connect(&self) -> Box<dyn Future<Item = Client<Transport>, Error = Error> + Send> {
+        let options = self.options.clone();
+        let addr = self.addr;
+
+        Box::new(
+            connect_socket(self.hostname.clone(), self.addr, self.tls.as_ref())
+                .map_err(|source| Error::Connect { source })
+                .and_then(move |stream| Client::connect(stream, options).map_err(amqp_error))
+                .map(move |(client, heartbeat)| {
+                    tokio::spawn(heartbeat.map_err(
+                        move |error| warn!(message = "AMQP heartbeat failed.", %addr, %error),
+                    ));
+                    client
+                }),
+        )
+    }
+}
@@ -126,6 +126,9 @@ impl BufferConfig {
 
 pub enum Acker {
     Disk(Arc<AtomicUsize>, Arc<AtomicTask>),
+    // Counts the acks of a sink polled by another one, which reads the count
+    // back after polling it.
+    Counter(Arc<AtomicUsize>),
     Null,
 }
 
@@ -143,6 +146,9 @@ impl Acker {
                 counter.fetch_add(num, Ordering::Relaxed);
                 notifier.notify();
             }
+            Acker::Counter(counter) => {
+                counter.fetch_add(num, Ordering::Relaxed);
+            }
         }
     }
 
@@ -11,9 +11,13 @@ extern crate derivative;
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+pub mod amqp;
 pub mod buffers;
 pub mod event;
 pub mod metrics;
+pub mod mqtt;
+pub mod nats;
+pub mod redis;
 pub mod region;
 pub mod sinks;
 pub mod sources;
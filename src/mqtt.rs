@@ -0,0 +1,590 @@
+//! The parts of MQTT 3.1.1 spoken by the `mqtt` source and sink, which are
+//! both clients of a broker.
+
+use crate::sinks::{
+    tcp::{connect_socket, Socket, SocketError, TcpSinkTls, TlsConfig},
+    util::load_balance::resolve_address,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Future, Sink, Stream};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{io, net::SocketAddr};
+use tokio::codec::{Decoder, Encoder, Framed};
+
+/// The largest packet accepted from a broker.
+const MAX_PACKET_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The largest remaining length that fits the four bytes allowed for it.
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    Connect { source: SocketError },
+    #[snafu(display("connection to the MQTT broker failed: {}", source))]
+    Io { source: io::Error },
+    #[snafu(display("MQTT broker refused the connection: {}", reason))]
+    Refused { reason: &'static str },
+    #[snafu(display("MQTT broker sent an unexpected {:?}", packet))]
+    Unexpected { packet: Packet },
+    #[snafu(display("MQTT broker closed the connection"))]
+    Closed,
+}
+
+/// How hard the broker and clients try to deliver a message.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QoS {
+    AtMostOnce,
+    AtLeastOnce,
+    ExactlyOnce,
+}
+
+impl QoS {
+    fn from_u8(qos: u8) -> io::Result<Self> {
+        match qos {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            other => Err(invalid(format!("invalid QoS {}", other))),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => 1,
+            QoS::ExactlyOnce => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connect {
+    pub client_id: String,
+    pub keep_alive: u16,
+    pub clean_session: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Publish {
+    pub topic: String,
+    pub qos: QoS,
+    /// Only set for messages published with a QoS above `at_most_once`.
+    pub packet_id: Option<u16>,
+    pub retain: bool,
+    pub dup: bool,
+    pub payload: Bytes,
+}
+
+/// The packets exchanged with a broker, leaving out wills and unsubscribing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    Connect(Connect),
+    ConnAck {
+        session_present: bool,
+        code: u8,
+    },
+    Publish(Publish),
+    PubAck(u16),
+    PubRec(u16),
+    PubRel(u16),
+    PubComp(u16),
+    Subscribe {
+        packet_id: u16,
+        filters: Vec<(String, QoS)>,
+    },
+    SubAck {
+        packet_id: u16,
+        codes: Vec<u8>,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+/// Frames packets with their fixed header, whose remaining length is encoded
+/// 7 bits at a time, least significant first.
+pub struct MqttCodec {
+    max_length: usize,
+}
+
+impl MqttCodec {
+    pub fn new() -> Self {
+        Self {
+            max_length: MAX_PACKET_LENGTH,
+        }
+    }
+}
+
+impl Decoder for MqttCodec {
+    type Item = Packet;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, io::Error> {
+        let (header_length, length) = match remaining_length(src)? {
+            Some(lengths) => lengths,
+            None => return Ok(None),
+        };
+        if length > self.max_length {
+            return Err(invalid(format!("packet of {} bytes is too long", length)));
+        }
+        if src.len() < header_length + length {
+            src.reserve(header_length + length - src.len());
+            return Ok(None);
+        }
+
+        let first = src[0];
+        src.advance(header_length);
+        let body = Reader(src.split_to(length).freeze());
+        parse_packet(first >> 4, first & 0x0f, body).map(Some)
+    }
+}
+
+impl Encoder for MqttCodec {
+    type Item = Packet;
+    type Error = io::Error;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> Result<(), io::Error> {
+        let mut body = Vec::new();
+        let first = write_packet(packet, &mut body);
+        if body.len() > MAX_REMAINING_LENGTH {
+            return Err(invalid(format!(
+                "packet of {} bytes is too long",
+                body.len()
+            )));
+        }
+
+        dst.reserve(5 + body.len());
+        dst.put_u8(first);
+        let mut length = body.len();
+        loop {
+            let byte = (length % 128) as u8;
+            length /= 128;
+            if length == 0 {
+                dst.put_u8(byte);
+                break;
+            }
+            dst.put_u8(byte | 0x80);
+        }
+        dst.put_slice(&body);
+        Ok(())
+    }
+}
+
+/// The length of the fixed header and the remaining length of the packet at
+/// the start of `src`, once enough of it has arrived.
+fn remaining_length(src: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let mut length = 0;
+    for i in 0..4 {
+        let byte = match src.get(1 + i) {
+            Some(&byte) => byte,
+            None => return Ok(None),
+        };
+        length |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((2 + i, length)));
+        }
+    }
+    Err(invalid("malformed remaining length"))
+}
+
+fn parse_packet(kind: u8, flags: u8, mut body: Reader) -> io::Result<Packet> {
+    let packet = match kind {
+        CONNECT => {
+            if body.string()? != "MQTT" || body.u8()? != 4 {
+                return Err(invalid("unsupported protocol version"));
+            }
+            let connect_flags = body.u8()?;
+            let keep_alive = body.u16()?;
+            let client_id = body.string()?;
+            if connect_flags & 0x04 != 0 {
+                // Wills are skipped over.
+                body.string()?;
+                body.binary()?;
+            }
+            let username = match connect_flags & 0x80 {
+                0 => None,
+                _ => Some(body.string()?),
+            };
+            let password = match connect_flags & 0x40 {
+                0 => None,
+                _ => Some(String::from_utf8_lossy(&body.binary()?).into_owned()),
+            };
+            Packet::Connect(Connect {
+                client_id,
+                keep_alive,
+                clean_session: connect_flags & 0x02 != 0,
+                username,
+                password,
+            })
+        }
+        CONNACK => Packet::ConnAck {
+            session_present: body.u8()? & 0x01 != 0,
+            code: body.u8()?,
+        },
+        PUBLISH => {
+            let qos = QoS::from_u8((flags >> 1) & 0x03)?;
+            let topic = body.string()?;
+            let packet_id = match qos {
+                QoS::AtMostOnce => None,
+                _ => Some(body.u16()?),
+            };
+            Packet::Publish(Publish {
+                topic,
+                qos,
+                packet_id,
+                retain: flags & 0x01 != 0,
+                dup: flags & 0x08 != 0,
+                payload: body.0,
+            })
+        }
+        PUBACK => Packet::PubAck(body.u16()?),
+        PUBREC => Packet::PubRec(body.u16()?),
+        PUBREL => Packet::PubRel(body.u16()?),
+        PUBCOMP => Packet::PubComp(body.u16()?),
+        SUBSCRIBE => {
+            let packet_id = body.u16()?;
+            let mut filters = Vec::new();
+            while !body.0.is_empty() {
+                let filter = body.string()?;
+                filters.push((filter, QoS::from_u8(body.u8()?)?));
+            }
+            Packet::Subscribe { packet_id, filters }
+        }
+        SUBACK => Packet::SubAck {
+            packet_id: body.u16()?,
+            codes: body.0.to_vec(),
+        },
+        PINGREQ => Packet::PingReq,
+        PINGRESP => Packet::PingResp,
+        DISCONNECT => Packet::Disconnect,
+        other => return Err(invalid(format!("unsupported packet type {}", other))),
+    };
+    Ok(packet)
+}
+
+/// Write the body of `packet` to `body`, returning the first byte of its
+/// fixed header.
+fn write_packet(packet: Packet, body: &mut Vec<u8>) -> u8 {
+    match packet {
+        Packet::Connect(connect) => {
+            write_binary(body, b"MQTT");
+            body.put_u8(4);
+            let mut connect_flags = 0;
+            if connect.clean_session {
+                connect_flags |= 0x02;
+            }
+            if connect.username.is_some() {
+                connect_flags |= 0x80;
+            }
+            if connect.password.is_some() {
+                connect_flags |= 0x40;
+            }
+            body.put_u8(connect_flags);
+            body.put_slice(&connect.keep_alive.to_be_bytes());
+            write_binary(body, connect.client_id.as_bytes());
+            if let Some(username) = connect.username {
+                write_binary(body, username.as_bytes());
+            }
+            if let Some(password) = connect.password {
+                write_binary(body, password.as_bytes());
+            }
+            CONNECT << 4
+        }
+        Packet::ConnAck {
+            session_present,
+            code,
+        } => {
+            body.put_u8(session_present as u8);
+            body.put_u8(code);
+            CONNACK << 4
+        }
+        Packet::Publish(publish) => {
+            write_binary(body, publish.topic.as_bytes());
+            if let Some(packet_id) = publish.packet_id {
+                body.put_slice(&packet_id.to_be_bytes());
+            }
+            body.put_slice(&publish.payload);
+            let mut flags = publish.qos.to_u8() << 1;
+            if publish.retain {
+                flags |= 0x01;
+            }
+            if publish.dup {
+                flags |= 0x08;
+            }
+            PUBLISH << 4 | flags
+        }
+        Packet::PubAck(packet_id) => {
+            body.put_slice(&packet_id.to_be_bytes());
+            PUBACK << 4
+        }
+        Packet::PubRec(packet_id) => {
+            body.put_slice(&packet_id.to_be_bytes());
+            PUBREC << 4
+        }
+        Packet::PubRel(packet_id) => {
+            body.put_slice(&packet_id.to_be_bytes());
+            PUBREL << 4 | 0x02
+        }
+        Packet::PubComp(packet_id) => {
+            body.put_slice(&packet_id.to_be_bytes());
+            PUBCOMP << 4
+        }
+        Packet::Subscribe { packet_id, filters } => {
+            body.put_slice(&packet_id.to_be_bytes());
+            for (filter, qos) in filters {
+                write_binary(body, filter.as_bytes());
+                body.put_u8(qos.to_u8());
+            }
+            SUBSCRIBE << 4 | 0x02
+        }
+        Packet::SubAck { packet_id, codes } => {
+            body.put_slice(&packet_id.to_be_bytes());
+            body.put_slice(&codes);
+            SUBACK << 4
+        }
+        Packet::PingReq => PINGREQ << 4,
+        Packet::PingResp => PINGRESP << 4,
+        Packet::Disconnect => DISCONNECT << 4,
+    }
+}
+
+fn write_binary(body: &mut Vec<u8>, data: &[u8]) {
+    body.put_slice(&(data.len() as u16).to_be_bytes());
+    body.put_slice(data);
+}
+
+/// Reads the fields of a packet body, failing on truncated ones.
+struct Reader(Bytes);
+
+impl Reader {
+    fn take(&mut self, length: usize) -> io::Result<Bytes> {
+        if self.0.len() < length {
+            return Err(invalid("truncated packet"));
+        }
+        Ok(self.0.split_to(length))
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn binary(&mut self) -> io::Result<Bytes> {
+        let length = self.u16()?;
+        self.take(length as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let bytes = self.binary()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string is not valid UTF-8"))
+    }
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// The options shared by the `mqtt` source and sink to reach the broker.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConnectionConfig {
+    /// The `host:port` of the broker.
+    pub address: String,
+    /// Left empty, the broker assigns one to the connection.
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: Option<TlsConfig>,
+}
+
+impl ConnectionConfig {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            client_id: None,
+            username: None,
+            password: None,
+            tls: None,
+        }
+    }
+
+    pub fn build(&self, keep_alive: u16) -> Result<Connector, crate::Error> {
+        let (hostname, addr) = resolve_address(&self.address)?;
+
+        Ok(Connector {
+            hostname,
+            addr,
+            tls: TcpSinkTls::build(&self.tls)?,
+            connect: Connect {
+                client_id: self.client_id.clone().unwrap_or_default(),
+                keep_alive,
+                // Messages in flight are retried by the sink, and the source
+                // subscribes again, so there is no session worth resuming.
+                clean_session: true,
+                username: self.username.clone(),
+                password: self.password.clone(),
+            },
+        })
+    }
+}
+
+pub type Transport = Framed<Box<dyn Socket>, MqttCodec>;
+
+/// Opens connections to the broker, over TLS if enabled.
+pub struct Connector {
+    hostname: String,
+    addr: SocketAddr,
+    tls: Option<TcpSinkTls>,
+    connect: Connect,
+}
+
+impl Connector {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connect to the broker, resolving once it accepted the connection.
+    pub fn connect(&self) -> Box<dyn Future<Item = Transport, Error = Error> + Send> {
+        let connect = Packet::Connect(self.connect.clone());
+        let stream = connect_socket(self.hostname.clone(), self.addr, self.tls.as_ref())
+            .map_err(|source| Error::Connect { source });
+
+        Box::new(
+            stream
+                .and_then(move |stream| {
+                    Framed::new(stream, MqttCodec::new())
+                        .send(connect)
+                        .then(|result| result.context(Io))
+                })
+                .and_then(|transport| {
+                    transport
+                        .into_future()
+                        .map_err(|(source, _)| Error::Io { source })
+                })
+                .and_then(|(packet, transport)| match packet {
+                    Some(Packet::ConnAck { code: 0, .. }) => Ok(transport),
+                    Some(Packet::ConnAck { code, .. }) => Err(Error::Refused {
+                        reason: refusal(code),
+                    }),
+                    Some(packet) => Err(Error::Unexpected { packet }),
+                    None => Err(Error::Closed),
+                }),
+        )
+    }
+}
+
+fn refusal(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Connect, MqttCodec, Packet, Publish, QoS};
+    use bytes::BytesMut;
+    use tokio::codec::{Decoder, Encoder};
+
+    fn round_trip(packet: Packet) {
+        let mut codec = MqttCodec::new();
+        let mut buffer = BytesMut::new();
+        codec.encode(packet.clone(), &mut buffer).unwrap();
+
+        // Nothing comes out of an incomplete packet.
+        let mut partial = BytesMut::from(&buffer[..buffer.len() - 1]);
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(packet));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn mqtt_codec_round_trips() {
+        round_trip(Packet::Connect(Connect {
+            client_id: "vector".into(),
+            keep_alive: 60,
+            clean_session: true,
+            username: Some("user".into()),
+            password: Some("secret".into()),
+        }));
+        round_trip(Packet::ConnAck {
+            session_present: false,
+            code: 4,
+        });
+        round_trip(Packet::Publish(Publish {
+            topic: "sensors/1/temperature".into(),
+            qos: QoS::ExactlyOnce,
+            packet_id: Some(7),
+            retain: true,
+            dup: true,
+            // Long enough for the remaining length to take two bytes.
+            payload: vec![b'x'; 300].into(),
+        }));
+        round_trip(Packet::Publish(Publish {
+            topic: "sensors".into(),
+            qos: QoS::AtMostOnce,
+            packet_id: None,
+            retain: false,
+            dup: false,
+            payload: "21.5".into(),
+        }));
+        round_trip(Packet::PubRel(7));
+        round_trip(Packet::Subscribe {
+            packet_id: 1,
+            filters: vec![
+                ("sensors/+/temperature".into(), QoS::AtLeastOnce),
+                ("alerts/#".into(), QoS::AtMostOnce),
+            ],
+        });
+        round_trip(Packet::SubAck {
+            packet_id: 1,
+            codes: vec![1, 0x80],
+        });
+        round_trip(Packet::PingReq);
+    }
+
+    #[test]
+    fn mqtt_codec_rejects_invalid_packets() {
+        let mut codec = MqttCodec::new();
+        assert!(codec
+            .decode(&mut BytesMut::from(&b"\x30\xff\xff\xff\xff\x01"[..]))
+            .is_err());
+        // A publish with QoS 3.
+        assert!(codec
+            .decode(&mut BytesMut::from(&b"\x36\x03\x00\x01a"[..]))
+            .is_err());
+        // A topic longer than the packet.
+        assert!(codec
+            .decode(&mut BytesMut::from(&b"\x30\x03\x00\x05a"[..]))
+            .is_err());
+    }
+}
@@ -0,0 +1,508 @@
+//! The parts of the NATS client protocol spoken by the `nats` source and sink.
+//! Operations are control lines ending with `\r\n`, those publishing and
+//! delivering messages being followed by their payload.
+
+use crate::sinks::util::load_balance::resolve_address;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{
+    future::{self, Loop},
+    Future, Sink, Stream,
+};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{io, net::SocketAddr, str};
+use tokio::{
+    codec::{Decoder, Encoder, Framed},
+    net::TcpStream,
+};
+
+/// The longest control line accepted from a server.
+const MAX_CONTROL_LINE: usize = 4096;
+
+/// The largest payload accepted from a server.
+const MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not connect to {}: {}", addr, source))]
+    ConnectFailed { addr: SocketAddr, source: io::Error },
+    #[snafu(display("connection to the NATS server failed: {}", source))]
+    Io { source: io::Error },
+    #[snafu(display("NATS server reported an error: {}", message))]
+    Server { message: String },
+    #[snafu(display("NATS server sent an unexpected {:?}", op))]
+    Unexpected { op: Op },
+    #[snafu(display("NATS server closed the connection"))]
+    Closed,
+}
+
+/// The options a client connects with.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Connect {
+    pub verbose: bool,
+    pub pedantic: bool,
+    pub name: String,
+    pub lang: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pass: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+}
+
+/// The operations exchanged with a server, leaving out unsubscribing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    /// The JSON object describing the server, kept as is.
+    Info(String),
+    Connect(Connect),
+    Pub {
+        subject: String,
+        reply_to: Option<String>,
+        payload: Bytes,
+    },
+    Sub {
+        subject: String,
+        queue_group: Option<String>,
+        sid: String,
+    },
+    Msg {
+        subject: String,
+        sid: String,
+        reply_to: Option<String>,
+        payload: Bytes,
+    },
+    Ping,
+    Pong,
+    Ok,
+    Err(String),
+}
+
+/// Subjects are made of tokens separated by `.`, and can't hold whitespace as
+/// that separates the arguments of operations.
+pub fn is_valid_subject(subject: &str) -> bool {
+    !subject.is_empty() && !subject.contains(char::is_whitespace)
+}
+
+pub struct NatsCodec {
+    max_payload: usize,
+}
+
+impl NatsCodec {
+    pub fn new() -> Self {
+        Self {
+            max_payload: MAX_PAYLOAD,
+        }
+    }
+}
+
+impl Decoder for NatsCodec {
+    type Item = Op;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Op>, io::Error> {
+        let line_length = match src.windows(2).position(|window| window == b"\r\n") {
+            Some(length) => length,
+            None if src.len() > MAX_CONTROL_LINE => {
+                return Err(invalid("control line is too long"))
+            }
+            None => return Ok(None),
+        };
+
+        let line = str::from_utf8(&src[..line_length])
+            .map_err(|_| invalid("control line is not valid UTF-8"))?;
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(end) => (&line[..end], line[end..].trim()),
+            None => (line, ""),
+        };
+        let name = name.to_ascii_uppercase();
+        let args = rest
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        let op = match (name.as_str(), args.len()) {
+            ("INFO", _) => Op::Info(rest.into()),
+            ("CONNECT", _) => Op::Connect(serde_json::from_str(rest).map_err(invalid)?),
+            ("SUB", 2) => Op::Sub {
+                subject: args[0].clone(),
+                queue_group: None,
+                sid: args[1].clone(),
+            },
+            ("SUB", 3) => Op::Sub {
+                subject: args[0].clone(),
+                queue_group: Some(args[1].clone()),
+                sid: args[2].clone(),
+            },
+            ("PING", 0) => Op::Ping,
+            ("PONG", 0) => Op::Pong,
+            ("+OK", 0) => Op::Ok,
+            ("-ERR", _) => Op::Err(rest.trim_matches('\'').into()),
+            ("PUB", 2) | ("PUB", 3) | ("MSG", 3) | ("MSG", 4) => {
+                return self.decode_payload(src, line_length, &name, args)
+            }
+            _ => return Err(invalid(format!("invalid operation: {:?}", line))),
+        };
+
+        src.advance(line_length + 2);
+        Ok(Some(op))
+    }
+}
+
+impl NatsCodec {
+    /// Decode the operations followed by a payload, whose length is their
+    /// last argument, once all of it has arrived.
+    fn decode_payload(
+        &self,
+        src: &mut BytesMut,
+        line_length: usize,
+        name: &str,
+        mut args: Vec<String>,
+    ) -> io::Result<Option<Op>> {
+        let length = args
+            .pop()
+            .and_then(|length| length.parse::<usize>().ok())
+            .ok_or_else(|| invalid("invalid payload length"))?;
+        if length > self.max_payload {
+            return Err(invalid(format!("payload of {} bytes is too long", length)));
+        }
+
+        let total = line_length + 2 + length + 2;
+        if src.len() < total {
+            src.reserve(total - src.len());
+            return Ok(None);
+        }
+        if &src[total - 2..total] != b"\r\n" {
+            return Err(invalid("payload is not followed by a line end"));
+        }
+
+        src.advance(line_length + 2);
+        let payload = src.split_to(length).freeze();
+        src.advance(2);
+
+        let mut args = args.into_iter();
+        let subject = args.next().unwrap();
+        Ok(Some(if name == "PUB" {
+            Op::Pub {
+                subject,
+                reply_to: args.next(),
+                payload,
+            }
+        } else {
+            Op::Msg {
+                subject,
+                sid: args.next().unwrap(),
+                reply_to: args.next(),
+                payload,
+            }
+        }))
+    }
+}
+
+impl Encoder for NatsCodec {
+    type Item = Op;
+    type Error = io::Error;
+
+    fn encode(&mut self, op: Op, dst: &mut BytesMut) -> Result<(), io::Error> {
+        let (line, payload) = match op {
+            Op::Info(info) => (format!("INFO {}", info), None),
+            Op::Connect(connect) => (
+                format!(
+                    "CONNECT {}",
+                    serde_json::to_string(&connect).map_err(invalid)?
+                ),
+                None,
+            ),
+            Op::Pub {
+                subject,
+                reply_to,
+                payload,
+            } => {
+                check_subject(&subject)?;
+                let line = match reply_to {
+                    Some(reply_to) => format!("PUB {} {} {}", subject, reply_to, payload.len()),
+                    None => format!("PUB {} {}", subject, payload.len()),
+                };
+                (line, Some(payload))
+            }
+            Op::Sub {
+                subject,
+                queue_group,
+                sid,
+            } => {
+                check_subject(&subject)?;
+                match queue_group {
+                    Some(queue_group) => (format!("SUB {} {} {}", subject, queue_group, sid), None),
+                    None => (format!("SUB {} {}", subject, sid), None),
+                }
+            }
+            Op::Msg {
+                subject,
+                sid,
+                reply_to,
+                payload,
+            } => {
+                let line = match reply_to {
+                    Some(reply_to) => {
+                        format!("MSG {} {} {} {}", subject, sid, reply_to, payload.len())
+                    }
+                    None => format!("MSG {} {} {}", subject, sid, payload.len()),
+                };
+                (line, Some(payload))
+            }
+            Op::Ping => ("PING".into(), None),
+            Op::Pong => ("PONG".into(), None),
+            Op::Ok => ("+OK".into(), None),
+            Op::Err(message) => (format!("-ERR '{}'", message), None),
+        };
+
+        dst.reserve(line.len() + 2 + payload.as_ref().map_or(0, |payload| payload.len() + 2));
+        dst.put_slice(line.as_bytes());
+        dst.put_slice(b"\r\n");
+        if let Some(payload) = payload {
+            dst.put_slice(&payload);
+            dst.put_slice(b"\r\n");
+        }
+        Ok(())
+    }
+}
+
+fn check_subject(subject: &str) -> io::Result<()> {
+    if is_valid_subject(subject) {
+        Ok(())
+    } else {
+        Err(invalid(format!("invalid subject: {:?}", subject)))
+    }
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// The options shared by the `nats` source and sink to reach the server.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConnectionConfig {
+    /// The `host:port` of the server.
+    pub address: String,
+    /// The name the connection is listed under by the server.
+    #[serde(default = "default_name")]
+    pub name: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+}
+
+fn default_name() -> String {
+    "vector".into()
+}
+
+impl ConnectionConfig {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            name: default_name(),
+            username: None,
+            password: None,
+            token: None,
+        }
+    }
+
+    pub fn build(&self) -> Result<Connector, crate::Error> {
+        let (_, addr) = resolve_address(&self.address)?;
+
+        Ok(Connector {
+            addr,
+            connect: Connect {
+                verbose: false,
+                pedantic: false,
+                name: self.name.clone(),
+                lang: "rust".into(),
+                version: env!("CARGO_PKG_VERSION").into(),
+                user: self.username.clone(),
+                pass: self.password.clone(),
+                auth_token: self.token.clone(),
+            },
+        })
+    }
+}
+
+pub type Transport = Framed<TcpStream, NatsCodec>;
+
+/// Opens connections to the server.
+pub struct Connector {
+    addr: SocketAddr,
+    connect: Connect,
+}
+
+impl Connector {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connect to the server, resolving once it accepted the connection.
+    pub fn connect(&self) -> Box<dyn Future<Item = Transport, Error = Error> + Send> {
+        let connect = Op::Connect(self.connect.clone());
+        let addr = self.addr;
+
+        Box::new(
+            future::lazy(move || TcpStream::connect(&addr))
+                .then(move |result| result.context(ConnectFailed { addr }))
+                .and_then(|stream| {
+                    Framed::new(stream, NatsCodec::new())
+                        .into_future()
+                        .map_err(|(source, _)| Error::Io { source })
+                })
+                .and_then(|(op, transport)| match op {
+                    Some(Op::Info(_)) => Ok(transport),
+                    op => Err(unexpected(op)),
+                })
+                .and_then(move |transport| {
+                    transport
+                        .send(connect)
+                        .and_then(|transport| transport.send(Op::Ping))
+                        .then(|result| result.context(Io))
+                })
+                // Servers reject a connection with an error before answering
+                // the ping that follows it.
+                .and_then(|transport| {
+                    future::loop_fn(transport, |transport| {
+                        transport
+                            .into_future()
+                            .map_err(|(source, _)| Error::Io { source })
+                            .and_then(|(op, transport)| match op {
+                                Some(Op::Pong) => Ok(Loop::Break(transport)),
+                                Some(Op::Ok) | Some(Op::Info(_)) => Ok(Loop::Continue(transport)),
+                                op => Err(unexpected(op)),
+                            })
+                    })
+                }),
+        )
+    }
+}
+
+/// The error for an operation the client didn't expect, or for the connection
+/// being closed instead.
+pub fn unexpected(op: Option<Op>) -> Error {
+    match op {
+        Some(Op::Err(message)) => Error::Server { message },
+        Some(op) => Error::Unexpected { op },
+        None => Error::Closed,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Connect, NatsCodec, Op};
+    use bytes::BytesMut;
+    use tokio::codec::{Decoder, Encoder};
+
+    fn round_trip(op: Op) {
+        let mut buffer = BytesMut::new();
+        NatsCodec::new().encode(op.clone(), &mut buffer).unwrap();
+
+        // Operations are only decoded once all of them has arrived.
+        let mut partial = BytesMut::from(&buffer[..buffer.len() - 1]);
+        assert_eq!(NatsCodec::new().decode(&mut partial).unwrap(), None);
+
+        assert_eq!(NatsCodec::new().decode(&mut buffer).unwrap(), Some(op));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn nats_codec_round_trips() {
+        round_trip(Op::Info(
+            r#"{"server_id":"test","max_payload":1048576}"#.into(),
+        ));
+        round_trip(Op::Connect(Connect {
+            verbose: false,
+            pedantic: false,
+            name: "vector".into(),
+            lang: "rust".into(),
+            version: "0.4.0".into(),
+            user: Some("user".into()),
+            pass: Some("secret".into()),
+            auth_token: None,
+        }));
+        round_trip(Op::Pub {
+            subject: "logs.web".into(),
+            reply_to: None,
+            payload: "hello\r\nworld".into(),
+        });
+        round_trip(Op::Pub {
+            subject: "logs.web".into(),
+            reply_to: Some("inbox.1".into()),
+            payload: "".into(),
+        });
+        round_trip(Op::Sub {
+            subject: "logs.>".into(),
+            queue_group: Some("vector".into()),
+            sid: "1".into(),
+        });
+        round_trip(Op::Sub {
+            subject: "logs.*".into(),
+            queue_group: None,
+            sid: "2".into(),
+        });
+        round_trip(Op::Msg {
+            subject: "logs.web".into(),
+            sid: "1".into(),
+            reply_to: None,
+            payload: "hello".into(),
+        });
+        round_trip(Op::Msg {
+            subject: "logs.web".into(),
+            sid: "1".into(),
+            reply_to: Some("inbox.2".into()),
+            payload: "hello".into(),
+        });
+        round_trip(Op::Ping);
+        round_trip(Op::Pong);
+        round_trip(Op::Ok);
+        round_trip(Op::Err("Authorization Violation".into()));
+    }
+
+    #[test]
+    fn nats_codec_decodes_case_insensitively() {
+        let mut buffer = BytesMut::from(&b"ping\r\nmsg foo 1 2\r\nhi\r\n"[..]);
+        let mut codec = NatsCodec::new();
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Op::Ping));
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap(),
+            Some(Op::Msg {
+                subject: "foo".into(),
+                sid: "1".into(),
+                reply_to: None,
+                payload: "hi".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn nats_codec_rejects_invalid_operations() {
+        for invalid in &[
+            &b"HELLO\r\n"[..],
+            b"MSG foo 1\r\n",
+            b"MSG foo 1 two\r\n",
+            b"MSG foo 1 2\r\nhello\r\n",
+            b"SUB foo\r\n",
+            b"CONNECT {\r\n",
+        ] {
+            assert!(NatsCodec::new()
+                .decode(&mut BytesMut::from(*invalid))
+                .is_err());
+        }
+
+        let mut buffer = BytesMut::new();
+        let publish = Op::Pub {
+            subject: "logs web".into(),
+            reply_to: None,
+            payload: "hello".into(),
+        };
+        assert!(NatsCodec::new().encode(publish, &mut buffer).is_err());
+    }
+}
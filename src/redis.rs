@@ -0,0 +1,355 @@
+//! The parts of the Redis serialization protocol (RESP) spoken by the `redis`
+//! source and sink. Commands are arrays of bulk strings, and every command
+//! gets a reply, except while subscribed to channels.
+
+use crate::sinks::util::load_balance::resolve_address;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{stream, Future, Sink, Stream};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{io, net::SocketAddr, str, time::Duration};
+use tokio::{
+    codec::{Decoder, Encoder, Framed},
+    net::TcpStream,
+};
+
+/// The largest bulk string accepted from a server.
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+
+/// How long a connection stays idle before the system checks on it, so that
+/// a lost server is noticed by blocked and subscribed connections.
+const KEEPALIVE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not connect to {}: {}", addr, source))]
+    ConnectFailed { addr: SocketAddr, source: io::Error },
+    #[snafu(display("connection to the Redis server failed: {}", source))]
+    Io { source: io::Error },
+    #[snafu(display("Redis server replied with an error: {}", message))]
+    Server { message: String },
+    #[snafu(display("Redis server sent an unexpected {:?}", value))]
+    Unexpected { value: Value },
+    #[snafu(display("Redis server closed the connection"))]
+    Closed,
+}
+
+/// A RESP value, null bulk strings and arrays both being `Nil`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Data(Bytes),
+    Array(Vec<Value>),
+}
+
+/// Build a command out of its name and arguments.
+pub fn command<I>(args: I) -> Value
+where
+    I: IntoIterator,
+    I::Item: Into<Bytes>,
+{
+    Value::Array(
+        args.into_iter()
+            .map(|arg| Value::Data(arg.into()))
+            .collect(),
+    )
+}
+
+pub struct RespCodec {
+    max_length: usize,
+}
+
+impl Default for RespCodec {
+    fn default() -> Self {
+        Self {
+            max_length: MAX_BULK_LENGTH,
+        }
+    }
+}
+
+impl RespCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the value starting at `pos`, resolving with it and where it ends
+    /// once all of it has arrived.
+    fn parse(&self, src: &[u8], pos: usize) -> io::Result<Option<(Value, usize)>> {
+        let line_end = match src[pos..].windows(2).position(|window| window == b"\r\n") {
+            Some(length) => pos + length,
+            None => return Ok(None),
+        };
+        if line_end == pos {
+            return Err(invalid("empty line"));
+        }
+        let line = str::from_utf8(&src[pos + 1..line_end])
+            .map_err(|_| invalid("line is not valid UTF-8"))?;
+        let next = line_end + 2;
+
+        let value = match src[pos] {
+            b'+' => Value::Status(line.into()),
+            b'-' => Value::Error(line.into()),
+            b':' => Value::Integer(parse_integer(line)?),
+            b'$' => {
+                let length = parse_integer(line)?;
+                if length < 0 {
+                    return Ok(Some((Value::Nil, next)));
+                }
+                let length = length as usize;
+                if length > self.max_length {
+                    return Err(invalid(format!(
+                        "bulk string of {} bytes is too long",
+                        length
+                    )));
+                }
+                if src.len() < next + length + 2 {
+                    return Ok(None);
+                }
+                if &src[next + length..next + length + 2] != b"\r\n" {
+                    return Err(invalid("bulk string is not followed by a line end"));
+                }
+                let data = Bytes::from(&src[next..next + length]);
+                return Ok(Some((Value::Data(data), next + length + 2)));
+            }
+            b'*' => {
+                let count = parse_integer(line)?;
+                if count < 0 {
+                    return Ok(Some((Value::Nil, next)));
+                }
+                let mut values = Vec::new();
+                let mut pos = next;
+                for _ in 0..count {
+                    match self.parse(src, pos)? {
+                        Some((value, end)) => {
+                            values.push(value);
+                            pos = end;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                return Ok(Some((Value::Array(values), pos)));
+            }
+            other => return Err(invalid(format!("invalid type byte {:?}", other as char))),
+        };
+        Ok(Some((value, next)))
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = Value;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Value>, io::Error> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        match self.parse(src, 0)? {
+            Some((value, end)) => {
+                src.advance(end);
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder for RespCodec {
+    type Item = Value;
+    type Error = io::Error;
+
+    fn encode(&mut self, value: Value, dst: &mut BytesMut) -> Result<(), io::Error> {
+        match value {
+            Value::Nil => put_line(dst, b'$', "-1"),
+            Value::Status(status) => put_line(dst, b'+', &status),
+            Value::Error(message) => put_line(dst, b'-', &message),
+            Value::Integer(integer) => put_line(dst, b':', &integer.to_string()),
+            Value::Data(data) => {
+                put_line(dst, b'$', &data.len().to_string());
+                dst.reserve(data.len() + 2);
+                dst.put_slice(&data);
+                dst.put_slice(b"\r\n");
+            }
+            Value::Array(values) => {
+                put_line(dst, b'*', &values.len().to_string());
+                for value in values {
+                    self.encode(value, dst)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn put_line(dst: &mut BytesMut, kind: u8, line: &str) {
+    dst.reserve(line.len() + 3);
+    dst.put_u8(kind);
+    dst.put_slice(line.as_bytes());
+    dst.put_slice(b"\r\n");
+}
+
+fn parse_integer(line: &str) -> io::Result<i64> {
+    line.parse()
+        .map_err(|_| invalid(format!("invalid integer {:?}", line)))
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// The options shared by the `redis` source and sink to reach the server.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConnectionConfig {
+    /// The `host:port` of the server.
+    pub address: String,
+    pub password: Option<String>,
+    /// The logical database to select, the server defaulting to `0`.
+    pub database: Option<u32>,
+}
+
+impl ConnectionConfig {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            password: None,
+            database: None,
+        }
+    }
+
+    pub fn build(&self) -> Result<Connector, crate::Error> {
+        let (_, addr) = resolve_address(&self.address)?;
+
+        let mut setup = Vec::new();
+        if let Some(password) = &self.password {
+            setup.push(command(vec!["AUTH".into(), password.clone()]));
+        }
+        if let Some(database) = self.database {
+            setup.push(command(vec!["SELECT".into(), database.to_string()]));
+        }
+
+        Ok(Connector { addr, setup })
+    }
+}
+
+pub type Transport = Framed<TcpStream, RespCodec>;
+
+/// Opens connections to the server, authenticated and on the database to use.
+pub struct Connector {
+    addr: SocketAddr,
+    setup: Vec<Value>,
+}
+
+impl Connector {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connect to the server, resolving once it accepted the commands setting
+    /// up the connection.
+    pub fn connect(&self) -> Box<dyn Future<Item = Transport, Error = Error> + Send> {
+        let addr = self.addr;
+        let setup = self.setup.clone();
+
+        Box::new(
+            futures::future::lazy(move || TcpStream::connect(&addr))
+                .and_then(|stream| stream.set_keepalive(Some(KEEPALIVE)).map(|()| stream))
+                .then(move |result| result.context(ConnectFailed { addr }))
+                .and_then(|stream| {
+                    let transport = Framed::new(stream, RespCodec::new());
+                    stream::iter_ok(setup).fold(transport, |transport, command| {
+                        request(transport, command).and_then(|(reply, transport)| match reply {
+                            Value::Status(_) => Ok(transport),
+                            reply => Err(unexpected(Some(reply))),
+                        })
+                    })
+                }),
+        )
+    }
+}
+
+/// Send a command and wait for its reply.
+pub fn request(
+    transport: Transport,
+    command: Value,
+) -> impl Future<Item = (Value, Transport), Error = Error> {
+    transport
+        .send(command)
+        .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+        .then(|result| result.context(Io))
+        .and_then(|(reply, transport)| match reply {
+            Some(reply) => Ok((reply, transport)),
+            None => Err(Error::Closed),
+        })
+}
+
+/// The error for a reply the client didn't expect, or for the connection being
+/// closed instead.
+pub fn unexpected(value: Option<Value>) -> Error {
+    match value {
+        Some(Value::Error(message)) => Error::Server { message },
+        Some(value) => Error::Unexpected { value },
+        None => Error::Closed,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command, RespCodec, Value};
+    use bytes::BytesMut;
+    use tokio::codec::{Decoder, Encoder};
+
+    fn round_trip(value: Value) {
+        let mut buffer = BytesMut::new();
+        RespCodec::new().encode(value.clone(), &mut buffer).unwrap();
+
+        // Values are only decoded once all of them has arrived.
+        let mut partial = BytesMut::from(&buffer[..buffer.len() - 1]);
+        assert_eq!(RespCodec::new().decode(&mut partial).unwrap(), None);
+
+        assert_eq!(RespCodec::new().decode(&mut buffer).unwrap(), Some(value));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn redis_codec_round_trips() {
+        round_trip(Value::Nil);
+        round_trip(Value::Status("OK".into()));
+        round_trip(Value::Error("WRONGTYPE Operation against a key".into()));
+        round_trip(Value::Integer(-42));
+        round_trip(Value::Data("hello\r\nworld".into()));
+        round_trip(Value::Data("".into()));
+        round_trip(command(vec!["RPUSH", "logs", "hello"]));
+        round_trip(Value::Array(vec![
+            Value::Data("message".into()),
+            Value::Array(vec![Value::Integer(1), Value::Nil]),
+        ]));
+        round_trip(Value::Array(vec![]));
+    }
+
+    #[test]
+    fn redis_codec_decodes_null_arrays() {
+        let mut buffer = BytesMut::from(&b"*-1\r\n:1\r\n"[..]);
+        let mut codec = RespCodec::new();
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Value::Nil));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Value::Integer(1)));
+    }
+
+    #[test]
+    fn redis_codec_rejects_invalid_values() {
+        for invalid in &[
+            &b"hello\r\n"[..],
+            b":one\r\n",
+            b"$2\r\nhello\r\n",
+            b"*x\r\n",
+        ] {
+            assert!(RespCodec::new()
+                .decode(&mut BytesMut::from(*invalid))
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use super::util::{encode_event, reconnect::Connect, Encoding, InFlight, Reconnect};
+use crate::{
+    amqp::{self, amqp_error, ConnectionConfig, Connector, Transport},
+    buffers::Acker,
+    event::Event,
+    template::Template,
+    topology::config::{DataType, SinkConfig},
+};
+use futures::{stream::FuturesUnordered, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use lapin_futures::channel::{BasicProperties, BasicPublishOptions, Channel, ConfirmSelectOptions};
+use serde::{Deserialize, Serialize};
+
+/// How many messages may await confirmation from the broker.
+const MAX_IN_FLIGHT: usize = 1000;
+
+/// Publishes events to an AMQP exchange, with a routing key rendered from each
+/// of them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AmqpSinkConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    /// Left empty, messages go through the default exchange, which routes
+    /// them to the queue named by their routing key.
+    #[serde(default)]
+    pub exchange: String,
+    #[serde(default = "default_routing_key")]
+    pub routing_key: Template,
+    pub encoding: Option<Encoding>,
+}
+
+fn default_routing_key() -> Template {
+    Template::from("")
+}
+
+impl AmqpSinkConfig {
+    pub fn new(connection: ConnectionConfig, exchange: String) -> Self {
+        Self {
+            connection,
+            exchange,
+            routing_key: default_routing_key(),
+            encoding: None,
+        }
+    }
+}
+
+#[typetag::serde(name = "amqp")]
+impl SinkConfig for AmqpSinkConfig {
+    fn build(&self, acker: Acker) -> Result<(super::RouterSink, super::Healthcheck), crate::Error> {
+        let connector = self.connection.build()?;
+        let healthcheck = Box::new(
+            connector
+                .connect()
+                .and_then(|client| client.create_channel().map_err(amqp_error))
+                .map(|_| ())
+                .map_err(Into::into),
+        );
+        let sink = AmqpSink::new(connector, self.clone(), acker);
+
+        Ok((Box::new(sink), healthcheck))
+    }
+
+    fn input_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+struct Message {
+    seqno: usize,
+    routing_key: String,
+    payload: Vec<u8>,
+    confirmed: bool,
+}
+
+type Confirm = Box<dyn Future<Item = (usize, Option<u64>), Error = amqp::Error> + Send>;
+
+/// Keeps messages until the broker confirms them, publishing them again over a
+/// new channel when it is lost first.
+pub struct AmqpSink {
+    exchange: String,
+    routing_key: Template,
+    encoding: Option<Encoding>,
+    seq_head: usize,
+    in_flight: InFlight<Message>,
+    /// How many of the messages in flight were published over this channel.
+    sent: usize,
+    confirms: FuturesUnordered<Confirm>,
+    connection: Reconnect<Channel<Transport>, amqp::Error>,
+}
+
+impl AmqpSink {
+    fn new(connector: Connector, config: AmqpSinkConfig, acker: Acker) -> Self {
+        let addr = connector.addr();
+        let connect = move || -> Connect<Channel<Transport>, amqp::Error> {
+            Box::new(connector.connect().and_then(|client| {
+                client
+                    .create_confirm_channel(ConfirmSelectOptions::default())
+                    .map_err(amqp_error)
+            }))
+        };
+
+        Self {
+            exchange: config.exchange,
+            routing_key: config.routing_key,
+            encoding: config.encoding,
+            seq_head: 0,
+            in_flight: InFlight::new(acker),
+            sent: 0,
+            confirms: FuturesUnordered::new(),
+            connection: Reconnect::new(addr, connect),
+        }
+    }
+
+    /// Publish whatever wasn't published yet over the channel, then handle the
+    /// confirms received so far.
+    fn poll_channel(&mut self) -> Result<(), amqp::Error> {
+        let channel = self.connection.get_mut();
+
+        while self.sent < self.in_flight.len() {
+            let message = &self.in_flight[self.sent];
+            self.sent += 1;
+            if message.confirmed {
+                continue;
+            }
+
+            let seqno = message.seqno;
+            let confirm = channel
+                .basic_publish(
+                    &self.exchange,
+                    &message.routing_key,
+                    message.payload.clone(),
+                    BasicPublishOptions::default(),
+                    BasicProperties::default(),
+                )
+                .map(move |confirm| (seqno, confirm))
+                .map_err(amqp_error);
+            self.confirms.push(Box::new(confirm));
+        }
+
+        while let Async::Ready(Some((seqno, confirm))) = self.confirms.poll()? {
+            if confirm.is_none() {
+                return Err(amqp::Error::Unconfirmed);
+            }
+            match self
+                .in_flight
+                .iter_mut()
+                .find(|message| message.seqno == seqno)
+            {
+                Some(message) => message.confirmed = true,
+                None => debug!(message = "confirm of unknown message.", seqno),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Sink for AmqpSink {
+    type SinkItem = Event;
+    type SinkError = ();
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.in_flight.len() >= MAX_IN_FLIGHT {
+            self.poll_complete()?;
+            if self.in_flight.len() >= MAX_IN_FLIGHT {
+                return Ok(AsyncSink::NotReady(event));
+            }
+        }
+
+        let routing_key = match self.routing_key.render_string(&event) {
+            Ok(routing_key) => routing_key,
+            Err(missing_keys) => {
+                warn!(
+                    message =
+                        "keys in routing_key template do not exist on the event; dropping event.",
+                    ?missing_keys
+                );
+                self.in_flight.drop_event();
+                return Ok(AsyncSink::Ready);
+            }
+        };
+
+        let seqno = self.seq_head;
+        self.seq_head += 1;
+        self.in_flight.push(Message {
+            seqno,
+            routing_key,
+            payload: encode_event(&event, &self.encoding).to_vec(),
+            confirmed: false,
+        });
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        while !self.in_flight.is_empty() {
+            match self.connection.poll() {
+                // Confirms are tied to the channel they were published on, so
+                // whatever wasn't confirmed is published again.
+                Async::Ready(true) => {
+                    self.sent = 0;
+                    self.confirms = FuturesUnordered::new();
+                }
+                Async::Ready(false) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+
+            match self.poll_channel() {
+                Ok(()) => {
+                    let confirmed = self.in_flight.ack_while(|message| message.confirmed);
+                    self.sent = self.sent.saturating_sub(confirmed);
+                    if !self.in_flight.is_empty() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                Err(error) => self.connection.lost(error),
+            }
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(feature = "amqp-integration-tests")]
+#[cfg(test)]
+mod integration_test {
+    use super::AmqpSinkConfig;
+    use crate::{
+        amqp::{amqp_error, ConnectionConfig},
+        buffers::Acker,
+        event::Event,
+        sinks::util::Encoding,
+        test_util::{random_lines_with_stream, random_string, runtime, wait_for},
+        topology::config::SinkConfig,
+    };
+    use futures::{Future, Sink, Stream};
+    use lapin_futures::{
+        channel::{BasicConsumeOptions, QueueDeclareOptions},
+        types::FieldTable,
+    };
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn amqp_publishes_to_exchange() {
+        let queue = format!("test-{}", random_string(10));
+        let connection = ConnectionConfig::new("localhost:5672".into());
+
+        // The default exchange routes messages to the queue named by their
+        // routing key.
+        let mut config = AmqpSinkConfig::new(connection.clone(), String::new());
+        config.routing_key = "{{queue}}".into();
+        config.encoding = Some(Encoding::Text);
+        let (acker, acks) = Acker::new_for_testing();
+        let (sink, healthcheck) = config.build(acker).unwrap();
+
+        let mut rt = runtime();
+        rt.block_on(healthcheck).unwrap();
+
+        let declare_queue = queue.clone();
+        let consumer = connection
+            .build()
+            .unwrap()
+            .connect()
+            .and_then(|client| client.create_channel().map_err(amqp_error))
+            .and_then(move |channel| {
+                channel
+                    .queue_declare(
+                        &declare_queue,
+                        QueueDeclareOptions::default(),
+                        FieldTable::new(),
+                    )
+                    .and_then(move |queue| {
+                        channel.basic_consume(
+                            &queue,
+                            "test",
+                            BasicConsumeOptions {
+                                no_ack: true,
+                                ..BasicConsumeOptions::default()
+                            },
+                            FieldTable::new(),
+                        )
+                    })
+                    .map_err(amqp_error)
+            });
+        let consumer = rt.block_on(consumer).unwrap();
+
+        let num_events = 100;
+        let (input, events) = random_lines_with_stream(100, num_events);
+        let events = events.map(move |mut event: Event| {
+            event
+                .as_mut_log()
+                .insert_explicit("queue".into(), queue.as_str().into());
+            event
+        });
+        rt.block_on(sink.send_all(events)).unwrap();
+
+        let deliveries = rt
+            .block_on(consumer.take(num_events as u64).collect())
+            .unwrap();
+        let output = deliveries
+            .into_iter()
+            .map(|delivery| String::from_utf8(delivery.data).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(output, input);
+
+        wait_for(|| acks.load(Ordering::Relaxed) == num_events);
+    }
+}
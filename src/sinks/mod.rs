@@ -1,6 +1,7 @@
 use futures::{Future, Sink};
 use snafu::Snafu;
 
+pub mod amqp;
 pub mod aws_cloudwatch_logs;
 pub mod aws_cloudwatch_metrics;
 pub mod aws_kinesis_streams;
@@ -13,7 +14,10 @@ pub mod file;
 pub mod http;
 #[cfg(feature = "rdkafka")]
 pub mod kafka;
+pub mod mqtt;
+pub mod nats;
 pub mod prometheus;
+pub mod redis;
 pub mod splunk_hec;
 pub mod tcp;
 pub mod util;
@@ -34,6 +38,8 @@ enum BuildError {
     SocketAddressError { source: std::io::Error },
     #[snafu(display("URI parse error: {}", source))]
     UriParseError { source: ::http::uri::InvalidUri },
+    #[snafu(display("Must specify at least one of `address` or `addresses`"))]
+    MissingAddress,
 }
 
 /// Common healthcheck errors
@@ -0,0 +1,356 @@
+use super::util::{encode_event, Encoding, InFlight, Reconnect};
+use crate::{
+    buffers::Acker,
+    event::Event,
+    mqtt::{self, ConnectionConfig, Connector, Packet, Publish, QoS, Transport},
+    template::Template,
+    topology::config::{DataType, SinkConfig},
+};
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// How many messages may await acknowledgement from the broker.
+const MAX_IN_FLIGHT: usize = 100;
+
+/// Publishes events to an MQTT broker, under a topic rendered from each of
+/// them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MqttSinkConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    pub topic: Template,
+    #[serde(default = "default_qos")]
+    pub qos: QoS,
+    #[serde(default)]
+    pub retain: bool,
+    pub encoding: Option<Encoding>,
+}
+
+fn default_qos() -> QoS {
+    QoS::AtLeastOnce
+}
+
+impl MqttSinkConfig {
+    pub fn new(connection: ConnectionConfig, topic: Template) -> Self {
+        Self {
+            connection,
+            topic,
+            qos: default_qos(),
+            retain: false,
+            encoding: None,
+        }
+    }
+}
+
+#[typetag::serde(name = "mqtt")]
+impl SinkConfig for MqttSinkConfig {
+    fn build(&self, acker: Acker) -> Result<(super::RouterSink, super::Healthcheck), crate::Error> {
+        // The sink is only polled while it has events to send, so it can't
+        // keep an idle connection alive, and relies on noticing it was lost
+        // once it sends again.
+        let connector = self.connection.build(0)?;
+        let healthcheck = Box::new(connector.connect().map(|_| ()).map_err(Into::into));
+        let sink = MqttSink::new(connector, self.clone(), acker);
+
+        Ok((Box::new(sink), healthcheck))
+    }
+
+    fn input_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+struct Message {
+    publish: Publish,
+    acked: bool,
+}
+
+/// Keeps messages until the broker acknowledges them, as their QoS requires,
+/// publishing them again over a new connection when it is lost first.
+pub struct MqttSink {
+    topic: Template,
+    qos: QoS,
+    retain: bool,
+    encoding: Option<Encoding>,
+    packet_id: u16,
+    in_flight: InFlight<Message>,
+    /// How many of the messages in flight were sent over this connection.
+    sent: usize,
+    /// The messages sent exactly once whose release is yet to be sent.
+    releases: VecDeque<u16>,
+    connection: Reconnect<Transport, mqtt::Error>,
+}
+
+impl MqttSink {
+    fn new(connector: Connector, config: MqttSinkConfig, acker: Acker) -> Self {
+        Self {
+            topic: config.topic,
+            qos: config.qos,
+            retain: config.retain,
+            encoding: config.encoding,
+            packet_id: 0,
+            in_flight: InFlight::new(acker),
+            sent: 0,
+            releases: VecDeque::new(),
+            connection: Reconnect::new(connector.addr(), move || connector.connect()),
+        }
+    }
+
+    /// Packet ids go from 1 to 65535 before wrapping, which can't reuse one
+    /// still in flight.
+    fn next_packet_id(&mut self) -> u16 {
+        self.packet_id = self.packet_id % u16::max_value() + 1;
+        self.packet_id
+    }
+
+    /// Handle the acknowledgements received so far, then send whatever wasn't
+    /// sent yet over the connection.
+    fn poll_transport(&mut self) -> Result<(), mqtt::Error> {
+        let transport = self.connection.get_mut();
+
+        while let Async::Ready(packet) = transport.poll().map_err(io_error)? {
+            let packet = packet.ok_or(mqtt::Error::Closed)?;
+            let (id, received) = match packet {
+                Packet::PubAck(id) | Packet::PubComp(id) => (id, false),
+                Packet::PubRec(id) => (id, true),
+                Packet::PingResp => continue,
+                packet => {
+                    debug!(message = "ignoring packet.", ?packet);
+                    continue;
+                }
+            };
+            match self
+                .in_flight
+                .iter_mut()
+                .find(|message| message.publish.packet_id == Some(id))
+            {
+                Some(message) => {
+                    if !received {
+                        message.acked = true;
+                    } else if !message.acked {
+                        self.releases.push_back(id);
+                    }
+                }
+                None => debug!(message = "acknowledgement of unknown message.", id),
+            }
+        }
+
+        while let Some(id) = self.releases.pop_front() {
+            if let AsyncSink::NotReady(_) =
+                transport.start_send(Packet::PubRel(id)).map_err(io_error)?
+            {
+                self.releases.push_front(id);
+                break;
+            }
+        }
+
+        while self.sent < self.in_flight.len() {
+            let message = &mut self.in_flight[self.sent];
+            let packet = Packet::Publish(message.publish.clone());
+            if let AsyncSink::NotReady(_) = transport.start_send(packet).map_err(io_error)? {
+                break;
+            }
+
+            if message.publish.qos == QoS::AtMostOnce {
+                message.acked = true;
+            } else {
+                // Publishing it again flags the message as a duplicate.
+                message.publish.dup = true;
+            }
+            self.sent += 1;
+        }
+
+        transport.poll_complete().map_err(io_error)?;
+        Ok(())
+    }
+}
+
+impl Sink for MqttSink {
+    type SinkItem = Event;
+    type SinkError = ();
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.in_flight.len() >= MAX_IN_FLIGHT {
+            self.poll_complete()?;
+            if self.in_flight.len() >= MAX_IN_FLIGHT {
+                return Ok(AsyncSink::NotReady(event));
+            }
+        }
+
+        let topic = match self.topic.render_string(&event) {
+            Ok(topic) => topic,
+            Err(missing_keys) => {
+                warn!(
+                    message = "keys in topic template do not exist on the event; dropping event.",
+                    ?missing_keys
+                );
+                self.in_flight.drop_event();
+                return Ok(AsyncSink::Ready);
+            }
+        };
+
+        let packet_id = match self.qos {
+            QoS::AtMostOnce => None,
+            _ => Some(self.next_packet_id()),
+        };
+        self.in_flight.push(Message {
+            publish: Publish {
+                topic,
+                qos: self.qos,
+                packet_id,
+                retain: self.retain,
+                dup: false,
+                payload: encode_event(&event, &self.encoding),
+            },
+            acked: false,
+        });
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        while !self.in_flight.is_empty() {
+            match self.connection.poll() {
+                // A clean session forgets every message in flight, so they
+                // are all published again.
+                Async::Ready(true) => {
+                    self.sent = 0;
+                    self.releases.clear();
+                }
+                Async::Ready(false) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+
+            match self.poll_transport() {
+                Ok(()) => {
+                    let acked = self.in_flight.ack_while(|message| message.acked);
+                    self.sent = self.sent.saturating_sub(acked);
+                    if !self.in_flight.is_empty() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                Err(error) => self.connection.lost(error),
+            }
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+fn io_error(source: std::io::Error) -> mqtt::Error {
+    mqtt::Error::Io { source }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MqttSinkConfig;
+    use crate::{
+        buffers::Acker,
+        event::Event,
+        mqtt::{ConnectionConfig, MqttCodec, Packet, Publish, QoS},
+        sinks::util::Encoding,
+        test_util::{next_addr, wait_for},
+        topology::config::SinkConfig,
+    };
+    use futures::{stream, Future, Sink, Stream};
+    use std::{io, sync::atomic::Ordering};
+    use tokio::{codec::Framed, net::TcpListener};
+
+    #[test]
+    fn mqtt_publishes_events() {
+        let address = next_addr();
+        let listener = TcpListener::bind(&address).unwrap();
+
+        let mut config = MqttSinkConfig::new(
+            ConnectionConfig::new(address.to_string()),
+            "devices/{{device}}".into(),
+        );
+        config.encoding = Some(Encoding::Text);
+        let (acker, acks) = Acker::new_for_testing();
+        let (sink, _healthcheck) = config.build(acker).unwrap();
+
+        let events = ["d1", "", "d2"]
+            .iter()
+            .map(|device| {
+                let mut event = Event::from(format!("reading from {}", device));
+                if !device.is_empty() {
+                    event
+                        .as_mut_log()
+                        .insert_explicit("device".into(), (*device).into());
+                }
+                event
+            })
+            .collect::<Vec<_>>();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(
+            sink.send_all(stream::iter_ok::<_, ()>(events))
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+
+        let broker = listener
+            .incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(socket, _)| {
+                Framed::new(socket.unwrap(), MqttCodec::new())
+                    .into_future()
+                    .map_err(|(error, _)| error)
+            })
+            .and_then(|(connect, transport)| {
+                assert!(match connect {
+                    Some(Packet::Connect(_)) => true,
+                    _ => false,
+                });
+                transport.send(Packet::ConnAck {
+                    session_present: false,
+                    code: 0,
+                })
+            })
+            .and_then(|transport| {
+                // Acknowledge every message as it arrives.
+                let (writer, reader) = transport.split();
+                let publishes = reader.take(2).collect();
+                publishes.and_then(|publishes| {
+                    let acks = publishes
+                        .iter()
+                        .map(|packet| match packet {
+                            Packet::Publish(publish) => Packet::PubAck(publish.packet_id.unwrap()),
+                            other => panic!("expected a publish, got {:?}", other),
+                        })
+                        .collect::<Vec<_>>();
+                    writer
+                        .send_all(stream::iter_ok::<_, io::Error>(acks))
+                        .map(|(writer, _)| (publishes, writer))
+                })
+            });
+        let (publishes, _writer) = rt.block_on(broker).unwrap();
+
+        assert_eq!(
+            publishes,
+            vec![
+                Packet::Publish(Publish {
+                    topic: "devices/d1".into(),
+                    qos: QoS::AtLeastOnce,
+                    packet_id: Some(1),
+                    retain: false,
+                    dup: false,
+                    payload: "reading from d1".into(),
+                }),
+                Packet::Publish(Publish {
+                    topic: "devices/d2".into(),
+                    qos: QoS::AtLeastOnce,
+                    packet_id: Some(2),
+                    retain: false,
+                    dup: false,
+                    payload: "reading from d2".into(),
+                }),
+            ]
+        );
+        // The event without a device was dropped, but acknowledged as well.
+        wait_for(|| acks.load(Ordering::SeqCst) == 3);
+    }
+}
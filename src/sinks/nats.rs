@@ -0,0 +1,281 @@
+use super::util::{encode_event, Encoding, InFlight, Reconnect};
+use crate::{
+    buffers::Acker,
+    event::Event,
+    nats::{self, unexpected, ConnectionConfig, Connector, Op, Transport},
+    template::Template,
+    topology::config::{DataType, SinkConfig},
+};
+use bytes::Bytes;
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use serde::{Deserialize, Serialize};
+
+/// How many messages may wait to be written to the connection.
+const MAX_IN_FLIGHT: usize = 100;
+
+/// Publishes events to a NATS server, under a subject rendered from each of
+/// them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NatsSinkConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    pub subject: Template,
+    pub encoding: Option<Encoding>,
+}
+
+impl NatsSinkConfig {
+    pub fn new(connection: ConnectionConfig, subject: Template) -> Self {
+        Self {
+            connection,
+            subject,
+            encoding: None,
+        }
+    }
+}
+
+#[typetag::serde(name = "nats")]
+impl SinkConfig for NatsSinkConfig {
+    fn build(&self, acker: Acker) -> Result<(super::RouterSink, super::Healthcheck), crate::Error> {
+        let connector = self.connection.build()?;
+        let healthcheck = Box::new(connector.connect().map(|_| ()).map_err(Into::into));
+        let sink = NatsSink::new(connector, self.clone(), acker);
+
+        Ok((Box::new(sink), healthcheck))
+    }
+
+    fn input_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+struct Message {
+    subject: String,
+    payload: Bytes,
+}
+
+/// NATS doesn't acknowledge messages, so they are kept until they are flushed
+/// to the connection, and written again over a new one when it is lost first.
+pub struct NatsSink {
+    subject: Template,
+    encoding: Option<Encoding>,
+    in_flight: InFlight<Message>,
+    /// How many of the messages in flight were written to this connection.
+    written: usize,
+    /// How many pings of the server are yet to be answered.
+    pongs: usize,
+    connection: Reconnect<Transport, nats::Error>,
+}
+
+impl NatsSink {
+    fn new(connector: Connector, config: NatsSinkConfig, acker: Acker) -> Self {
+        Self {
+            subject: config.subject,
+            encoding: config.encoding,
+            in_flight: InFlight::new(acker),
+            written: 0,
+            pongs: 0,
+            connection: Reconnect::new(connector.addr(), move || connector.connect()),
+        }
+    }
+
+    /// Answer the pings of the server, and write whatever wasn't written yet
+    /// to the connection, resolving with how many messages were flushed.
+    fn poll_transport(&mut self) -> Result<Option<usize>, nats::Error> {
+        let transport = self.connection.get_mut();
+
+        // Reading also notices the connection being closed before messages
+        // are written to it.
+        while let Async::Ready(op) = transport.poll().map_err(io_error)? {
+            match op {
+                Some(Op::Ping) => self.pongs += 1,
+                Some(Op::Pong) | Some(Op::Ok) | Some(Op::Info(_)) => (),
+                op => return Err(unexpected(op)),
+            }
+        }
+
+        while self.pongs > 0 {
+            if let AsyncSink::NotReady(_) = transport.start_send(Op::Pong).map_err(io_error)? {
+                break;
+            }
+            self.pongs -= 1;
+        }
+
+        while self.written < self.in_flight.len() {
+            let message = &self.in_flight[self.written];
+            let publish = Op::Pub {
+                subject: message.subject.clone(),
+                reply_to: None,
+                payload: message.payload.clone(),
+            };
+            if let AsyncSink::NotReady(_) = transport.start_send(publish).map_err(io_error)? {
+                break;
+            }
+            self.written += 1;
+        }
+
+        match transport.poll_complete().map_err(io_error)? {
+            Async::Ready(()) => Ok(Some(self.written)),
+            Async::NotReady => Ok(None),
+        }
+    }
+}
+
+impl Sink for NatsSink {
+    type SinkItem = Event;
+    type SinkError = ();
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.in_flight.len() >= MAX_IN_FLIGHT {
+            self.poll_complete()?;
+            if self.in_flight.len() >= MAX_IN_FLIGHT {
+                return Ok(AsyncSink::NotReady(event));
+            }
+        }
+
+        let subject = match self.subject.render_string(&event) {
+            Ok(subject) => subject,
+            Err(missing_keys) => {
+                warn!(
+                    message = "keys in subject template do not exist on the event; dropping event.",
+                    ?missing_keys
+                );
+                self.in_flight.drop_event();
+                return Ok(AsyncSink::Ready);
+            }
+        };
+        if !nats::is_valid_subject(&subject) {
+            warn!(
+                message = "rendered subject is not valid; dropping event.",
+                subject = subject.as_str()
+            );
+            self.in_flight.drop_event();
+            return Ok(AsyncSink::Ready);
+        }
+
+        self.in_flight.push(Message {
+            subject,
+            payload: encode_event(&event, &self.encoding),
+        });
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        while !self.in_flight.is_empty() {
+            match self.connection.poll() {
+                Async::Ready(true) => {
+                    self.written = 0;
+                    self.pongs = 0;
+                }
+                Async::Ready(false) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+
+            match self.poll_transport() {
+                // Once flushed, there is room to write the rest.
+                Ok(Some(flushed)) => {
+                    self.in_flight.ack(flushed);
+                    self.written -= flushed;
+                }
+                Ok(None) => return Ok(Async::NotReady),
+                Err(error) => self.connection.lost(error),
+            }
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+fn io_error(source: std::io::Error) -> nats::Error {
+    nats::Error::Io { source }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NatsSinkConfig;
+    use crate::{
+        buffers::Acker,
+        event::Event,
+        nats::{ConnectionConfig, NatsCodec, Op},
+        sinks::util::Encoding,
+        test_util::{next_addr, wait_for},
+        topology::config::SinkConfig,
+    };
+    use futures::{stream, Future, Sink, Stream};
+    use std::sync::atomic::Ordering;
+    use tokio::{codec::Framed, net::TcpListener};
+
+    #[test]
+    fn nats_publishes_events() {
+        let address = next_addr();
+        let listener = TcpListener::bind(&address).unwrap();
+
+        let mut config = NatsSinkConfig::new(
+            ConnectionConfig::new(address.to_string()),
+            "logs.{{service}}".into(),
+        );
+        config.encoding = Some(Encoding::Text);
+        let (acker, acks) = Acker::new_for_testing();
+        let (sink, _healthcheck) = config.build(acker).unwrap();
+
+        let events = ["web", "", "bad service", "db"]
+            .iter()
+            .map(|service| {
+                let mut event = Event::from(format!("line from {}", service));
+                if !service.is_empty() {
+                    event
+                        .as_mut_log()
+                        .insert_explicit("service".into(), (*service).into());
+                }
+                event
+            })
+            .collect::<Vec<_>>();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(
+            sink.send_all(stream::iter_ok::<_, ()>(events))
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(socket, _)| {
+                Framed::new(socket.unwrap(), NatsCodec::new()).send(Op::Info("{}".into()))
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(connect, transport)| {
+                match connect {
+                    Some(Op::Connect(connect)) => assert_eq!(connect.name, "vector"),
+                    other => panic!("expected a connect, got {:?}", other),
+                }
+                transport.into_future().map_err(|(error, _)| error)
+            })
+            .and_then(|(ping, transport)| {
+                assert_eq!(ping, Some(Op::Ping));
+                transport.send(Op::Pong)
+            })
+            .and_then(|transport| transport.take(2).collect());
+        let published = rt.block_on(server).unwrap();
+
+        assert_eq!(
+            published,
+            vec![
+                Op::Pub {
+                    subject: "logs.web".into(),
+                    reply_to: None,
+                    payload: "line from web".into(),
+                },
+                Op::Pub {
+                    subject: "logs.db".into(),
+                    reply_to: None,
+                    payload: "line from db".into(),
+                },
+            ]
+        );
+        wait_for(|| acks.load(Ordering::Relaxed) == 4);
+    }
+}
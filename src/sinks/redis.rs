@@ -0,0 +1,287 @@
+use super::util::{encode_event, Encoding, InFlight, Reconnect};
+use crate::{
+    buffers::Acker,
+    event::Event,
+    redis::{self, command, unexpected, ConnectionConfig, Connector, Transport, Value},
+    template::Template,
+    topology::config::{DataType, SinkConfig},
+};
+use bytes::Bytes;
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use serde::{Deserialize, Serialize};
+
+/// How many commands may await their reply from the server.
+const MAX_IN_FLIGHT: usize = 100;
+
+/// Pushes events to Redis lists, or publishes them to channels, under a key
+/// rendered from each of them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedisSinkConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    /// The list, or channel, events are sent to.
+    pub key: Template,
+    #[serde(default = "default_method")]
+    pub method: Method,
+    pub encoding: Option<Encoding>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    Lpush,
+    Rpush,
+    Publish,
+}
+
+impl Method {
+    fn command(self) -> &'static str {
+        match self {
+            Method::Lpush => "LPUSH",
+            Method::Rpush => "RPUSH",
+            Method::Publish => "PUBLISH",
+        }
+    }
+}
+
+fn default_method() -> Method {
+    Method::Rpush
+}
+
+impl RedisSinkConfig {
+    pub fn new(connection: ConnectionConfig, key: Template) -> Self {
+        Self {
+            connection,
+            key,
+            method: default_method(),
+            encoding: None,
+        }
+    }
+}
+
+#[typetag::serde(name = "redis")]
+impl SinkConfig for RedisSinkConfig {
+    fn build(&self, acker: Acker) -> Result<(super::RouterSink, super::Healthcheck), crate::Error> {
+        let connector = self.connection.build()?;
+        let healthcheck = Box::new(
+            connector
+                .connect()
+                .and_then(|transport| redis::request(transport, command(vec!["PING"])))
+                .and_then(|(reply, _)| match reply {
+                    Value::Status(_) => Ok(()),
+                    reply => Err(unexpected(Some(reply))),
+                })
+                .map_err(Into::into),
+        );
+        let sink = RedisSink::new(connector, self.clone(), acker);
+
+        Ok((Box::new(sink), healthcheck))
+    }
+
+    fn input_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+/// Keeps commands until the server replies to them, sending them again over a
+/// new connection when it is lost first. Replies come in the order commands
+/// were sent, so they are pipelined.
+pub struct RedisSink {
+    key: Template,
+    method: Method,
+    encoding: Option<Encoding>,
+    in_flight: InFlight<Value>,
+    /// How many of the commands in flight were sent over this connection.
+    sent: usize,
+    connection: Reconnect<Transport, redis::Error>,
+}
+
+impl RedisSink {
+    fn new(connector: Connector, config: RedisSinkConfig, acker: Acker) -> Self {
+        Self {
+            key: config.key,
+            method: config.method,
+            encoding: config.encoding,
+            in_flight: InFlight::new(acker),
+            sent: 0,
+            connection: Reconnect::new(connector.addr(), move || connector.connect()),
+        }
+    }
+
+    /// Handle the replies received so far, then send whatever wasn't sent yet
+    /// over the connection.
+    fn poll_transport(&mut self) -> Result<(), redis::Error> {
+        let transport = self.connection.get_mut();
+
+        while let Async::Ready(reply) = transport.poll().map_err(io_error)? {
+            let reply = reply.ok_or(redis::Error::Closed)?;
+            if self.sent == 0 {
+                return Err(unexpected(Some(reply)));
+            }
+            // Errors are about the command itself, like pushing to a key
+            // holding something else than a list, so sending it again won't
+            // help.
+            if let Value::Error(error) = reply {
+                error!(message = "Redis command failed; dropping event.", %error);
+            }
+            self.in_flight.ack(1);
+            self.sent -= 1;
+        }
+
+        while self.sent < self.in_flight.len() {
+            let command = self.in_flight[self.sent].clone();
+            if let AsyncSink::NotReady(_) = transport.start_send(command).map_err(io_error)? {
+                break;
+            }
+            self.sent += 1;
+        }
+
+        transport.poll_complete().map_err(io_error)?;
+        Ok(())
+    }
+}
+
+impl Sink for RedisSink {
+    type SinkItem = Event;
+    type SinkError = ();
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.in_flight.len() >= MAX_IN_FLIGHT {
+            self.poll_complete()?;
+            if self.in_flight.len() >= MAX_IN_FLIGHT {
+                return Ok(AsyncSink::NotReady(event));
+            }
+        }
+
+        let key = match self.key.render(&event) {
+            Ok(key) => key,
+            Err(missing_keys) => {
+                warn!(
+                    message = "keys in key template do not exist on the event; dropping event.",
+                    ?missing_keys
+                );
+                self.in_flight.drop_event();
+                return Ok(AsyncSink::Ready);
+            }
+        };
+
+        self.in_flight.push(command(vec![
+            Bytes::from(self.method.command()),
+            key,
+            encode_event(&event, &self.encoding),
+        ]));
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        while !self.in_flight.is_empty() {
+            match self.connection.poll() {
+                Async::Ready(true) => self.sent = 0,
+                Async::Ready(false) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+
+            match self.poll_transport() {
+                Ok(()) => {
+                    if !self.in_flight.is_empty() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                Err(error) => self.connection.lost(error),
+            }
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+fn io_error(source: std::io::Error) -> redis::Error {
+    redis::Error::Io { source }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Method, RedisSinkConfig};
+    use crate::{
+        buffers::Acker,
+        event::Event,
+        redis::{command, ConnectionConfig, RespCodec, Value},
+        sinks::util::Encoding,
+        test_util::{next_addr, wait_for},
+        topology::config::SinkConfig,
+    };
+    use futures::{stream, Future, Sink, Stream};
+    use std::sync::atomic::Ordering;
+    use tokio::{codec::Framed, net::TcpListener};
+
+    #[test]
+    fn redis_pushes_events() {
+        let address = next_addr();
+        let listener = TcpListener::bind(&address).unwrap();
+
+        let mut connection = ConnectionConfig::new(address.to_string());
+        connection.password = Some("secret".into());
+        let mut config = RedisSinkConfig::new(connection, "logs:{{service}}".into());
+        config.method = Method::Lpush;
+        config.encoding = Some(Encoding::Text);
+        let (acker, acks) = Acker::new_for_testing();
+        let (sink, _healthcheck) = config.build(acker).unwrap();
+
+        let events = ["web", "", "db"]
+            .iter()
+            .map(|service| {
+                let mut event = Event::from(format!("line from {}", service));
+                if !service.is_empty() {
+                    event
+                        .as_mut_log()
+                        .insert_explicit("service".into(), (*service).into());
+                }
+                event
+            })
+            .collect::<Vec<_>>();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(
+            sink.send_all(stream::iter_ok::<_, ()>(events))
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(socket, _)| {
+                Framed::new(socket.unwrap(), RespCodec::new())
+                    .into_future()
+                    .map_err(|(error, _)| error)
+            })
+            .and_then(|(auth, transport)| {
+                assert_eq!(auth, Some(command(vec!["AUTH", "secret"])));
+                transport.send(Value::Status("OK".into()))
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(push, transport)| {
+                assert_eq!(
+                    push,
+                    Some(command(vec!["LPUSH", "logs:web", "line from web"]))
+                );
+                transport.send(Value::Integer(1))
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(push, transport)| {
+                assert_eq!(
+                    push,
+                    Some(command(vec!["LPUSH", "logs:db", "line from db"]))
+                );
+                transport.send(Value::Error("WRONGTYPE Operation against a key".into()))
+            });
+        let _transport = rt.block_on(server).unwrap();
+
+        // The command that failed is acknowledged, as sending it again won't
+        // help.
+        wait_for(|| acks.load(Ordering::Relaxed) == 3);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::{
     buffers::Acker,
     event::{self, Event},
-    sinks::util::SinkExt,
+    sinks::util::{
+        load_balance::{load_balanced, load_balanced_healthcheck, resolve_addresses},
+        LoadBalance, SinkExt,
+    },
     topology::config::{DataType, SinkConfig},
 };
 use bytes::Bytes;
@@ -17,12 +20,13 @@ use snafu::{ResultExt, Snafu};
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::Read;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use tokio::{
     codec::{BytesCodec, FramedWrite},
+    io::{AsyncRead, AsyncWrite},
     net::tcp::{ConnectFuture, TcpStream},
     timer::Delay,
 };
@@ -74,7 +78,12 @@ enum BuildError {
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct TcpSinkConfig {
-    pub address: String,
+    pub address: Option<String>,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    pub load_balance: Option<LoadBalance>,
+    pub healthcheck_interval_secs: Option<u64>,
+    pub ack_timeout_secs: Option<u64>,
     pub encoding: Option<Encoding>,
     pub tls: Option<TlsConfig>,
 }
@@ -100,7 +109,11 @@ pub struct TlsConfig {
 impl TcpSinkConfig {
     pub fn new(address: String) -> Self {
         Self {
-            address,
+            address: Some(address),
+            addresses: Vec::new(),
+            load_balance: None,
+            healthcheck_interval_secs: None,
+            ack_timeout_secs: None,
             encoding: None,
             tls: None,
         }
@@ -110,58 +123,38 @@ impl TcpSinkConfig {
 #[typetag::serde(name = "tcp")]
 impl SinkConfig for TcpSinkConfig {
     fn build(&self, acker: Acker) -> Result<(super::RouterSink, super::Healthcheck), crate::Error> {
-        let addr = self
-            .address
-            .to_socket_addrs()
-            .context(super::SocketAddressError)?
-            .next()
-            .ok_or(Box::new(super::BuildError::DNSFailure {
-                address: self.address.clone(),
-            }))?;
-
-        let tls = match self.tls {
-            Some(ref tls) => {
-                if tls.enabled.unwrap_or(false) {
-                    if tls.key_file.is_some() != tls.crt_file.is_some() {
-                        return Err(Box::new(BuildError::MissingCrtKeyFile));
-                    }
-                    let add_ca = match &tls.ca_file {
-                        None => None,
-                        Some(filename) => Some(load_certificate(filename)?),
-                    };
-                    let identity = match &tls.crt_file {
-                        None => None,
-                        Some(filename) => {
-                            // This unwrap is safe because of the crt/key check above
-                            let key = load_key(tls.key_file.as_ref().unwrap(), &tls.key_phrase)?;
-                            let crt = load_x509(filename)?;
-                            Some(
-                                Pkcs12::builder()
-                                    .build("", filename, &key, &crt)
-                                    .context(Pkcs12Error)?,
-                            )
-                        }
-                    };
-                    Some(TcpSinkTls {
-                        verify: tls.verify.unwrap_or(true),
-                        add_ca,
-                        identity,
-                    })
-                } else {
-                    None
-                }
-            }
-            None => None,
-        };
-
-        let sink = raw_tcp(
-            self.address.clone(),
-            addr,
+        let addrs = resolve_addresses(&self.address, &self.addresses)?;
+
+        // Each endpoint needs its own TLS settings, since the identity can't
+        // be shared between connectors.
+        let mut tls = addrs
+            .iter()
+            .map(|_| self.build_tls())
+            .collect::<Result<Vec<_>, _>>()?;
+        let hostnames = addrs
+            .iter()
+            .map(|(address, _)| address.clone())
+            .collect::<Vec<_>>();
+        let socket_addrs = addrs.iter().map(|(_, addr)| *addr).collect::<Vec<_>>();
+        let encoding = self.encoding.clone();
+
+        let sink = load_balanced(
+            socket_addrs.clone(),
+            self.load_balance.clone().unwrap_or_default(),
+            Duration::from_secs(self.healthcheck_interval_secs.unwrap_or(10)),
+            Duration::from_secs(self.ack_timeout_secs.unwrap_or(60)),
             acker,
-            self.encoding.clone(),
-            tls,
+            |index, addr, acker| {
+                raw_tcp(
+                    hostnames[index].clone(),
+                    addr,
+                    acker,
+                    encoding.clone(),
+                    tls[index].take(),
+                )
+            },
         );
-        let healthcheck = tcp_healthcheck(addr);
+        let healthcheck = load_balanced_healthcheck(&socket_addrs);
 
         Ok((sink, healthcheck))
     }
@@ -171,6 +164,12 @@ impl SinkConfig for TcpSinkConfig {
     }
 }
 
+impl TcpSinkConfig {
+    fn build_tls(&self) -> Result<Option<TcpSinkTls>, crate::Error> {
+        TcpSinkTls::build(&self.tls)
+    }
+}
+
 fn load_certificate<T: AsRef<Path> + Debug>(filename: T) -> Result<Certificate, crate::Error> {
     let filename = filename.as_ref();
     let data = open_read(filename, "certificate authority")?;
@@ -237,6 +236,62 @@ type TcpOrTlsStream = MaybeTlsStream<
     FramedWrite<TlsStream<TcpStream>, BytesCodec>,
 >;
 
+/// A connection to a remote, whether it is over TLS or not.
+pub trait Socket: AsyncRead + AsyncWrite + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync> Socket for T {}
+
+#[derive(Debug, Snafu)]
+pub enum SocketError {
+    #[snafu(display("Could not connect to {}: {}", addr, source))]
+    ConnectFailed {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not build TLS connector: {}", source))]
+    TlsConnectorFailed { source: crate::Error },
+    #[snafu(display("Could not negotiate TLS with {}: {}", addr, source))]
+    TlsNegotiationFailed {
+        addr: SocketAddr,
+        source: native_tls::Error,
+    },
+}
+
+/// Connect to `addr`, negotiating TLS with `hostname` when `tls` is given,
+/// for protocols built on top of the connection. Nothing is attempted before
+/// the future is first polled.
+pub fn connect_socket(
+    hostname: String,
+    addr: SocketAddr,
+    tls: Option<&TcpSinkTls>,
+) -> Box<dyn Future<Item = Box<dyn Socket>, Error = SocketError> + Send> {
+    let connector = match tls.map(TcpSinkTls::make_connector) {
+        Some(Ok(connector)) => Some(connector),
+        Some(Err(source)) => {
+            return Box::new(future::err(SocketError::TlsConnectorFailed { source }))
+        }
+        None => None,
+    };
+
+    Box::new(
+        future::lazy(move || TcpStream::connect(&addr))
+            .then(move |result| result.context(ConnectFailed { addr }))
+            .and_then(
+                move |socket| -> Box<dyn Future<Item = Box<dyn Socket>, Error = SocketError> + Send> {
+                    match connector {
+                        Some(connector) => Box::new(
+                            connector
+                                .connect(&hostname, socket)
+                                .then(move |result| result.context(TlsNegotiationFailed { addr }))
+                                .map(|stream| Box::new(stream) as Box<dyn Socket>),
+                        ),
+                        None => Box::new(future::ok(Box::new(socket) as Box<dyn Socket>)),
+                    }
+                },
+            ),
+    )
+}
+
 #[derive(Default)]
 pub struct TcpSinkTls {
     verify: bool,
@@ -245,7 +300,47 @@ pub struct TcpSinkTls {
 }
 
 impl TcpSinkTls {
-    fn make_connector(&self) -> Result<TlsConnector, crate::Error> {
+    /// Build the TLS settings out of `config`, if TLS is enabled.
+    pub fn build(config: &Option<TlsConfig>) -> Result<Option<Self>, crate::Error> {
+        let tls = match config {
+            Some(ref tls) => {
+                if tls.enabled.unwrap_or(false) {
+                    if tls.key_file.is_some() != tls.crt_file.is_some() {
+                        return Err(Box::new(BuildError::MissingCrtKeyFile));
+                    }
+                    let add_ca = match &tls.ca_file {
+                        None => None,
+                        Some(filename) => Some(load_certificate(filename)?),
+                    };
+                    let identity = match &tls.crt_file {
+                        None => None,
+                        Some(filename) => {
+                            // This unwrap is safe because of the crt/key check above
+                            let key = load_key(tls.key_file.as_ref().unwrap(), &tls.key_phrase)?;
+                            let crt = load_x509(filename)?;
+                            Some(
+                                Pkcs12::builder()
+                                    .build("", filename, &key, &crt)
+                                    .context(Pkcs12Error)?,
+                            )
+                        }
+                    };
+                    Some(TcpSinkTls {
+                        verify: tls.verify.unwrap_or(true),
+                        add_ca,
+                        identity,
+                    })
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        Ok(tls)
+    }
+
+    pub fn make_connector(&self) -> Result<TlsConnector, crate::Error> {
         let mut connector = native_tls::TlsConnector::builder();
         connector.danger_accept_invalid_certs(!self.verify);
         if let Some(ref certificate) = self.add_ca {
@@ -0,0 +1,49 @@
+use crate::event::{self, Event};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// How sinks publishing each event as a message encode it. By default,
+/// structured events are encoded as JSON, and the others as their message.
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Text,
+    Json,
+}
+
+pub fn encode_event(event: &Event, encoding: &Option<Encoding>) -> Bytes {
+    match (encoding, event.as_log().is_structured()) {
+        (&Some(Encoding::Json), _) | (_, true) => {
+            serde_json::to_vec(&event.as_log().clone().unflatten())
+                .unwrap()
+                .into()
+        }
+        (&Some(Encoding::Text), _) | (_, false) => event
+            .as_log()
+            .get(&event::MESSAGE)
+            .map(|v| v.as_bytes())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_event, Encoding};
+    use crate::event::Event;
+    use std::collections::HashMap;
+
+    #[test]
+    fn encodes_events() {
+        let mut event = Event::from("hello world");
+        assert_eq!(encode_event(&event, &None), "hello world");
+        assert_eq!(encode_event(&event, &Some(Encoding::Text)), "hello world");
+
+        event
+            .as_mut_log()
+            .insert_explicit("service".into(), "web".into());
+        let body = encode_event(&event, &Some(Encoding::Json));
+        let map: HashMap<String, String> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(map["message"], "hello world");
+        assert_eq!(map["service"], "web");
+    }
+}
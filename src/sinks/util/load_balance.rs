@@ -0,0 +1,718 @@
+use crate::{
+    buffers::Acker,
+    event::Event,
+    sinks::{tcp::tcp_healthcheck, Healthcheck, RouterSink},
+};
+use futures::{future, task, Async, AsyncSink, Future, Poll, Sink, StartSend};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::{
+    collections::VecDeque,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::Delay;
+use tokio_retry::strategy::ExponentialBackoff;
+use tracing::field;
+
+/// How events are distributed between the endpoints of a sink that was given
+/// more than one address.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum LoadBalance {
+    RoundRobin,
+    ConsistentHash { key_field: Atom },
+}
+
+impl Default for LoadBalance {
+    fn default() -> Self {
+        LoadBalance::RoundRobin
+    }
+}
+
+// Points on the hash ring per endpoint, to spread keys evenly.
+const VIRTUAL_NODES: usize = 128;
+
+/// Combine the `address` and `addresses` options of a sink and resolve each of
+/// them, keeping the original string around for use as a hostname.
+pub fn resolve_addresses(
+    address: &Option<String>,
+    addresses: &[String],
+) -> Result<Vec<(String, SocketAddr)>, crate::Error> {
+    let all = address.iter().chain(addresses.iter()).collect::<Vec<_>>();
+    if all.is_empty() {
+        return Err(Box::new(crate::sinks::BuildError::MissingAddress));
+    }
+
+    all.into_iter()
+        .map(|address| {
+            let addr = address
+                .to_socket_addrs()
+                .context(crate::sinks::SocketAddressError)?
+                .next()
+                .ok_or(Box::new(crate::sinks::BuildError::DNSFailure {
+                    address: address.clone(),
+                }))?;
+            Ok((address.clone(), addr))
+        })
+        .collect()
+}
+
+/// Resolve the `address` option of a client of a single server, along with
+/// the host part of it, for use as a hostname.
+pub fn resolve_address(address: &str) -> Result<(String, SocketAddr), crate::Error> {
+    let (address, addr) = resolve_addresses(&Some(address.into()), &[])?.remove(0);
+    let hostname = match address.rfind(':') {
+        Some(colon) => address[..colon].into(),
+        None => address,
+    };
+    Ok((hostname, addr))
+}
+
+/// Build one sink per address, distributing events between them according to
+/// `strategy`. With a single address the sink is returned as is.
+pub fn load_balanced<F>(
+    addrs: Vec<SocketAddr>,
+    strategy: LoadBalance,
+    healthcheck_interval: Duration,
+    ack_timeout: Duration,
+    acker: Acker,
+    mut build: F,
+) -> RouterSink
+where
+    F: FnMut(usize, SocketAddr, Acker) -> RouterSink,
+{
+    if addrs.len() == 1 {
+        return build(0, addrs[0], acker);
+    }
+
+    Box::new(LoadBalancedSink::new(
+        addrs,
+        strategy,
+        healthcheck_interval,
+        ack_timeout,
+        acker,
+        build,
+    ))
+}
+
+/// Healthy as long as at least one of the endpoints is.
+pub fn load_balanced_healthcheck(addrs: &[SocketAddr]) -> Healthcheck {
+    let checks = addrs.iter().map(|addr| tcp_healthcheck(*addr));
+    Box::new(future::select_ok(checks).map(|_| ()))
+}
+
+/// A sink that distributes events between several endpoint sinks.
+///
+/// Every endpoint is checked with `tcp_healthcheck` on an interval. Endpoints
+/// failing their check, or not acknowledging an event within `ack_timeout`,
+/// are ejected, and are checked again with exponential backoff until they
+/// recover.
+///
+/// A copy of every event is kept until its endpoint acknowledges it, and the
+/// events an ejected endpoint hasn't acknowledged are sent again to the
+/// others. The ejected endpoint may still deliver them once it reconnects, so
+/// they may be delivered twice, but never lost. Each endpoint acknowledges its
+/// own events in order, and events are only acknowledged upstream once they
+/// and all of the events received before them were acknowledged.
+pub struct LoadBalancedSink {
+    endpoints: Vec<Endpoint>,
+    strategy: LoadBalance,
+    ring: Vec<(u64, usize)>,
+    next: usize,
+    acker: Acker,
+    ack_timeout: Duration,
+    ack_timer: Delay,
+    // The events not acknowledged upstream yet, in the order they were
+    // received, the first one being number `first_seq`. Events acknowledged
+    // by their endpoint are replaced by `None`.
+    in_flight: VecDeque<Option<Event>>,
+    first_seq: u64,
+    // The events taken back from ejected endpoints, to be sent to another one.
+    taken_back: VecDeque<u64>,
+}
+
+struct Endpoint {
+    addr: SocketAddr,
+    sink: RouterSink,
+    acked: Arc<AtomicUsize>,
+    // The events sent to the endpoint and not acknowledged yet, in order,
+    // along with when they were sent.
+    sent: VecDeque<(u64, Instant)>,
+    // Events taken back when the endpoint was ejected, whose acknowledgements
+    // are ignored if they come in after all.
+    abandoned: usize,
+    healthy: bool,
+    healthcheck_interval: Duration,
+    backoff: ExponentialBackoff,
+    timer: Delay,
+    check: Option<Healthcheck>,
+}
+
+impl LoadBalancedSink {
+    pub fn new<F>(
+        addrs: Vec<SocketAddr>,
+        strategy: LoadBalance,
+        healthcheck_interval: Duration,
+        ack_timeout: Duration,
+        acker: Acker,
+        mut build: F,
+    ) -> Self
+    where
+        F: FnMut(usize, SocketAddr, Acker) -> RouterSink,
+    {
+        let endpoints = addrs
+            .into_iter()
+            .enumerate()
+            .map(|(index, addr)| {
+                // Endpoint sinks acknowledge their events while they are
+                // polled by this sink, which reads the count back afterwards.
+                let acked = Arc::new(AtomicUsize::new(0));
+                Endpoint {
+                    addr,
+                    sink: build(index, addr, Acker::Counter(Arc::clone(&acked))),
+                    acked,
+                    sent: VecDeque::new(),
+                    abandoned: 0,
+                    healthy: true,
+                    healthcheck_interval,
+                    backoff: fresh_backoff(),
+                    timer: Delay::new(Instant::now() + healthcheck_interval),
+                    check: None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let ring = build_ring(&endpoints);
+
+        Self {
+            endpoints,
+            strategy,
+            ring,
+            next: 0,
+            acker,
+            ack_timeout,
+            ack_timer: Delay::new(Instant::now() + ack_timeout),
+            in_flight: VecDeque::new(),
+            first_seq: 0,
+            taken_back: VecDeque::new(),
+        }
+    }
+
+    /// Pick the endpoints an event may go to, in order of preference.
+    fn candidates(&mut self, event: &Event) -> Vec<usize> {
+        match &self.strategy {
+            LoadBalance::RoundRobin => {
+                let len = self.endpoints.len();
+                let start = self.next;
+                let candidates = (0..len)
+                    .map(|offset| (start + offset) % len)
+                    .filter(|&index| self.endpoints[index].healthy)
+                    .collect::<Vec<_>>();
+                if let Some(first) = candidates.first() {
+                    self.next = (first + 1) % len;
+                }
+                candidates
+            }
+            LoadBalance::ConsistentHash { key_field } => {
+                let key = event
+                    .as_log()
+                    .get(key_field)
+                    .map(|value| value.as_bytes())
+                    .unwrap_or_default();
+                let hash = seahash::hash(&key);
+
+                // Walk the ring from the key's position to the first healthy
+                // endpoint, so keys only move when their endpoint is ejected.
+                let start = match self.ring.binary_search_by_key(&hash, |&(point, _)| point) {
+                    Ok(position) | Err(position) => position,
+                };
+                let len = self.ring.len();
+                (0..len)
+                    .map(|offset| self.ring[(start + offset) % len].1)
+                    .find(|&index| self.endpoints[index].healthy)
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+
+    /// Hand event number `seq` to the first endpoint taking it, giving it back
+    /// if none does.
+    fn send(&mut self, seq: u64, event: Event) -> Result<Option<Event>, ()> {
+        let candidates = self.candidates(&event);
+        if candidates.is_empty() {
+            debug!(message = "no healthy endpoints; waiting for one to recover.");
+        }
+
+        let mut event = event;
+        for index in candidates {
+            let endpoint = &mut self.endpoints[index];
+            match endpoint.sink.start_send(event)? {
+                AsyncSink::Ready => {
+                    endpoint.sent.push_back((seq, Instant::now()));
+                    return Ok(None);
+                }
+                AsyncSink::NotReady(returned) => event = returned,
+            }
+        }
+        Ok(Some(event))
+    }
+
+    /// Send the events taken back from ejected endpoints to the others,
+    /// resolving to whether all of them were.
+    fn resend(&mut self) -> Result<bool, ()> {
+        while let Some(&seq) = self.taken_back.front() {
+            let event = self.in_flight[(seq - self.first_seq) as usize]
+                .clone()
+                .expect("acknowledged an event taken back from its endpoint");
+            if self.send(seq, event)?.is_some() {
+                return Ok(false);
+            }
+            self.taken_back.pop_front();
+        }
+        Ok(true)
+    }
+
+    /// Take back the events an ejected endpoint hasn't acknowledged, to send
+    /// them to the others.
+    fn take_back(&mut self, index: usize) {
+        let endpoint = &mut self.endpoints[index];
+        endpoint.abandoned += endpoint.sent.len();
+        self.taken_back
+            .extend(endpoint.sent.drain(..).map(|(seq, _)| seq));
+    }
+
+    fn poll_health(&mut self) {
+        for index in 0..self.endpoints.len() {
+            if self.endpoints[index].poll_health() {
+                self.take_back(index);
+            }
+        }
+    }
+
+    fn ack_in_order(&mut self) {
+        for endpoint in &mut self.endpoints {
+            let mut acked = endpoint.acked.swap(0, Ordering::Relaxed);
+            let late = acked.min(endpoint.abandoned);
+            endpoint.abandoned -= late;
+            acked -= late;
+
+            let acked = acked.min(endpoint.sent.len());
+            for (seq, _) in endpoint.sent.drain(..acked) {
+                self.in_flight[(seq - self.first_seq) as usize] = None;
+            }
+        }
+
+        let mut num_to_ack = 0;
+        while let Some(None) = self.in_flight.front() {
+            self.in_flight.pop_front();
+            num_to_ack += 1;
+        }
+        self.first_seq += num_to_ack as u64;
+        if num_to_ack > 0 {
+            self.acker.ack(num_to_ack);
+        }
+    }
+
+    /// Eject the endpoints whose oldest event wasn't acknowledged in time, and
+    /// wake the task up once the next one would time out. Resolves to whether
+    /// any endpoint was ejected.
+    fn poll_ack_timeouts(&mut self) -> bool {
+        let now = Instant::now();
+        let mut ejected = false;
+        let mut deadline = None::<Instant>;
+        for index in 0..self.endpoints.len() {
+            let endpoint = &mut self.endpoints[index];
+            let timeout = match endpoint.sent.front() {
+                Some(&(_, sent_at)) => sent_at + self.ack_timeout,
+                None => continue,
+            };
+            if timeout <= now {
+                warn!(
+                    message = "endpoint did not acknowledge events in time; ejecting.",
+                    addr = &field::display(&endpoint.addr),
+                    count = endpoint.sent.len()
+                );
+                endpoint.eject();
+                self.take_back(index);
+                ejected = true;
+            } else {
+                deadline = Some(deadline.map_or(timeout, |deadline| deadline.min(timeout)));
+            }
+        }
+
+        if let Some(deadline) = deadline {
+            if self.ack_timer.deadline() != deadline {
+                self.ack_timer.reset(deadline);
+            }
+            match self.ack_timer.poll() {
+                Ok(Async::NotReady) => (),
+                // Err can only occur if the tokio runtime has been shutdown or if more than 2^63 timers have been created
+                Err(err) => unreachable!(err),
+                Ok(Async::Ready(())) => task::current().notify(),
+            }
+        }
+        ejected
+    }
+}
+
+impl Sink for LoadBalancedSink {
+    type SinkItem = Event;
+    type SinkError = ();
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.poll_health();
+
+        // Events taken back from ejected endpoints go first.
+        if !self.resend()? {
+            return Ok(AsyncSink::NotReady(event));
+        }
+
+        let seq = self.first_seq + self.in_flight.len() as u64;
+        match self.send(seq, event.clone())? {
+            None => {
+                self.in_flight.push_back(Some(event));
+                Ok(AsyncSink::Ready)
+            }
+            Some(_) => Ok(AsyncSink::NotReady(event)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.poll_health();
+
+        loop {
+            let mut ready = self.resend()?;
+            for endpoint in &mut self.endpoints {
+                if endpoint.sink.poll_complete()?.is_not_ready() {
+                    ready = false;
+                }
+            }
+
+            self.ack_in_order();
+
+            // The events of endpoints ejected just now are sent to the others
+            // before going on.
+            if !self.poll_ack_timeouts() {
+                return if ready {
+                    Ok(Async::Ready(()))
+                } else {
+                    Ok(Async::NotReady)
+                };
+            }
+        }
+    }
+}
+
+impl Endpoint {
+    /// Poll the healthcheck of the endpoint, resolving to whether it was
+    /// ejected just now.
+    fn poll_health(&mut self) -> bool {
+        let mut ejected = false;
+        loop {
+            if let Some(check) = &mut self.check {
+                match check.poll() {
+                    Ok(Async::NotReady) => return ejected,
+                    Ok(Async::Ready(())) => {
+                        if !self.healthy {
+                            info!(
+                                message = "endpoint recovered; readmitting.",
+                                addr = &field::display(&self.addr)
+                            );
+                        }
+                        self.healthy = true;
+                        self.backoff = fresh_backoff();
+                        self.timer = Delay::new(Instant::now() + self.healthcheck_interval);
+                        self.check = None;
+                    }
+                    Err(error) => {
+                        if self.healthy {
+                            warn!(
+                                message = "endpoint failed healthcheck; ejecting.",
+                                addr = &field::display(&self.addr),
+                                %error
+                            );
+                            ejected = true;
+                        }
+                        self.eject();
+                    }
+                }
+            } else {
+                match self.timer.poll() {
+                    Ok(Async::NotReady) => return ejected,
+                    // Err can only occur if the tokio runtime has been shutdown or if more than 2^63 timers have been created
+                    Err(err) => unreachable!(err),
+                    Ok(Async::Ready(())) => self.check = Some(tcp_healthcheck(self.addr)),
+                }
+            }
+        }
+    }
+
+    /// Stop sending events to the endpoint until its healthcheck passes again,
+    /// after a delay growing with each failure.
+    fn eject(&mut self) {
+        self.healthy = false;
+        self.check = None;
+        self.timer = Delay::new(Instant::now() + self.backoff.next().unwrap());
+    }
+}
+
+fn fresh_backoff() -> ExponentialBackoff {
+    ExponentialBackoff::from_millis(2)
+        .factor(250)
+        .max_delay(Duration::from_secs(60))
+}
+
+fn build_ring(endpoints: &[Endpoint]) -> Vec<(u64, usize)> {
+    let mut ring = endpoints
+        .iter()
+        .enumerate()
+        .flat_map(|(index, endpoint)| {
+            (0..VIRTUAL_NODES).map(move |node| {
+                let point = seahash::hash(format!("{}-{}", endpoint.addr, node).as_bytes());
+                (point, index)
+            })
+        })
+        .collect::<Vec<_>>();
+    ring.sort();
+    ring
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LoadBalance, LoadBalancedSink};
+    use crate::{buffers::Acker, event::Event, sinks::RouterSink};
+    use futures::{future, stream, sync::mpsc, Future, Sink, Stream};
+    use std::{
+        net::SocketAddr,
+        sync::{atomic::Ordering, Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+    use string_cache::DefaultAtom as Atom;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn addrs(count: u16) -> Vec<SocketAddr> {
+        (0..count)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], 9000 + port)))
+            .collect()
+    }
+
+    // Endpoints that record which events they received and hold on to the
+    // acker so that tests can acknowledge events out of order.
+    fn recording_sink(
+        strategy: LoadBalance,
+        count: u16,
+    ) -> (
+        LoadBalancedSink,
+        Vec<mpsc::UnboundedReceiver<Event>>,
+        Arc<Mutex<Vec<Acker>>>,
+    ) {
+        let mut receivers = Vec::new();
+        let ackers = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let ackers = Arc::clone(&ackers);
+            LoadBalancedSink::new(
+                addrs(count),
+                strategy,
+                Duration::from_secs(3600),
+                Duration::from_secs(3600),
+                Acker::new_for_testing().0,
+                |_, _, acker| {
+                    let (tx, rx) = mpsc::unbounded();
+                    receivers.push(rx);
+                    ackers.lock().unwrap().push(acker);
+                    let sink: RouterSink = Box::new(tx.sink_map_err(|e| panic!("{:?}", e)));
+                    sink
+                },
+            )
+        };
+        (sink, receivers, ackers)
+    }
+
+    fn messages(rx: mpsc::UnboundedReceiver<Event>) -> Vec<String> {
+        drop_sender_and_collect(rx)
+            .into_iter()
+            .map(|event| event.as_log()[&crate::event::MESSAGE].to_string_lossy())
+            .collect()
+    }
+
+    fn drop_sender_and_collect(rx: mpsc::UnboundedReceiver<Event>) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut rx = rx.wait();
+        while let Some(Ok(event)) = rx.next() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn load_balance_round_robin() {
+        let (sink, receivers, _) = recording_sink(LoadBalance::RoundRobin, 3);
+
+        let events = (0..6).map(|i| Event::from(format!("{}", i)));
+        let mut rt = Runtime::new().unwrap();
+        let sink = rt
+            .block_on(sink.send_all(stream::iter_ok(events)))
+            .unwrap()
+            .0;
+        drop(sink);
+
+        let received = receivers.into_iter().map(messages).collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            vec![vec!["0", "3"], vec!["1", "4"], vec!["2", "5"]]
+        );
+    }
+
+    #[test]
+    fn load_balance_consistent_hash_keeps_keys_together() {
+        let strategy = LoadBalance::ConsistentHash {
+            key_field: "host".into(),
+        };
+        let (sink, receivers, _) = recording_sink(strategy, 3);
+
+        let events = (0..30).map(|i| {
+            let mut event = Event::from(format!("{}", i));
+            event
+                .as_mut_log()
+                .insert_explicit("host".into(), format!("host-{}", i % 5).into());
+            event
+        });
+        let mut rt = Runtime::new().unwrap();
+        let sink = rt
+            .block_on(sink.send_all(stream::iter_ok(events)))
+            .unwrap()
+            .0;
+        drop(sink);
+
+        for rx in receivers {
+            let hosts = drop_sender_and_collect(rx)
+                .into_iter()
+                .map(|event| event.as_log()[&Atom::from("host")].to_string_lossy())
+                .collect::<Vec<_>>();
+            for host in &hosts {
+                assert_eq!(hosts.iter().filter(|h| *h == host).count(), 6);
+            }
+        }
+    }
+
+    #[test]
+    fn load_balance_skips_ejected_endpoints() {
+        let (mut sink, receivers, _) = recording_sink(LoadBalance::RoundRobin, 3);
+        sink.endpoints[1].healthy = false;
+
+        let events = (0..4).map(|i| Event::from(format!("{}", i)));
+        let mut rt = Runtime::new().unwrap();
+        let sink = rt
+            .block_on(sink.send_all(stream::iter_ok(events)))
+            .unwrap()
+            .0;
+        drop(sink);
+
+        let received = receivers.into_iter().map(messages).collect::<Vec<_>>();
+        assert_eq!(received, vec![vec!["0", "2"], vec![], vec!["1", "3"]]);
+    }
+
+    #[test]
+    fn load_balance_acks_in_order() {
+        let (acker, ack_counter) = Acker::new_for_testing();
+        let (sink, _receivers, ackers) = recording_sink(LoadBalance::RoundRobin, 2);
+        let mut sink = LoadBalancedSink { acker, ..sink };
+        let mut rt = Runtime::new().unwrap();
+
+        // Polling the healthchecks needs the timer of the runtime.
+        rt.block_on(future::lazy(|| {
+            for i in 0..4 {
+                let event = Event::from(format!("{}", i));
+                assert!(sink.start_send(event).unwrap().is_ready());
+            }
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        // The second endpoint acknowledging its events doesn't ack anything
+        // upstream until the first endpoint's earlier events are acknowledged.
+        ackers.lock().unwrap()[1].ack(2);
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 0);
+
+        ackers.lock().unwrap()[0].ack(1);
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 2);
+
+        ackers.lock().unwrap()[0].ack(1);
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn load_balance_resends_events_of_stalled_endpoints() {
+        let (acker, ack_counter) = Acker::new_for_testing();
+        let (sink, receivers, ackers) = recording_sink(LoadBalance::RoundRobin, 2);
+        let mut sink = LoadBalancedSink {
+            acker,
+            ack_timeout: Duration::from_millis(50),
+            ..sink
+        };
+        let mut rt = Runtime::new().unwrap();
+
+        // Polling the healthchecks needs the timer of the runtime.
+        rt.block_on(future::lazy(|| {
+            for i in 0..4 {
+                let event = Event::from(format!("{}", i));
+                assert!(sink.start_send(event).unwrap().is_ready());
+            }
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        // The first endpoint never acknowledges its events, which are sent to
+        // the second one once they time out, and only acknowledged upstream
+        // once it acknowledges them.
+        ackers.lock().unwrap()[1].ack(2);
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 0);
+
+        thread::sleep(Duration::from_millis(100));
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert!(!sink.endpoints[0].healthy);
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 0);
+
+        ackers.lock().unwrap()[1].ack(2);
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 4);
+
+        // Acknowledgements of events taken back coming in late are ignored.
+        let sent = rt.block_on(future::lazy(|| sink.start_send(Event::from("4"))));
+        assert!(sent.unwrap().is_ready());
+        ackers.lock().unwrap()[0].ack(2);
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 4);
+
+        ackers.lock().unwrap()[1].ack(1);
+        rt.block_on(future::poll_fn(|| sink.poll_complete()))
+            .unwrap();
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 5);
+
+        drop(sink);
+        let received = receivers.into_iter().map(messages).collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            vec![vec!["0", "2"], vec!["1", "3", "0", "2", "4"]]
+        );
+    }
+}
@@ -1,7 +1,10 @@
 pub mod batch;
 pub mod buffer;
+pub mod encoding;
 pub mod http;
+pub mod load_balance;
 pub mod partition;
+pub mod reconnect;
 pub mod retries;
 
 use crate::buffers::Acker;
@@ -15,7 +18,10 @@ use tower::Service;
 
 pub use batch::{Batch, BatchSink};
 pub use buffer::{Buffer, Compression, PartitionBuffer, PartitionInnerBuffer};
+pub use encoding::{encode_event, Encoding};
+pub use load_balance::LoadBalance;
 pub use partition::{Partition, PartitionedBatchSink};
+pub use reconnect::{InFlight, Reconnect};
 
 pub trait SinkExt<T>
 where
@@ -0,0 +1,208 @@
+use crate::buffers::Acker;
+use futures::{Async, Future};
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::SocketAddr,
+    ops::{Index, IndexMut},
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+use tokio_retry::strategy::ExponentialBackoff;
+
+pub type Connect<T, E> = Box<dyn Future<Item = T, Error = E> + Send>;
+
+/// The delays between attempts to connect to a server, starting at half a
+/// second and doubling up to a minute.
+pub fn fresh_backoff() -> ExponentialBackoff {
+    ExponentialBackoff::from_millis(2)
+        .factor(250)
+        .max_delay(Duration::from_secs(60))
+}
+
+enum State<T, E> {
+    Disconnected,
+    Connecting(Connect<T, E>),
+    Connected(T),
+    Backoff(Delay),
+}
+
+/// A connection to a server, made again after a growing delay whenever it
+/// can't be made or is lost.
+pub struct Reconnect<T, E> {
+    addr: SocketAddr,
+    connect: Box<dyn FnMut() -> Connect<T, E> + Send>,
+    state: State<T, E>,
+    backoff: ExponentialBackoff,
+}
+
+impl<T, E: fmt::Display> Reconnect<T, E> {
+    pub fn new<F>(addr: SocketAddr, connect: F) -> Self
+    where
+        F: FnMut() -> Connect<T, E> + Send + 'static,
+    {
+        Self {
+            addr,
+            connect: Box::new(connect),
+            state: State::Disconnected,
+            backoff: fresh_backoff(),
+        }
+    }
+
+    fn next_delay(&mut self) -> Delay {
+        Delay::new(Instant::now() + self.backoff.next().unwrap())
+    }
+
+    /// Resolve once connected, with whether the connection was just made, in
+    /// which case whatever was sent over the previous one is to be sent again.
+    pub fn poll(&mut self) -> Async<bool> {
+        let mut fresh = false;
+        loop {
+            self.state = match self.state {
+                State::Disconnected => {
+                    debug!(message = "connecting.", addr = %self.addr);
+                    State::Connecting((self.connect)())
+                }
+                State::Backoff(ref mut delay) => match delay.poll() {
+                    Ok(Async::NotReady) => return Async::NotReady,
+                    // Err can only occur if the tokio runtime has been shutdown or if more than 2^63 timers have been created
+                    Err(err) => unreachable!(err),
+                    Ok(Async::Ready(())) => State::Disconnected,
+                },
+                State::Connecting(ref mut connect) => match connect.poll() {
+                    Ok(Async::Ready(connection)) => {
+                        debug!(message = "connected.", addr = %self.addr);
+                        self.backoff = fresh_backoff();
+                        fresh = true;
+                        State::Connected(connection)
+                    }
+                    Ok(Async::NotReady) => return Async::NotReady,
+                    Err(error) => {
+                        error!(message = "unable to connect.", addr = %self.addr, %error);
+                        State::Backoff(self.next_delay())
+                    }
+                },
+                State::Connected(_) => return Async::Ready(fresh),
+            };
+        }
+    }
+
+    /// The connection, once `poll` resolved.
+    pub fn get_mut(&mut self) -> &mut T {
+        match self.state {
+            State::Connected(ref mut connection) => connection,
+            _ => unreachable!("used the connection while disconnected"),
+        }
+    }
+
+    /// Drop the connection once it failed, to connect again after a delay.
+    pub fn lost(&mut self, error: impl fmt::Display) {
+        error!(message = "connection lost.", addr = %self.addr, %error);
+        self.state = State::Backoff(self.next_delay());
+    }
+}
+
+/// Messages kept until the server acknowledges them, each along with how many
+/// events are acknowledged with it, counting those dropped after it.
+pub struct InFlight<M> {
+    messages: VecDeque<(M, usize)>,
+    acker: Acker,
+}
+
+impl<M> InFlight<M> {
+    pub fn new(acker: Acker) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            acker,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn push(&mut self, message: M) {
+        self.messages.push_back((message, 1));
+    }
+
+    /// Drop an event that can't be sent. Events are acknowledged in order, so
+    /// it is acknowledged along with the message before it, if there is one.
+    pub fn drop_event(&mut self) {
+        match self.messages.back_mut() {
+            Some((_, events)) => *events += 1,
+            None => self.acker.ack(1),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut M> {
+        self.messages.iter_mut().map(|(message, _)| message)
+    }
+
+    /// Acknowledge the first `count` messages.
+    pub fn ack(&mut self, count: usize) {
+        let events: usize = self.messages.drain(..count).map(|(_, events)| events).sum();
+        if events > 0 {
+            self.acker.ack(events);
+        }
+    }
+
+    /// Acknowledge the messages at the front for as long as `done` holds for
+    /// them, resolving with how many there were.
+    pub fn ack_while(&mut self, done: impl Fn(&M) -> bool) -> usize {
+        let count = self
+            .messages
+            .iter()
+            .take_while(|(message, _)| done(message))
+            .count();
+        self.ack(count);
+        count
+    }
+}
+
+impl<M> Index<usize> for InFlight<M> {
+    type Output = M;
+
+    fn index(&self, index: usize) -> &M {
+        &self.messages[index].0
+    }
+}
+
+impl<M> IndexMut<usize> for InFlight<M> {
+    fn index_mut(&mut self, index: usize) -> &mut M {
+        &mut self.messages[index].0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InFlight;
+    use crate::buffers::Acker;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn in_flight_acks_dropped_events_with_the_message_before() {
+        let (acker, acks) = Acker::new_for_testing();
+        let mut in_flight = InFlight::new(acker);
+
+        in_flight.drop_event();
+        assert_eq!(acks.load(Ordering::Relaxed), 1);
+
+        in_flight.push(false);
+        in_flight.drop_event();
+        in_flight.push(false);
+        in_flight.push(true);
+
+        assert_eq!(in_flight.ack_while(|&acked| acked), 0);
+        in_flight[0] = true;
+        assert_eq!(in_flight.ack_while(|&acked| acked), 1);
+        assert_eq!(acks.load(Ordering::Relaxed), 3);
+
+        in_flight.ack(2);
+        assert!(in_flight.is_empty());
+        assert_eq!(acks.load(Ordering::Relaxed), 5);
+    }
+}
@@ -1,45 +1,88 @@
 use crate::{
     buffers::Acker,
     event::proto,
-    sinks::tcp::TcpSink,
-    sinks::util::SinkExt,
+    sinks::util::{
+        load_balance::{load_balanced, load_balanced_healthcheck, resolve_addresses},
+        Compression, LoadBalance, SinkExt,
+    },
     topology::config::{DataType, SinkConfig},
     Event,
 };
-use bytes::{BufMut, Bytes, BytesMut};
-use futures::{future, Future, Sink};
+use bytes::{Bytes, BytesMut};
+use flate2::write::GzEncoder;
+use futures::{future, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
 use prost::Message;
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
-use std::net::{SocketAddr, ToSocketAddrs};
-use tokio::net::TcpStream;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::{
+    codec::{Framed, LengthDelimitedCodec},
+    net::tcp::{ConnectFuture, TcpStream},
+    timer::Delay,
+};
+use tokio_retry::strategy::ExponentialBackoff;
+use tracing::field;
+
+/// The version of the batched and acknowledged protocol spoken between the
+/// `vector` sink and source. Older sinks send bare `EventWrapper` frames and
+/// never announce a version.
+pub const PROTOCOL_VERSION: u32 = 2;
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct VectorSinkConfig {
-    pub address: String,
+    pub address: Option<String>,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    pub load_balance: Option<LoadBalance>,
+    pub healthcheck_interval_secs: Option<u64>,
+    pub ack_timeout_secs: Option<u64>,
+    pub batch_size: Option<usize>,
+    pub batch_timeout: Option<u64>,
+    pub compression: Option<Compression>,
+    pub max_unacked_batches: Option<usize>,
+    pub handshake_timeout_secs: Option<u64>,
 }
 
 impl VectorSinkConfig {
     pub fn new(address: String) -> Self {
-        Self { address }
+        Self {
+            address: Some(address),
+            addresses: Vec::new(),
+            load_balance: None,
+            healthcheck_interval_secs: None,
+            ack_timeout_secs: None,
+            batch_size: None,
+            batch_timeout: None,
+            compression: None,
+            max_unacked_batches: None,
+            handshake_timeout_secs: None,
+        }
     }
 }
 
 #[typetag::serde(name = "vector")]
 impl SinkConfig for VectorSinkConfig {
     fn build(&self, acker: Acker) -> Result<(super::RouterSink, super::Healthcheck), crate::Error> {
-        let addr = self
-            .address
-            .to_socket_addrs()
-            .context(super::SocketAddressError)?
-            .next()
-            .ok_or(Box::new(super::BuildError::DNSFailure {
-                address: self.address.clone(),
-            }))?;
-
-        let sink = vector(self.address.clone(), addr, acker);
-        let healthcheck = super::tcp::tcp_healthcheck(addr);
+        let addrs = resolve_addresses(&self.address, &self.addresses)?
+            .into_iter()
+            .map(|(_, addr)| addr)
+            .collect::<Vec<_>>();
+
+        // Every endpoint keeps its own unacknowledged batches, so events
+        // handed to an endpoint that fails are retransmitted once it is back.
+        let sink = load_balanced(
+            addrs.clone(),
+            self.load_balance.clone().unwrap_or_default(),
+            Duration::from_secs(self.healthcheck_interval_secs.unwrap_or(10)),
+            Duration::from_secs(self.ack_timeout_secs.unwrap_or(60)),
+            acker,
+            |_, addr, acker| vector(self, addr, acker),
+        );
+        let healthcheck = load_balanced_healthcheck(&addrs);
 
         Ok((sink, healthcheck))
     }
@@ -49,11 +92,22 @@ impl SinkConfig for VectorSinkConfig {
     }
 }
 
-pub fn vector(hostname: String, addr: SocketAddr, acker: Acker) -> super::RouterSink {
+pub fn vector(config: &VectorSinkConfig, addr: SocketAddr, acker: Acker) -> super::RouterSink {
+    let batch_size = config.batch_size.unwrap_or(1000);
+    let batch_timeout = config.batch_timeout.unwrap_or(1);
+    let compression = config.compression.unwrap_or(Compression::None);
+    let max_unacked_batches = config.max_unacked_batches.unwrap_or(10);
+    let handshake_timeout = Duration::from_secs(config.handshake_timeout_secs.unwrap_or(10));
+
     Box::new(
-        TcpSink::new(hostname, addr, None)
-            .stream_ack(acker)
-            .with(move |event| encode_event(event)),
+        VectorSink::new(
+            addr,
+            acker,
+            compression,
+            max_unacked_batches,
+            handshake_timeout,
+        )
+        .batched_with_min(Vec::new(), batch_size, Duration::from_secs(batch_timeout)),
     )
 }
 
@@ -74,13 +128,457 @@ pub fn vector_healthcheck(addr: SocketAddr) -> super::Healthcheck {
     Box::new(check)
 }
 
-fn encode_event(event: Event) -> Result<Bytes, ()> {
-    let event = proto::EventWrapper::from(event);
-    let event_len = event.encoded_len() as u32;
-    let full_len = event_len + 4;
+#[derive(Debug, Snafu)]
+enum ConnectionError {
+    #[snafu(display("I/O error: {}", source))]
+    IoError { source: std::io::Error },
+    #[snafu(display("Connection closed by peer"))]
+    ClosedByPeer,
+    #[snafu(display("Could not decode response: {}", source))]
+    DecodeError { source: prost::DecodeError },
+    #[snafu(display("Peer speaks unsupported protocol version {}", version))]
+    UnsupportedVersion { version: u32 },
+    #[snafu(display(
+        "Peer did not answer the handshake in time, it may be a vector source \
+         that predates the batched protocol"
+    ))]
+    HandshakeTimedOut,
+}
+
+struct PendingBatch {
+    id: u64,
+    frame: Bytes,
+    num_events: usize,
+}
+
+/// A sink that writes batches of events to a `vector` source and holds on to
+/// them until the source acknowledges having accepted them. Batches that are
+/// still unacknowledged when the connection drops are retransmitted once it
+/// has been re-established, so delivery is at least once.
+///
+/// Batches are only written once the source answered the handshake of the
+/// connection. Sources that predate the batched protocol never do, and the
+/// connection is dropped after `handshake_timeout`.
+pub struct VectorSink {
+    addr: SocketAddr,
+    acker: Acker,
+    compression: Compression,
+    max_unacked_batches: usize,
+    handshake_timeout: Duration,
+    // Set until the source answers the handshake of the current connection.
+    handshake: Option<Delay>,
+    next_id: u64,
+    pending: VecDeque<PendingBatch>,
+    // How many of the `pending` batches have been written to the current
+    // connection.
+    sent: usize,
+    state: VectorSinkState,
+    backoff: ExponentialBackoff,
+}
+
+enum VectorSinkState {
+    Disconnected,
+    Connecting(ConnectFuture),
+    Connected(Framed<TcpStream, LengthDelimitedCodec>),
+    Backoff(Delay),
+}
+
+impl VectorSink {
+    pub fn new(
+        addr: SocketAddr,
+        acker: Acker,
+        compression: Compression,
+        max_unacked_batches: usize,
+        handshake_timeout: Duration,
+    ) -> Self {
+        Self {
+            addr,
+            acker,
+            compression,
+            max_unacked_batches,
+            handshake_timeout,
+            handshake: None,
+            next_id: 0,
+            pending: VecDeque::new(),
+            sent: 0,
+            state: VectorSinkState::Disconnected,
+            backoff: Self::fresh_backoff(),
+        }
+    }
+
+    fn fresh_backoff() -> ExponentialBackoff {
+        ExponentialBackoff::from_millis(2)
+            .factor(250)
+            .max_delay(Duration::from_secs(60))
+    }
+
+    fn next_delay(&mut self) -> Delay {
+        Delay::new(Instant::now() + self.backoff.next().unwrap())
+    }
+
+    fn poll_connection(&mut self) -> Poll<(), ()> {
+        loop {
+            self.state = match self.state {
+                VectorSinkState::Disconnected => {
+                    debug!(message = "connecting", addr = &field::display(&self.addr));
+                    VectorSinkState::Connecting(TcpStream::connect(&self.addr))
+                }
+                VectorSinkState::Backoff(ref mut delay) => match delay.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // Err can only occur if the tokio runtime has been shutdown or if more than 2^63 timers have been created
+                    Err(err) => unreachable!(err),
+                    Ok(Async::Ready(())) => VectorSinkState::Disconnected,
+                },
+                VectorSinkState::Connecting(ref mut connect_future) => {
+                    match connect_future.poll() {
+                        Ok(Async::Ready(socket)) => {
+                            debug!(message = "connected", addr = &field::display(&self.addr));
+                            let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+                            // The codec buffers writes, so this can not be refused.
+                            let _ = framed.start_send(encode_request(
+                                proto::request::Message::Hello(proto::Hello {
+                                    version: PROTOCOL_VERSION,
+                                }),
+                            ));
+                            self.sent = 0;
+                            self.handshake =
+                                Some(Delay::new(Instant::now() + self.handshake_timeout));
+                            VectorSinkState::Connected(framed)
+                        }
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(err) => {
+                            error!("Error connecting to {}: {}", self.addr, err);
+                            VectorSinkState::Backoff(self.next_delay())
+                        }
+                    }
+                }
+                VectorSinkState::Connected(_) => return Ok(Async::Ready(())),
+            };
+        }
+    }
+
+    /// Process the responses the source has sent back and, once it answered
+    /// the handshake, write out any batches not yet sent on the current
+    /// connection.
+    fn poll_io(&mut self) -> Result<(), ConnectionError> {
+        let framed = match self.state {
+            VectorSinkState::Connected(ref mut framed) => framed,
+            _ => return Ok(()),
+        };
+
+        loop {
+            let frame = match framed.poll().context(IoError)? {
+                Async::Ready(Some(frame)) => frame,
+                Async::Ready(None) => return Err(ConnectionError::ClosedByPeer),
+                Async::NotReady => break,
+            };
+
+            let response = proto::Response::decode(frame).context(DecodeError)?;
+            match response.message {
+                Some(proto::response::Message::Hello(hello)) => {
+                    if hello.version != PROTOCOL_VERSION {
+                        return Err(ConnectionError::UnsupportedVersion {
+                            version: hello.version,
+                        });
+                    }
+                    debug!(message = "handshake complete.", version = hello.version);
+                    self.handshake = None;
+                    self.backoff = Self::fresh_backoff();
+                }
+                Some(proto::response::Message::Ack(ack)) => {
+                    let mut num_to_ack = 0;
+                    while self
+                        .pending
+                        .front()
+                        .map_or(false, |batch| batch.id <= ack.id)
+                    {
+                        let batch = self.pending.pop_front().unwrap();
+                        num_to_ack += batch.num_events;
+                        self.sent = self.sent.saturating_sub(1);
+                    }
+                    trace!(
+                        message = "batches acknowledged.",
+                        id = ack.id,
+                        events = num_to_ack
+                    );
+                    self.acker.ack(num_to_ack);
+                }
+                None => warn!(message = "ignoring unknown response."),
+            }
+        }
+
+        if self.handshake.is_none() {
+            while self.sent < self.pending.len() {
+                let frame = self.pending[self.sent].frame.clone();
+                match framed.start_send(frame).context(IoError)? {
+                    AsyncSink::Ready => self.sent += 1,
+                    AsyncSink::NotReady(_) => break,
+                }
+            }
+        }
+        // Also flushes the hello written when connecting.
+        framed.poll_complete().context(IoError)?;
+
+        if let Some(ref mut handshake) = self.handshake {
+            match handshake.poll() {
+                Ok(Async::NotReady) => (),
+                // Err can only occur if the tokio runtime has been shutdown or if more than 2^63 timers have been created
+                Err(err) => unreachable!(err),
+                Ok(Async::Ready(())) => return Err(ConnectionError::HandshakeTimedOut),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Sink for VectorSink {
+    type SinkItem = Vec<Event>;
+    type SinkError = ();
+
+    fn start_send(&mut self, events: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.pending.len() >= self.max_unacked_batches {
+            self.poll_complete()?;
+
+            if self.pending.len() >= self.max_unacked_batches {
+                return Ok(AsyncSink::NotReady(events));
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let num_events = events.len();
+        let frame = encode_batch(id, events, self.compression);
+        debug!(
+            message = "queueing batch.",
+            id = id,
+            events = num_events,
+            bytes = frame.len()
+        );
+        self.pending.push_back(PendingBatch {
+            id,
+            frame,
+            num_events,
+        });
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        loop {
+            // Stream::forward will immediately poll_complete the sink it's forwarding to,
+            // but we don't want to connect before the first batch actually comes through.
+            if self.pending.is_empty() {
+                return Ok(Async::Ready(()));
+            }
+
+            try_ready!(self.poll_connection());
+
+            match self.poll_io() {
+                Ok(()) if self.pending.is_empty() => return Ok(Async::Ready(())),
+                Ok(()) => return Ok(Async::NotReady),
+                Err(error) => {
+                    error!(
+                        message = "connection error; will retransmit unacknowledged batches.",
+                        addr = &field::display(&self.addr),
+                        %error,
+                        unacked = self.pending.len()
+                    );
+                    self.sent = 0;
+                    self.state = VectorSinkState::Backoff(self.next_delay());
+                }
+            }
+        }
+    }
+}
+
+fn encode_batch(id: u64, events: Vec<Event>, compression: Compression) -> Bytes {
+    let batch = proto::EventBatch {
+        events: events.into_iter().map(proto::EventWrapper::from).collect(),
+    };
+    let mut payload = Vec::with_capacity(batch.encoded_len());
+    batch.encode(&mut payload).unwrap();
+
+    let (compression, payload) = match compression {
+        Compression::None => (proto::batch::Compression::None, payload),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&payload).unwrap();
+            (proto::batch::Compression::Gzip, encoder.finish().unwrap())
+        }
+    };
+
+    encode_request(proto::request::Message::Batch(proto::Batch {
+        id,
+        compression: compression as i32,
+        payload,
+    }))
+}
+
+fn encode_request(message: proto::request::Message) -> Bytes {
+    let request = proto::Request {
+        message: Some(message),
+    };
+    let mut out = BytesMut::with_capacity(request.encoded_len());
+    request.encode(&mut out).unwrap();
+    out.freeze()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{next_addr, runtime, wait_for};
+    use futures::stream;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::net::TcpListener;
+
+    fn decode_request(frame: BytesMut) -> proto::Request {
+        proto::Request::decode(frame).unwrap()
+    }
 
-    let mut out = BytesMut::with_capacity(full_len as usize);
-    out.put_u32_be(event_len);
-    event.encode(&mut out).unwrap();
-    Ok(out.freeze())
+    fn encode_response(message: proto::response::Message) -> Bytes {
+        let response = proto::Response {
+            message: Some(message),
+        };
+        let mut out = BytesMut::with_capacity(response.encoded_len());
+        response.encode(&mut out).unwrap();
+        out.freeze()
+    }
+
+    #[test]
+    fn vector_sink_encodes_compressed_batches() {
+        let frame = encode_batch(
+            7,
+            vec![Event::from("hello"), Event::from("world")],
+            Compression::Gzip,
+        );
+
+        let batch = match decode_request(frame.into()).message {
+            Some(proto::request::Message::Batch(batch)) => batch,
+            other => panic!("unexpected request {:?}", other),
+        };
+        assert_eq!(batch.id, 7);
+        assert_eq!(batch.compression(), proto::batch::Compression::Gzip);
+        assert_eq!(&batch.payload[..2], &[0x1f, 0x8b]);
+    }
+
+    // A fake source that hangs up as soon as the first batch arrives on its
+    // first connection, and acknowledges everything on later connections.
+    #[test]
+    fn vector_sink_retransmits_unacked_batches() {
+        let addr = next_addr();
+        let listener = TcpListener::bind(&addr).unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let server = {
+            let connections = Arc::clone(&connections);
+            listener
+                .incoming()
+                .map_err(|e| panic!("{:?}", e))
+                .for_each(move |socket| {
+                    let first_connection = connections.fetch_add(1, Ordering::SeqCst) == 0;
+                    let (writer, reader) = Framed::new(socket, LengthDelimitedCodec::new()).split();
+
+                    let responses = reader
+                        .map_err(|e| panic!("{:?}", e))
+                        .map(decode_request)
+                        .take_while(move |request| {
+                            let is_batch = match request.message {
+                                Some(proto::request::Message::Batch(_)) => true,
+                                _ => false,
+                            };
+                            Ok(!(first_connection && is_batch))
+                        })
+                        .filter_map(|request| match request.message {
+                            Some(proto::request::Message::Hello(_)) => Some(encode_response(
+                                proto::response::Message::Hello(proto::Hello {
+                                    version: PROTOCOL_VERSION,
+                                }),
+                            )),
+                            Some(proto::request::Message::Batch(batch)) => {
+                                Some(encode_response(proto::response::Message::Ack(proto::Ack {
+                                    id: batch.id,
+                                })))
+                            }
+                            None => None,
+                        })
+                        .forward(writer.sink_map_err(|e| panic!("{:?}", e)))
+                        .map(|_| ());
+
+                    tokio::spawn(responses);
+                    Ok(())
+                })
+        };
+
+        let mut rt = runtime();
+        rt.spawn(server);
+
+        let (acker, ack_counter) = Acker::new_for_testing();
+        let sink = VectorSink::new(addr, acker, Compression::None, 10, Duration::from_secs(10));
+        let batches = vec![vec![Event::from("one"), Event::from("two")]];
+
+        rt.spawn(sink.send_all(stream::iter_ok(batches)).map(|_| ()));
+
+        wait_for(|| ack_counter.load(Ordering::Relaxed) == 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    // A fake source predating the batched protocol, which never answers the
+    // handshake.
+    #[test]
+    fn vector_sink_times_out_handshake() {
+        let addr = next_addr();
+        let listener = TcpListener::bind(&addr).unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let batches = Arc::new(AtomicUsize::new(0));
+
+        let server = {
+            let connections = Arc::clone(&connections);
+            let batches = Arc::clone(&batches);
+            listener
+                .incoming()
+                .map_err(|e| panic!("{:?}", e))
+                .for_each(move |socket| {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    let batches = Arc::clone(&batches);
+                    let requests = Framed::new(socket, LengthDelimitedCodec::new())
+                        .map_err(|e| panic!("{:?}", e))
+                        .for_each(move |frame| {
+                            if let Some(proto::request::Message::Batch(_)) =
+                                decode_request(frame).message
+                            {
+                                batches.fetch_add(1, Ordering::SeqCst);
+                            }
+                            Ok(())
+                        });
+
+                    tokio::spawn(requests);
+                    Ok(())
+                })
+        };
+
+        let mut rt = runtime();
+        rt.spawn(server);
+
+        let (acker, ack_counter) = Acker::new_for_testing();
+        let sink = VectorSink::new(
+            addr,
+            acker,
+            Compression::None,
+            10,
+            Duration::from_millis(50),
+        );
+        let events = vec![vec![Event::from("one")]];
+
+        rt.spawn(sink.send_all(stream::iter_ok(events)).map(|_| ()));
+
+        // The sink gives up on the first connection and connects again.
+        wait_for(|| connections.load(Ordering::SeqCst) == 2);
+        assert_eq!(batches.load(Ordering::SeqCst), 0);
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 0);
+    }
 }
@@ -0,0 +1,287 @@
+use super::util::{forward_and_store, reconnect, Decoding, DecodingConfig, SessionError};
+use crate::{
+    amqp::{self, amqp_error, ConnectionConfig, Connector},
+    event::Event,
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use bytes::Bytes;
+use futures::{sync::mpsc, Future, Stream};
+use lapin_futures::{
+    channel::{BasicConsumeOptions, BasicQosOptions, QueueDeclareOptions},
+    types::FieldTable,
+};
+use serde::{Deserialize, Serialize};
+
+/// Consumes the messages of an AMQP queue, acknowledging each of them once
+/// its events are sent on.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AmqpSourceConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    /// The queue must already exist, it is not declared by the source.
+    pub queue: String,
+    #[serde(default = "default_consumer_tag")]
+    pub consumer_tag: String,
+    /// How many messages the broker delivers before waiting for them to be
+    /// acknowledged.
+    #[serde(default = "default_prefetch_count")]
+    pub prefetch_count: u16,
+    pub exchange_key: Option<String>,
+    pub routing_key_key: Option<String>,
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    #[serde(default)]
+    pub decoding: DecodingConfig,
+}
+
+fn default_consumer_tag() -> String {
+    "vector".into()
+}
+
+fn default_prefetch_count() -> u16 {
+    100
+}
+
+fn default_max_length() -> usize {
+    bytesize::kib(100u64) as usize
+}
+
+impl AmqpSourceConfig {
+    pub fn new(connection: ConnectionConfig, queue: String) -> Self {
+        Self {
+            connection,
+            queue,
+            consumer_tag: default_consumer_tag(),
+            prefetch_count: default_prefetch_count(),
+            exchange_key: None,
+            routing_key_key: None,
+            max_length: default_max_length(),
+            decoding: DecodingConfig::default(),
+        }
+    }
+}
+
+#[typetag::serde(name = "amqp")]
+impl SourceConfig for AmqpSourceConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        let connector = self.connection.build()?;
+        let decoding = self.decoding.build(self.max_length)?;
+        Ok(amqp_source(connector, self.clone(), decoding, out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn amqp_source(
+    connector: Connector,
+    config: AmqpSourceConfig,
+    decoding: Decoding,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let addr = connector.addr();
+    Box::new(reconnect(
+        addr,
+        move || connector.connect(),
+        move |client| {
+            consume(client, config.clone(), decoding.clone(), out.clone())
+                .map(|()| warn!("AMQP broker canceled the consumer."))
+        },
+    ))
+}
+
+/// Forward the messages of the queue until the connection is lost or the
+/// consumer canceled.
+fn consume(
+    client: lapin_futures::client::Client<amqp::Transport>,
+    config: AmqpSourceConfig,
+    decoding: Decoding,
+    out: mpsc::Sender<Event>,
+) -> impl Future<Item = (), Error = SessionError<amqp::Error>> {
+    let qos = BasicQosOptions {
+        prefetch_count: config.prefetch_count,
+        ..BasicQosOptions::default()
+    };
+    // Declaring passively only checks that the queue exists.
+    let declare = QueueDeclareOptions {
+        passive: true,
+        ..QueueDeclareOptions::default()
+    };
+
+    client
+        .create_channel()
+        .and_then(move |channel| channel.basic_qos(qos).map(move |()| channel))
+        .and_then(move |channel| {
+            channel
+                .queue_declare(&config.queue, declare, FieldTable::new())
+                .map(move |queue| (channel, queue, config))
+        })
+        .and_then(|(channel, queue, config)| {
+            channel
+                .basic_consume(
+                    &queue,
+                    &config.consumer_tag,
+                    BasicConsumeOptions::default(),
+                    FieldTable::new(),
+                )
+                .map(move |consumer| (channel, consumer, config))
+        })
+        .map_err(|error| SessionError::Lost(amqp_error(error)))
+        .and_then(move |(channel, consumer, config)| {
+            info!(message = "consuming.", queue = config.queue.as_str());
+
+            // A failing consumer ends the stream, so that the only error left
+            // is the output being closed.
+            let messages = consumer
+                .then(|delivery| match delivery {
+                    Ok(delivery) => Ok(Some(delivery)),
+                    Err(error) => {
+                        error!(message = "AMQP consumer failed.", %error);
+                        Ok(None)
+                    }
+                })
+                .take_while(|delivery| Ok(delivery.is_some()))
+                .filter_map(|delivery| delivery)
+                .map(move |delivery| {
+                    let events = delivery_to_events(
+                        &config,
+                        &decoding,
+                        delivery.data,
+                        &delivery.exchange,
+                        &delivery.routing_key,
+                    );
+                    (events, delivery.delivery_tag)
+                });
+
+            forward_and_store(messages, out, move |delivery_tag| {
+                tokio::spawn(
+                    channel
+                        .basic_ack(delivery_tag, false)
+                        .map_err(|error| error!(message = "Cannot acknowledge message", %error)),
+                );
+            })
+            .map_err(|()| SessionError::OutputClosed)
+        })
+}
+
+fn delivery_to_events(
+    config: &AmqpSourceConfig,
+    decoding: &Decoding,
+    data: Vec<u8>,
+    exchange: &str,
+    routing_key: &str,
+) -> Vec<Event> {
+    let mut events = decoding.decode_message(Bytes::from(data));
+    for event in &mut events {
+        let log = event.as_mut_log();
+        if let Some(exchange_key) = &config.exchange_key {
+            log.insert_implicit(exchange_key.clone().into(), exchange.into());
+        }
+        if let Some(routing_key_key) = &config.routing_key_key {
+            log.insert_implicit(routing_key_key.clone().into(), routing_key.into());
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::{delivery_to_events, AmqpSourceConfig};
+    use crate::{amqp::ConnectionConfig, event};
+
+    #[test]
+    fn amqp_delivery_to_events() {
+        let mut config = AmqpSourceConfig::new(
+            ConnectionConfig::new("localhost:5672".into()),
+            "logs".into(),
+        );
+        config.routing_key_key = Some("routing_key".into());
+        let decoding = config.decoding.build(config.max_length).unwrap();
+
+        let events = delivery_to_events(
+            &config,
+            &decoding,
+            b"first\nsecond".to_vec(),
+            "amq.topic",
+            "app.web",
+        );
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].as_log()[&event::MESSAGE], "first".into());
+        assert_eq!(events[1].as_log()[&event::MESSAGE], "second".into());
+        assert_eq!(events[1].as_log()[&"routing_key".into()], "app.web".into());
+        assert_eq!(events[1].as_log().get(&"exchange".into()), None);
+    }
+}
+
+#[cfg(feature = "amqp-integration-tests")]
+#[cfg(test)]
+mod integration_test {
+    use super::{amqp_source, AmqpSourceConfig};
+    use crate::{
+        amqp::{amqp_error, ConnectionConfig},
+        event,
+        test_util::{collect_n, random_string, runtime},
+    };
+    use futures::{future, sync::mpsc, Future};
+    use lapin_futures::{
+        channel::{BasicProperties, BasicPublishOptions, QueueDeclareOptions},
+        types::FieldTable,
+    };
+
+    #[test]
+    fn amqp_source_consumes_queue() {
+        let queue = format!("test-{}", random_string(10));
+        let connection = ConnectionConfig::new("localhost:5672".into());
+        let connector = connection.build().unwrap();
+
+        let mut rt = runtime();
+        let publish_queue = queue.clone();
+        let publish = connector
+            .connect()
+            .and_then(|client| client.create_channel().map_err(amqp_error))
+            .and_then(move |channel| {
+                channel
+                    .queue_declare(
+                        &publish_queue,
+                        QueueDeclareOptions::default(),
+                        FieldTable::new(),
+                    )
+                    .and_then(move |_| {
+                        future::join_all((0..10).map(move |i| {
+                            channel.basic_publish(
+                                "",
+                                &publish_queue,
+                                format!("line {}", i).into_bytes(),
+                                BasicPublishOptions::default(),
+                                BasicProperties::default(),
+                            )
+                        }))
+                    })
+                    .map_err(amqp_error)
+            });
+        rt.block_on(publish).unwrap();
+
+        let mut config = AmqpSourceConfig::new(connection, queue.clone());
+        config.routing_key_key = Some("routing_key".into());
+        let decoding = config.decoding.build(config.max_length).unwrap();
+        let (tx, rx) = mpsc::channel(10);
+        rt.spawn(amqp_source(connector, config, decoding, tx));
+
+        let events = rt.block_on(collect_n(rx, 10)).unwrap();
+        for (i, event) in events.iter().enumerate() {
+            assert_eq!(
+                event.as_log()[&event::MESSAGE],
+                format!("line {}", i).into()
+            );
+            assert_eq!(event.as_log()[&"routing_key".into()], queue.as_str().into());
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use crate::{
+    event::{self, Event},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use futures::{future, stream, sync::mpsc, Future, Sink, Stream};
+use hyper::{
+    header::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE},
+    service::service_fn,
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{
+    io::{self, Read},
+    net::SocketAddr,
+    sync::Arc,
+};
+use string_cache::DefaultAtom as Atom;
+
+lazy_static! {
+    static ref REQUEST_ID: Atom = Atom::from("request_id");
+    static ref SOURCE_ARN: Atom = Atom::from("source_arn");
+    static ref LOG_GROUP: Atom = Atom::from("log_group");
+    static ref LOG_STREAM: Atom = Atom::from("log_stream");
+    static ref OWNER: Atom = Atom::from("owner");
+}
+
+const REQUEST_ID_HEADER: &str = "X-Amz-Firehose-Request-Id";
+const ACCESS_KEY_HEADER: &str = "X-Amz-Firehose-Access-Key";
+const SOURCE_ARN_HEADER: &str = "X-Amz-Firehose-Source-Arn";
+
+#[derive(Debug, Snafu)]
+enum RequestError {
+    #[snafu(display("Method {} is not allowed", method))]
+    MethodNotAllowed { method: Method },
+    #[snafu(display("Invalid access key"))]
+    InvalidAccessKey,
+    #[snafu(display("Could not decompress request body: {}", source))]
+    Decompress { source: io::Error },
+    #[snafu(display("Invalid request body: {}", source))]
+    InvalidBody { source: serde_json::Error },
+}
+
+impl RequestError {
+    fn status(&self) -> StatusCode {
+        match self {
+            RequestError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            RequestError::InvalidAccessKey => StatusCode::UNAUTHORIZED,
+            RequestError::Decompress { .. } | RequestError::InvalidBody { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+enum RecordError {
+    #[snafu(display("invalid base64: {}", source))]
+    Base64 { source: base64::DecodeError },
+    #[snafu(display("invalid gzip: {}", source))]
+    Gzip { source: io::Error },
+}
+
+/// Receives records from an AWS Kinesis Firehose delivery stream with an HTTP
+/// endpoint destination.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AwsKinesisFirehoseConfig {
+    pub address: SocketAddr,
+    /// The access key configured on the delivery stream, which requests must
+    /// carry if set.
+    pub access_key: Option<String>,
+}
+
+#[typetag::serde(name = "aws_kinesis_firehose")]
+impl SourceConfig for AwsKinesisFirehoseConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        Ok(firehose(self.address, self.access_key.clone(), out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FirehoseRequest {
+    request_id: String,
+    /// Milliseconds since the epoch.
+    timestamp: i64,
+    records: Vec<FirehoseRecord>,
+}
+
+#[derive(Deserialize)]
+struct FirehoseRecord {
+    data: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FirehoseResponse {
+    request_id: String,
+    timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_message: Option<String>,
+}
+
+/// The records delivered by a CloudWatch Logs subscription.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CloudwatchLogsMessage {
+    message_type: String,
+    owner: String,
+    log_group: String,
+    log_stream: String,
+    log_events: Vec<CloudwatchLogEvent>,
+}
+
+#[derive(Deserialize)]
+struct CloudwatchLogEvent {
+    timestamp: i64,
+    message: String,
+}
+
+/// What the events of a request share.
+struct RequestContext {
+    request_id: String,
+    source_arn: Option<String>,
+    timestamp: DateTime<Utc>,
+}
+
+fn firehose(
+    address: SocketAddr,
+    access_key: Option<String>,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let access_key = Arc::new(access_key);
+
+    Box::new(future::lazy(move || {
+        let new_service = move || {
+            let access_key = Arc::clone(&access_key);
+            let out = out.clone();
+            service_fn(move |request| handle(request, &access_key, out.clone()))
+        };
+
+        info!(message = "listening.", %address, r#type = "http");
+
+        Server::bind(&address)
+            .serve(new_service)
+            .map_err(|error| error!(message = "server error.", %error))
+    }))
+}
+
+/// Implements the delivery contract of Firehose: every response echoes the
+/// request id, and only a `200 OK` acknowledges the records, which happens
+/// once their events are sent on.
+fn handle(
+    request: Request<Body>,
+    access_key: &Option<String>,
+    out: mpsc::Sender<Event>,
+) -> Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send> {
+    let headers = request.headers();
+    let request_id = header(headers, REQUEST_ID_HEADER).unwrap_or("").to_owned();
+    let source_arn = header(headers, SOURCE_ARN_HEADER).map(String::from);
+    let gzip = header(headers, CONTENT_ENCODING.as_str()) == Some("gzip");
+
+    if request.method() != Method::POST {
+        let error = RequestError::MethodNotAllowed {
+            method: request.method().clone(),
+        };
+        return Box::new(future::ok(respond(request_id, Err(error))));
+    }
+    if let Some(access_key) = access_key {
+        if header(headers, ACCESS_KEY_HEADER) != Some(access_key.as_str()) {
+            warn!(
+                message = "request with an invalid access key.",
+                request_id = request_id.as_str()
+            );
+            return Box::new(future::ok(respond(
+                request_id,
+                Err(RequestError::InvalidAccessKey),
+            )));
+        }
+    }
+
+    Box::new(request.into_body().concat2().and_then(move |body| {
+        let request = match parse_request(&body, gzip) {
+            Ok(request) => request,
+            Err(error) => {
+                warn!(
+                    message = "invalid request.",
+                    request_id = request_id.as_str(),
+                    %error
+                );
+                return future::Either::A(future::ok(respond(request_id, Err(error))));
+            }
+        };
+
+        let context = RequestContext {
+            request_id: request.request_id,
+            source_arn,
+            timestamp: from_millis(request.timestamp).unwrap_or_else(Utc::now),
+        };
+        let events = request
+            .records
+            .iter()
+            .flat_map(|record| match decode_record(&record.data) {
+                Ok(data) => data_to_events(data, &context),
+                Err(error) => {
+                    warn!(
+                        message = "failed to decode record.",
+                        request_id = context.request_id.as_str(),
+                        %error,
+                        decode_errors_counter = 1,
+                    );
+                    Vec::new()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        future::Either::B(
+            out.send_all(stream::iter_ok::<_, mpsc::SendError<Event>>(events))
+                .then(move |result| match result {
+                    Ok(_) => Ok(respond(request_id, Ok(()))),
+                    Err(error) => {
+                        error!(message = "error sending event.", %error);
+                        let mut response = respond(request_id, Ok(()));
+                        // Firehose retries the records until it gets an answer.
+                        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                        Ok(response)
+                    }
+                }),
+        )
+    }))
+}
+
+fn respond(request_id: String, result: Result<(), RequestError>) -> Response<Body> {
+    let (status, error_message) = match result {
+        Ok(()) => (StatusCode::OK, None),
+        Err(error) => (error.status(), Some(error.to_string())),
+    };
+    let body = FirehoseResponse {
+        request_id,
+        timestamp: Utc::now().timestamp_millis(),
+        error_message,
+    };
+
+    let mut response = Response::new(Body::from(
+        serde_json::to_vec(&body).expect("response can always be serialized"),
+    ));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn parse_request(body: &[u8], gzip: bool) -> Result<FirehoseRequest, RequestError> {
+    if gzip {
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(body)
+            .read_to_end(&mut decompressed)
+            .context(Decompress)?;
+        serde_json::from_slice(&decompressed).context(InvalidBody)
+    } else {
+        serde_json::from_slice(body).context(InvalidBody)
+    }
+}
+
+/// Records are base64 encoded, and compressed when they come from CloudWatch
+/// Logs.
+fn decode_record(data: &str) -> Result<Vec<u8>, RecordError> {
+    let data = base64::decode(data).context(Base64)?;
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(&data[..])
+            .read_to_end(&mut decompressed)
+            .context(Gzip)?;
+        Ok(decompressed)
+    } else {
+        Ok(data)
+    }
+}
+
+/// CloudWatch Logs subscriptions are unpacked into an event per log event,
+/// and any other record becomes a single event.
+fn data_to_events(data: Vec<u8>, context: &RequestContext) -> Vec<Event> {
+    match serde_json::from_slice::<CloudwatchLogsMessage>(&data) {
+        // Control messages only check that the destination is reachable.
+        Ok(ref message) if message.message_type == "CONTROL_MESSAGE" => Vec::new(),
+        Ok(message) => message
+            .log_events
+            .iter()
+            .map(|log_event| {
+                let mut event = new_event(log_event.message.as_str().into(), context);
+                let log = event.as_mut_log();
+                log.insert_implicit(
+                    event::TIMESTAMP.clone(),
+                    from_millis(log_event.timestamp)
+                        .unwrap_or(context.timestamp)
+                        .into(),
+                );
+                log.insert_explicit(LOG_GROUP.clone(), message.log_group.clone().into());
+                log.insert_explicit(LOG_STREAM.clone(), message.log_stream.clone().into());
+                log.insert_explicit(OWNER.clone(), message.owner.clone().into());
+                event
+            })
+            .collect(),
+        Err(_) => vec![new_event(data.into(), context)],
+    }
+}
+
+fn new_event(message: event::ValueKind, context: &RequestContext) -> Event {
+    let mut event = Event::new_empty_log();
+    let log = event.as_mut_log();
+    log.insert_implicit(event::MESSAGE.clone(), message);
+    log.insert_implicit(event::TIMESTAMP.clone(), context.timestamp.into());
+    log.insert_implicit(REQUEST_ID.clone(), context.request_id.clone().into());
+    if let Some(source_arn) = &context.source_arn {
+        log.insert_implicit(SOURCE_ARN.clone(), source_arn.clone().into());
+    }
+    event
+}
+
+/// Timestamps are taken from the request, which can hold any number, so those
+/// that are negative or out of range are left out.
+fn from_millis(millis: i64) -> Option<DateTime<Utc>> {
+    if millis < 0 {
+        return None;
+    }
+    Utc.timestamp_opt(millis / 1000, (millis % 1000) as u32 * 1_000_000)
+        .single()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        data_to_events, decode_record, from_millis, AwsKinesisFirehoseConfig, RequestContext,
+    };
+    use crate::{
+        event,
+        test_util::{collect_n, next_addr, wait_for_tcp},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use chrono::{TimeZone, Utc};
+    use flate2::{write::GzEncoder, Compression};
+    use futures::{sync::mpsc, Future, Stream};
+    use hyper::{Body, Client, Request, StatusCode};
+    use serde_json::{json, Value};
+    use std::io::Write;
+    use string_cache::DefaultAtom as Atom;
+
+    const CLOUDWATCH_LOGS: &str = r#"{
+        "messageType": "DATA_MESSAGE",
+        "owner": "071959437513",
+        "logGroup": "/jesse/test",
+        "logStream": "test",
+        "subscriptionFilters": ["Destination"],
+        "logEvents": [
+            {"id": "35683658089614582423604394983260738922885519999578275840", "timestamp": 1600110569039, "message": "{\"bytes\":26780,\"datetime\":\"14/Sep/2020:11:45:41 -0400\"}"},
+            {"id": "35683658089659183914001456229543810359430816722590236673", "timestamp": 1600110569041, "message": "second"}
+        ]
+    }"#;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn context() -> RequestContext {
+        RequestContext {
+            request_id: "ed1d787c-b9e2-4631-92dc-8e7c9d26d804".into(),
+            source_arn: None,
+            timestamp: Utc.timestamp(1_600_110_760, 0),
+        }
+    }
+
+    #[test]
+    fn aws_kinesis_firehose_unpacks_cloudwatch_logs() {
+        let data = decode_record(&base64::encode(&gzip(CLOUDWATCH_LOGS.as_bytes()))).unwrap();
+        let events = data_to_events(data, &context());
+        assert_eq!(events.len(), 2);
+
+        let log = events[0].as_log();
+        assert_eq!(
+            log[&event::MESSAGE],
+            r#"{"bytes":26780,"datetime":"14/Sep/2020:11:45:41 -0400"}"#.into()
+        );
+        assert_eq!(
+            log[&event::TIMESTAMP],
+            Utc.timestamp(1_600_110_569, 39_000_000).into()
+        );
+        assert_eq!(log[&Atom::from("log_group")], "/jesse/test".into());
+        assert_eq!(log[&Atom::from("log_stream")], "test".into());
+        assert_eq!(log[&Atom::from("owner")], "071959437513".into());
+        assert_eq!(
+            log[&Atom::from("request_id")],
+            "ed1d787c-b9e2-4631-92dc-8e7c9d26d804".into()
+        );
+        assert_eq!(events[1].as_log()[&event::MESSAGE], "second".into());
+
+        let control = CLOUDWATCH_LOGS.replace("DATA_MESSAGE", "CONTROL_MESSAGE");
+        assert!(data_to_events(control.into_bytes(), &context()).is_empty());
+    }
+
+    #[test]
+    fn aws_kinesis_firehose_replaces_invalid_timestamps() {
+        for timestamp in &["-1", "9223372036854775807"] {
+            let message = CLOUDWATCH_LOGS.replace("1600110569039", timestamp);
+            let events = data_to_events(message.into_bytes(), &context());
+            assert_eq!(
+                events[0].as_log()[&event::TIMESTAMP],
+                context().timestamp.into()
+            );
+        }
+        assert_eq!(from_millis(-1), None);
+        assert_eq!(from_millis(i64::max_value()), None);
+    }
+
+    #[test]
+    fn aws_kinesis_firehose_passes_other_records() {
+        let data = decode_record(&base64::encode(b"plain record")).unwrap();
+        let events = data_to_events(data, &context());
+        assert_eq!(events.len(), 1);
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "plain record".into());
+        assert_eq!(log[&event::TIMESTAMP], context().timestamp.into());
+
+        assert!(decode_record("not base64!").is_err());
+        assert!(decode_record(&base64::encode(&[0x1f, 0x8b, 0])).is_err());
+    }
+
+    #[test]
+    fn aws_kinesis_firehose_receives_requests() {
+        let (tx, rx) = mpsc::channel(10);
+        let address = next_addr();
+        let config = AwsKinesisFirehoseConfig {
+            address,
+            access_key: Some("secret".into()),
+        };
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+        wait_for_tcp(address);
+
+        let body = json!({
+            "requestId": "ed1d787c-b9e2-4631-92dc-8e7c9d26d804",
+            "timestamp": 1_600_110_760_000i64,
+            "records": [
+                {"data": base64::encode(&gzip(CLOUDWATCH_LOGS.as_bytes()))},
+                {"data": base64::encode(b"plain record")}
+            ]
+        });
+        let request = |access_key: &str| {
+            Request::post(format!("http://{}/", address).as_str())
+                .header(
+                    "X-Amz-Firehose-Request-Id",
+                    "ed1d787c-b9e2-4631-92dc-8e7c9d26d804",
+                )
+                .header("X-Amz-Firehose-Access-Key", access_key)
+                .header(
+                    "X-Amz-Firehose-Source-Arn",
+                    "arn:aws:firehose:us-east-1:111111111111:deliverystream/test",
+                )
+                .header("Content-Encoding", "gzip")
+                .body(Body::from(gzip(body.to_string().as_bytes())))
+                .unwrap()
+        };
+
+        let client = Client::new();
+        let mut send = |request| {
+            rt.block_on(client.request(request).and_then(|response| {
+                let status = response.status();
+                response.into_body().concat2().map(move |body| {
+                    let body: Value = serde_json::from_slice(&body).unwrap();
+                    (status, body)
+                })
+            }))
+            .unwrap()
+        };
+
+        let (status, body) = send(request("wrong"));
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["requestId"], "ed1d787c-b9e2-4631-92dc-8e7c9d26d804");
+        assert_eq!(body["errorMessage"], "Invalid access key");
+
+        let (status, body) = send(request("secret"));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["requestId"], "ed1d787c-b9e2-4631-92dc-8e7c9d26d804");
+        assert!(body["timestamp"].is_i64());
+        assert!(body.get("errorMessage").is_none());
+
+        let events = rt.block_on(collect_n(rx, 3)).ok().unwrap();
+        assert_eq!(events[1].as_log()[&event::MESSAGE], "second".into());
+        let log = events[2].as_log();
+        assert_eq!(log[&event::MESSAGE], "plain record".into());
+        assert_eq!(
+            log[&Atom::from("source_arn")],
+            "arn:aws:firehose:us-east-1:111111111111:deliverystream/test".into()
+        );
+    }
+}
@@ -0,0 +1,655 @@
+use crate::{
+    event::{self, Event},
+    region::RegionOrEndpoint,
+    sinks::aws_s3::S3Sink,
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use bytes::Bytes;
+use flate2::read::MultiGzDecoder;
+use futures::{
+    future::{self, Loop},
+    stream,
+    sync::mpsc,
+    try_ready, Async, Future, Poll, Sink, Stream,
+};
+use lazy_static::lazy_static;
+use rusoto_core::Region;
+use rusoto_s3::{GetObjectError, GetObjectRequest, S3Client, S3};
+use rusoto_sqs::{
+    DeleteMessageRequest, Message, ReceiveMessageError, ReceiveMessageRequest, Sqs, SqsClient,
+};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::{
+    convert::TryInto,
+    io::{self, BufRead, BufReader, Read},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::Delay;
+use url::percent_encoding::percent_decode;
+
+lazy_static! {
+    static ref BUCKET: Atom = Atom::from("bucket");
+    static ref OBJECT: Atom = Atom::from("object");
+}
+
+/// Ingests the objects written to S3 buckets, found through the event
+/// notifications sent to an SQS queue.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AwsS3Config {
+    #[serde(flatten)]
+    pub region: RegionOrEndpoint,
+    #[serde(default)]
+    pub compression: Compression,
+    pub sqs: SqsConfig,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SqsConfig {
+    pub queue_url: String,
+    /// The region or endpoint of the queue, if not the same as the buckets'.
+    #[serde(flatten)]
+    pub region: RegionOrEndpoint,
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u32,
+    #[serde(default = "default_visibility_timeout_secs")]
+    pub visibility_timeout_secs: u32,
+}
+
+fn default_poll_secs() -> u32 {
+    20
+}
+
+fn default_visibility_timeout_secs() -> u32 {
+    300
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Decompress objects starting with the gzip magic bytes.
+    Auto,
+    Gzip,
+    None,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Auto
+    }
+}
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display(
+        "`poll_secs` can be at most 20, the longest SQS allows, got {}",
+        poll_secs
+    ))]
+    InvalidPollSecs { poll_secs: u32 },
+}
+
+#[derive(Debug, Snafu)]
+enum FetchError {
+    #[snafu(display("Could not get object {:?} from bucket {:?}: {}", key, bucket, source))]
+    GetObject {
+        bucket: String,
+        key: String,
+        source: GetObjectError,
+    },
+    #[snafu(display("Object {:?} of bucket {:?} has no body", key, bucket))]
+    NoBody { bucket: String, key: String },
+    #[snafu(display("Could not read object {:?} of bucket {:?}: {}", key, bucket, source))]
+    ReadObject {
+        bucket: String,
+        key: String,
+        source: io::Error,
+    },
+}
+
+#[typetag::serde(name = "aws_s3")]
+impl SourceConfig for AwsS3Config {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        if self.sqs.poll_secs > 20 {
+            return Err(BuildError::InvalidPollSecs {
+                poll_secs: self.sqs.poll_secs,
+            }
+            .into());
+        }
+
+        let region: Region = self.region.clone().try_into()?;
+        let sqs_region: Region = if self.sqs.region == RegionOrEndpoint::default() {
+            region.clone()
+        } else {
+            self.sqs.region.clone().try_into()?
+        };
+
+        let ingestor = Ingestor {
+            s3: S3Sink::create_client(region),
+            sqs: SqsClient::new(sqs_region),
+            queue_url: self.sqs.queue_url.clone(),
+            poll_secs: self.sqs.poll_secs,
+            visibility_timeout_secs: self.sqs.visibility_timeout_secs,
+            compression: self.compression,
+        };
+        Ok(Box::new(ingestor.run(out)))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+struct Ingestor {
+    s3: S3Client,
+    sqs: SqsClient,
+    queue_url: String,
+    poll_secs: u32,
+    visibility_timeout_secs: u32,
+    compression: Compression,
+}
+
+type Step<T> = Box<dyn Future<Item = T, Error = ()> + Send>;
+
+impl Ingestor {
+    /// Receive notifications until the output is closed, handling one at a
+    /// time so that messages are deleted in the order their events are sent.
+    fn run(self, out: mpsc::Sender<Event>) -> impl Future<Item = (), Error = ()> {
+        let ingestor = Arc::new(self);
+
+        info!(message = "polling queue.", queue_url = %ingestor.queue_url);
+
+        future::loop_fn(out, move |out| {
+            let ingestor = Arc::clone(&ingestor);
+            ingestor
+                .receive()
+                .then(move |result| -> Step<Loop<(), mpsc::Sender<Event>>> {
+                    match result {
+                        Ok(messages) => Box::new(
+                            stream::iter_ok(messages)
+                                .fold(out, move |out, message| {
+                                    Arc::clone(&ingestor).handle(message, out)
+                                })
+                                .map(Loop::Continue),
+                        ),
+                        Err(error) => {
+                            error!(message = "failed to receive messages.", %error);
+                            Box::new(
+                                Delay::new(Instant::now() + Duration::from_secs(1))
+                                    .map_err(|error| error!(message = "timer error.", %error))
+                                    .map(move |_| Loop::Continue(out)),
+                            )
+                        }
+                    }
+                })
+        })
+    }
+
+    fn receive(&self) -> impl Future<Item = Vec<Message>, Error = ReceiveMessageError> {
+        let request = ReceiveMessageRequest {
+            queue_url: self.queue_url.clone(),
+            max_number_of_messages: Some(10),
+            wait_time_seconds: Some(i64::from(self.poll_secs)),
+            visibility_timeout: Some(i64::from(self.visibility_timeout_secs)),
+            ..Default::default()
+        };
+        self.sqs
+            .receive_message(request)
+            .map(|result| result.messages.unwrap_or_default())
+    }
+
+    /// Send the events of every object in a notification as the objects are
+    /// read, then delete it. Notifications that fail are left on the queue,
+    /// to be received again once their visibility timeout has passed, so the
+    /// events already sent from them will be sent again.
+    fn handle(
+        self: Arc<Self>,
+        message: Message,
+        out: mpsc::Sender<Event>,
+    ) -> Step<mpsc::Sender<Event>> {
+        let body = message.body.unwrap_or_default();
+        let objects = match parse_notification(&body) {
+            Ok(objects) => objects,
+            Err(error) => {
+                warn!(
+                    message = "invalid S3 event notification.",
+                    %error,
+                    invalid_notifications_counter = 1,
+                );
+                return Box::new(future::ok(out));
+            }
+        };
+        let receipt_handle = match message.receipt_handle {
+            Some(receipt_handle) => receipt_handle,
+            None => return Box::new(future::ok(out)),
+        };
+
+        let ingestor = Arc::clone(&self);
+        let events = stream::iter_ok::<_, FetchError>(objects)
+            .map(move |object| ingestor.fetch(object))
+            .flatten()
+            .map_err(Some);
+        // A failed fetch leaves the output usable, so the events are sent
+        // through a clone of it, with `None` standing for a closed output.
+        let sink = out.clone().sink_map_err(|_| None::<FetchError>);
+        Box::new(sink.send_all(events).then(move |result| match result {
+            Ok(_) => future::Either::A(self.delete(receipt_handle).map(|_| out)),
+            Err(Some(error)) => {
+                error!(message = "failed to fetch object.", %error, fetch_errors_counter = 1);
+                future::Either::B(future::ok(out))
+            }
+            Err(None) => {
+                error!(message = "error sending event.");
+                future::Either::B(future::err(()))
+            }
+        }))
+    }
+
+    fn fetch(&self, object: S3Object) -> impl Stream<Item = Event, Error = FetchError> {
+        let request = GetObjectRequest {
+            bucket: object.bucket.clone(),
+            key: object.key.clone(),
+            ..Default::default()
+        };
+        let compression = self.compression;
+        let S3Object { bucket, key } = object;
+
+        self.s3
+            .get_object(request)
+            .then({
+                let (bucket, key) = (bucket.clone(), key.clone());
+                move |result| result.context(GetObject { bucket, key })
+            })
+            .and_then({
+                let (bucket, key) = (bucket.clone(), key.clone());
+                move |output| output.body.context(NoBody { bucket, key })
+            })
+            .map(move |body| {
+                object_lines(body.into_blocking_read(), compression)
+                    .map({
+                        let (bucket, key) = (bucket.clone(), key.clone());
+                        move |line| line_to_event(line, &bucket, &key)
+                    })
+                    .map_err(move |source| FetchError::ReadObject {
+                        bucket: bucket.clone(),
+                        key: key.clone(),
+                        source,
+                    })
+            })
+            .flatten_stream()
+    }
+
+    fn delete(&self, receipt_handle: String) -> impl Future<Item = (), Error = ()> {
+        let request = DeleteMessageRequest {
+            queue_url: self.queue_url.clone(),
+            receipt_handle,
+        };
+        // A message that can't be deleted will be received and its objects
+        // ingested again, which is better than stopping.
+        self.sqs.delete_message(request).then(|result| {
+            if let Err(error) = result {
+                error!(message = "failed to delete message.", %error);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The lines of an object, decompressed and split as its body is received.
+/// Reading the body blocks, so it is done on the blocking threads of the
+/// thread pool.
+fn object_lines(
+    body: impl Read + Send + 'static,
+    compression: Compression,
+) -> impl Stream<Item = Bytes, Error = io::Error> {
+    let mut body = Some(body);
+    future::poll_fn(move || {
+        tokio_threadpool::blocking(|| decompress(body.take().unwrap(), compression))
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+    })
+    .and_then(|result| result)
+    .map(|reader| ObjectLines { reader })
+    .flatten_stream()
+}
+
+fn decompress(
+    mut body: impl Read + Send + 'static,
+    compression: Compression,
+) -> Result<Box<dyn BufRead + Send>, io::Error> {
+    let mut head = Vec::with_capacity(2);
+    (&mut body).take(2).read_to_end(&mut head)?;
+    let gzip = match compression {
+        Compression::Auto => head.starts_with(&[0x1f, 0x8b]),
+        Compression::Gzip => true,
+        Compression::None => false,
+    };
+
+    let body = io::Cursor::new(head).chain(body);
+    if gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(body))))
+    } else {
+        Ok(Box::new(BufReader::new(body)))
+    }
+}
+
+struct ObjectLines {
+    reader: Box<dyn BufRead + Send>,
+}
+
+impl Stream for ObjectLines {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, io::Error> {
+        let reader = &mut self.reader;
+        let mut line = Vec::new();
+        let read = try_ready!(
+            tokio_threadpool::blocking(|| reader.read_until(b'\n', &mut line))
+                .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+        )?;
+        if read == 0 {
+            return Ok(Async::Ready(None));
+        }
+
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Ok(Async::Ready(Some(line.into())))
+    }
+}
+
+/// Each line of an object becomes an event, with the bucket and key of the
+/// object it came from.
+fn line_to_event(line: Bytes, bucket: &str, key: &str) -> Event {
+    let mut event = Event::from(line);
+    let log = event.as_mut_log();
+    log.insert_implicit(BUCKET.clone(), bucket.into());
+    log.insert_implicit(OBJECT.clone(), key.into());
+    event
+}
+
+#[derive(Debug, PartialEq)]
+struct S3Object {
+    bucket: String,
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct Notification {
+    #[serde(rename = "Records", default)]
+    records: Vec<NotificationRecord>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NotificationRecord {
+    event_name: String,
+    s3: NotificationEntity,
+}
+
+#[derive(Deserialize)]
+struct NotificationEntity {
+    bucket: NotificationBucket,
+    object: NotificationObject,
+}
+
+#[derive(Deserialize)]
+struct NotificationBucket {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct NotificationObject {
+    key: String,
+}
+
+/// The objects created, according to an S3 event notification. Other
+/// events, and the test event sent when notifications are set up, have none.
+fn parse_notification(body: &str) -> Result<Vec<S3Object>, serde_json::Error> {
+    let notification: Notification = serde_json::from_str(body)?;
+    Ok(notification
+        .records
+        .into_iter()
+        .filter(|record| record.event_name.starts_with("ObjectCreated:"))
+        .map(|record| S3Object {
+            bucket: record.s3.bucket.name,
+            // Keys are URL encoded, with spaces as `+`.
+            key: percent_decode(record.s3.object.key.replace('+', " ").as_bytes())
+                .decode_utf8_lossy()
+                .into_owned(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{line_to_event, object_lines, parse_notification, Compression, S3Object};
+    use crate::{event, test_util::block_on};
+    use flate2::{write::GzEncoder, Compression as Level};
+    use futures::Stream;
+    use std::io::{self, Write};
+    use string_cache::DefaultAtom as Atom;
+
+    #[test]
+    fn aws_s3_parses_notifications() {
+        let body = r#"{"Records":[
+            {"eventVersion":"2.1","eventSource":"aws:s3","awsRegion":"us-east-1",
+             "eventName":"ObjectCreated:Put",
+             "s3":{"bucket":{"name":"logs","arn":"arn:aws:s3:::logs"},
+                   "object":{"key":"AWSLogs/elb/2019%3A10%3A02+log.gz","size":1024}}},
+            {"eventVersion":"2.1","eventSource":"aws:s3","awsRegion":"us-east-1",
+             "eventName":"ObjectRemoved:Delete",
+             "s3":{"bucket":{"name":"logs"},"object":{"key":"old.log"}}}
+        ]}"#;
+        assert_eq!(
+            parse_notification(body).unwrap(),
+            vec![S3Object {
+                bucket: "logs".into(),
+                key: "AWSLogs/elb/2019:10:02 log.gz".into(),
+            }]
+        );
+
+        let test_event = r#"{"Service":"Amazon S3","Event":"s3:TestEvent","Bucket":"logs"}"#;
+        assert!(parse_notification(test_event).unwrap().is_empty());
+
+        assert!(parse_notification("not json").is_err());
+    }
+
+    fn lines(body: &[u8], compression: Compression) -> Result<Vec<String>, io::Error> {
+        let lines = object_lines(io::Cursor::new(body.to_vec()), compression).collect();
+        let lines = block_on(lines)?;
+        Ok(lines
+            .iter()
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect())
+    }
+
+    #[test]
+    fn aws_s3_splits_objects_into_lines() {
+        let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+        encoder.write_all(b"first\r\nsecond\n\nthird").unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        for compression in &[Compression::Auto, Compression::Gzip] {
+            assert_eq!(
+                lines(&gzipped, *compression).unwrap(),
+                vec!["first", "second", "", "third"]
+            );
+        }
+
+        assert_eq!(
+            lines(b"plain\nlines\n", Compression::Auto).unwrap(),
+            vec!["plain", "lines"]
+        );
+        assert_eq!(lines(b"p", Compression::Auto).unwrap(), vec!["p"]);
+        assert!(lines(b"", Compression::Auto).unwrap().is_empty());
+        assert_eq!(
+            lines(&gzipped, Compression::None).unwrap(),
+            vec![String::from_utf8_lossy(&gzipped).into_owned()]
+        );
+        assert!(lines(b"plain", Compression::Gzip).is_err());
+    }
+
+    #[test]
+    fn aws_s3_reads_concatenated_gzip_members() {
+        let mut gzipped = Vec::new();
+        for member in &["first\n", "second\n"] {
+            let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+            encoder.write_all(member.as_bytes()).unwrap();
+            gzipped.extend(encoder.finish().unwrap());
+        }
+
+        assert_eq!(
+            lines(&gzipped, Compression::Auto).unwrap(),
+            vec!["first", "second"]
+        );
+    }
+
+    #[test]
+    fn aws_s3_adds_object_fields() {
+        let event = line_to_event("line".into(), "logs", "a.log.gz");
+        let log = event.as_log();
+        assert_eq!(log[&event::MESSAGE], "line".into());
+        assert_eq!(log[&Atom::from("bucket")], "logs".into());
+        assert_eq!(log[&Atom::from("object")], "a.log.gz".into());
+    }
+}
+
+#[cfg(feature = "sqs-integration-tests")]
+#[cfg(test)]
+mod integration_tests {
+    use super::{AwsS3Config, Compression, SqsConfig};
+    use crate::{
+        event,
+        region::RegionOrEndpoint,
+        sinks::aws_s3::S3Sink,
+        test_util::{collect_n, random_lines, random_string},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use flate2::{write::GzEncoder, Compression as Level};
+    use futures::{sync::mpsc, Future};
+    use rusoto_core::Region;
+    use rusoto_s3::{CreateBucketError, CreateBucketRequest, PutObjectRequest, S3};
+    use rusoto_sqs::{
+        CreateQueueRequest, ReceiveMessageRequest, SendMessageRequest, Sqs, SqsClient,
+    };
+    use std::{io::Write, thread, time::Duration};
+    use string_cache::DefaultAtom as Atom;
+
+    const BUCKET: &str = "source-tests";
+    const S3_ENDPOINT: &str = "http://localhost:9000";
+    const SQS_ENDPOINT: &str = "http://localhost:4576";
+
+    #[test]
+    fn aws_s3_ingests_notified_objects() {
+        let s3 = S3Sink::create_client(Region::Custom {
+            name: "minio".into(),
+            endpoint: S3_ENDPOINT.into(),
+        });
+        let sqs = SqsClient::new(Region::Custom {
+            name: "localstack".into(),
+            endpoint: SQS_ENDPOINT.into(),
+        });
+
+        match s3
+            .create_bucket(CreateBucketRequest {
+                bucket: BUCKET.into(),
+                ..Default::default()
+            })
+            .sync()
+        {
+            Ok(_) | Err(CreateBucketError::BucketAlreadyOwnedByYou(_)) => {}
+            Err(error) => panic!("Couldn't create bucket: {}", error),
+        }
+        let queue_url = sqs
+            .create_queue(CreateQueueRequest {
+                queue_name: random_string(10),
+                ..Default::default()
+            })
+            .sync()
+            .unwrap()
+            .queue_url
+            .unwrap();
+
+        let lines = random_lines(100).take(10).collect::<Vec<_>>();
+        let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+        encoder.write_all(lines.join("\n").as_bytes()).unwrap();
+        let key = format!("{}/app logs.gz", random_string(10));
+        s3.put_object(PutObjectRequest {
+            bucket: BUCKET.into(),
+            key: key.clone(),
+            body: Some(encoder.finish().unwrap().into()),
+            ..Default::default()
+        })
+        .sync()
+        .unwrap();
+
+        let notification = format!(
+            r#"{{"Records":[{{"eventName":"ObjectCreated:Put","s3":{{"bucket":{{"name":"{}"}},"object":{{"key":"{}"}}}}}}]}}"#,
+            BUCKET,
+            key.replace(' ', "+")
+        );
+        sqs.send_message(SendMessageRequest {
+            queue_url: queue_url.clone(),
+            message_body: notification,
+            ..Default::default()
+        })
+        .sync()
+        .unwrap();
+
+        let config = AwsS3Config {
+            region: RegionOrEndpoint::with_endpoint(S3_ENDPOINT.into()),
+            compression: Compression::Auto,
+            sqs: SqsConfig {
+                queue_url: queue_url.clone(),
+                region: RegionOrEndpoint::with_endpoint(SQS_ENDPOINT.into()),
+                poll_secs: 1,
+                visibility_timeout_secs: 1,
+            },
+        };
+        let (tx, rx) = mpsc::channel(100);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        let events = rt.block_on(collect_n(rx, 10)).unwrap();
+        let messages = events
+            .iter()
+            .map(|event| event.as_log()[&event::MESSAGE].to_string_lossy())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, lines);
+        assert_eq!(
+            events[0].as_log()[&Atom::from("object")],
+            key.as_str().into()
+        );
+
+        // Give the source time to delete the message, then stop it and wait
+        // for the message to have become visible again had it not been.
+        thread::sleep(Duration::from_secs(1));
+        rt.shutdown_now().wait().unwrap();
+        thread::sleep(Duration::from_secs(2));
+        let remaining = sqs
+            .receive_message(ReceiveMessageRequest {
+                queue_url,
+                ..Default::default()
+            })
+            .sync()
+            .unwrap()
+            .messages
+            .unwrap_or_default();
+        assert!(remaining.is_empty());
+    }
+}
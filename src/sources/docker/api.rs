@@ -0,0 +1,215 @@
+//! A client of the Docker Engine API, reached over its Unix socket.
+
+use super::logs::LogDecoder;
+use bytes::{Bytes, BytesMut};
+use futures::{future, stream, Future, Stream};
+use hyper::{
+    client::connect::{Connect, Connected, Destination},
+    Body, Client, Request, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use snafu::{ResultExt, Snafu};
+use std::{collections::BTreeMap, io, path::PathBuf};
+use tokio::codec::{Decoder, LinesCodec};
+use tokio_uds::UnixStream;
+
+/// The container events that start and stop logs.
+const EVENT_FILTERS: &str = r#"{"type":["container"],"event":["start","die"]}"#;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("request to the Docker API failed: {}", source))]
+    Http { source: hyper::Error },
+    #[snafu(display("Docker API responded with {}: {}", status, message))]
+    Response { status: StatusCode, message: String },
+    #[snafu(display("invalid response from the Docker API: {}", source))]
+    InvalidJson { source: serde_json::Error },
+    #[snafu(display("invalid stream from the Docker API: {}", source))]
+    InvalidStream { source: io::Error },
+}
+
+/// Connects every request to the Docker socket, whatever its host.
+#[derive(Clone)]
+struct UnixConnector {
+    path: PathBuf,
+}
+
+impl Connect for UnixConnector {
+    type Transport = UnixStream;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = (UnixStream, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, _destination: Destination) -> Self::Future {
+        Box::new(UnixStream::connect(&self.path).map(|stream| (stream, Connected::new())))
+    }
+}
+
+#[derive(Clone)]
+pub struct Docker {
+    client: Client<UnixConnector, Body>,
+}
+
+/// What the source needs to know about a container.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub labels: BTreeMap<String, String>,
+    pub tty: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerSummary {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInspect {
+    id: String,
+    name: String,
+    config: ContainerInspectConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInspectConfig {
+    image: String,
+    labels: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    tty: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContainerEvent {
+    #[serde(rename = "Action")]
+    pub action: String,
+    #[serde(rename = "Actor")]
+    pub actor: Actor,
+    pub time: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Actor {
+    #[serde(rename = "ID")]
+    pub id: String,
+}
+
+impl Docker {
+    pub fn new(socket_path: PathBuf) -> Self {
+        let client = Client::builder().build(UnixConnector { path: socket_path });
+        Self { client }
+    }
+
+    /// The IDs of the running containers.
+    pub fn list_containers(&self) -> impl Future<Item = Vec<String>, Error = Error> {
+        self.get_json("/containers/json".into())
+            .map(|containers: Vec<ContainerSummary>| {
+                containers
+                    .into_iter()
+                    .map(|container| container.id)
+                    .collect()
+            })
+    }
+
+    pub fn inspect(&self, id: &str) -> impl Future<Item = Container, Error = Error> {
+        self.get_json(format!("/containers/{}/json", id))
+            .map(|container: ContainerInspect| Container {
+                id: container.id,
+                // Names are given with a leading slash.
+                name: container.name.trim_start_matches('/').into(),
+                image: container.config.image,
+                labels: container.config.labels.unwrap_or_default(),
+                tty: container.config.tty,
+            })
+    }
+
+    /// Starts and stops of containers since `since`, as they happen.
+    pub fn events(&self, since: i64) -> impl Stream<Item = ContainerEvent, Error = Error> {
+        let filters =
+            url::form_urlencoded::byte_serialize(EVENT_FILTERS.as_bytes()).collect::<String>();
+        self.get(format!("/events?since={}&filters={}", since, filters))
+            .map(|body| {
+                decode(body, LinesCodec::new())
+                    .and_then(|line| serde_json::from_str(&line).context(InvalidJson))
+            })
+            .flatten_stream()
+    }
+
+    /// The entries logged by a container since `since`, followed as they
+    /// are written.
+    pub fn logs(
+        &self,
+        id: &str,
+        tty: bool,
+        since: i64,
+    ) -> impl Stream<Item = (&'static str, Bytes), Error = Error> {
+        let path = format!(
+            "/containers/{}/logs?follow=1&stdout=1&stderr=1&timestamps=1&since={}",
+            id, since
+        );
+        self.get(path)
+            .map(move |body| decode(body, LogDecoder::new(tty)))
+            .flatten_stream()
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, path: String) -> impl Future<Item = T, Error = Error> {
+        self.get(path)
+            .and_then(|body| body.concat2().then(|body| body.context(Http)))
+            .and_then(|body| serde_json::from_slice(&body).context(InvalidJson))
+    }
+
+    fn get(&self, path: String) -> impl Future<Item = Body, Error = Error> {
+        // The host is ignored by the connector.
+        let request = Request::get(format!("http://docker{}", path).as_str())
+            .body(Body::empty())
+            .expect("invalid Docker API request");
+
+        self.client
+            .request(request)
+            .then(|result| result.context(Http))
+            .and_then(|response| {
+                let status = response.status();
+                if status.is_success() {
+                    return future::Either::A(future::ok(response.into_body()));
+                }
+
+                future::Either::B(response.into_body().concat2().then(
+                    move |body| -> Result<Body, Error> {
+                        let body = body.context(Http)?;
+                        Err(Error::Response {
+                            status,
+                            message: String::from_utf8_lossy(&body).trim().into(),
+                        })
+                    },
+                ))
+            })
+    }
+}
+
+/// Split a response body into frames as its chunks arrive.
+fn decode<D>(body: Body, mut decoder: D) -> impl Stream<Item = D::Item, Error = Error>
+where
+    D: Decoder<Error = io::Error>,
+{
+    let mut buffer = BytesMut::new();
+    body.then(|chunk| chunk.context(Http))
+        .map(move |chunk| {
+            buffer.extend_from_slice(&chunk);
+            let mut frames = Vec::new();
+            loop {
+                match decoder.decode(&mut buffer).context(InvalidStream) {
+                    Ok(Some(frame)) => frames.push(Ok(frame)),
+                    Ok(None) => break,
+                    Err(error) => {
+                        frames.push(Err(error));
+                        break;
+                    }
+                }
+            }
+            stream::iter_result(frames)
+        })
+        .flatten()
+}
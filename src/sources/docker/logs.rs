@@ -0,0 +1,181 @@
+//! The framing of container logs, as streamed by `/containers/{id}/logs`.
+
+use crate::sources::kubernetes_logs::parser::ContainerLine;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::io;
+use tokio::codec::Decoder;
+
+/// The length of the header framing every write of a multiplexed stream.
+const HEADER_LENGTH: usize = 8;
+
+#[derive(Debug, Snafu)]
+pub enum EntryError {
+    #[snafu(display("log entry has no timestamp"))]
+    MissingTimestamp,
+    #[snafu(display("invalid timestamp {:?}: {}", timestamp, source))]
+    InvalidTimestamp {
+        timestamp: String,
+        source: chrono::ParseError,
+    },
+}
+
+/// Splits the logs of a container into entries, along with the stream they
+/// were written to.
+///
+/// Without a TTY, stdout and stderr are multiplexed, with every entry framed
+/// by a header holding its stream (1 for stdout, 2 for stderr), three zero
+/// bytes and its big-endian length. With a TTY, there is a single raw stream
+/// of newline-terminated entries.
+pub struct LogDecoder {
+    tty: bool,
+}
+
+impl LogDecoder {
+    pub fn new(tty: bool) -> Self {
+        Self { tty }
+    }
+}
+
+impl Decoder for LogDecoder {
+    type Item = (&'static str, Bytes);
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, io::Error> {
+        if self.tty {
+            return Ok(decode_raw(src));
+        }
+
+        let (stream, length) = match src.get(..HEADER_LENGTH) {
+            Some(header) => {
+                let stream = match header[0] {
+                    1 => "stdout",
+                    2 => "stderr",
+                    other => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("unknown stream {}", other),
+                        ))
+                    }
+                };
+                let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+                (stream, length as usize)
+            }
+            None => return Ok(None),
+        };
+
+        if src.len() < HEADER_LENGTH + length {
+            src.reserve(HEADER_LENGTH + length - src.len());
+            return Ok(None);
+        }
+        src.advance(HEADER_LENGTH);
+        Ok(Some((stream, src.split_to(length).freeze())))
+    }
+}
+
+fn decode_raw(src: &mut BytesMut) -> Option<(&'static str, Bytes)> {
+    let newline = src.iter().position(|&b| b == b'\n')?;
+    let mut entry = src.split_to(newline + 1);
+    // Terminals end lines with a carriage return as well.
+    if entry.ends_with(b"\r\n") {
+        let length = entry.len();
+        entry[length - 2] = b'\n';
+        entry.truncate(length - 1);
+    }
+    Some(("stdout", entry.freeze()))
+}
+
+/// Parse an entry of the form `<RFC 3339 timestamp> <message>`, which is only
+/// part of a line when it doesn't end with a newline, as Docker splits long
+/// lines into 16KiB entries.
+pub fn parse_entry(stream: &str, mut entry: Bytes) -> Result<ContainerLine, EntryError> {
+    let space = entry
+        .iter()
+        .position(|&b| b == b' ')
+        .context(MissingTimestamp)?;
+    let timestamp = String::from_utf8_lossy(&entry[..space]).into_owned();
+    let timestamp = DateTime::parse_from_rfc3339(&timestamp)
+        .with_context(|| InvalidTimestamp {
+            timestamp: timestamp.clone(),
+        })?
+        .with_timezone(&Utc);
+
+    entry.advance(space + 1);
+    let partial = !entry.ends_with(b"\n");
+    if !partial {
+        entry.truncate(entry.len() - 1);
+    }
+
+    Ok(ContainerLine {
+        message: entry,
+        timestamp,
+        stream: stream.into(),
+        partial,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_entry, LogDecoder};
+    use bytes::BytesMut;
+    use chrono::{TimeZone, Utc};
+    use tokio::codec::Decoder;
+
+    fn frame(stream: u8, payload: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn docker_decodes_multiplexed_logs() {
+        let mut data = frame(1, "2019-06-25T18:40:12Z out\n");
+        data.extend(frame(2, "2019-06-25T18:40:13Z err\n"));
+        data.extend(frame(3, "2019-06-25T18:40:14Z in\n"));
+
+        let mut decoder = LogDecoder::new(false);
+        let mut src = BytesMut::from(&data[..20]);
+        assert_eq!(decoder.decode(&mut src).unwrap(), None);
+        src.extend_from_slice(&data[20..]);
+
+        let (stream, entry) = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(stream, "stdout");
+        assert_eq!(entry, "2019-06-25T18:40:12Z out\n");
+        let (stream, entry) = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(stream, "stderr");
+        assert_eq!(entry, "2019-06-25T18:40:13Z err\n");
+        assert!(decoder.decode(&mut src).is_err());
+
+        let mut decoder = LogDecoder::new(true);
+        let mut src = BytesMut::from(&b"2019-06-25T18:40:12Z tty\r\n2019-06-25T18:40:13Z"[..]);
+        let (stream, entry) = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(stream, "stdout");
+        assert_eq!(entry, "2019-06-25T18:40:12Z tty\n");
+        assert_eq!(decoder.decode(&mut src).unwrap(), None);
+    }
+
+    #[test]
+    fn docker_parses_log_entries() {
+        let line = parse_entry(
+            "stdout",
+            "2019-06-25T18:40:12.178052862Z hello world\n".into(),
+        )
+        .unwrap();
+        assert_eq!(line.message, "hello world");
+        assert_eq!(
+            line.timestamp,
+            Utc.ymd(2019, 6, 25).and_hms_nano(18, 40, 12, 178052862)
+        );
+        assert_eq!(line.stream, "stdout");
+        assert!(!line.partial);
+
+        let line = parse_entry("stderr", "2019-06-25T18:40:12Z hello ".into()).unwrap();
+        assert_eq!(line.message, "hello ");
+        assert!(line.partial);
+
+        assert!(parse_entry("stdout", "hello\n".into()).is_err());
+        assert!(parse_entry("stdout", "yesterday hello\n".into()).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use crate::{
+    event::{self, Event},
+    sources::kubernetes_logs::parser::{ContainerLine, Reassembler},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use api::{Container, ContainerEvent, Docker};
+use chrono::Utc;
+use futures::{
+    future::{self, Loop},
+    sync::mpsc,
+    Future, Sink, Stream,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use stream_cancel::{StreamExt, Tripwire};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::Delay;
+
+mod api;
+mod logs;
+
+lazy_static! {
+    static ref STREAM: Atom = Atom::from("stream");
+    static ref CONTAINER_ID: Atom = Atom::from("container_id");
+    static ref CONTAINER_NAME: Atom = Atom::from("container_name");
+    static ref IMAGE: Atom = Atom::from("image");
+}
+
+/// How long to wait before watching containers again once the event stream
+/// is lost.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Follows the logs of containers through the Docker Engine API.
+///
+/// Only containers matching every non-empty `include_*` option, and none of
+/// the `exclude_*` ones, are followed.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DockerConfig {
+    #[serde(default = "default_socket_path")]
+    pub socket_path: PathBuf,
+    /// Container names or ID prefixes.
+    #[serde(default)]
+    pub include_containers: Vec<String>,
+    #[serde(default)]
+    pub exclude_containers: Vec<String>,
+    /// Images, with or without their tag.
+    #[serde(default)]
+    pub include_images: Vec<String>,
+    #[serde(default)]
+    pub exclude_images: Vec<String>,
+    /// Labels, as `key` or `key=value`.
+    #[serde(default)]
+    pub include_labels: Vec<String>,
+    #[serde(default)]
+    pub exclude_labels: Vec<String>,
+}
+
+fn default_socket_path() -> PathBuf {
+    "/var/run/docker.sock".into()
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        Self {
+            socket_path: default_socket_path(),
+            include_containers: Vec::new(),
+            exclude_containers: Vec::new(),
+            include_images: Vec::new(),
+            exclude_images: Vec::new(),
+            include_labels: Vec::new(),
+            exclude_labels: Vec::new(),
+        }
+    }
+}
+
+impl DockerConfig {
+    fn includes(&self, container: &Container) -> bool {
+        let any = |patterns: &[String], matches: fn(&Container, &str) -> bool| {
+            patterns.iter().any(|pattern| matches(container, pattern))
+        };
+        let included = |patterns: &[String], matches: fn(&Container, &str) -> bool| {
+            patterns.is_empty() || any(patterns, matches)
+        };
+
+        included(&self.include_containers, matches_container)
+            && included(&self.include_images, matches_image)
+            && included(&self.include_labels, matches_label)
+            && !any(&self.exclude_containers, matches_container)
+            && !any(&self.exclude_images, matches_image)
+            && !any(&self.exclude_labels, matches_label)
+    }
+}
+
+fn matches_container(container: &Container, pattern: &str) -> bool {
+    !pattern.is_empty() && (container.name == pattern || container.id.starts_with(pattern))
+}
+
+fn matches_image(container: &Container, pattern: &str) -> bool {
+    let image = container.image.as_str();
+    // Tags follow the last colon, unless it belongs to a registry's port.
+    let untagged = match image.rfind(':') {
+        Some(colon) if !image[colon..].contains('/') => &image[..colon],
+        _ => image,
+    };
+    image == pattern || untagged == pattern
+}
+
+fn matches_label(container: &Container, pattern: &str) -> bool {
+    match pattern.find('=') {
+        Some(equals) => {
+            container.labels.get(&pattern[..equals]).map(String::as_str)
+                == Some(&pattern[equals + 1..])
+        }
+        None => container.labels.contains_key(pattern),
+    }
+}
+
+#[typetag::serde(name = "docker")]
+impl SourceConfig for DockerConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        Ok(docker_source(self.clone(), out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+struct Follower {
+    docker: Docker,
+    config: DockerConfig,
+    /// The IDs of the containers whose logs are being followed.
+    followed: Mutex<HashSet<String>>,
+    tripwire: Tripwire,
+    out: mpsc::Sender<Event>,
+}
+
+fn docker_source(config: DockerConfig, out: mpsc::Sender<Event>) -> super::Source {
+    let (trigger, tripwire) = Tripwire::new();
+    let follower = Arc::new(Follower {
+        docker: Docker::new(config.socket_path.clone()),
+        config,
+        followed: Mutex::default(),
+        tripwire,
+        out,
+    });
+
+    Box::new(
+        future::loop_fn((), move |()| {
+            let since = Utc::now().timestamp();
+            let listed = Arc::clone(&follower);
+            let watched = Arc::clone(&follower);
+
+            follower
+                .docker
+                .list_containers()
+                .map(move |ids| {
+                    for id in ids {
+                        follow(&listed, id, since);
+                    }
+                })
+                // Replaying the events since listing catches the containers
+                // started in between.
+                .and_then(move |()| {
+                    watched.docker.events(since).for_each(move |event| {
+                        handle_event(&watched, event);
+                        Ok(())
+                    })
+                })
+                .then(|result| {
+                    match result {
+                        Ok(()) => warn!("Docker event stream ended."),
+                        Err(error) => {
+                            error!(message = "Unable to watch Docker containers.", %error)
+                        }
+                    }
+                    Delay::new(Instant::now() + RETRY_DELAY)
+                        .then(|_| Ok::<_, ()>(Loop::<(), ()>::Continue(())))
+                })
+        })
+        // Dropping the trigger stops following logs.
+        .inspect(|_| drop(trigger)),
+    )
+}
+
+fn handle_event(follower: &Arc<Follower>, event: ContainerEvent) {
+    match event.action.as_str() {
+        "start" => follow(follower, event.actor.id, event.time),
+        action => debug!(
+            message = "Container event.",
+            action,
+            container = event.actor.id.as_str()
+        ),
+    }
+}
+
+/// Follow the logs of container `id` written since `since`, unless they are
+/// already followed or the container isn't included.
+fn follow(follower: &Arc<Follower>, id: String, since: i64) {
+    if !follower.followed.lock().unwrap().insert(id.clone()) {
+        return;
+    }
+
+    let inner = Arc::clone(follower);
+    let done = Arc::clone(follower);
+    let task = follower
+        .docker
+        .inspect(&id)
+        .map_err(|error| error!(message = "Unable to inspect container.", %error))
+        .and_then(move |container| -> Box<dyn Future<Item = (), Error = ()> + Send> {
+            if !inner.config.includes(&container) {
+                debug!(message = "Skipping container.", container = container.name.as_str());
+                return Box::new(future::ok(()));
+            }
+            info!(message = "Following container logs.", container = container.name.as_str());
+
+            let mut reassembler = Reassembler::default();
+            let logs = inner
+                .docker
+                .logs(&container.id, container.tty, since)
+                .map_err(|error| error!(message = "Unable to read container logs.", %error))
+                .take_until(inner.tripwire.clone())
+                .filter_map(move |(stream, entry)| {
+                    let line = logs::parse_entry(stream, entry)
+                        .map_err(|error| warn!(message = "Unable to parse container log entry.", %error))
+                        .ok()?;
+                    let line = reassembler.push(stream, line)?;
+                    Some(line_to_event(line, &container))
+                })
+                .forward(inner.out.clone().sink_map_err(|e| error!(%e)))
+                .map(|_| ());
+            Box::new(logs)
+        })
+        // The logs end when the container stops, and are followed again
+        // when it restarts.
+        .then(move |_| {
+            done.followed.lock().unwrap().remove(&id);
+            Ok(())
+        });
+
+    tokio::spawn(task);
+}
+
+fn line_to_event(line: ContainerLine, container: &Container) -> Event {
+    let mut event = Event::new_empty_log();
+    let log = event.as_mut_log();
+    log.insert_implicit(event::MESSAGE.clone(), line.message.into());
+    log.insert_implicit(event::TIMESTAMP.clone(), line.timestamp.into());
+    log.insert_explicit(STREAM.clone(), line.stream.into());
+    log.insert_explicit(CONTAINER_ID.clone(), container.id.clone().into());
+    log.insert_explicit(CONTAINER_NAME.clone(), container.name.clone().into());
+    log.insert_explicit(IMAGE.clone(), container.image.clone().into());
+    for (key, value) in &container.labels {
+        log.insert_explicit(format!("labels.{}", key).into(), value.clone().into());
+    }
+    event
+}
+
+#[cfg(test)]
+mod test {
+    use super::{api::Container, DockerConfig};
+    use crate::{
+        event,
+        test_util::collect_n,
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use chrono::Utc;
+    use futures::{future, stream, sync::mpsc, Future, Stream};
+    use hyper::{service::service_fn_ok, Body, Request, Response, Server, StatusCode};
+    use std::{collections::BTreeMap, io};
+    use string_cache::DefaultAtom as Atom;
+    use tempfile::tempdir;
+    use tokio_uds::UnixListener;
+
+    fn container(name: &str, image: &str, labels: &[(&str, &str)]) -> Container {
+        Container {
+            id: "4a8cfa1f3b2c".into(),
+            name: name.into(),
+            image: image.into(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+            tty: false,
+        }
+    }
+
+    #[test]
+    fn docker_filters_containers() {
+        let web = container("web", "registry:5000/nginx:1.17", &[("app", "web")]);
+
+        assert!(DockerConfig::default().includes(&web));
+
+        let mut config = DockerConfig::default();
+        config.include_containers = vec!["db".into(), "4a8c".into()];
+        assert!(config.includes(&web));
+        config.include_containers = vec!["db".into(), "".into()];
+        assert!(!config.includes(&web));
+
+        let mut config = DockerConfig::default();
+        config.include_images = vec!["registry:5000/nginx".into()];
+        assert!(config.includes(&web));
+        config.include_images = vec!["registry".into()];
+        assert!(!config.includes(&web));
+        config.include_images = vec!["registry:5000/nginx:1.17".into()];
+        config.exclude_containers = vec!["web".into()];
+        assert!(!config.includes(&web));
+
+        let mut config = DockerConfig::default();
+        config.include_labels = vec!["app".into()];
+        assert!(config.includes(&web));
+        config.include_labels = vec!["app=db".into()];
+        assert!(!config.includes(&web));
+        config.include_labels = vec!["app=web".into()];
+        config.exclude_labels = vec!["app".into()];
+        assert!(!config.includes(&web));
+    }
+
+    fn frame(stream: u8, payload: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    }
+
+    fn inspect(id: &str, name: &str, image: &str, tty: bool) -> Body {
+        format!(
+            r#"{{"Id":"{}","Name":"/{}","Config":{{"Image":"{}","Labels":{{"app":"{}"}},"Tty":{}}}}}"#,
+            id, name, image, name, tty
+        )
+        .into()
+    }
+
+    /// Answers like the Docker Engine API, with a running `web` and `db`
+    /// container, and a `worker` container starting after them.
+    fn respond(request: Request<Body>) -> Response<Body> {
+        let body = match request.uri().path() {
+            "/containers/json" => r#"[{"Id":"aaaa"},{"Id":"bbbb"}]"#.into(),
+            "/containers/aaaa/json" => inspect("aaaa", "web", "nginx:1.17", false),
+            "/containers/bbbb/json" => inspect("bbbb", "db", "postgres", false),
+            "/containers/cccc/json" => inspect("cccc", "worker", "worker", true),
+            "/containers/aaaa/logs" => {
+                let mut logs = frame(1, "2019-06-25T18:40:12Z hello\n");
+                logs.extend(frame(2, "2019-06-25T18:40:13Z big "));
+                logs.extend(frame(2, "2019-06-25T18:40:13Z world\n"));
+                logs.into()
+            }
+            "/containers/cccc/logs" => "2019-06-25T18:40:14Z tty line\r\n".into(),
+            "/events" => {
+                let event = format!(
+                    r#"{{"Type":"container","Action":"start","Actor":{{"ID":"cccc"}},"time":{}}}"#,
+                    Utc::now().timestamp()
+                );
+                // The event stream stays open.
+                let events = stream::iter_ok::<_, io::Error>(vec![event + "\n"])
+                    .chain(future::empty().into_stream());
+                Body::wrap_stream(events)
+            }
+            _ => {
+                let mut response = Response::new(r#"{"message":"not found"}"#.into());
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return response;
+            }
+        };
+        Response::new(body)
+    }
+
+    #[test]
+    fn docker_follows_container_logs() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("docker.sock");
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let server = Server::builder(listener.incoming())
+            .serve(|| service_fn_ok(respond))
+            .map_err(|error| error!(message = "server error.", %error));
+        rt.spawn(server);
+
+        let (tx, rx) = mpsc::channel(10);
+        let config = DockerConfig {
+            socket_path,
+            exclude_images: vec!["postgres".into()],
+            ..DockerConfig::default()
+        };
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        rt.spawn(source);
+
+        let mut events = rt.block_on(collect_n(rx, 3)).ok().unwrap();
+        events.sort_by_key(|event| event.as_log()[&event::TIMESTAMP].to_string_lossy());
+
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "hello".into());
+        assert_eq!(log[&Atom::from("stream")], "stdout".into());
+        assert_eq!(log[&Atom::from("container_id")], "aaaa".into());
+        assert_eq!(log[&Atom::from("container_name")], "web".into());
+        assert_eq!(log[&Atom::from("image")], "nginx:1.17".into());
+        assert_eq!(log[&Atom::from("labels.app")], "web".into());
+
+        let log = events[1].as_log();
+        assert_eq!(log[&event::MESSAGE], "big world".into());
+        assert_eq!(log[&Atom::from("stream")], "stderr".into());
+
+        let log = events[2].as_log();
+        assert_eq!(log[&event::MESSAGE], "tty line".into());
+        assert_eq!(log[&Atom::from("stream")], "stdout".into());
+        assert_eq!(log[&Atom::from("container_name")], "worker".into());
+    }
+}
@@ -1,18 +1,18 @@
+use super::util::{DecodingConfig, LineAgg, MultilineConfig};
 use crate::{
     event::{self, Event},
     topology::config::{DataType, GlobalOptions, SourceConfig},
 };
-use bytes::{Bytes, BytesMut};
-use file_source::{FileServer, Fingerprinter};
-use futures::{future, sync::mpsc, Async, Future, Poll, Sink, Stream};
+use bytes::Bytes;
+use file_source::{FileServer, Fingerprinter, NotificationMode};
+use futures::{future, stream, sync::mpsc, Future, Sink, Stream};
 use regex::bytes::Regex;
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
-use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::thread;
 use std::time::{Duration, SystemTime};
-use tokio::timer::DelayQueue;
+use string_cache::DefaultAtom as Atom;
 use tracing::dispatcher;
 
 #[derive(Debug, Snafu)]
@@ -60,8 +60,27 @@ pub struct FileConfig {
     pub fingerprinting: FingerprintingConfig,
     pub message_start_indicator: Option<String>,
     pub multi_line_timeout: u64, // millis
+    pub multiline: Option<MultilineConfig>,
     pub max_read_bytes: usize,
     pub oldest_first: bool,
+    pub notification_mode: NotificationModeConfig,
+    pub decoding: DecodingConfig,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationModeConfig {
+    Poll,
+    Inotify,
+}
+
+impl From<NotificationModeConfig> for NotificationMode {
+    fn from(config: NotificationModeConfig) -> NotificationMode {
+        match config {
+            NotificationModeConfig::Poll => NotificationMode::Poll,
+            NotificationModeConfig::Inotify => NotificationMode::Inotify,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -112,8 +131,11 @@ impl Default for FileConfig {
             glob_minimum_cooldown: 1000, // millis
             message_start_indicator: None,
             multi_line_timeout: 1000, // millis
+            multiline: None,
             max_read_bytes: 2048,
             oldest_first: false,
+            notification_mode: NotificationModeConfig::Poll,
+            decoding: DecodingConfig::default(),
         }
     }
 }
@@ -136,6 +158,11 @@ impl SourceConfig for FileConfig {
             Regex::new(indicator).with_context(|| InvalidMessageStartIndicator { indicator })?;
         }
 
+        if let Some(ref multiline) = self.multiline {
+            multiline.build()?;
+        }
+        self.decoding.build(self.max_line_bytes)?;
+
         Ok(file_source(self, data_dir, out))
     }
 
@@ -167,16 +194,22 @@ pub fn file_source(
         glob_minimum_cooldown: glob_minimum_cooldown,
         fingerprinter: config.fingerprinting.clone().into(),
         oldest_first: config.oldest_first,
+        notification_mode: config.notification_mode.into(),
     };
 
+    let decoding = config.decoding.build(config.max_line_bytes).unwrap(); // validated in build
     let file_key = config.file_key.clone();
     let host_key = config.host_key.clone().unwrap_or(event::HOST.to_string());
     let hostname = hostname::get_hostname();
 
     let include = config.include.clone();
     let exclude = config.exclude.clone();
-    let message_start_indicator = config.message_start_indicator.clone();
-    let multi_line_timeout = config.multi_line_timeout;
+    // The `multiline` options supersede the legacy `message_start_indicator`.
+    let multiline = config.multiline.clone().or_else(|| {
+        config.message_start_indicator.clone().map(|indicator| {
+            MultilineConfig::from_start_indicator(indicator, config.multi_line_timeout)
+        })
+    });
     Box::new(future::lazy(move || {
         info!(message = "Starting file server.", ?include, ?exclude);
 
@@ -184,11 +217,10 @@ pub fn file_source(
         let (tx, rx) = futures::sync::mpsc::channel(100);
 
         let messages: Box<dyn Stream<Item = (Bytes, String), Error = ()> + Send> =
-            if let Some(msi) = message_start_indicator {
+            if let Some(multiline) = multiline {
                 Box::new(LineAgg::new(
                     rx,
-                    Regex::new(&msi).unwrap(), // validated in build
-                    multi_line_timeout,
+                    multiline.build().unwrap(), // validated in build
                 ))
             } else {
                 Box::new(rx)
@@ -198,8 +230,14 @@ pub fn file_source(
             messages
                 .map(move |(msg, file): (Bytes, String)| {
                     trace!(message = "Received one event.", file = file.as_str());
-                    create_event(msg, file, &host_key, &hostname, &file_key)
+                    let events = decoding
+                        .decode_message(msg)
+                        .into_iter()
+                        .map(|event| create_event(event, &file, &host_key, &hostname, &file_key))
+                        .collect::<Vec<_>>();
+                    stream::iter_ok(events)
                 })
+                .flatten()
                 .forward(out.sink_map_err(|e| error!(%e)))
                 .map(|_| ()),
         );
@@ -221,115 +259,26 @@ pub fn file_source(
     }))
 }
 
-struct LineAgg<T> {
-    inner: T,
-    marker: Regex,
-    timeout: u64,
-    buffers: HashMap<String, BytesMut>,
-    draining: Option<Vec<(Bytes, String)>>,
-    timeouts: DelayQueue<String>,
-    expired: VecDeque<String>,
-}
-
-impl<T> LineAgg<T> {
-    fn new(inner: T, marker: Regex, timeout: u64) -> Self {
-        Self {
-            inner,
-            marker,
-            timeout,
-            draining: None,
-            buffers: HashMap::new(),
-            timeouts: DelayQueue::new(),
-            expired: VecDeque::new(),
-        }
-    }
-}
-
-impl<T: Stream<Item = (Bytes, String), Error = ()>> Stream for LineAgg<T> {
-    type Item = (Bytes, String);
-    type Error = ();
-
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        loop {
-            if let Some(to_drain) = &mut self.draining {
-                if let Some((data, key)) = to_drain.pop() {
-                    return Ok(Async::Ready(Some((data, key))));
-                } else {
-                    return Ok(Async::Ready(None));
-                }
-            }
-
-            // check for keys that have hit their timeout
-            while let Ok(Async::Ready(Some(expired_key))) = self.timeouts.poll() {
-                self.expired.push_back(expired_key.into_inner());
-            }
-
-            match self.inner.poll() {
-                Ok(Async::Ready(Some((line, src)))) => {
-                    // look for buffered content from same source
-                    if self.buffers.contains_key(&src) {
-                        if self.marker.is_match(line.as_ref()) {
-                            // buffer the incoming line and flush the existing data
-                            let buffered = self
-                                .buffers
-                                .insert(src.clone(), line.into())
-                                .expect("already asserted key is present");
-                            return Ok(Async::Ready(Some((buffered.freeze(), src))));
-                        } else {
-                            // append new line to the buffered data
-                            let buffered = self
-                                .buffers
-                                .get_mut(&src)
-                                .expect("already asserted key is present");
-                            buffered.extend_from_slice(b"\n");
-                            buffered.extend_from_slice(&line);
-                        }
-                    } else {
-                        // no existing data for this source so buffer it with timeout
-                        self.timeouts
-                            .insert(src.clone(), Duration::from_millis(self.timeout));
-                        self.buffers.insert(src, line.into());
-                    }
-                }
-                Ok(Async::Ready(None)) => {
-                    // start flushing all existing data, stop polling inner
-                    self.draining =
-                        Some(self.buffers.drain().map(|(k, v)| (v.into(), k)).collect());
-                }
-                Ok(Async::NotReady) => {
-                    if let Some(key) = self.expired.pop_front() {
-                        if let Some(buffered) = self.buffers.remove(&key) {
-                            return Ok(Async::Ready(Some((buffered.freeze(), key))));
-                        }
-                    }
-
-                    return Ok(Async::NotReady);
-                }
-                Err(()) => return Err(()),
-            };
-        }
-    }
-}
-
 fn create_event(
-    line: Bytes,
-    file: String,
+    mut event: Event,
+    file: &str,
     host_key: &String,
     hostname: &Option<String>,
     file_key: &Option<String>,
 ) -> Event {
-    let mut event = Event::from(line);
-
     if let Some(file_key) = &file_key {
         event
             .as_mut_log()
             .insert_implicit(file_key.clone().into(), file.into());
     }
 
+    // Codecs like syslog may already have found the host.
     if let Some(hostname) = &hostname {
-        event
-            .as_mut_log()
-            .insert_implicit(host_key.clone().into(), hostname.clone().into());
+        let host_key = Atom::from(host_key.as_str());
+        let log = event.as_mut_log();
+        if log.get(&host_key).is_none() {
+            log.insert_implicit(host_key, hostname.clone().into());
+        }
     }
 
     event
@@ -421,6 +370,13 @@ mod tests {
                 ignored_header_bytes: 512,
             }
         );
+        let config: FileConfig = toml::from_str(
+            r#"
+        notification_mode = "inotify"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.notification_mode, NotificationModeConfig::Inotify);
     }
 
     #[test]
@@ -452,7 +408,7 @@ mod tests {
         let hostname = Some("Some.Machine".to_string());
         let file_key = Some("file".to_string());
 
-        let event = create_event(line, file, &host_key, &hostname, &file_key);
+        let event = create_event(Event::from(line), &file, &host_key, &hostname, &file_key);
         let log = event.into_log();
 
         assert_eq!(log[&"file".into()], "some_file.rs".into());
@@ -661,6 +617,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn file_rotate_with_inotify() {
+        let n = 5;
+        let (tx, rx) = futures::sync::mpsc::channel(2 * n);
+        let (trigger, tripwire) = Tripwire::new();
+
+        let dir = tempdir().unwrap();
+        let config = file::FileConfig {
+            include: vec![dir.path().join("*.log")],
+            notification_mode: file::NotificationModeConfig::Inotify,
+            ..test_default_file_config(&dir)
+        };
+        let source = file::file_source(&config, config.data_dir.clone().unwrap(), tx);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.spawn(source.select(tripwire).map(|_| ()).map_err(|_| ()));
+
+        let path = dir.path().join("file.log");
+        let archive_path = dir.path().join("file.log.1");
+
+        sleep(); // The file must be discovered through notifications alone
+
+        let mut file = File::create(&path).unwrap();
+        for i in 0..n {
+            writeln!(&mut file, "prerot {}", i).unwrap();
+        }
+
+        sleep(); // The writes must be observed before rotating
+
+        fs::rename(&path, archive_path).unwrap();
+        let mut file = File::create(&path).unwrap();
+        for i in 0..n {
+            writeln!(&mut file, "postrot {}", i).unwrap();
+        }
+
+        sleep();
+
+        drop(trigger);
+        shutdown_on_idle(rt);
+
+        let received = wait_with_timeout(rx.collect());
+        let lines = received
+            .into_iter()
+            .map(|event| event.as_log()[&event::MESSAGE].to_string_lossy())
+            .collect::<Vec<_>>();
+
+        let expected = (0..n)
+            .map(|i| format!("prerot {}", i))
+            .chain((0..n).map(|i| format!("postrot {}", i)))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, expected);
+    }
+
     #[test]
     fn file_multiple_paths() {
         let n = 5;
@@ -0,0 +1,287 @@
+use bytes::{Bytes, BytesMut};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Chunked messages start with these two bytes.
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+/// The magic bytes, an 8 byte message id, the sequence number and count.
+const HEADER_LEN: usize = 12;
+/// The most chunks a message may be split into.
+const MAX_CHUNKS: u8 = 128;
+
+/// Reassembles GELF messages sent over UDP in several chunks, keeping at most
+/// `max_pending` incomplete messages for up to `timeout` each.
+pub struct ChunkAssembler {
+    pending: HashMap<u64, Pending>,
+    /// Ids of pending messages in the order their first chunk arrived, which
+    /// is also the order they expire in.
+    arrivals: VecDeque<(Instant, u64)>,
+    timeout: Duration,
+    max_pending: usize,
+    max_length: usize,
+}
+
+struct Pending {
+    first_seen: Instant,
+    chunks: Vec<Option<Bytes>>,
+    received: usize,
+    length: usize,
+}
+
+impl ChunkAssembler {
+    pub fn new(timeout: Duration, max_pending: usize, max_length: usize) -> Self {
+        ChunkAssembler {
+            pending: HashMap::new(),
+            arrivals: VecDeque::new(),
+            timeout,
+            max_pending,
+            max_length,
+        }
+    }
+
+    /// Add a datagram received at `now`, returning the message it completes,
+    /// if any. Datagrams that are not chunks are complete messages already.
+    pub fn push(&mut self, datagram: Bytes, now: Instant) -> Option<Bytes> {
+        if !datagram.starts_with(&CHUNK_MAGIC) {
+            return Some(datagram);
+        }
+
+        self.expire(now);
+
+        if datagram.len() < HEADER_LEN {
+            warn!(
+                message = "dropping truncated chunk.",
+                len = datagram.len(),
+                dropped_chunks_counter = 1,
+            );
+            return None;
+        }
+
+        let mut id = [0; 8];
+        id.copy_from_slice(&datagram[2..10]);
+        let id = u64::from_be_bytes(id);
+        let sequence = datagram[10];
+        let count = datagram[11];
+
+        if count == 0 || count > MAX_CHUNKS || sequence >= count {
+            warn!(
+                message = "dropping chunk with invalid sequence.",
+                sequence,
+                count,
+                dropped_chunks_counter = 1,
+            );
+            return None;
+        }
+
+        let chunk = datagram.slice_from(HEADER_LEN);
+        if count == 1 {
+            return Some(chunk);
+        }
+
+        if !self.pending.contains_key(&id) {
+            if self.pending.len() >= self.max_pending {
+                self.evict_oldest();
+            }
+            self.arrivals.push_back((now, id));
+        }
+        let pending = self.pending.entry(id).or_insert_with(|| Pending {
+            first_seen: now,
+            chunks: vec![None; count as usize],
+            received: 0,
+            length: 0,
+        });
+
+        if pending.chunks.len() != count as usize {
+            warn!(
+                message = "dropping chunk with a different count than the rest of its message.",
+                id,
+                count,
+                expected = pending.chunks.len(),
+                dropped_chunks_counter = 1,
+            );
+            return None;
+        }
+
+        let slot = &mut pending.chunks[sequence as usize];
+        if slot.is_some() {
+            debug!(message = "ignoring duplicate chunk.", id, sequence);
+            return None;
+        }
+        pending.received += 1;
+        pending.length += chunk.len();
+        *slot = Some(chunk);
+
+        if pending.length > self.max_length {
+            warn!(
+                message = "dropping message larger than max_length.",
+                id,
+                max_length = self.max_length,
+                dropped_messages_counter = 1,
+            );
+            self.pending.remove(&id);
+            return None;
+        }
+
+        if pending.received < pending.chunks.len() {
+            return None;
+        }
+
+        let pending = self
+            .pending
+            .remove(&id)
+            .expect("pending message is present");
+        let mut message = BytesMut::with_capacity(pending.length);
+        for chunk in pending.chunks.iter().flatten() {
+            message.extend_from_slice(chunk);
+        }
+        Some(message.freeze())
+    }
+
+    /// Drop messages that have been waiting for their remaining chunks for
+    /// longer than `timeout`.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(first_seen, id)) = self.arrivals.front() {
+            if now.duration_since(first_seen) < self.timeout {
+                break;
+            }
+            self.arrivals.pop_front();
+            if self.remove_pending(first_seen, id) {
+                warn!(
+                    message = "dropping incomplete message after timeout.",
+                    id,
+                    dropped_messages_counter = 1,
+                );
+            }
+        }
+    }
+
+    /// Make room for a new message by dropping the oldest pending one.
+    fn evict_oldest(&mut self) {
+        while let Some((first_seen, id)) = self.arrivals.pop_front() {
+            if self.remove_pending(first_seen, id) {
+                warn!(
+                    message = "dropping incomplete message, too many messages are pending.",
+                    id,
+                    max_pending = self.max_pending,
+                    dropped_messages_counter = 1,
+                );
+                return;
+            }
+        }
+    }
+
+    /// Remove the message `id` first seen at `first_seen`, unless it has
+    /// already completed, and possibly been replaced by another message that
+    /// reuses its id.
+    fn remove_pending(&mut self, first_seen: Instant, id: u64) -> bool {
+        let current = self
+            .pending
+            .get(&id)
+            .map_or(false, |pending| pending.first_seen == first_seen);
+        if current {
+            self.pending.remove(&id);
+        }
+        current
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ChunkAssembler;
+    use bytes::{BufMut, Bytes, BytesMut};
+    use std::time::{Duration, Instant};
+
+    fn chunk(id: u64, sequence: u8, count: u8, data: &str) -> Bytes {
+        let mut chunk = BytesMut::with_capacity(12 + data.len());
+        chunk.put_slice(&[0x1e, 0x0f]);
+        chunk.put_u64_be(id);
+        chunk.put_u8(sequence);
+        chunk.put_u8(count);
+        chunk.put_slice(data.as_bytes());
+        chunk.freeze()
+    }
+
+    fn assembler() -> ChunkAssembler {
+        ChunkAssembler::new(Duration::from_secs(5), 2, 1024)
+    }
+
+    #[test]
+    fn gelf_chunks_pass_through_unchunked() {
+        let mut assembler = assembler();
+        let now = Instant::now();
+
+        let message = Bytes::from(r#"{"short_message":"hi"}"#);
+        assert_eq!(assembler.push(message.clone(), now), Some(message));
+        assert_eq!(
+            assembler.push(chunk(1, 0, 1, "whole"), now),
+            Some("whole".into())
+        );
+    }
+
+    #[test]
+    fn gelf_chunks_reassemble_out_of_order() {
+        let mut assembler = assembler();
+        let now = Instant::now();
+
+        assert_eq!(assembler.push(chunk(1, 2, 3, "three"), now), None);
+        assert_eq!(assembler.push(chunk(2, 1, 2, "b"), now), None);
+        assert_eq!(assembler.push(chunk(1, 0, 3, "one "), now), None);
+        assert_eq!(assembler.push(chunk(1, 0, 3, "one "), now), None);
+        assert_eq!(
+            assembler.push(chunk(1, 1, 3, "two "), now),
+            Some("one two three".into())
+        );
+        assert_eq!(assembler.push(chunk(2, 0, 2, "a"), now), Some("ab".into()));
+        assert!(assembler.pending.is_empty());
+    }
+
+    #[test]
+    fn gelf_chunks_drop_invalid() {
+        let mut assembler = assembler();
+        let now = Instant::now();
+
+        assert_eq!(assembler.push(Bytes::from(&[0x1e, 0x0f, 1][..]), now), None);
+        assert_eq!(assembler.push(chunk(1, 2, 2, "a"), now), None);
+        assert_eq!(assembler.push(chunk(1, 0, 129, "a"), now), None);
+        assert_eq!(assembler.push(chunk(1, 0, 2, "a"), now), None);
+        assert_eq!(assembler.push(chunk(1, 1, 3, "b"), now), None);
+        assert_eq!(assembler.push(chunk(1, 1, 2, "b"), now), Some("ab".into()));
+    }
+
+    #[test]
+    fn gelf_chunks_expire() {
+        let mut assembler = assembler();
+        let now = Instant::now();
+
+        assert_eq!(assembler.push(chunk(1, 0, 2, "a"), now), None);
+        let later = now + Duration::from_secs(5);
+        assert_eq!(assembler.push(chunk(1, 1, 2, "b"), later), None);
+        assert_eq!(assembler.pending.len(), 1);
+        assert_eq!(assembler.pending[&1].received, 1);
+    }
+
+    #[test]
+    fn gelf_chunks_limit_pending_messages() {
+        let mut assembler = assembler();
+        let now = Instant::now();
+
+        assert_eq!(assembler.push(chunk(1, 0, 2, "a"), now), None);
+        assert_eq!(assembler.push(chunk(2, 0, 2, "a"), now), None);
+        assert_eq!(assembler.push(chunk(3, 0, 2, "a"), now), None);
+        assert_eq!(assembler.pending.len(), 2);
+        assert_eq!(assembler.push(chunk(1, 1, 2, "b"), now), None);
+        assert_eq!(assembler.push(chunk(3, 1, 2, "b"), now), Some("ab".into()));
+    }
+
+    #[test]
+    fn gelf_chunks_limit_length() {
+        let mut assembler = ChunkAssembler::new(Duration::from_secs(5), 2, 4);
+        let now = Instant::now();
+
+        assert_eq!(assembler.push(chunk(1, 0, 2, "abc"), now), None);
+        assert_eq!(assembler.push(chunk(1, 1, 2, "de"), now), None);
+        assert!(assembler.pending.is_empty());
+    }
+}
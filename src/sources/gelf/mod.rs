@@ -0,0 +1,523 @@
+use super::util::TcpSource;
+use crate::{
+    event::{self, Event, ValueKind},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+    transforms::json_parser,
+};
+use bytes::Bytes;
+use chrono::{TimeZone, Utc};
+use codec::BytesDelimitedCodec;
+use derive_is_enum_variant::is_enum_variant;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::{future, sync::mpsc, Future, Sink, Stream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::{ResultExt, Snafu};
+use std::{
+    io::{self, Read},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::{
+    codec::BytesCodec,
+    net::{UdpFramed, UdpSocket},
+};
+
+mod chunks;
+
+use chunks::ChunkAssembler;
+
+#[derive(Deserialize, Serialize, Debug)]
+// TODO: add back when serde-rs/serde#1358 is addressed
+// #[serde(deny_unknown_fields)]
+pub struct GelfConfig {
+    #[serde(flatten)]
+    pub mode: Mode,
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    pub host_key: Option<Atom>,
+    #[serde(default = "default_chunk_timeout_secs")]
+    pub chunk_timeout_secs: u64,
+    #[serde(default = "default_max_pending_messages")]
+    pub max_pending_messages: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, is_enum_variant)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Mode {
+    Tcp { address: SocketAddr },
+    Udp { address: SocketAddr },
+}
+
+fn default_max_length() -> usize {
+    bytesize::mib(1u64) as usize
+}
+
+fn default_chunk_timeout_secs() -> u64 {
+    5
+}
+
+fn default_max_pending_messages() -> usize {
+    1000
+}
+
+impl GelfConfig {
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            max_length: default_max_length(),
+            host_key: None,
+            chunk_timeout_secs: default_chunk_timeout_secs(),
+            max_pending_messages: default_max_pending_messages(),
+        }
+    }
+}
+
+#[typetag::serde(name = "gelf")]
+impl SourceConfig for GelfConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        let host_key = self.host_key.clone().unwrap_or(event::HOST.clone());
+
+        match self.mode.clone() {
+            Mode::Tcp { address } => {
+                let source = GelfTcpSource {
+                    max_length: self.max_length,
+                    host_key,
+                };
+                let shutdown_secs = 30;
+                source.run(address, shutdown_secs, out)
+            }
+            Mode::Udp { address } => {
+                let assembler = ChunkAssembler::new(
+                    Duration::from_secs(self.chunk_timeout_secs),
+                    self.max_pending_messages,
+                    self.max_length,
+                );
+                Ok(udp(address, host_key, self.max_length, assembler, out))
+            }
+        }
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+#[derive(Debug, Snafu)]
+enum ParseError {
+    #[snafu(display("Could not decompress message: {}", source))]
+    Decompress { source: io::Error },
+    #[snafu(display("Decompressed message is larger than {} bytes", max_length))]
+    TooLarge { max_length: usize },
+    #[snafu(display("Message is not valid JSON: {}", source))]
+    InvalidJson { source: serde_json::Error },
+    #[snafu(display("Message is not a JSON object"))]
+    NotAnObject,
+    #[snafu(display("Message has no `short_message`"))]
+    MissingShortMessage,
+    #[snafu(display("Field `{}` has an invalid value: {}", field, value))]
+    InvalidField { field: String, value: Value },
+}
+
+/// TCP carries uncompressed messages, each terminated by a null byte.
+#[derive(Debug, Clone)]
+struct GelfTcpSource {
+    max_length: usize,
+    host_key: Atom,
+}
+
+impl TcpSource for GelfTcpSource {
+    type Decoder = BytesDelimitedCodec;
+
+    fn decoder(&self) -> Self::Decoder {
+        BytesDelimitedCodec::new_with_max_length(b'\0', self.max_length)
+    }
+
+    fn build_event(&self, frame: Bytes, host: Option<Bytes>) -> Option<Event> {
+        build_event(&frame, &self.host_key, host)
+    }
+}
+
+/// UDP carries messages that may be compressed and split into chunks.
+pub fn udp(
+    address: SocketAddr,
+    host_key: Atom,
+    max_length: usize,
+    mut assembler: ChunkAssembler,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let out = out.sink_map_err(|e| error!("error sending event: {:?}", e));
+
+    Box::new(
+        future::lazy(move || {
+            let socket = UdpSocket::bind(&address).expect("failed to bind to udp listener socket");
+
+            info!(message = "listening.", %address, r#type = "udp");
+
+            Ok(socket)
+        })
+        .and_then(move |socket| {
+            UdpFramed::new(socket, BytesCodec::new())
+                .map_err(|error: io::Error| error!(message = "error reading datagram.", %error))
+                .filter_map(move |(datagram, addr)| {
+                    let message = assembler.push(datagram.freeze(), Instant::now())?;
+                    let message = decompress(message, max_length)
+                        .map_err(|error| {
+                            warn!(
+                                message = "failed to decompress message.",
+                                %error,
+                                decode_errors_counter = 1,
+                            )
+                        })
+                        .ok()?;
+                    build_event(&message, &host_key, Some(addr.to_string().into()))
+                })
+                .forward(out)
+                .map(|_| info!("finished sending"))
+        }),
+    )
+}
+
+/// UDP messages may be compressed with gzip or zlib, which are told apart
+/// from JSON by their first bytes.
+fn decompress(message: Bytes, max_length: usize) -> Result<Bytes, ParseError> {
+    match message.get(..2) {
+        Some([0x1f, 0x8b]) => read_to_end(GzDecoder::new(&message[..]), max_length),
+        // A zlib header is a multiple of 31, and 0x78 means deflate with a
+        // 32K window, which is what zlib always uses.
+        Some([0x78, flags]) if (0x7800 + u16::from(*flags)) % 31 == 0 => {
+            read_to_end(ZlibDecoder::new(&message[..]), max_length)
+        }
+        _ => Ok(message),
+    }
+}
+
+fn read_to_end(reader: impl Read, max_length: usize) -> Result<Bytes, ParseError> {
+    let mut buf = Vec::new();
+    reader
+        .take(max_length as u64 + 1)
+        .read_to_end(&mut buf)
+        .context(Decompress)?;
+    if buf.len() > max_length {
+        return Err(ParseError::TooLarge { max_length });
+    }
+    Ok(buf.into())
+}
+
+fn build_event(message: &[u8], host_key: &Atom, default_host: Option<Bytes>) -> Option<Event> {
+    parse_message(message, host_key, default_host)
+        .map(|event| {
+            trace!(message = "Received one event.", ?event);
+            event
+        })
+        .map_err(|error| {
+            warn!(
+                message = "failed to parse GELF message.",
+                %error,
+                decode_errors_counter = 1,
+            )
+        })
+        .ok()
+}
+
+/// Map a GELF message into an event. `short_message` becomes the `message`,
+/// additional fields lose their leading underscore, and the host of the
+/// sender is used if the message names none.
+fn parse_message(
+    message: &[u8],
+    host_key: &Atom,
+    default_host: Option<Bytes>,
+) -> Result<Event, ParseError> {
+    let object = match serde_json::from_slice(message).context(InvalidJson)? {
+        Value::Object(object) => object,
+        _ => return Err(ParseError::NotAnObject),
+    };
+
+    let mut event = Event::new_empty_log();
+    for (name, value) in object {
+        match name.as_str() {
+            // 1.1 is the only version in use, with nothing to tell apart.
+            "version" => {}
+            "short_message" => {
+                let message = string_field(&name, value)?;
+                event
+                    .as_mut_log()
+                    .insert_implicit(event::MESSAGE.clone(), message.into());
+            }
+            "full_message" => {
+                let message = string_field(&name, value)?;
+                event
+                    .as_mut_log()
+                    .insert_implicit("full_message".into(), message.into());
+            }
+            "host" => {
+                let host = string_field(&name, value)?;
+                event
+                    .as_mut_log()
+                    .insert_implicit(host_key.clone(), host.into());
+            }
+            "timestamp" => {
+                // Seconds since the epoch, usually with milliseconds, which a
+                // `f64` this large can only hold to about a microsecond.
+                let timestamp = value
+                    .as_f64()
+                    .filter(|timestamp| (0.0..std::i64::MAX as f64).contains(timestamp))
+                    .and_then(|timestamp| {
+                        let secs = timestamp.trunc();
+                        let micros = ((timestamp - secs) * 1e6).round() as u32;
+                        Utc.timestamp_opt(secs as i64, micros.min(999_999) * 1000)
+                            .single()
+                    })
+                    .ok_or_else(|| ParseError::InvalidField {
+                        field: name.clone(),
+                        value: value.clone(),
+                    })?;
+                event
+                    .as_mut_log()
+                    .insert_implicit(event::TIMESTAMP.clone(), timestamp.into());
+            }
+            "level" => {
+                let level = value.as_i64().ok_or_else(|| ParseError::InvalidField {
+                    field: name.clone(),
+                    value: value.clone(),
+                })?;
+                event
+                    .as_mut_log()
+                    .insert_implicit("level".into(), ValueKind::from(level));
+            }
+            // Reserved, as Graylog uses it for its own message ids.
+            "_id" => {}
+            _ if name.starts_with('_') => {
+                json_parser::insert(&mut event, name[1..].to_string(), value);
+            }
+            // The likes of `facility`, `file` and `line`, which GELF 1.1
+            // deprecated in favor of additional fields.
+            _ => json_parser::insert(&mut event, name, value),
+        }
+    }
+
+    let log = event.as_mut_log();
+    if log.get(&event::MESSAGE).is_none() {
+        return Err(ParseError::MissingShortMessage);
+    }
+    if log.get(host_key).is_none() {
+        if let Some(host) = default_host {
+            log.insert_implicit(host_key.clone(), host.into());
+        }
+    }
+    if log.get(&event::TIMESTAMP).is_none() {
+        log.insert_implicit(event::TIMESTAMP.clone(), Utc::now().into());
+    }
+
+    Ok(event)
+}
+
+fn string_field(name: &str, value: Value) -> Result<String, ParseError> {
+    match value {
+        Value::String(string) => Ok(string),
+        value => Err(ParseError::InvalidField {
+            field: name.into(),
+            value,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decompress, parse_message, GelfConfig, Mode};
+    use crate::event::{self, Event};
+    use crate::test_util::{collect_n, next_addr, wait_for_tcp};
+    use crate::topology::config::{GlobalOptions, SourceConfig};
+    use bytes::{BufMut, Bytes, BytesMut};
+    use chrono::{TimeZone, Utc};
+    use flate2::{
+        write::{GzEncoder, ZlibEncoder},
+        Compression,
+    };
+    use futures::sync::mpsc;
+    use std::{
+        io::Write,
+        net::{SocketAddr, TcpStream, UdpSocket},
+        thread,
+        time::Duration,
+    };
+    use string_cache::DefaultAtom as Atom;
+
+    fn parse(message: &str) -> Event {
+        parse_message(message.as_bytes(), &event::HOST, Some("peer".into())).unwrap()
+    }
+
+    fn start(mode: Mode, tx: mpsc::Sender<Event>) -> tokio::runtime::Runtime {
+        let server = GelfConfig::new(mode)
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        rt
+    }
+
+    #[test]
+    fn gelf_config() {
+        let config: GelfConfig = toml::from_str(
+            r#"
+            mode = "udp"
+            address = "127.0.0.1:12201"
+            chunk_timeout_secs = 10
+          "#,
+        )
+        .unwrap();
+        assert!(config.mode.is_udp());
+        assert_eq!(config.chunk_timeout_secs, 10);
+        assert_eq!(config.max_pending_messages, 1000);
+
+        let config: GelfConfig = toml::from_str(
+            r#"
+            mode = "tcp"
+            address = "127.0.0.1:12201"
+          "#,
+        )
+        .unwrap();
+        assert!(config.mode.is_tcp());
+    }
+
+    #[test]
+    fn gelf_maps_fields() {
+        let event = parse(
+            r#"{
+                "version": "1.1",
+                "host": "example.org",
+                "short_message": "A short message",
+                "full_message": "Backtrace here\n\nmore stuff",
+                "timestamp": 1385053862.3072,
+                "level": 1,
+                "_user_id": 9001,
+                "_some_info": "foo",
+                "_id": "ignored",
+                "facility": "app"
+            }"#,
+        );
+        let log = event.as_log();
+
+        assert_eq!(log[&event::MESSAGE], "A short message".into());
+        assert_eq!(
+            log[&Atom::from("full_message")],
+            "Backtrace here\n\nmore stuff".into()
+        );
+        assert_eq!(log[&event::HOST], "example.org".into());
+        assert_eq!(
+            log[&event::TIMESTAMP],
+            Utc.timestamp(1385053862, 307_200_000).into()
+        );
+        assert_eq!(log[&Atom::from("level")], 1.into());
+        assert_eq!(log[&Atom::from("user_id")], 9001.into());
+        assert_eq!(log[&Atom::from("some_info")], "foo".into());
+        assert_eq!(log[&Atom::from("facility")], "app".into());
+        assert!(log.get(&Atom::from("id")).is_none());
+        assert!(log.get(&Atom::from("_id")).is_none());
+        assert!(log.get(&Atom::from("version")).is_none());
+    }
+
+    #[test]
+    fn gelf_defaults_host_and_timestamp() {
+        let event = parse(r#"{"short_message": "hi"}"#);
+        let log = event.as_log();
+
+        assert_eq!(log[&event::HOST], "peer".into());
+        assert!(log.get(&event::TIMESTAMP).is_some());
+    }
+
+    #[test]
+    fn gelf_rejects_invalid_messages() {
+        let host_key = event::HOST.clone();
+        for message in &[
+            "not json",
+            "[1]",
+            r#"{"host": "example.org"}"#,
+            r#"{"short_message": 1}"#,
+            r#"{"short_message": "hi", "level": "high"}"#,
+            r#"{"short_message": "hi", "timestamp": "yesterday"}"#,
+        ] {
+            assert!(parse_message(message.as_bytes(), &host_key, None).is_err());
+        }
+    }
+
+    #[test]
+    fn gelf_decompresses() {
+        let message = r#"{"short_message": "hi"}"#;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(message.as_bytes()).unwrap();
+        let gzip = Bytes::from(gzip.finish().unwrap());
+        assert_eq!(decompress(gzip.clone(), 1024).unwrap(), message);
+        assert!(decompress(gzip, 10).is_err());
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(message.as_bytes()).unwrap();
+        let zlib = Bytes::from(zlib.finish().unwrap());
+        assert_eq!(decompress(zlib, 1024).unwrap(), message);
+
+        assert_eq!(decompress(message.into(), 1024).unwrap(), message);
+    }
+
+    #[test]
+    fn gelf_udp_chunked_message() {
+        let (tx, rx) = mpsc::channel(10);
+        let address = next_addr();
+        let mut rt = start(Mode::Udp { address }, tx);
+        thread::sleep(Duration::from_millis(100));
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(br#"{"host": "a", "short_message": "chunked", "_n": 1}"#)
+            .unwrap();
+        let message = zlib.finish().unwrap();
+        let (first, second) = message.split_at(message.len() / 2);
+
+        let socket = UdpSocket::bind(next_addr()).unwrap();
+        let send = |datagram: &[u8], address: SocketAddr| {
+            socket.send_to(datagram, address).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        };
+        for (sequence, data) in vec![(1, second), (0, first)] {
+            let mut chunk = BytesMut::with_capacity(12 + data.len());
+            chunk.put_slice(&[0x1e, 0x0f]);
+            chunk.put_u64_be(42);
+            chunk.put_u8(sequence);
+            chunk.put_u8(2);
+            chunk.put_slice(data);
+            send(&chunk[..], address);
+        }
+        send(&br#"{"short_message": "plain"}"#[..], address);
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+
+        assert_eq!(events[0].as_log()[&event::MESSAGE], "chunked".into());
+        assert_eq!(events[0].as_log()[&event::HOST], "a".into());
+        assert_eq!(events[0].as_log()[&Atom::from("n")], 1.into());
+        assert_eq!(events[1].as_log()[&event::MESSAGE], "plain".into());
+    }
+
+    #[test]
+    fn gelf_tcp_null_delimited() {
+        let (tx, rx) = mpsc::channel(10);
+        let address = next_addr();
+        let mut rt = start(Mode::Tcp { address }, tx);
+        wait_for_tcp(address);
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"{\"short_message\": \"one\"}\0{\"short_message\": \"two\\nlines\"}\0")
+            .unwrap();
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+
+        assert_eq!(events[0].as_log()[&event::MESSAGE], "one".into());
+        assert_eq!(events[0].as_log()[&event::HOST], "127.0.0.1".into());
+        assert_eq!(events[1].as_log()[&event::MESSAGE], "two\nlines".into());
+    }
+}
@@ -8,13 +8,13 @@ use futures::{future, sync::mpsc, Future, Sink};
 use journald::{Journal, Record};
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use snafu::{ResultExt, Snafu};
-use std::collections::HashSet;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Error, Read, Seek, SeekFrom, Write};
 use std::iter::FromIterator;
 use std::path::PathBuf;
-use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
 use std::thread;
 use std::time;
 use string_cache::DefaultAtom as Atom;
@@ -29,16 +29,31 @@ lazy_static! {
 #[derive(Debug, Snafu)]
 enum BuildError {
     #[snafu(display("journald error: {}", source))]
-    JournaldError { source: ::journald::Error },
+    JournaldError { source: io::Error },
+    #[snafu(display("invalid priority {:?}, expected a level or range of levels", priority))]
+    InvalidPriority { priority: String },
 }
 
+const DEFAULT_BATCH_SIZE: usize = 16;
+
+/// Syslog priority names, in order of their numeric values.
+const PRIORITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
 #[derive(Deserialize, Serialize, Debug, Default)]
 #[serde(deny_unknown_fields, default)]
 pub struct JournaldConfig {
     pub current_runtime_only: Option<bool>,
     pub local_only: Option<bool>,
     pub units: Vec<String>,
+    pub include_matches: Vec<BTreeMap<String, Vec<String>>>,
+    pub exclude_matches: BTreeMap<String, Vec<String>>,
+    pub transports: Vec<String>,
+    pub priority: Option<String>,
+    pub batch_size: Option<usize>,
     pub data_dir: Option<PathBuf>,
+    pub journal_directory: Option<PathBuf>,
 }
 
 #[typetag::serde(name = "journald")]
@@ -52,26 +67,18 @@ impl SourceConfig for JournaldConfig {
         let local_only = self.local_only.unwrap_or(true);
         let runtime_only = self.current_runtime_only.unwrap_or(true);
         let data_dir = globals.resolve_and_make_data_subdir(self.data_dir.as_ref(), name)?;
-        let journal = Journal::open(local_only, runtime_only).context(JournaldError)?;
-
-        // Map the given unit names into valid systemd units by
-        // appending ".service" if no extension is present.
-        let units = self
-            .units
-            .iter()
-            .map(|unit| {
-                if let Some(_) = unit.find('.') {
-                    unit.into()
-                } else {
-                    format!("{}.service", unit)
-                }
-            })
-            .collect::<HashSet<String>>();
+        let mut journal = match self.journal_directory {
+            Some(ref directory) => Journal::open_directories(vec![directory.clone()]),
+            None => Journal::open(local_only, runtime_only),
+        }
+        .context(JournaldError)?;
+        self.add_matches(&mut journal)?;
 
         let checkpointer = Checkpointer::new(data_dir)
             .map_err(|err| format!("Unable to open checkpoint file: {}", err))?;
+        let batch_size = self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
 
-        Ok(journald_source(journal, out, checkpointer, units))
+        Ok(journald_source(journal, out, checkpointer, batch_size))
     }
 
     fn output_type(&self) -> DataType {
@@ -79,11 +86,86 @@ impl SourceConfig for JournaldConfig {
     }
 }
 
+impl JournaldConfig {
+    /// Push the configured filters down into the journal reader, so that
+    /// entries which don't match are skipped without being decoded.
+    fn add_matches(&self, journal: &mut Journal) -> Result<(), crate::Error> {
+        // Each of the include groups is an alternative.
+        for group in &self.include_matches {
+            for (field, values) in group {
+                for value in values {
+                    journal
+                        .add_match(&format!("{}={}", field, value))
+                        .context(JournaldError)?;
+                }
+            }
+            journal.add_disjunction();
+        }
+        journal.add_conjunction();
+
+        // Map the given unit names into valid systemd units by
+        // appending ".service" if no extension is present.
+        let units = self.units.iter().map(|unit| {
+            if let Some(_) = unit.find('.') {
+                unit.clone()
+            } else {
+                format!("{}.service", unit)
+            }
+        });
+        let priorities = match self.priority {
+            Some(ref priority) => {
+                let (min, max) = parse_priority(priority).context(InvalidPriority { priority })?;
+                (min..=max).map(|level| level.to_string()).collect()
+            }
+            None => Vec::new(),
+        };
+        let required = units
+            .map(|unit| ("_SYSTEMD_UNIT", unit))
+            .chain(self.transports.iter().map(|t| ("_TRANSPORT", t.clone())))
+            .chain(priorities.into_iter().map(|p| ("PRIORITY", p)));
+        for (field, value) in required {
+            journal
+                .add_match(&format!("{}={}", field, value))
+                .context(JournaldError)?;
+        }
+        journal.add_conjunction();
+
+        for (field, values) in &self.exclude_matches {
+            for value in values {
+                journal
+                    .add_exclude(&format!("{}={}", field, value))
+                    .context(JournaldError)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse a priority the way `journalctl --priority` does: a single level,
+/// which includes all more important ones, or an inclusive range of levels.
+/// Levels are given by name or number.
+fn parse_priority(priority: &str) -> Option<(u8, u8)> {
+    let level = |level: &str| {
+        PRIORITIES
+            .iter()
+            .position(|name| *name == level)
+            .map(|level| level as u8)
+            .or_else(|| level.parse::<u8>().ok().filter(|level| *level <= 7))
+    };
+    match priority.find("..") {
+        Some(dots) => {
+            let (a, b) = (level(&priority[..dots])?, level(&priority[dots + 2..])?);
+            Some((a.min(b), a.max(b)))
+        }
+        None => Some((0, level(priority)?)),
+    }
+}
+
 fn journald_source<J>(
     journal: J,
     out: mpsc::Sender<Event>,
     checkpointer: Checkpointer,
-    units: HashSet<String>,
+    batch_size: usize,
 ) -> super::Source
 where
     J: Iterator<Item = Result<Record, io::Error>> + JournalCursor + Send + 'static,
@@ -99,7 +181,7 @@ where
 
         let journald_server = JournaldServer {
             journal,
-            units,
+            batch_size,
             channel: out,
             shutdown: shutdown_rx,
             checkpointer,
@@ -160,7 +242,7 @@ impl JournalCursor for Journal {
 
 struct JournaldServer<J, T> {
     journal: J,
-    units: HashSet<String>,
+    batch_size: usize,
     channel: T,
     shutdown: std::sync::mpsc::Receiver<()>,
     checkpointer: Checkpointer,
@@ -193,47 +275,56 @@ where
         }
 
         loop {
+            // Send the records in batches, checkpointing after each one.
             loop {
-                let record = match self.journal.next() {
-                    None => break,
-                    Some(Ok(record)) => record,
-                    Some(Err(err)) => {
-                        error!(
-                            message = "Could not read from journald source",
-                            error = field::display(&err),
-                        );
-                        break;
-                    }
-                };
-                if self.units.len() > 0 {
-                    // Make sure the systemd unit is exactly one of the specified units
-                    if let Some(unit) = record.get("_SYSTEMD_UNIT") {
-                        if !self.units.contains(unit) {
-                            continue;
+                let mut count = 0;
+                let mut exhausted = false;
+                while count < self.batch_size {
+                    let record = match self.journal.next() {
+                        None => {
+                            exhausted = true;
+                            break;
+                        }
+                        Some(Ok(record)) => record,
+                        Some(Err(err)) => {
+                            error!(
+                                message = "Could not read from journald source",
+                                error = field::display(&err),
+                            );
+                            exhausted = true;
+                            break;
                         }
-                    } else {
-                        continue;
+                    };
+                    count += 1;
+                    match channel.send(record).wait() {
+                        Ok(_) => {}
+                        Err(()) => error!(message = "Could not send journald log"),
                     }
                 }
-                match channel.send(record).wait() {
-                    Ok(_) => {}
-                    Err(()) => error!(message = "Could not send journald log"),
-                }
-            }
 
-            match self.journal.cursor() {
-                Ok(cursor) => {
-                    if let Err(err) = self.checkpointer.set(&cursor) {
-                        error!(
-                            message = "Could not set journald checkpoint.",
+                if count > 0 {
+                    match self.journal.cursor() {
+                        Ok(cursor) => {
+                            if let Err(err) = self.checkpointer.set(&cursor) {
+                                error!(
+                                    message = "Could not set journald checkpoint.",
+                                    error = field::display(&err)
+                                );
+                            }
+                        }
+                        Err(err) => error!(
+                            message = "Could not retrieve journald checkpoint.",
                             error = field::display(&err)
-                        );
+                        ),
                     }
                 }
-                Err(err) => error!(
-                    message = "Could not retrieve journald checkpoint.",
-                    error = field::display(&err)
-                ),
+
+                if exhausted {
+                    break;
+                }
+                if let Err(TryRecvError::Disconnected) = self.shutdown.try_recv() {
+                    return;
+                }
             }
 
             match self.shutdown.recv_timeout(timeout) {
@@ -334,7 +425,6 @@ mod tests {
     use crate::test_util::{block_on, runtime, shutdown_on_idle};
     use futures::stream::Stream;
     use std::io::Error;
-    use std::iter::FromIterator;
     use std::time::{Duration, SystemTime};
     use stream_cancel::Tripwire;
     use tempfile::tempdir;
@@ -391,20 +481,19 @@ mod tests {
         journal
     }
 
-    fn run_journal(units: &[&str], cursor: Option<&str>) -> Vec<Event> {
+    fn run_journal(cursor: Option<&str>) -> Vec<Event> {
         let (tx, rx) = futures::sync::mpsc::channel(10);
         let (trigger, tripwire) = Tripwire::new();
         let tempdir = tempdir().unwrap();
         let mut checkpointer =
             Checkpointer::new(tempdir.path().to_path_buf()).expect("Creating checkpointer failed!");
-        let units = HashSet::<String>::from_iter(units.into_iter().map(|&s| s.into()));
 
         if let Some(cursor) = cursor {
             checkpointer.set(cursor).expect("Could not set checkpoint");
         }
 
         let journal = fake_journal();
-        let source = journald_source(journal, tx, checkpointer, units);
+        let source = journald_source(journal, tx, checkpointer, DEFAULT_BATCH_SIZE);
         let mut rt = runtime();
         rt.spawn(source.select(tripwire).map(|_| ()).map_err(|_| ()));
 
@@ -417,7 +506,7 @@ mod tests {
 
     #[test]
     fn journald_source_works() {
-        let received = run_journal(&[], None);
+        let received = run_journal(None);
         assert_eq!(received.len(), 2);
         assert_eq!(
             received[0].as_log()[&event::MESSAGE],
@@ -430,8 +519,8 @@ mod tests {
     }
 
     #[test]
-    fn journald_source_filters_units() {
-        let received = run_journal(&["unit.service"], None);
+    fn journald_source_handles_checkpoint() {
+        let received = run_journal(Some("1"));
         assert_eq!(received.len(), 1);
         assert_eq!(
             received[0].as_log()[&event::MESSAGE],
@@ -439,13 +528,124 @@ mod tests {
         );
     }
 
+    /// Run the source on the checked-in journal file, returning the events
+    /// and the checkpoint written.
+    fn run_journal_file(config: JournaldConfig) -> (Vec<Event>, String) {
+        let journal_dir = tempdir().unwrap();
+        std::fs::copy(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/lib/journald/tests/data/zstd.journal"
+            ),
+            journal_dir.path().join("system.journal"),
+        )
+        .unwrap();
+        let data_dir = tempdir().unwrap();
+
+        let config = JournaldConfig {
+            data_dir: Some(data_dir.path().into()),
+            journal_directory: Some(journal_dir.path().into()),
+            ..config
+        };
+        let (tx, rx) = futures::sync::mpsc::channel(10);
+        let (trigger, tripwire) = Tripwire::new();
+        let source = config
+            .build("journald", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = runtime();
+        rt.spawn(source.select(tripwire).map(|_| ()).map_err(|_| ()));
+
+        std::thread::sleep(Duration::from_millis(100));
+        drop(trigger);
+        shutdown_on_idle(rt);
+
+        let received =
+            block_on(rx.collect().timeout(Duration::from_secs(1))).expect("Unclosed channel");
+        let checkpoint = std::fs::read_to_string(data_dir.path().join(CHECKPOINT_FILENAME))
+            .expect("Could not read checkpoint");
+        (received, checkpoint)
+    }
+
+    fn messages(events: &[Event]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| event.as_log()[&event::MESSAGE].to_string_lossy())
+            .collect()
+    }
+
     #[test]
-    fn journald_source_handles_checkpoint() {
-        let received = run_journal(&[], Some("1"));
-        assert_eq!(received.len(), 1);
+    fn journald_source_filters_units() {
+        let (received, checkpoint) = run_journal_file(JournaldConfig {
+            units: vec!["ntpd".into()],
+            ..JournaldConfig::default()
+        });
+        assert_eq!(received.len(), 3);
         assert_eq!(
             received[0].as_log()[&event::MESSAGE],
-            ValueKind::Bytes("unit message".into())
+            ValueKind::Bytes(
+                "reply from 192.168.1.2: offset -0.001791 delay 0.000176, next query 1500s".into()
+            )
+        );
+        assert_eq!(
+            received[2].as_log()[&event::MESSAGE],
+            ValueKind::Bytes("multi\nline message".into())
+        );
+        assert_eq!(
+            received[0].as_log()[&event::HOST],
+            ValueKind::Bytes("vm".into())
+        );
+        assert_eq!(
+            received[0].as_log()[&"_SYSTEMD_UNIT".into()],
+            ValueKind::Bytes("ntpd.service".into())
+        );
+        // The cursor of the last entry sent.
+        assert!(checkpoint.starts_with("s=7e90f8b58af242178d7bcaccb5042ab6;i=7;"));
+    }
+
+    #[test]
+    fn journald_source_matches_fields() {
+        let mut sshd = BTreeMap::new();
+        sshd.insert("_SYSTEMD_UNIT".into(), vec!["sshd.service".into()]);
+        let mut started = BTreeMap::new();
+        started.insert("MESSAGE".into(), vec!["Journal started".into()]);
+        let mut exclude = BTreeMap::new();
+        exclude.insert(
+            "MESSAGE".into(),
+            vec!["Received disconnect from 10.0.0.2 port 51234".into()],
+        );
+
+        let (received, _) = run_journal_file(JournaldConfig {
+            include_matches: vec![sshd, started],
+            exclude_matches: exclude,
+            transports: vec!["journal".into(), "driver".into()],
+            priority: Some("info".into()),
+            batch_size: Some(1),
+            ..JournaldConfig::default()
+        });
+        assert_eq!(
+            messages(&received),
+            vec![
+                "Journal started",
+                "Server listening on 0.0.0.0 port 22.",
+                "Accepted publickey for vector from 10.0.0.2 port 51234",
+            ]
         );
+
+        let (received, _) = run_journal_file(JournaldConfig {
+            priority: Some("warning".into()),
+            ..JournaldConfig::default()
+        });
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn journald_parses_priority() {
+        assert_eq!(parse_priority("err"), Some((0, 3)));
+        assert_eq!(parse_priority("6"), Some((0, 6)));
+        assert_eq!(parse_priority("warning..err"), Some((3, 4)));
+        assert_eq!(parse_priority("notice..debug"), Some((5, 7)));
+        assert_eq!(parse_priority("8"), None);
+        assert_eq!(parse_priority("loud"), None);
+        assert_eq!(parse_priority("err.."), None);
     }
 }
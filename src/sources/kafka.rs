@@ -1,19 +1,20 @@
+use super::util::{forward_and_store, Decoding, DecodingConfig};
 use crate::{
     event::Event,
     topology::config::{DataType, GlobalOptions, SourceConfig},
 };
 use bytes::Bytes;
-use futures::{future, sync::mpsc, Future, Poll, Sink, Stream};
+use futures::{future, sync::mpsc, Future, Poll, Stream};
 use owning_ref::OwningHandle;
 use rdkafka::{
     config::ClientConfig,
     consumer::{Consumer, DefaultConsumerContext, MessageStream, StreamConsumer},
     error::KafkaResult,
-    message::{BorrowedMessage, Message},
+    message::{BorrowedMessage, Headers, Message},
 };
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 #[derive(Debug, Snafu)]
 enum BuildError {
@@ -21,6 +22,10 @@ enum BuildError {
     KafkaCreateError { source: rdkafka::error::KafkaError },
     #[snafu(display("Could not subscribe to Kafka topics: {}", source))]
     KafkaSubscribeError { source: rdkafka::error::KafkaError },
+    #[snafu(display("Must specify both TLS key_file and crt_file"))]
+    MissingCrtKeyFile,
+    #[snafu(display("Must specify both SASL username and password"))]
+    MissingSaslCredentials,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -33,18 +38,78 @@ pub struct KafkaSourceConfig {
     auto_offset_reset: String,
     #[serde(default = "default_session_timeout_ms")]
     session_timeout_ms: u64,
+    #[serde(default = "default_commit_interval_ms")]
+    commit_interval_ms: u64,
     host_key: Option<String>,
     key_field: Option<String>,
+    topic_key: Option<String>,
+    partition_key: Option<String>,
+    offset_key: Option<String>,
+    headers_key: Option<String>,
+    tls: Option<KafkaTlsConfig>,
+    sasl: Option<KafkaSaslConfig>,
+    librdkafka_options: Option<HashMap<String, String>>,
+    #[serde(default = "default_max_length")]
+    max_length: usize,
+    #[serde(default)]
+    decoding: DecodingConfig,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaTlsConfig {
+    enabled: Option<bool>,
+    ca_file: Option<String>,
+    crt_file: Option<String>,
+    key_file: Option<String>,
+    key_phrase: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaSaslConfig {
+    enabled: Option<bool>,
+    mechanism: Option<SaslMechanism>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum SaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512,
+}
+
+impl SaslMechanism {
+    fn as_str(self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
 }
 
 fn default_session_timeout_ms() -> u64 {
     10000 // default in librdkafka
 }
 
+fn default_commit_interval_ms() -> u64 {
+    5000 // default in librdkafka
+}
+
 fn default_auto_offset_reset() -> String {
     "largest".into() // default in librdkafka
 }
 
+fn default_max_length() -> usize {
+    bytesize::kib(100u64) as usize
+}
+
 #[typetag::serde(name = "kafka")]
 impl SourceConfig for KafkaSourceConfig {
     fn build(
@@ -65,7 +130,24 @@ fn kafka_source(
     config: KafkaSourceConfig,
     out: mpsc::Sender<Event>,
 ) -> Result<super::Source, crate::Error> {
-    let consumer = Arc::new(create_consumer(config.clone())?);
+    let decoding = config.decoding.build(config.max_length)?;
+    let consumer = create_consumer(&config)?;
+
+    let topics: Vec<&str> = config.topics.iter().map(|s| s.as_str()).collect();
+    consumer.subscribe(&topics).context(KafkaSubscribeError)?;
+
+    Ok(consume(config, decoding, consumer, out))
+}
+
+/// Send the events of the messages `consumer` receives to `out`, storing the
+/// offset of each message once its events were sent.
+fn consume(
+    config: KafkaSourceConfig,
+    decoding: Decoding,
+    consumer: StreamConsumer,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let consumer = Arc::new(consumer);
     let source = future::lazy(move || {
         let consumer_ref = Arc::clone(&consumer);
 
@@ -77,62 +159,155 @@ fn kafka_source(
             }),
         };
 
-        stream
+        let messages = stream
             .then(move |message| {
-                match message {
-                    Err(e) => Err(error!(message = "Error reading message from Kafka", error = ?e)),
-                    Ok(Err(e)) => Err(error!(message = "Kafka returned error", error = ?e)),
-                    Ok(Ok(msg)) => {
-                        let payload = match msg.payload_view::<[u8]>() {
-                            None => return Err(()), // skip messages with empty payload
-                            Some(Err(e)) => {
-                                return Err(error!(message = "Cannot extract payload", error = ?e))
-                            }
-                            Some(Ok(payload)) => Bytes::from(payload),
-                        };
-                        let mut event = Event::from(payload);
-
-                        if let Some(key_field) = &config.key_field {
-                            match msg.key_view::<[u8]>() {
-                                None => (),
-                                Some(Err(e)) => {
-                                    return Err(error!(message = "Cannot extract key", error = ?e))
-                                }
-                                Some(Ok(key)) => event
-                                    .as_mut_log()
-                                    .insert_implicit(key_field.clone().into(), key.into()),
-                            }
-                        }
-
-                        consumer_ref
-                            .store_offset(&msg)
-                            .map_err(|e| error!(message = "Cannot store offset", error = ?e))?;
-                        Ok(event)
+                Ok(match message {
+                    Err(e) => {
+                        error!(message = "Error reading message from Kafka", error = ?e);
+                        None
+                    }
+                    Ok(Err(e)) => {
+                        error!(message = "Kafka returned error", error = ?e);
+                        None
                     }
-                }
+                    Ok(Ok(msg)) => Some((
+                        message_to_events(&config, &decoding, &msg),
+                        SendMessage(msg),
+                    )),
+                })
             })
-            .forward(out.sink_map_err(|e| error!(message = "Error sending to sink", error = ?e)))
-            .map(|_| ())
+            .filter_map(|message| message);
+
+        forward_and_store(messages, out, move |SendMessage(msg)| {
+            if let Err(e) = consumer_ref.store_offset(&msg) {
+                error!(message = "Cannot store offset", error = ?e);
+            }
+        })
     });
 
-    Ok(Box::new(source))
+    Box::new(source)
 }
 
-fn create_consumer(config: KafkaSourceConfig) -> Result<StreamConsumer, crate::Error> {
-    let consumer: StreamConsumer = ClientConfig::new()
+fn message_to_events(
+    config: &KafkaSourceConfig,
+    decoding: &Decoding,
+    msg: &BorrowedMessage,
+) -> Vec<Event> {
+    let payload = match msg.payload_view::<[u8]>() {
+        None => return Vec::new(), // skip messages with empty payload
+        Some(Err(e)) => {
+            error!(message = "Cannot extract payload", error = ?e);
+            return Vec::new();
+        }
+        Some(Ok(payload)) => Bytes::from(payload),
+    };
+    let mut events = decoding.decode_message(payload);
+    for event in &mut events {
+        add_metadata(config, msg, event);
+    }
+    events
+}
+
+fn add_metadata(config: &KafkaSourceConfig, msg: &BorrowedMessage, event: &mut Event) {
+    let log = event.as_mut_log();
+
+    if let Some(key_field) = &config.key_field {
+        match msg.key_view::<[u8]>() {
+            None => (),
+            Some(Err(e)) => error!(message = "Cannot extract key", error = ?e),
+            Some(Ok(key)) => log.insert_implicit(key_field.clone().into(), key.into()),
+        }
+    }
+    if let Some(topic_key) = &config.topic_key {
+        log.insert_implicit(topic_key.clone().into(), msg.topic().into());
+    }
+    if let Some(partition_key) = &config.partition_key {
+        log.insert_implicit(partition_key.clone().into(), msg.partition().into());
+    }
+    if let Some(offset_key) = &config.offset_key {
+        log.insert_implicit(offset_key.clone().into(), msg.offset().into());
+    }
+    if let (Some(headers_key), Some(headers)) = (&config.headers_key, msg.headers()) {
+        for i in 0..headers.count() {
+            if let Some((name, value)) = headers.get(i) {
+                log.insert_implicit(format!("{}.{}", headers_key, name).into(), value.into());
+            }
+        }
+    }
+}
+
+fn create_consumer(config: &KafkaSourceConfig) -> Result<StreamConsumer, crate::Error> {
+    let mut client_config = ClientConfig::new();
+    client_config
         .set("group.id", &config.group_id)
         .set("bootstrap.servers", &config.bootstrap_servers)
         .set("auto.offset.reset", &config.auto_offset_reset)
         .set("session.timeout.ms", &config.session_timeout_ms.to_string())
         .set("enable.partition.eof", "false")
-        .set("enable.auto.commit", "false")
-        .set("client.id", "vector")
-        .create()
-        .context(KafkaCreateError)?;
+        // Offsets are committed periodically, but only once they have been
+        // stored after the events made it out of the source.
+        .set("enable.auto.commit", "true")
+        .set(
+            "auto.commit.interval.ms",
+            &config.commit_interval_ms.to_string(),
+        )
+        .set("enable.auto.offset.store", "false")
+        .set("client.id", "vector");
 
-    let topics: Vec<&str> = config.topics.iter().map(|s| s.as_str()).collect();
-    consumer.subscribe(&topics).context(KafkaSubscribeError)?;
+    let tls = config
+        .tls
+        .as_ref()
+        .filter(|tls| tls.enabled.unwrap_or(false));
+    if let Some(tls) = tls {
+        if tls.key_file.is_some() != tls.crt_file.is_some() {
+            return Err(Box::new(BuildError::MissingCrtKeyFile));
+        }
+        if let Some(ca_file) = &tls.ca_file {
+            client_config.set("ssl.ca.location", ca_file);
+        }
+        if let Some(crt_file) = &tls.crt_file {
+            client_config.set("ssl.certificate.location", crt_file);
+        }
+        if let Some(key_file) = &tls.key_file {
+            client_config.set("ssl.key.location", key_file);
+        }
+        if let Some(key_phrase) = &tls.key_phrase {
+            client_config.set("ssl.key.password", key_phrase);
+        }
+    }
+
+    let sasl = config
+        .sasl
+        .as_ref()
+        .filter(|sasl| sasl.enabled.unwrap_or(false));
+    if let Some(sasl) = sasl {
+        let (username, password) = match (&sasl.username, &sasl.password) {
+            (Some(username), Some(password)) => (username, password),
+            _ => return Err(Box::new(BuildError::MissingSaslCredentials)),
+        };
+        let mechanism = sasl.mechanism.unwrap_or(SaslMechanism::Plain);
+        client_config
+            .set("sasl.mechanisms", mechanism.as_str())
+            .set("sasl.username", username)
+            .set("sasl.password", password);
+    }
+
+    let protocol = match (sasl.is_some(), tls.is_some()) {
+        (false, false) => "plaintext",
+        (false, true) => "ssl",
+        (true, false) => "sasl_plaintext",
+        (true, true) => "sasl_ssl",
+    };
+    client_config.set("security.protocol", protocol);
 
+    // These come last, so they can override any of the above.
+    if let Some(options) = &config.librdkafka_options {
+        for (key, value) in options {
+            client_config.set(key, value);
+        }
+    }
+
+    let consumer: StreamConsumer = client_config.create().context(KafkaCreateError)?;
     Ok(consumer)
 }
 
@@ -150,10 +325,37 @@ impl Stream for OwnedConsumerStream {
     }
 }
 
+/// A message held on to until its event has been sent, so its offset can be
+/// stored. `BorrowedMessage` is only a handle to a message owned by
+/// librdkafka, which is safe to move across threads, and is `Send` in later
+/// versions of rdkafka. See https://github.com/fede1024/rust-rdkafka/pull/190
+struct SendMessage(BorrowedMessage<'static>);
+
+unsafe impl Send for SendMessage {}
+
 #[cfg(test)]
 mod test {
-    use super::{kafka_source, KafkaSourceConfig};
-    use futures::sync::mpsc;
+    use super::{
+        consume, create_consumer, forward_and_store, kafka_source, KafkaSaslConfig,
+        KafkaSourceConfig, KafkaTlsConfig,
+    };
+    use crate::{
+        event::{self, Event},
+        sources::util::DecodingConfig,
+        test_util::{collect_n, runtime, wait_for},
+    };
+    use futures::{stream, sync::mpsc, Future, Stream};
+    use rdkafka::{
+        config::ClientConfig,
+        consumer::{BaseConsumer, Consumer},
+        producer::{FutureProducer, FutureRecord},
+        topic_partition_list::TopicPartitionList,
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
 
     fn make_config() -> KafkaSourceConfig {
         KafkaSourceConfig {
@@ -162,8 +364,18 @@ mod test {
             group_id: "group-id".to_string(),
             auto_offset_reset: "earliest".to_string(),
             session_timeout_ms: 10000,
+            commit_interval_ms: 5000,
             host_key: None,
             key_field: Some("message_key".to_string()),
+            topic_key: None,
+            partition_key: None,
+            offset_key: None,
+            headers_key: None,
+            tls: None,
+            sasl: None,
+            librdkafka_options: None,
+            max_length: default_max_length(),
+            decoding: DecodingConfig::default(),
         }
     }
 
@@ -181,6 +393,199 @@ mod test {
         };
         assert!(kafka_source(config, mpsc::channel(1).0).is_err());
     }
+
+    #[test]
+    fn kafka_source_create_with_tls_and_sasl() {
+        let config = KafkaSourceConfig {
+            sasl: Some(KafkaSaslConfig {
+                enabled: Some(true),
+                mechanism: None,
+                username: Some("user".into()),
+                password: Some("pass".into()),
+            }),
+            ..make_config()
+        };
+        assert!(create_consumer(&config).is_ok());
+
+        let config = KafkaSourceConfig {
+            tls: Some(KafkaTlsConfig {
+                enabled: Some(true),
+                crt_file: Some("/path/to/host.crt".into()),
+                ..Default::default()
+            }),
+            ..make_config()
+        };
+        assert!(create_consumer(&config).is_err());
+
+        let config = KafkaSourceConfig {
+            sasl: Some(KafkaSaslConfig {
+                enabled: Some(true),
+                username: Some("user".into()),
+                ..Default::default()
+            }),
+            ..make_config()
+        };
+        assert!(create_consumer(&config).is_err());
+    }
+
+    #[test]
+    fn kafka_source_passes_librdkafka_options() {
+        let config = KafkaSourceConfig {
+            librdkafka_options: Some(
+                vec![("fetch.min.bytes".to_string(), "1024".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..make_config()
+        };
+        assert!(create_consumer(&config).is_ok());
+
+        let config = KafkaSourceConfig {
+            librdkafka_options: Some(
+                vec![("no.such.option".to_string(), "1".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..make_config()
+        };
+        assert!(create_consumer(&config).is_err());
+    }
+
+    #[test]
+    fn kafka_source_stores_offsets_after_delivery() {
+        let messages = vec![
+            (vec![Event::from("zero")], 0),
+            (vec![], 1),
+            (vec![Event::from("two"), Event::from("two again")], 2),
+            (vec![Event::from("three")], 3),
+        ];
+        let stored = Arc::new(Mutex::new(Vec::new()));
+
+        let mut rt = runtime();
+        let (tx, rx) = mpsc::channel(1);
+        let stored_ref = Arc::clone(&stored);
+        rt.spawn(forward_and_store(
+            stream::iter_ok(messages),
+            tx,
+            move |offset| stored_ref.lock().unwrap().push(offset),
+        ));
+
+        // The channel fills up with the events of the third message, so its
+        // offset and those of the messages after it must not be stored yet.
+        wait_for(|| stored.lock().unwrap().len() == 2);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(*stored.lock().unwrap(), vec![0, 1]);
+
+        let events = rt.block_on(collect_n(rx, 4)).unwrap();
+        assert_eq!(events.len(), 4);
+        wait_for(|| stored.lock().unwrap().len() == 4);
+        assert_eq!(*stored.lock().unwrap(), vec![0, 1, 2, 3]);
+    }
+
+    // A mock cluster run by librdkafka, kept alive by the client it was
+    // created with, and the address other clients bootstrap from.
+    fn mock_cluster() -> (BaseConsumer, String) {
+        let cluster: BaseConsumer = ClientConfig::new()
+            .set("test.mock.num.brokers", "1")
+            .create()
+            .unwrap();
+        let metadata = cluster
+            .fetch_metadata(None, Duration::from_secs(5))
+            .unwrap();
+        let bootstrap_servers = metadata
+            .brokers()
+            .iter()
+            .map(|broker| format!("{}:{}", broker.host(), broker.port()))
+            .collect::<Vec<_>>()
+            .join(",");
+        (cluster, bootstrap_servers)
+    }
+
+    fn produce(bootstrap_servers: &str, messages: &[&str]) {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", bootstrap_servers)
+            .set("message.timeout.ms", "5000")
+            .create()
+            .unwrap();
+        for message in messages {
+            let record = FutureRecord::to("logs")
+                .partition(0)
+                .key(*message)
+                .payload(*message);
+            producer
+                .send_result(record)
+                .map_err(|(error, _)| error)
+                .unwrap()
+                .wait()
+                .unwrap()
+                .map_err(|(error, _)| error)
+                .unwrap();
+        }
+    }
+
+    /// Run the source until `count` events were taken out of its output, which
+    /// holds a single event, and then for a few commit intervals before
+    /// stopping it.
+    fn consume_messages(bootstrap_servers: &str, count: usize) -> Vec<String> {
+        let config = KafkaSourceConfig {
+            bootstrap_servers: bootstrap_servers.into(),
+            topics: vec!["logs".into()],
+            commit_interval_ms: 100,
+            ..make_config()
+        };
+        let decoding = config.decoding.build(config.max_length).unwrap();
+        let consumer = create_consumer(&config).unwrap();
+        // Assigning the partition directly doesn't wait on a group rebalance.
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition("logs", 0);
+        consumer.assign(&assignment).unwrap();
+
+        let mut rt = runtime();
+        let (tx, rx) = mpsc::channel(0);
+        rt.spawn(consume(config, decoding, consumer, tx));
+
+        let mut rx = rx.wait();
+        let messages = (0..count)
+            .map(|_| {
+                let event = rx.next().unwrap().unwrap();
+                event.as_log()[&event::MESSAGE].to_string_lossy()
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(500));
+        // Closing the consumer also commits the offsets stored last.
+        rt.shutdown_now().wait().unwrap();
+        messages
+    }
+
+    #[test]
+    fn kafka_source_commits_offsets_of_sent_events() {
+        let (_cluster, bootstrap_servers) = mock_cluster();
+        produce(&bootstrap_servers, &["zero", "one", "two"]);
+        assert_eq!(
+            consume_messages(&bootstrap_servers, 3),
+            vec!["zero", "one", "two"]
+        );
+
+        // Starting again picks up after the messages that were sent.
+        produce(&bootstrap_servers, &["three"]);
+        assert_eq!(consume_messages(&bootstrap_servers, 1), vec!["three"]);
+    }
+
+    #[test]
+    fn kafka_source_redelivers_unsent_messages_after_restart() {
+        let (_cluster, bootstrap_servers) = mock_cluster();
+        produce(&bootstrap_servers, &["zero", "one", "two", "three", "four"]);
+
+        // Once "zero" and "one" are taken out of the output, "two" is sent to
+        // it, and the source waits to send "three". All of the messages were
+        // fetched from the cluster by then, but only the offsets of the sent
+        // ones are stored.
+        assert_eq!(consume_messages(&bootstrap_servers, 2), vec!["zero", "one"]);
+        assert_eq!(
+            consume_messages(&bootstrap_servers, 2),
+            vec!["three", "four"]
+        );
+    }
 }
 
 #[cfg(feature = "kafka-integration-tests")]
@@ -229,8 +634,18 @@ mod integration_test {
             group_id: group_id.clone(),
             auto_offset_reset: "beginning".into(),
             session_timeout_ms: 6000,
+            commit_interval_ms: 1000,
             host_key: None,
             key_field: Some("message_key".to_string()),
+            topic_key: Some("topic".to_string()),
+            partition_key: Some("partition".to_string()),
+            offset_key: Some("offset".to_string()),
+            headers_key: None,
+            tls: None,
+            sasl: None,
+            librdkafka_options: None,
+            max_length: default_max_length(),
+            decoding: Default::default(),
         };
 
         let mut rt = runtime();
@@ -246,5 +661,8 @@ mod integration_test {
             events[0].as_log()[&Atom::from("message_key")],
             "my key".into()
         );
+        assert_eq!(events[0].as_log()[&Atom::from("topic")], topic.into());
+        assert_eq!(events[0].as_log()[&Atom::from("partition")], 0.into());
+        assert_eq!(events[0].as_log()[&Atom::from("offset")], 0.into());
     }
 }
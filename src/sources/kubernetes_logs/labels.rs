@@ -0,0 +1,295 @@
+use futures::{future, Future, Stream};
+use http::{header::AUTHORIZATION, Request, Uri};
+use hyper::{client::HttpConnector, Body, Client};
+use hyper_tls::HttpsConnector;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::timer::Interval;
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("invalid Kubernetes API endpoint {:?}: {}", endpoint, source))]
+    InvalidEndpoint {
+        endpoint: String,
+        source: http::uri::InvalidUri,
+    },
+    #[snafu(display("could not read CA file {:?}: {}", path, source))]
+    ReadCaFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("invalid CA certificate: {}", source))]
+    InvalidCa { source: native_tls::Error },
+    #[snafu(display("could not build TLS connector: {}", source))]
+    TlsBuild { source: native_tls::Error },
+}
+
+/// Where to look up the labels of the pods whose logs are collected.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PodLabelsConfig {
+    /// List the pods on this node from the Kubernetes API.
+    Api {
+        #[serde(default = "default_endpoint")]
+        endpoint: String,
+        node_name: Option<String>,
+        #[serde(default = "default_token_file")]
+        token_file: PathBuf,
+        #[serde(default = "default_ca_file")]
+        ca_file: PathBuf,
+        #[serde(default = "default_refresh_interval_secs")]
+        refresh_interval_secs: u64,
+    },
+    /// Read a pod list, in the same format as the API returns, from a file.
+    File {
+        path: PathBuf,
+        #[serde(default = "default_refresh_interval_secs")]
+        refresh_interval_secs: u64,
+    },
+}
+
+fn default_endpoint() -> String {
+    "https://kubernetes.default.svc".into()
+}
+
+fn default_token_file() -> PathBuf {
+    "/var/run/secrets/kubernetes.io/serviceaccount/token".into()
+}
+
+fn default_ca_file() -> PathBuf {
+    "/var/run/secrets/kubernetes.io/serviceaccount/ca.crt".into()
+}
+
+fn default_refresh_interval_secs() -> u64 {
+    60
+}
+
+impl PodLabelsConfig {
+    pub fn build(&self) -> Result<(Fetcher, Duration), crate::Error> {
+        match self {
+            PodLabelsConfig::Api {
+                endpoint,
+                node_name,
+                token_file,
+                ca_file,
+                refresh_interval_secs,
+            } => {
+                // Only the pods running on this node are of interest.
+                let node_name = node_name
+                    .clone()
+                    .or_else(|| std::env::var("VECTOR_NODE_NAME").ok());
+                let uri = match node_name {
+                    Some(node_name) => format!(
+                        "{}/api/v1/pods?fieldSelector=spec.nodeName%3D{}",
+                        endpoint.trim_end_matches('/'),
+                        node_name
+                    ),
+                    None => {
+                        warn!("No node name given, so the labels of all pods will be listed.");
+                        format!("{}/api/v1/pods", endpoint.trim_end_matches('/'))
+                    }
+                };
+                let uri = uri
+                    .parse::<Uri>()
+                    .with_context(|| InvalidEndpoint { endpoint })?;
+
+                let ca = fs::read(ca_file).with_context(|| ReadCaFile {
+                    path: ca_file.clone(),
+                })?;
+                let ca = native_tls::Certificate::from_pem(&ca).context(InvalidCa)?;
+                let tls = native_tls::TlsConnector::builder()
+                    .add_root_certificate(ca)
+                    .build()
+                    .context(TlsBuild)?;
+                let mut http = HttpConnector::new(1);
+                http.enforce_http(false);
+                let client = Client::builder().build(HttpsConnector::from((http, tls)));
+
+                let fetcher = Fetcher::Api {
+                    client,
+                    uri,
+                    token_file: token_file.clone(),
+                };
+                Ok((fetcher, Duration::from_secs(*refresh_interval_secs)))
+            }
+            PodLabelsConfig::File {
+                path,
+                refresh_interval_secs,
+            } => Ok((
+                Fetcher::File { path: path.clone() },
+                Duration::from_secs(*refresh_interval_secs),
+            )),
+        }
+    }
+}
+
+/// The labels of each known pod, keyed by pod uid.
+#[derive(Clone, Default)]
+pub struct PodLabels(Arc<RwLock<HashMap<String, BTreeMap<String, String>>>>);
+
+impl PodLabels {
+    pub fn get(&self, uid: &str) -> Option<BTreeMap<String, String>> {
+        self.0.read().unwrap().get(uid).cloned()
+    }
+
+    fn replace(&self, pods: HashMap<String, BTreeMap<String, String>>) {
+        *self.0.write().unwrap() = pods;
+    }
+
+    /// Fetch the labels of all pods once, replacing those known.
+    pub fn load(&self, fetcher: &Fetcher) -> impl Future<Item = (), Error = ()> {
+        let labels = self.clone();
+        fetcher.fetch().then(move |result| {
+            match result {
+                Ok(pods) => {
+                    debug!(message = "Loaded pod labels.", pods = pods.len());
+                    labels.replace(pods);
+                }
+                Err(error) => warn!(message = "Unable to fetch pod labels.", %error),
+            }
+            Ok(())
+        })
+    }
+
+    /// Fetch the labels of all pods again every `interval`.
+    pub fn refresh(
+        &self,
+        fetcher: Fetcher,
+        interval: Duration,
+    ) -> impl Future<Item = (), Error = ()> {
+        let labels = self.clone();
+        Interval::new(Instant::now() + interval, interval)
+            .map_err(|error| error!(message = "Timer error.", %error))
+            .for_each(move |_| labels.load(&fetcher))
+    }
+}
+
+pub enum Fetcher {
+    Api {
+        client: Client<HttpsConnector<HttpConnector>, Body>,
+        uri: Uri,
+        token_file: PathBuf,
+    },
+    File {
+        path: PathBuf,
+    },
+}
+
+impl Fetcher {
+    fn fetch(
+        &self,
+    ) -> Box<
+        dyn Future<Item = HashMap<String, BTreeMap<String, String>>, Error = crate::Error> + Send,
+    > {
+        match self {
+            Fetcher::Api {
+                client,
+                uri,
+                token_file,
+            } => {
+                // The service account token is rotated, so read it every time.
+                let token = match fs::read_to_string(token_file) {
+                    Ok(token) => token,
+                    Err(error) => return Box::new(future::err(error.into())),
+                };
+                let request = Request::get(uri.clone())
+                    .header(AUTHORIZATION, format!("Bearer {}", token.trim()))
+                    .body(Body::empty())
+                    .unwrap();
+
+                let fut = client
+                    .request(request)
+                    .and_then(|response| {
+                        let status = response.status();
+                        response
+                            .into_body()
+                            .concat2()
+                            .map(move |body| (status, body))
+                    })
+                    .map_err(|error| error.into())
+                    .and_then(|(status, body)| {
+                        if status.is_success() {
+                            parse_pod_list(&body)
+                        } else {
+                            Err(format!("Unexpected status: {}", status).into())
+                        }
+                    });
+                Box::new(fut)
+            }
+            Fetcher::File { path } => Box::new(future::result(
+                fs::read(path)
+                    .map_err(Into::into)
+                    .and_then(|body| parse_pod_list(&body)),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PodList {
+    items: Vec<Pod>,
+}
+
+#[derive(Deserialize)]
+struct Pod {
+    metadata: PodMetadata,
+}
+
+#[derive(Deserialize)]
+struct PodMetadata {
+    uid: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+fn parse_pod_list(body: &[u8]) -> Result<HashMap<String, BTreeMap<String, String>>, crate::Error> {
+    let list: PodList = serde_json::from_slice(body)?;
+    Ok(list
+        .items
+        .into_iter()
+        .map(|pod| (pod.metadata.uid, pod.metadata.labels))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_pod_list;
+
+    #[test]
+    fn parse_pod_labels() {
+        let body = br#"{
+            "kind": "PodList",
+            "apiVersion": "v1",
+            "items": [
+                {
+                    "metadata": {
+                        "name": "web-5d8c7b6d4f-abcde",
+                        "namespace": "default",
+                        "uid": "9b1a1c3e-9f2e-4b7e-8a51-0f1c2d3e4f50",
+                        "labels": {"app": "web", "tier": "frontend"}
+                    },
+                    "spec": {"nodeName": "node-1"}
+                },
+                {
+                    "metadata": {
+                        "name": "unlabeled",
+                        "namespace": "default",
+                        "uid": "0d7f6c2a-1b3e-4c5d-9e8f-7a6b5c4d3e2f"
+                    }
+                }
+            ]
+        }"#;
+
+        let pods = parse_pod_list(body).unwrap();
+        assert_eq!(pods.len(), 2);
+        let labels = &pods["9b1a1c3e-9f2e-4b7e-8a51-0f1c2d3e4f50"];
+        assert_eq!(labels["app"], "web");
+        assert_eq!(labels["tier"], "frontend");
+        assert!(pods["0d7f6c2a-1b3e-4c5d-9e8f-7a6b5c4d3e2f"].is_empty());
+    }
+}
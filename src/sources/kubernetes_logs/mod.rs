@@ -0,0 +1,297 @@
+use crate::{
+    event::{self, Event},
+    sources::file::{self, FileConfig, FingerprintingConfig},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use futures::{future, sync::mpsc, Future, Sink, Stream};
+use labels::{Fetcher, PodLabels, PodLabelsConfig};
+use lazy_static::lazy_static;
+use parser::Reassembler;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use stream_cancel::Tripwire;
+use string_cache::DefaultAtom as Atom;
+
+mod labels;
+pub(crate) mod parser;
+
+lazy_static! {
+    static ref FILE: Atom = Atom::from("file");
+    static ref STREAM: Atom = Atom::from("stream");
+    static ref NAMESPACE: Atom = Atom::from("kubernetes.pod_namespace");
+    static ref POD_NAME: Atom = Atom::from("kubernetes.pod_name");
+    static ref POD_UID: Atom = Atom::from("kubernetes.pod_uid");
+    static ref CONTAINER_NAME: Atom = Atom::from("kubernetes.container_name");
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KubernetesLogsConfig {
+    #[serde(default = "default_pods_dir")]
+    pub pods_dir: PathBuf,
+    pub data_dir: Option<PathBuf>,
+    pub pod_labels: Option<PodLabelsConfig>,
+}
+
+fn default_pods_dir() -> PathBuf {
+    "/var/log/pods".into()
+}
+
+#[typetag::serde(name = "kubernetes_logs")]
+impl SourceConfig for KubernetesLogsConfig {
+    fn build(
+        &self,
+        name: &str,
+        globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        let data_dir = globals.resolve_and_make_data_subdir(self.data_dir.as_ref(), name)?;
+        let pod_labels = match self.pod_labels {
+            Some(ref config) => Some(config.build()?),
+            None => None,
+        };
+
+        Ok(kubernetes_logs_source(
+            &self.pods_dir,
+            data_dir,
+            pod_labels,
+            out,
+        ))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn kubernetes_logs_source(
+    pods_dir: &Path,
+    data_dir: PathBuf,
+    pod_labels: Option<(Fetcher, Duration)>,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    // Kubernetes writes the logs of each container to
+    // `<pods_dir>/<namespace>_<pod_name>_<pod_uid>/<container_name>/<restart_count>.log`
+    let config = FileConfig {
+        include: vec![pods_dir.join("*").join("*").join("*.log")],
+        file_key: Some(FILE.to_string()),
+        // Container logs are often too short to be fingerprinted by checksum,
+        // and are rotated by renaming them out of the include pattern.
+        fingerprinting: FingerprintingConfig::DevInode,
+        ..FileConfig::default()
+    };
+
+    let (tx, rx) = mpsc::channel(100);
+    let files = file::file_source(&config, data_dir, tx);
+
+    let labels = PodLabels::default();
+    let mut reassembler = Reassembler::default();
+    let events = rx
+        .filter_map({
+            let labels = labels.clone();
+            move |event| transform(event, &mut reassembler, &labels)
+        })
+        .forward(out.sink_map_err(|e| error!(%e)))
+        .map(|_| ());
+
+    let (trigger, tripwire) = Tripwire::new();
+    Box::new(future::lazy(move || {
+        // Load the pod labels before reading any logs, so that they are
+        // attached from the very first event.
+        let loaded: Box<dyn Future<Item = (), Error = ()> + Send> = match pod_labels {
+            Some((fetcher, interval)) => Box::new(labels.load(&fetcher).map(move |()| {
+                let refresh = labels.refresh(fetcher, interval);
+                tokio::spawn(refresh.select(tripwire).map(|_| ()).map_err(|_| ()));
+            })),
+            None => Box::new(future::ok(())),
+        };
+
+        loaded
+            .and_then(move |()| files.select(events).map(|_| ()).map_err(|_| ()))
+            // Dropping the trigger stops refreshing the pod labels.
+            .inspect(|_| drop(trigger))
+    }))
+}
+
+/// Parse the container runtime's line out of `event`, adding the metadata of
+/// the container it came from.
+fn transform(mut event: Event, reassembler: &mut Reassembler, labels: &PodLabels) -> Option<Event> {
+    let file = event.as_log().get(&FILE)?.to_string_lossy();
+    let line = event.as_log().get(&event::MESSAGE)?.as_bytes();
+
+    let line = match parser::parse(&line) {
+        Ok(line) => line,
+        Err(error) => {
+            warn!(message = "Unable to parse container log line.", %error, file = file.as_str());
+            return None;
+        }
+    };
+    let line = reassembler.push(&file, line)?;
+
+    let log = event.as_mut_log();
+    log.insert_implicit(event::MESSAGE.clone(), line.message.into());
+    log.insert_implicit(event::TIMESTAMP.clone(), line.timestamp.into());
+    log.insert_explicit(STREAM.clone(), line.stream.into());
+
+    if let Some(container) = ContainerInfo::from_path(Path::new(&file)) {
+        if let Some(pod_labels) = labels.get(&container.pod_uid) {
+            for (key, value) in pod_labels {
+                log.insert_explicit(
+                    format!("kubernetes.pod_labels.{}", key).into(),
+                    value.into(),
+                );
+            }
+        }
+        log.insert_explicit(NAMESPACE.clone(), container.pod_namespace.into());
+        log.insert_explicit(POD_NAME.clone(), container.pod_name.into());
+        log.insert_explicit(POD_UID.clone(), container.pod_uid.into());
+        log.insert_explicit(CONTAINER_NAME.clone(), container.container_name.into());
+    }
+
+    Some(event)
+}
+
+#[derive(Debug, PartialEq)]
+struct ContainerInfo {
+    pod_namespace: String,
+    pod_name: String,
+    pod_uid: String,
+    container_name: String,
+}
+
+impl ContainerInfo {
+    /// Namespaces and pod names are DNS names, so can't contain underscores.
+    fn from_path(path: &Path) -> Option<Self> {
+        let container_dir = path.parent()?;
+        let pod_dir = container_dir.parent()?;
+        let container_name = container_dir.file_name()?.to_str()?;
+
+        let mut parts = pod_dir.file_name()?.to_str()?.splitn(3, '_');
+        let pod_namespace = parts.next()?;
+        let pod_name = parts.next()?;
+        let pod_uid = parts.next()?;
+
+        Some(ContainerInfo {
+            pod_namespace: pod_namespace.into(),
+            pod_name: pod_name.into(),
+            pod_uid: pod_uid.into(),
+            container_name: container_name.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ContainerInfo, KubernetesLogsConfig, PodLabelsConfig};
+    use crate::{
+        event,
+        test_util::{block_on, collect_n, shutdown_on_idle},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use futures::{sync::mpsc, Future};
+    use std::fs;
+    use std::path::Path;
+    use stream_cancel::Tripwire;
+    use tempfile::tempdir;
+
+    #[test]
+    fn container_info_from_path() {
+        assert_eq!(
+            ContainerInfo::from_path(Path::new(
+                "/var/log/pods/kube-system_coredns-5c98db65d4-2xcv7_5a6b1d2c-0e8f-4f3a-9b1c-2d3e4f5a6b7c/coredns/0.log"
+            )),
+            Some(ContainerInfo {
+                pod_namespace: "kube-system".into(),
+                pod_name: "coredns-5c98db65d4-2xcv7".into(),
+                pod_uid: "5a6b1d2c-0e8f-4f3a-9b1c-2d3e4f5a6b7c".into(),
+                container_name: "coredns".into(),
+            })
+        );
+        assert_eq!(
+            ContainerInfo::from_path(Path::new("/var/log/pods/not-a-pod/container/0.log")),
+            None
+        );
+    }
+
+    #[test]
+    fn kubernetes_logs_reads_containers() {
+        let dir = tempdir().unwrap();
+        let pods_dir = dir.path().join("pods");
+        let uid = "9b1a1c3e-9f2e-4b7e-8a51-0f1c2d3e4f50";
+
+        let cri_dir = pods_dir.join(format!("default_web_{}", uid)).join("app");
+        fs::create_dir_all(&cri_dir).unwrap();
+        fs::write(
+            cri_dir.join("0.log"),
+            "2019-06-25T18:40:12.000000001Z stdout P hello \n\
+             2019-06-25T18:40:12.000000002Z stdout F world\n\
+             2019-06-25T18:40:13.000000000Z stderr F oops\n",
+        )
+        .unwrap();
+
+        let docker_dir = pods_dir
+            .join("kube-system_proxy_0d7f6c2a-1b3e-4c5d-9e8f-7a6b5c4d3e2f")
+            .join("kube-proxy");
+        fs::create_dir_all(&docker_dir).unwrap();
+        fs::write(
+            docker_dir.join("1.log"),
+            "{\"log\":\"started\\n\",\"stream\":\"stdout\",\"time\":\"2019-06-25T18:40:14Z\"}\n",
+        )
+        .unwrap();
+
+        let labels_file = dir.path().join("pods.json");
+        fs::write(
+            &labels_file,
+            format!(
+                r#"{{"items": [{{"metadata": {{"uid": "{}", "labels": {{"app": "web"}}}}}}]}}"#,
+                uid
+            ),
+        )
+        .unwrap();
+
+        let config = KubernetesLogsConfig {
+            pods_dir,
+            data_dir: Some(dir.path().to_path_buf()),
+            pod_labels: Some(PodLabelsConfig::File {
+                path: labels_file,
+                refresh_interval_secs: 60,
+            }),
+        };
+
+        let (tx, rx) = mpsc::channel(10);
+        let (trigger, tripwire) = Tripwire::new();
+        let source = config
+            .build("kubernetes_logs", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source.select(tripwire).map(|_| ()).map_err(|_| ()));
+
+        let mut events = block_on(collect_n(rx, 3)).unwrap();
+        drop(trigger);
+        shutdown_on_idle(rt);
+
+        events.sort_by_key(|event| event.as_log()[&event::TIMESTAMP].as_timestamp().cloned());
+        let field = |index: usize, key: &str| events[index].as_log()[&key.into()].to_string_lossy();
+
+        assert_eq!(field(0, "message"), "hello world");
+        assert_eq!(field(0, "stream"), "stdout");
+        assert_eq!(field(0, "kubernetes.pod_namespace"), "default");
+        assert_eq!(field(0, "kubernetes.pod_name"), "web");
+        assert_eq!(field(0, "kubernetes.pod_uid"), uid);
+        assert_eq!(field(0, "kubernetes.container_name"), "app");
+        assert_eq!(field(0, "kubernetes.pod_labels.app"), "web");
+
+        assert_eq!(field(1, "message"), "oops");
+        assert_eq!(field(1, "stream"), "stderr");
+
+        assert_eq!(field(2, "message"), "started");
+        assert_eq!(field(2, "kubernetes.pod_namespace"), "kube-system");
+        assert_eq!(field(2, "kubernetes.container_name"), "kube-proxy");
+        assert!(events[2]
+            .as_log()
+            .get(&"kubernetes.pod_labels.app".into())
+            .is_none());
+    }
+}
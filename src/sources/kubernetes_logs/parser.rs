@@ -0,0 +1,227 @@
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+
+/// Partial lines are passed on once they grow this long, so that a line that
+/// is never finished doesn't keep growing in memory.
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Snafu)]
+pub enum ParseError {
+    #[snafu(display("invalid Docker JSON log line: {}", source))]
+    InvalidJson { source: serde_json::Error },
+    #[snafu(display("invalid CRI log line"))]
+    InvalidCri,
+    #[snafu(display("invalid timestamp {:?}: {}", timestamp, source))]
+    InvalidTimestamp {
+        timestamp: String,
+        source: chrono::ParseError,
+    },
+}
+
+/// A single line as written by the container runtime, which may only be part
+/// of a line the container logged.
+#[derive(Debug, PartialEq)]
+pub struct ContainerLine {
+    pub message: Bytes,
+    pub timestamp: DateTime<Utc>,
+    pub stream: String,
+    pub partial: bool,
+}
+
+#[derive(Deserialize)]
+struct DockerLine {
+    log: String,
+    stream: String,
+    time: String,
+}
+
+/// Parse a line in either the Docker JSON-file format:
+///
+/// `{"log":"message\n","stream":"stdout","time":"2019-06-25T18:40:12.178052862Z"}`
+///
+/// or the CRI format:
+///
+/// `2019-06-25T18:40:12.178052862Z stdout F message`
+pub fn parse(line: &[u8]) -> Result<ContainerLine, ParseError> {
+    if line.first() == Some(&b'{') {
+        parse_docker(line)
+    } else {
+        parse_cri(line)
+    }
+}
+
+fn parse_docker(line: &[u8]) -> Result<ContainerLine, ParseError> {
+    let line: DockerLine = serde_json::from_slice(line).context(InvalidJson)?;
+
+    // Docker splits long lines into 16KiB chunks, and only the last one ends
+    // with a newline.
+    let (message, partial) = if line.log.ends_with('\n') {
+        (&line.log[..line.log.len() - 1], false)
+    } else {
+        (&line.log[..], true)
+    };
+
+    Ok(ContainerLine {
+        message: message.into(),
+        timestamp: parse_timestamp(&line.time)?,
+        stream: line.stream,
+        partial,
+    })
+}
+
+fn parse_cri(line: &[u8]) -> Result<ContainerLine, ParseError> {
+    let mut parts = line.splitn(4, |&b| b == b' ');
+    let timestamp = parts.next().ok_or(ParseError::InvalidCri)?;
+    let stream = parts.next().ok_or(ParseError::InvalidCri)?;
+    let partial = match parts.next() {
+        Some(b"P") => true,
+        Some(b"F") => false,
+        _ => return Err(ParseError::InvalidCri),
+    };
+    let message = parts.next().unwrap_or(b"");
+
+    let timestamp = std::str::from_utf8(timestamp).map_err(|_| ParseError::InvalidCri)?;
+    let stream = std::str::from_utf8(stream).map_err(|_| ParseError::InvalidCri)?;
+
+    Ok(ContainerLine {
+        message: message.into(),
+        timestamp: parse_timestamp(timestamp)?,
+        stream: stream.into(),
+        partial,
+    })
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<DateTime<Utc>, ParseError> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .with_context(|| InvalidTimestamp {
+            timestamp: timestamp.to_owned(),
+        })
+}
+
+/// Joins partial lines back into the lines the container logged, separately
+/// for each stream of each of the sources, like files, they were read from.
+/// CRI files interleave the lines of both streams.
+#[derive(Default)]
+pub struct Reassembler {
+    partials: HashMap<(String, String), BytesMut>,
+}
+
+impl Reassembler {
+    /// Returns the complete line once `line` finishes it, or what there is of
+    /// it once it reaches `MAX_LINE_BYTES`.
+    pub fn push(&mut self, source: &str, mut line: ContainerLine) -> Option<ContainerLine> {
+        if !line.partial && self.partials.is_empty() {
+            return Some(line);
+        }
+
+        let key = (source.to_owned(), line.stream.clone());
+        let mut buffered = match self.partials.remove(&key) {
+            Some(buffered) => buffered,
+            None if !line.partial => return Some(line),
+            None => BytesMut::new(),
+        };
+        buffered.extend_from_slice(&line.message);
+
+        if line.partial && buffered.len() < MAX_LINE_BYTES {
+            self.partials.insert(key, buffered);
+            return None;
+        }
+        line.message = buffered.freeze();
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, ContainerLine, Reassembler, MAX_LINE_BYTES};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn parse_docker_lines() {
+        let line =
+            br#"{"log":"hello world\n","stream":"stdout","time":"2019-06-25T18:40:12.178052862Z"}"#;
+        assert_eq!(
+            parse(line).unwrap(),
+            ContainerLine {
+                message: "hello world".into(),
+                timestamp: Utc.ymd(2019, 6, 25).and_hms_nano(18, 40, 12, 178052862),
+                stream: "stdout".into(),
+                partial: false,
+            }
+        );
+
+        let line = br#"{"log":"hello ","stream":"stderr","time":"2019-06-25T18:40:12Z"}"#;
+        let line = parse(line).unwrap();
+        assert_eq!(line.message, "hello ");
+        assert_eq!(line.stream, "stderr");
+        assert!(line.partial);
+
+        assert!(parse(br#"{"log":"hello"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_cri_lines() {
+        let line = b"2019-06-25T18:40:12.178052862+00:00 stdout F hello world";
+        assert_eq!(
+            parse(line).unwrap(),
+            ContainerLine {
+                message: "hello world".into(),
+                timestamp: Utc.ymd(2019, 6, 25).and_hms_nano(18, 40, 12, 178052862),
+                stream: "stdout".into(),
+                partial: false,
+            }
+        );
+
+        let line = parse(b"2019-06-25T18:40:12Z stderr P hello ").unwrap();
+        assert_eq!(line.message, "hello ");
+        assert!(line.partial);
+
+        let line = parse(b"2019-06-25T18:40:12Z stdout F").unwrap();
+        assert_eq!(line.message, "");
+
+        assert!(parse(b"2019-06-25T18:40:12Z stdout X hello").is_err());
+        assert!(parse(b"not a timestamp stdout F hello").is_err());
+    }
+
+    #[test]
+    fn reassemble_partial_lines() {
+        let mut reassembler = Reassembler::default();
+        let lines = [
+            ("a.log", "2019-06-25T18:40:12Z stdout P hello "),
+            ("b.log", "2019-06-25T18:40:12Z stdout F other"),
+            ("a.log", "2019-06-25T18:40:12Z stderr P oh "),
+            ("a.log", "2019-06-25T18:40:12Z stdout P big "),
+            ("a.log", "2019-06-25T18:40:13Z stderr F no"),
+            ("a.log", "2019-06-25T18:40:13Z stdout F world"),
+            ("a.log", "2019-06-25T18:40:14Z stdout F next"),
+        ];
+
+        let complete = lines
+            .iter()
+            .filter_map(|(file, line)| reassembler.push(file, parse(line.as_bytes()).unwrap()))
+            .map(|line| String::from_utf8(line.message.to_vec()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(complete, vec!["other", "oh no", "hello big world", "next"]);
+    }
+
+    #[test]
+    fn reassemble_passes_on_overlong_partial_lines() {
+        let mut reassembler = Reassembler::default();
+        let chunk = "x".repeat(16 * 1024);
+        let partial = format!("2019-06-25T18:40:12Z stdout P {}", chunk);
+
+        let mut complete = Vec::new();
+        for _ in 0..(MAX_LINE_BYTES / chunk.len()) * 2 {
+            complete.extend(reassembler.push("a.log", parse(partial.as_bytes()).unwrap()));
+        }
+        assert_eq!(complete.len(), 2);
+        assert!(complete
+            .iter()
+            .all(|line| line.message.len() == MAX_LINE_BYTES));
+        assert!(reassembler.partials.is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{
+    event::{self, Event},
+    sources::syslog,
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use futures::{future, stream, sync::mpsc, Future, Sink, Stream};
+use hyper::{
+    header::HeaderMap, service::service_fn, Body, Method, Request, Response, Server, StatusCode,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, Snafu};
+use std::net::SocketAddr;
+use string_cache::DefaultAtom as Atom;
+
+lazy_static! {
+    static ref DRAIN_TOKEN: Atom = Atom::from("drain_token");
+}
+
+#[derive(Debug, Snafu)]
+enum FrameError {
+    #[snafu(display("frame at byte {} has no length", offset))]
+    MissingLength { offset: usize },
+    #[snafu(display("frame at byte {} has an invalid length", offset))]
+    InvalidLength { offset: usize },
+    #[snafu(display("frame at byte {} is truncated", offset))]
+    Truncated { offset: usize },
+}
+
+/// Receives the logs of Heroku apps through a Logplex HTTP(S) drain.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LogplexConfig {
+    pub address: SocketAddr,
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+}
+
+fn default_max_body_size() -> usize {
+    bytesize::mib(10u64) as usize
+}
+
+#[typetag::serde(name = "logplex")]
+impl SourceConfig for LogplexConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        Ok(logplex(self.address, self.max_body_size, out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn logplex(address: SocketAddr, max_body_size: usize, out: mpsc::Sender<Event>) -> super::Source {
+    Box::new(future::lazy(move || {
+        let new_service = move || {
+            let out = out.clone();
+            service_fn(move |request| handle(request, max_body_size, out.clone()))
+        };
+
+        info!(message = "listening.", %address, r#type = "http");
+
+        Server::bind(&address)
+            .serve(new_service)
+            .map_err(|error| error!(message = "server error.", %error))
+    }))
+}
+
+/// Accepts `POST` requests whose body is a sequence of octet-counted syslog
+/// frames, of up to `max_body_size` bytes, responding once their events are
+/// sent on.
+fn handle(
+    request: Request<Body>,
+    max_body_size: usize,
+    out: mpsc::Sender<Event>,
+) -> Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send> {
+    if request.method() != Method::POST {
+        return Box::new(future::ok(respond(StatusCode::METHOD_NOT_ALLOWED)));
+    }
+
+    let (parts, body) = request.into_parts();
+    let headers = parts.headers;
+
+    // A body over the limit ends the fold with no error, to be told apart
+    // from errors reading it.
+    let body = body.map_err(Some).fold(Vec::new(), move |mut body, chunk| {
+        if body.len() + chunk.len() > max_body_size {
+            return Err(None);
+        }
+        body.extend_from_slice(&chunk);
+        Ok(body)
+    });
+
+    Box::new(body.then(move |body| match body {
+        Ok(body) => future::Either::A(receive(&body, &headers, out)),
+        Err(None) => {
+            warn!(message = "logplex body is too large.", max_body_size);
+            future::Either::B(future::ok(respond(StatusCode::PAYLOAD_TOO_LARGE)))
+        }
+        Err(Some(error)) => future::Either::B(future::err(error)),
+    }))
+}
+
+fn receive(
+    body: &[u8],
+    headers: &HeaderMap,
+    out: mpsc::Sender<Event>,
+) -> impl Future<Item = Response<Body>, Error = hyper::Error> {
+    let frames = match parse_frames(body) {
+        Ok(frames) => frames,
+        Err(error) => {
+            warn!(message = "invalid logplex body.", %error);
+            return future::Either::A(future::ok(respond(StatusCode::BAD_REQUEST)));
+        }
+    };
+
+    let expected = header(headers, "Logplex-Msg-Count").and_then(|count| count.parse::<u64>().ok());
+    if let Some(expected) = expected {
+        if expected != frames.len() as u64 {
+            warn!(
+                message = "frame count doesn't match the message count header.",
+                expected,
+                received = frames.len() as u64,
+                frame_id = header(headers, "Logplex-Frame-Id").unwrap_or("-"),
+            );
+        }
+    }
+
+    let drain_token = header(headers, "Logplex-Drain-Token");
+    let events = frames
+        .into_iter()
+        .filter_map(|frame| frame_to_event(frame, drain_token))
+        .collect::<Vec<_>>();
+
+    future::Either::B(
+        out.send_all(stream::iter_ok::<_, mpsc::SendError<Event>>(events))
+            .then(|result| match result {
+                Ok(_) => Ok(respond(StatusCode::OK)),
+                Err(error) => {
+                    error!(message = "error sending event.", %error);
+                    Ok(respond(StatusCode::SERVICE_UNAVAILABLE))
+                }
+            }),
+    )
+}
+
+fn respond(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Split a body of the form `<length> <frame><length> <frame>...` into its
+/// frames, allowing newlines between them.
+fn parse_frames(body: &[u8]) -> Result<Vec<&[u8]>, FrameError> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    loop {
+        while body.get(offset).map_or(false, u8::is_ascii_whitespace) {
+            offset += 1;
+        }
+        if offset == body.len() {
+            return Ok(frames);
+        }
+
+        let rest = &body[offset..];
+        let space = rest
+            .iter()
+            .position(|&b| b == b' ')
+            .context(MissingLength { offset })?;
+        let end = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|length| length.parse::<usize>().ok())
+            .and_then(|length| (space + 1).checked_add(length))
+            .context(InvalidLength { offset })?;
+        let frame = rest.get(space + 1..end).context(Truncated { offset })?;
+
+        frames.push(frame);
+        offset += end;
+    }
+}
+
+/// Logplex frames are RFC 5424 messages without structured data, which is put
+/// back as nil for them to be parsed as syslog.
+fn frame_to_event(frame: &[u8], drain_token: Option<&str>) -> Option<Event> {
+    let frame = String::from_utf8_lossy(frame);
+    let mut fields = frame.splitn(7, ' ');
+    let prefix = (0..6)
+        .map(|_| fields.next())
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
+    let line = format!("{} - {}", prefix.join(" "), fields.next().unwrap_or(""));
+
+    let mut event = match syslog::parse_event(&event::HOST, None, &line) {
+        Some(event) => event,
+        None => {
+            warn!(
+                message = "failed to parse logplex frame.",
+                frame = frame.as_ref(),
+                decode_errors_counter = 1,
+            );
+            return None;
+        }
+    };
+    if let Some(drain_token) = drain_token {
+        event
+            .as_mut_log()
+            .insert_implicit(DRAIN_TOKEN.clone(), drain_token.into());
+    }
+    Some(event)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{frame_to_event, parse_frames, LogplexConfig};
+    use crate::{
+        event,
+        test_util::{collect_n, next_addr, wait_for_tcp},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use chrono::{TimeZone, Utc};
+    use futures::{sync::mpsc, Future};
+    use hyper::{Body, Client, Request, StatusCode};
+    use string_cache::DefaultAtom as Atom;
+
+    const BODY: &str = "\
+        83 <40>1 2012-11-30T06:45:29+00:00 host app web.3 - State changed from starting to up\n\
+        119 <40>1 2012-11-30T06:45:26+00:00 host app web.3 - Starting process with command `bundle exec rackup config.ru -p 24405`\n";
+
+    #[test]
+    fn logplex_parses_frames() {
+        let frames = parse_frames(BODY.as_bytes()).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].ends_with(b"starting to up\n"));
+        assert!(frames[1].starts_with(b"<40>1 2012-11-30T06:45:26+00:00"));
+
+        assert!(parse_frames(b"").unwrap().is_empty());
+        assert!(parse_frames(b"83").is_err());
+        assert!(parse_frames(b"x <40>1").is_err());
+        assert!(parse_frames(b"83 <40>1 2012-11-30T06:45:29+00:00").is_err());
+        assert!(parse_frames(b"18446744073709551615 <40>1").is_err());
+    }
+
+    #[test]
+    fn logplex_frames_become_events() {
+        let frame =
+            b"<158>1 2012-11-30T06:45:26+00:00 host heroku router - [abc] at=info method=GET";
+        let event = frame_to_event(frame, Some("d.8a6e2f4b")).unwrap();
+        let log = event.as_log();
+        assert_eq!(log[&event::MESSAGE], "[abc] at=info method=GET".into());
+        assert_eq!(
+            log[&event::TIMESTAMP],
+            Utc.ymd(2012, 11, 30).and_hms(6, 45, 26).into()
+        );
+        assert_eq!(log[&event::HOST], "host".into());
+        assert_eq!(log[&Atom::from("appname")], "heroku".into());
+        assert_eq!(log[&Atom::from("procid")], "router".into());
+        assert_eq!(log[&Atom::from("severity")], "info".into());
+        assert_eq!(log[&Atom::from("drain_token")], "d.8a6e2f4b".into());
+
+        assert!(frame_to_event(b"not syslog", None).is_none());
+    }
+
+    #[test]
+    fn logplex_receives_drains() {
+        let (tx, rx) = mpsc::channel(10);
+        let address = next_addr();
+        let config = LogplexConfig {
+            address,
+            max_body_size: BODY.len(),
+        };
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+        wait_for_tcp(address);
+
+        let request = |method: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(format!("http://{}/events", address).as_str())
+                .header("Content-Type", "application/logplex-1")
+                .header("Logplex-Msg-Count", "2")
+                .header("Logplex-Drain-Token", "d.8a6e2f4b")
+                .body(Body::from(body.to_owned()))
+                .unwrap()
+        };
+        let client = Client::new();
+        let mut status = |request| rt.block_on(client.request(request)).unwrap().status();
+
+        assert_eq!(status(request("GET", "")), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(status(request("POST", "83 <40>1")), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status(request("POST", &BODY.repeat(2))),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(status(request("POST", BODY)), StatusCode::OK);
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let log = events[0].as_log();
+        assert_eq!(
+            log[&event::MESSAGE],
+            "State changed from starting to up".into()
+        );
+        assert_eq!(log[&Atom::from("procid")], "web.3".into());
+        assert_eq!(log[&Atom::from("drain_token")], "d.8a6e2f4b".into());
+        let log = events[1].as_log();
+        assert_eq!(
+            log[&event::MESSAGE],
+            "Starting process with command `bundle exec rackup config.ru -p 24405`".into()
+        );
+    }
+}
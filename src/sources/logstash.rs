@@ -0,0 +1,498 @@
+use crate::{
+    event::{self, Event},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+    transforms::json_parser,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use flate2::read::ZlibDecoder;
+use futures::{future, stream::SplitSink, sync::mpsc, Future, Sink, Stream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    net::SocketAddr,
+};
+use stream_cancel::StreamExt;
+use string_cache::DefaultAtom as Atom;
+use tokio::{
+    codec::{Decoder, Encoder, Framed},
+    net::TcpStream,
+};
+
+/// Receives events from Beats, such as Filebeat, speaking version 2 of the
+/// Lumberjack protocol, as Logstash's `beats` input does.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LogstashConfig {
+    pub address: SocketAddr,
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+}
+
+fn default_max_length() -> usize {
+    bytesize::mib(64u64) as usize
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+impl LogstashConfig {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            max_length: default_max_length(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+        }
+    }
+}
+
+#[typetag::serde(name = "logstash")]
+impl SourceConfig for LogstashConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        let max_length = self.max_length;
+        super::util::listen(
+            self.address,
+            self.shutdown_timeout_secs,
+            move |socket, host, shutdown| {
+                let (writer, reader) =
+                    Framed::new(socket, LumberjackCodec::new(max_length)).split();
+
+                reader
+                    .take_until(shutdown)
+                    .map_err(|error| warn!(message = "connection error.", %error))
+                    .fold(
+                        (out.clone(), writer, Window::default()),
+                        move |(out, writer, window), frame| {
+                            handle_frame(frame, host.clone(), out, writer, window)
+                        },
+                    )
+                    .map(|_| debug!("connection closed."))
+            },
+        )
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+const VERSION: u8 = b'2';
+const WINDOW_SIZE: u8 = b'W';
+const COMPRESSED: u8 = b'C';
+const JSON: u8 = b'J';
+const ACK: u8 = b'A';
+
+#[derive(Debug, PartialEq)]
+enum Frame {
+    /// The number of events the client sends before waiting for an ACK.
+    WindowSize(u32),
+    /// An event, as JSON.
+    Json { sequence: u32, payload: Bytes },
+}
+
+/// Decodes Lumberjack frames, unpacking compressed frames into the frames
+/// they hold, and encodes ACKs of sequence numbers.
+struct LumberjackCodec {
+    max_length: usize,
+    pending: VecDeque<Frame>,
+}
+
+impl LumberjackCodec {
+    fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+enum RawFrame {
+    Frame(Frame),
+    Compressed(Bytes),
+}
+
+impl Decoder for LumberjackCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+        while self.pending.is_empty() {
+            match read_frame(buf, self.max_length)? {
+                None => return Ok(None),
+                Some(RawFrame::Frame(frame)) => return Ok(Some(frame)),
+                Some(RawFrame::Compressed(payload)) => {
+                    let mut payload = BytesMut::from(decompress(&payload, self.max_length)?);
+                    while !payload.is_empty() {
+                        match read_frame(&mut payload, self.max_length)? {
+                            Some(RawFrame::Frame(frame)) => self.pending.push_back(frame),
+                            Some(RawFrame::Compressed(_)) => {
+                                return Err(invalid_data("nested compressed frame"))
+                            }
+                            None => return Err(invalid_data("truncated compressed frame")),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl Encoder for LumberjackCodec {
+    type Item = u32;
+    type Error = io::Error;
+
+    fn encode(&mut self, sequence: u32, buf: &mut BytesMut) -> Result<(), io::Error> {
+        buf.reserve(6);
+        buf.put_u8(VERSION);
+        buf.put_u8(ACK);
+        buf.put_u32_be(sequence);
+        Ok(())
+    }
+}
+
+/// Read one frame from the start of `buf`, if all of it is there.
+fn read_frame(buf: &mut BytesMut, max_length: usize) -> Result<Option<RawFrame>, io::Error> {
+    let header = match buf.get(..2) {
+        Some([VERSION, WINDOW_SIZE]) => 6,
+        Some([VERSION, COMPRESSED]) => 6,
+        Some([VERSION, JSON]) => 10,
+        Some([VERSION, kind]) => {
+            return Err(invalid_data(format!(
+                "unsupported frame type {:?}",
+                char::from(*kind)
+            )))
+        }
+        Some([version, _]) => {
+            return Err(invalid_data(format!(
+                "unsupported protocol version {:?}",
+                char::from(*version)
+            )))
+        }
+        _ => return Ok(None),
+    };
+    if buf.len() < header {
+        return Ok(None);
+    }
+
+    let length = match buf[1] {
+        WINDOW_SIZE => 0,
+        _ => u32_at(buf, header - 4) as usize,
+    };
+    if length > max_length {
+        return Err(invalid_data(format!(
+            "frame of {} bytes is larger than {} bytes",
+            length, max_length
+        )));
+    }
+    if buf.len() < header + length {
+        return Ok(None);
+    }
+
+    let frame = buf.split_to(header + length).freeze();
+    Ok(Some(match frame[1] {
+        WINDOW_SIZE => RawFrame::Frame(Frame::WindowSize(u32_at(&frame, 2))),
+        COMPRESSED => RawFrame::Compressed(frame.slice_from(header)),
+        _ => RawFrame::Frame(Frame::Json {
+            sequence: u32_at(&frame, 2),
+            payload: frame.slice_from(header),
+        }),
+    }))
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_be_bytes(bytes)
+}
+
+fn decompress(payload: &[u8], max_length: usize) -> Result<Vec<u8>, io::Error> {
+    let mut buf = Vec::new();
+    ZlibDecoder::new(payload)
+        .take(max_length as u64 + 1)
+        .read_to_end(&mut buf)?;
+    if buf.len() > max_length {
+        return Err(invalid_data(format!(
+            "decompressed frame is larger than {} bytes",
+            max_length
+        )));
+    }
+    Ok(buf)
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// How far through the current window a connection is.
+#[derive(Clone, Copy, Debug, Default)]
+struct Window {
+    size: u32,
+    received: u32,
+}
+
+type AckWriter = SplitSink<Framed<TcpStream, LumberjackCodec>>;
+type FrameFuture =
+    Box<dyn Future<Item = (mpsc::Sender<Event>, AckWriter, Window), Error = ()> + Send>;
+
+/// Send the event of a frame on, and once the last event of a window has
+/// been sent, acknowledge its sequence number so the client sends the next.
+fn handle_frame(
+    frame: Frame,
+    peer: Option<Bytes>,
+    out: mpsc::Sender<Event>,
+    writer: AckWriter,
+    mut window: Window,
+) -> FrameFuture {
+    let (sequence, payload) = match frame {
+        Frame::WindowSize(size) => {
+            let window = Window { size, received: 0 };
+            return Box::new(future::ok((out, writer, window)));
+        }
+        Frame::Json { sequence, payload } => (sequence, payload),
+    };
+
+    let sent: Box<dyn Future<Item = mpsc::Sender<Event>, Error = ()> + Send> =
+        match parse_event(&payload, peer) {
+            Ok(event) => {
+                trace!(message = "Received one event.", ?event);
+                Box::new(
+                    out.send(event)
+                        .map_err(|error| error!(message = "error sending event.", %error)),
+                )
+            }
+            // Events that can't be parsed are acknowledged all the same, as
+            // the client would only send them again.
+            Err(error) => {
+                warn!(
+                    message = "dropping invalid event.",
+                    sequence = u64::from(sequence),
+                    %error,
+                    decode_errors_counter = 1,
+                );
+                Box::new(future::ok(out))
+            }
+        };
+
+    window.received += 1;
+    if window.received < window.size {
+        return Box::new(sent.map(move |out| (out, writer, window)));
+    }
+    window.received = 0;
+    Box::new(sent.and_then(move |out| {
+        writer
+            .send(sequence)
+            .map(move |writer| (out, writer, window))
+            .map_err(|error| warn!(message = "error writing ack.", %error))
+    }))
+}
+
+#[derive(Debug, Snafu)]
+enum ParseError {
+    #[snafu(display("Event is not valid JSON: {}", source))]
+    InvalidJson { source: serde_json::Error },
+    #[snafu(display("Event is not a JSON object"))]
+    NotAnObject,
+}
+
+/// Beats events become events with their fields, including `@metadata`, and
+/// the address of the client as `@metadata.ip_address`, as with Logstash.
+fn parse_event(payload: &[u8], peer: Option<Bytes>) -> Result<Event, ParseError> {
+    let fields = match serde_json::from_slice(payload).context(InvalidJson)? {
+        Value::Object(fields) => fields,
+        _ => return Err(ParseError::NotAnObject),
+    };
+
+    let mut event = Event::new_empty_log();
+    let mut timestamp = None;
+    for (name, value) in fields {
+        match (name.as_str(), value) {
+            ("@timestamp", Value::String(string)) => match DateTime::parse_from_rfc3339(&string) {
+                Ok(parsed) => timestamp = Some(parsed.with_timezone(&Utc)),
+                Err(_) => json_parser::insert(&mut event, name, Value::String(string)),
+            },
+            (_, value) => json_parser::insert(&mut event, name, value),
+        }
+    }
+
+    let log = event.as_mut_log();
+    log.insert_implicit(
+        event::TIMESTAMP.clone(),
+        timestamp.unwrap_or_else(Utc::now).into(),
+    );
+    if let Some(peer) = peer {
+        log.insert_explicit(Atom::from("@metadata.ip_address"), peer.into());
+    }
+
+    Ok(event)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_event, Frame, LogstashConfig, LumberjackCodec};
+    use crate::{
+        event,
+        test_util::{collect_n, next_addr, wait_for_tcp},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use bytes::{BufMut, Bytes, BytesMut};
+    use chrono::{TimeZone, Utc};
+    use flate2::{write::ZlibEncoder, Compression};
+    use futures::sync::mpsc;
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+    use string_cache::DefaultAtom as Atom;
+    use tokio::codec::{Decoder, Encoder};
+
+    fn window_size(size: u32) -> Vec<u8> {
+        let mut frame = b"2W".to_vec();
+        frame.put_u32_be(size);
+        frame
+    }
+
+    fn json(sequence: u32, payload: &str) -> Vec<u8> {
+        let mut frame = b"2J".to_vec();
+        frame.put_u32_be(sequence);
+        frame.put_u32_be(payload.len() as u32);
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    }
+
+    fn compressed(frames: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(frames).unwrap();
+        let payload = encoder.finish().unwrap();
+
+        let mut frame = b"2C".to_vec();
+        frame.put_u32_be(payload.len() as u32);
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    #[test]
+    fn logstash_decodes_frames() {
+        let mut inner = json(1, r#"{"message":"one"}"#);
+        inner.extend(json(2, r#"{"message":"two"}"#));
+        let mut frames = window_size(2);
+        frames.extend(compressed(&inner));
+
+        let mut codec = LumberjackCodec::new(1024);
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        // Feed the frames a byte at a time, as a slow client might.
+        for byte in frames {
+            buf.extend_from_slice(&[byte]);
+            while let Some(frame) = codec.decode(&mut buf).unwrap() {
+                decoded.push(frame);
+            }
+        }
+        assert!(buf.is_empty());
+        assert_eq!(
+            decoded,
+            vec![
+                Frame::WindowSize(2),
+                Frame::Json {
+                    sequence: 1,
+                    payload: Bytes::from(r#"{"message":"one"}"#),
+                },
+                Frame::Json {
+                    sequence: 2,
+                    payload: Bytes::from(r#"{"message":"two"}"#),
+                },
+            ]
+        );
+
+        let mut buf = BytesMut::new();
+        codec.encode(2, &mut buf).unwrap();
+        assert_eq!(&buf[..], b"2A\x00\x00\x00\x02");
+    }
+
+    #[test]
+    fn logstash_rejects_invalid_frames() {
+        for frames in &[
+            b"1W\x00\x00\x00\x01".to_vec(),
+            b"2D\x00\x00\x00\x01".to_vec(),
+            json(1, &"x".repeat(20)),
+            compressed(&json(1, &"x".repeat(20))),
+        ] {
+            let mut codec = LumberjackCodec::new(16);
+            assert!(codec.decode(&mut BytesMut::from(&frames[..])).is_err());
+        }
+    }
+
+    #[test]
+    fn logstash_parses_beats_events() {
+        let payload = r#"{
+            "@timestamp": "2019-10-02T07:06:40.512Z",
+            "@metadata": {"beat": "filebeat", "type": "_doc", "version": "7.4.0"},
+            "message": "GET /index.html 200",
+            "log": {"offset": 1024, "file": {"path": "/var/log/nginx/access.log"}},
+            "host": {"name": "web-1"},
+            "tags": ["nginx"]
+        }"#;
+        let event = parse_event(payload.as_bytes(), Some("10.0.0.5".into())).unwrap();
+        let log = event.as_log();
+
+        assert_eq!(
+            log[&event::TIMESTAMP],
+            Utc.ymd(2019, 10, 2).and_hms_milli(7, 6, 40, 512).into()
+        );
+        assert_eq!(log[&event::MESSAGE], "GET /index.html 200".into());
+        assert_eq!(log[&Atom::from("@metadata.beat")], "filebeat".into());
+        assert_eq!(log[&Atom::from("@metadata.version")], "7.4.0".into());
+        assert_eq!(log[&Atom::from("@metadata.ip_address")], "10.0.0.5".into());
+        assert_eq!(log[&Atom::from("log.offset")], 1024.into());
+        assert_eq!(log[&Atom::from("host.name")], "web-1".into());
+        assert_eq!(log[&Atom::from("tags[0]")], "nginx".into());
+        assert!(log.get(&Atom::from("@timestamp")).is_none());
+        assert!(log.is_structured());
+
+        assert!(parse_event(b"[1, 2]", None).is_err());
+        assert!(parse_event(b"{", None).is_err());
+    }
+
+    #[test]
+    fn logstash_acknowledges_windows() {
+        let (tx, rx) = mpsc::channel(10);
+        let address = next_addr();
+        let server = LogstashConfig::new(address)
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        wait_for_tcp(address);
+
+        let mut inner = json(1, r#"{"message":"one"}"#);
+        inner.extend(json(2, "not json"));
+        inner.extend(json(3, r#"{"message":"three"}"#));
+        let mut frames = window_size(3);
+        frames.extend(compressed(&inner));
+
+        let mut socket = TcpStream::connect(address).unwrap();
+        socket.write_all(&frames).unwrap();
+        let mut ack = [0; 6];
+        socket.read_exact(&mut ack).unwrap();
+        assert_eq!(&ack, b"2A\x00\x00\x00\x03");
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        assert_eq!(events[0].as_log()[&event::MESSAGE], "one".into());
+        assert_eq!(events[1].as_log()[&event::MESSAGE], "three".into());
+        assert_eq!(
+            events[1].as_log()[&Atom::from("@metadata.ip_address")],
+            "127.0.0.1".into()
+        );
+    }
+}
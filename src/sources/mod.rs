@@ -1,9 +1,24 @@
 use futures::Future;
 
+pub mod amqp;
+pub mod aws_kinesis_firehose;
+pub mod aws_s3;
+pub mod docker;
 pub mod file;
+pub mod gelf;
 pub mod journald;
 #[cfg(feature = "rdkafka")]
 pub mod kafka;
+pub mod kubernetes_logs;
+pub mod logplex;
+pub mod logstash;
+pub mod mqtt;
+pub mod nats;
+pub mod netflow;
+pub mod redis;
+pub mod server_status;
+pub mod snmp_trap;
+pub mod spool;
 pub mod statsd;
 pub mod stdin;
 pub mod syslog;
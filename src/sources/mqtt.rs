@@ -0,0 +1,386 @@
+use super::util::{reconnect, SessionError};
+use crate::{
+    event::Event,
+    mqtt::{self, ConnectionConfig, Connector, Packet, Publish, QoS, Transport},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use futures::{
+    future::{self, Either},
+    stream::{self, SplitSink},
+    sync::mpsc,
+    Future, Sink, Stream,
+};
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::{
+    collections::HashSet,
+    io,
+    time::{Duration, Instant},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::Interval;
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("at least one topic must be subscribed to"))]
+    NoTopics,
+}
+
+#[derive(Debug, Snafu)]
+enum ConnectionError {
+    #[snafu(display("{}", source))]
+    Broker { source: mqtt::Error },
+    #[snafu(display("MQTT broker refused every subscription"))]
+    SubscriptionsRefused,
+}
+
+/// Subscribes to topic filters on an MQTT broker, emitting an event per
+/// message published to them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MqttSourceConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    pub topics: Vec<String>,
+    /// The maximum QoS messages are delivered with.
+    #[serde(default = "default_qos")]
+    pub qos: QoS,
+    #[serde(default = "default_topic_key")]
+    pub topic_key: Atom,
+    #[serde(default = "default_keep_alive_secs")]
+    pub keep_alive_secs: u16,
+}
+
+fn default_qos() -> QoS {
+    QoS::AtLeastOnce
+}
+
+fn default_topic_key() -> Atom {
+    Atom::from("topic")
+}
+
+fn default_keep_alive_secs() -> u16 {
+    60
+}
+
+impl MqttSourceConfig {
+    pub fn new(connection: ConnectionConfig, topics: Vec<String>) -> Self {
+        Self {
+            connection,
+            topics,
+            qos: default_qos(),
+            topic_key: default_topic_key(),
+            keep_alive_secs: default_keep_alive_secs(),
+        }
+    }
+}
+
+#[typetag::serde(name = "mqtt")]
+impl SourceConfig for MqttSourceConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        if self.topics.is_empty() {
+            return Err(Box::new(BuildError::NoTopics));
+        }
+        let connector = self.connection.build(self.keep_alive_secs)?;
+        Ok(mqtt_source(connector, self.clone(), out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn mqtt_source(
+    connector: Connector,
+    config: MqttSourceConfig,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let subscribe = Packet::Subscribe {
+        packet_id: 1,
+        filters: config
+            .topics
+            .iter()
+            .map(|topic| (topic.clone(), config.qos))
+            .collect(),
+    };
+
+    let addr = connector.addr();
+    Box::new(reconnect(
+        addr,
+        move || connector.connect(),
+        move |transport| {
+            let topics = config.topics.clone();
+            let topic_key = config.topic_key.clone();
+            let keep_alive = config.keep_alive_secs;
+            let out = out.clone();
+
+            subscribe_to(transport, subscribe.clone(), topics)
+                .and_then(move |transport| session(transport, keep_alive, topic_key, out))
+        },
+    ))
+}
+
+fn subscribe_to(
+    transport: Transport,
+    subscribe: Packet,
+    topics: Vec<String>,
+) -> impl Future<Item = Transport, Error = SessionError<ConnectionError>> {
+    transport
+        .send(subscribe)
+        .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+        .map_err(io_error)
+        .and_then(move |(packet, transport)| match packet {
+            Some(Packet::SubAck { codes, .. }) => {
+                for (topic, code) in topics.iter().zip(&codes) {
+                    if *code == 0x80 {
+                        warn!(message = "subscription refused.", topic = topic.as_str());
+                    }
+                }
+                if codes.iter().all(|&code| code == 0x80) {
+                    Err(SessionError::Lost(ConnectionError::SubscriptionsRefused))
+                } else {
+                    info!(message = "subscribed.", topics = topics.join(", ").as_str());
+                    Ok(transport)
+                }
+            }
+            Some(packet) => Err(lost(mqtt::Error::Unexpected { packet })),
+            None => Err(lost(mqtt::Error::Closed)),
+        })
+}
+
+/// The connection and the ids of the messages received exactly once but not
+/// released yet.
+type State = (SplitSink<Transport>, mpsc::Sender<Event>, HashSet<u16>);
+
+enum Input {
+    Packet(Packet),
+    Ping,
+    Closed,
+}
+
+/// Forward the messages of a subscribed connection until it is lost, only
+/// acknowledging them once they are sent on.
+fn session(
+    transport: Transport,
+    keep_alive: u16,
+    topic_key: Atom,
+    out: mpsc::Sender<Event>,
+) -> impl Future<Item = (), Error = SessionError<ConnectionError>> {
+    let (writer, reader) = transport.split();
+
+    let pings: Box<dyn Stream<Item = Input, Error = io::Error> + Send> = if keep_alive == 0 {
+        Box::new(stream::empty())
+    } else {
+        // Pinging twice per keep alive leaves room for delays.
+        let period = Duration::from_millis(u64::from(keep_alive) * 500);
+        Box::new(
+            Interval::new(Instant::now() + period, period)
+                .map(|_| Input::Ping)
+                .map_err(|error| io::Error::new(io::ErrorKind::Other, error)),
+        )
+    };
+    let inputs = reader
+        .map(Input::Packet)
+        .chain(stream::once(Ok(Input::Closed)))
+        .select(pings);
+
+    inputs
+        .map_err(io_error)
+        .fold(
+            (writer, out, HashSet::new()),
+            move |(writer, out, mut unreleased),
+                  input|
+                  -> Box<
+                dyn Future<Item = State, Error = SessionError<ConnectionError>> + Send,
+            > {
+                let packet = match input {
+                    Input::Packet(packet) => packet,
+                    Input::Ping => {
+                        return Box::new(
+                            writer
+                                .send(Packet::PingReq)
+                                .map_err(io_error)
+                                .map(move |writer| (writer, out, unreleased)),
+                        )
+                    }
+                    Input::Closed => return Box::new(future::err(lost(mqtt::Error::Closed))),
+                };
+
+                match packet {
+                    Packet::Publish(publish) => {
+                        let (reply, forward) = match (publish.qos, publish.packet_id) {
+                            (QoS::AtLeastOnce, Some(id)) => (Some(Packet::PubAck(id)), true),
+                            // Messages sent exactly once may come again until
+                            // they are released, but are only forwarded once.
+                            (QoS::ExactlyOnce, Some(id)) => {
+                                (Some(Packet::PubRec(id)), unreleased.insert(id))
+                            }
+                            _ => (None, true),
+                        };
+
+                        let forwarded = if forward {
+                            let event = publish_to_event(publish, &topic_key);
+                            Either::A(out.send(event).map_err(|_| SessionError::OutputClosed))
+                        } else {
+                            Either::B(future::ok(out))
+                        };
+                        Box::new(forwarded.and_then(move |out| {
+                            match reply {
+                                Some(reply) => Either::A(
+                                    writer
+                                        .send(reply)
+                                        .map_err(io_error)
+                                        .map(move |writer| (writer, out, unreleased)),
+                                ),
+                                None => Either::B(future::ok((writer, out, unreleased))),
+                            }
+                        }))
+                    }
+                    Packet::PubRel(id) => {
+                        unreleased.remove(&id);
+                        Box::new(
+                            writer
+                                .send(Packet::PubComp(id))
+                                .map_err(io_error)
+                                .map(move |writer| (writer, out, unreleased)),
+                        )
+                    }
+                    Packet::PingResp => Box::new(future::ok((writer, out, unreleased))),
+                    packet => {
+                        debug!(message = "ignoring packet.", ?packet);
+                        Box::new(future::ok((writer, out, unreleased)))
+                    }
+                }
+            },
+        )
+        .map(|_| ())
+}
+
+fn publish_to_event(publish: Publish, topic_key: &Atom) -> Event {
+    let mut event = Event::from(publish.payload);
+    event
+        .as_mut_log()
+        .insert_implicit(topic_key.clone(), publish.topic.into());
+    event
+}
+
+fn lost(source: mqtt::Error) -> SessionError<ConnectionError> {
+    SessionError::Lost(ConnectionError::Broker { source })
+}
+
+fn io_error(source: io::Error) -> SessionError<ConnectionError> {
+    lost(mqtt::Error::Io { source })
+}
+
+#[cfg(test)]
+mod test {
+    use super::MqttSourceConfig;
+    use crate::{
+        event,
+        mqtt::{ConnectionConfig, MqttCodec, Packet, Publish, QoS},
+        test_util::{collect_n, next_addr},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use futures::{sync::mpsc, Future, Sink, Stream};
+    use string_cache::DefaultAtom as Atom;
+    use tokio::{codec::Framed, net::TcpListener};
+
+    fn publish(topic: &str, qos: QoS, packet_id: Option<u16>, payload: &str) -> Packet {
+        Packet::Publish(Publish {
+            topic: topic.into(),
+            qos,
+            packet_id,
+            retain: false,
+            dup: false,
+            payload: payload.into(),
+        })
+    }
+
+    #[test]
+    fn mqtt_source_receives_messages() {
+        let address = next_addr();
+        let listener = TcpListener::bind(&address).unwrap();
+
+        let mut connection = ConnectionConfig::new(address.to_string());
+        connection.username = Some("device".into());
+        connection.password = Some("secret".into());
+        let config = MqttSourceConfig::new(connection, vec!["sensors/+/temperature".into()]);
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        let broker = listener
+            .incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(socket, _)| {
+                Framed::new(socket.unwrap(), MqttCodec::new())
+                    .into_future()
+                    .map_err(|(error, _)| error)
+            })
+            .and_then(|(connect, transport)| {
+                match connect {
+                    Some(Packet::Connect(connect)) => {
+                        assert_eq!(connect.username, Some("device".into()));
+                        assert_eq!(connect.password, Some("secret".into()));
+                        assert!(connect.clean_session);
+                    }
+                    other => panic!("expected a connect, got {:?}", other),
+                }
+                transport.send(Packet::ConnAck {
+                    session_present: false,
+                    code: 0,
+                })
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(subscribe, transport)| {
+                assert_eq!(
+                    subscribe,
+                    Some(Packet::Subscribe {
+                        packet_id: 1,
+                        filters: vec![("sensors/+/temperature".into(), QoS::AtLeastOnce)],
+                    })
+                );
+                transport.send(Packet::SubAck {
+                    packet_id: 1,
+                    codes: vec![1],
+                })
+            })
+            .and_then(|transport| {
+                transport.send(publish(
+                    "sensors/1/temperature",
+                    QoS::AtLeastOnce,
+                    Some(42),
+                    "21.5",
+                ))
+            })
+            .and_then(|transport| {
+                transport.send(publish(
+                    "sensors/2/temperature",
+                    QoS::AtMostOnce,
+                    None,
+                    "19",
+                ))
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error));
+        let (ack, _transport) = rt.block_on(broker).unwrap();
+        assert_eq!(ack, Some(Packet::PubAck(42)));
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "21.5".into());
+        assert_eq!(log[&Atom::from("topic")], "sensors/1/temperature".into());
+        let log = events[1].as_log();
+        assert_eq!(log[&event::MESSAGE], "19".into());
+        assert_eq!(log[&Atom::from("topic")], "sensors/2/temperature".into());
+    }
+}
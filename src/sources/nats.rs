@@ -0,0 +1,310 @@
+use super::util::{reconnect, SessionError};
+use crate::{
+    event::Event,
+    nats::{self, unexpected, ConnectionConfig, Connector, Op, Transport},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use futures::{
+    future,
+    stream::{self, SplitSink},
+    sync::mpsc,
+    Future, Sink, Stream,
+};
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::Interval;
+
+/// How often the server is pinged, the connection being considered lost when
+/// two pings in a row go unanswered.
+const PING_INTERVAL: Duration = Duration::from_secs(120);
+const MAX_PINGS_OUT: usize = 2;
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("at least one subject must be subscribed to"))]
+    NoSubjects,
+    #[snafu(display("invalid subject or queue group: {:?}", name))]
+    InvalidName { name: String },
+}
+
+#[derive(Debug, Snafu)]
+enum ConnectionError {
+    #[snafu(display("{}", source))]
+    Server { source: nats::Error },
+    #[snafu(display("NATS server stopped answering pings"))]
+    Stale,
+}
+
+/// Subscribes to subjects on a NATS server, emitting an event per message
+/// published to them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NatsSourceConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    pub subjects: Vec<String>,
+    /// Each message is delivered to a single member of a queue group.
+    pub queue_group: Option<String>,
+    #[serde(default = "default_subject_key")]
+    pub subject_key: Atom,
+}
+
+fn default_subject_key() -> Atom {
+    Atom::from("subject")
+}
+
+impl NatsSourceConfig {
+    pub fn new(connection: ConnectionConfig, subjects: Vec<String>) -> Self {
+        Self {
+            connection,
+            subjects,
+            queue_group: None,
+            subject_key: default_subject_key(),
+        }
+    }
+}
+
+#[typetag::serde(name = "nats")]
+impl SourceConfig for NatsSourceConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        if self.subjects.is_empty() {
+            return Err(Box::new(BuildError::NoSubjects));
+        }
+        let mut names = self.subjects.iter().chain(&self.queue_group);
+        if let Some(name) = names.find(|name| !nats::is_valid_subject(name)) {
+            return Err(Box::new(BuildError::InvalidName { name: name.clone() }));
+        }
+        let connector = self.connection.build()?;
+        Ok(nats_source(connector, self.clone(), out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn nats_source(
+    connector: Connector,
+    config: NatsSourceConfig,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let subscriptions = config
+        .subjects
+        .iter()
+        .enumerate()
+        .map(|(i, subject)| Op::Sub {
+            subject: subject.clone(),
+            queue_group: config.queue_group.clone(),
+            sid: (i + 1).to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    let subjects = config.subjects.join(", ");
+    let addr = connector.addr();
+    Box::new(reconnect(
+        addr,
+        move || connector.connect(),
+        move |transport| {
+            let subjects = subjects.clone();
+            let subject_key = config.subject_key.clone();
+            let out = out.clone();
+
+            // Servers don't answer subscriptions, but report invalid ones as
+            // errors.
+            stream::iter_ok(subscriptions.clone())
+                .fold(transport, |transport, sub| transport.send(sub))
+                .map_err(io_error)
+                .and_then(move |transport| {
+                    info!(message = "subscribed.", subjects = subjects.as_str());
+                    session(transport, subject_key, out)
+                })
+        },
+    ))
+}
+
+/// The connection and how many pings went unanswered.
+type State = (SplitSink<Transport>, mpsc::Sender<Event>, usize);
+
+enum Input {
+    Op(Op),
+    Ping,
+    Closed,
+}
+
+/// Forward the messages of a subscribed connection until it is lost, keeping
+/// it alive on both ends.
+fn session(
+    transport: Transport,
+    subject_key: Atom,
+    out: mpsc::Sender<Event>,
+) -> impl Future<Item = (), Error = SessionError<ConnectionError>> {
+    let (writer, reader) = transport.split();
+
+    let pings = Interval::new(Instant::now() + PING_INTERVAL, PING_INTERVAL)
+        .map(|_| Input::Ping)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error));
+    let inputs = reader
+        .map(Input::Op)
+        .chain(stream::once(Ok(Input::Closed)))
+        .select(pings);
+
+    inputs
+        .map_err(io_error)
+        .fold(
+            (writer, out, 0),
+            move |(writer, out, pings_out),
+                  input|
+                  -> Box<
+                dyn Future<Item = State, Error = SessionError<ConnectionError>> + Send,
+            > {
+                let op = match input {
+                    Input::Op(op) => op,
+                    Input::Ping if pings_out >= MAX_PINGS_OUT => {
+                        return Box::new(future::err(SessionError::Lost(ConnectionError::Stale)))
+                    }
+                    Input::Ping => {
+                        return Box::new(
+                            writer
+                                .send(Op::Ping)
+                                .map_err(io_error)
+                                .map(move |writer| (writer, out, pings_out + 1)),
+                        )
+                    }
+                    Input::Closed => return Box::new(future::err(lost(nats::Error::Closed))),
+                };
+
+                match op {
+                    Op::Msg {
+                        subject, payload, ..
+                    } => {
+                        let mut event = Event::from(payload);
+                        event
+                            .as_mut_log()
+                            .insert_implicit(subject_key.clone(), subject.into());
+                        Box::new(
+                            out.send(event)
+                                .map_err(|_| SessionError::OutputClosed)
+                                .map(move |out| (writer, out, pings_out)),
+                        )
+                    }
+                    Op::Ping => Box::new(
+                        writer
+                            .send(Op::Pong)
+                            .map_err(io_error)
+                            .map(move |writer| (writer, out, pings_out)),
+                    ),
+                    Op::Pong => Box::new(future::ok((writer, out, 0))),
+                    Op::Ok | Op::Info(_) => Box::new(future::ok((writer, out, pings_out))),
+                    op => Box::new(future::err(lost(unexpected(Some(op))))),
+                }
+            },
+        )
+        .map(|_| ())
+}
+
+fn lost(source: nats::Error) -> SessionError<ConnectionError> {
+    SessionError::Lost(ConnectionError::Server { source })
+}
+
+fn io_error(source: io::Error) -> SessionError<ConnectionError> {
+    lost(nats::Error::Io { source })
+}
+
+#[cfg(test)]
+mod test {
+    use super::NatsSourceConfig;
+    use crate::{
+        event,
+        nats::{ConnectionConfig, NatsCodec, Op},
+        test_util::{collect_n, next_addr},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use futures::{sync::mpsc, Future, Sink, Stream};
+    use string_cache::DefaultAtom as Atom;
+    use tokio::{codec::Framed, net::TcpListener};
+
+    fn message(subject: &str, payload: &str) -> Op {
+        Op::Msg {
+            subject: subject.into(),
+            sid: "1".into(),
+            reply_to: None,
+            payload: payload.into(),
+        }
+    }
+
+    #[test]
+    fn nats_source_receives_messages() {
+        let address = next_addr();
+        let listener = TcpListener::bind(&address).unwrap();
+
+        let mut connection = ConnectionConfig::new(address.to_string());
+        connection.token = Some("secret".into());
+        let mut config = NatsSourceConfig::new(connection, vec!["logs.>".into()]);
+        config.queue_group = Some("vector".into());
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(socket, _)| {
+                Framed::new(socket.unwrap(), NatsCodec::new()).send(Op::Info("{}".into()))
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(connect, transport)| {
+                match connect {
+                    Some(Op::Connect(connect)) => {
+                        assert_eq!(connect.auth_token, Some("secret".into()));
+                        assert!(!connect.verbose);
+                    }
+                    other => panic!("expected a connect, got {:?}", other),
+                }
+                transport.into_future().map_err(|(error, _)| error)
+            })
+            .and_then(|(ping, transport)| {
+                assert_eq!(ping, Some(Op::Ping));
+                transport.send(Op::Pong)
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(sub, transport)| {
+                assert_eq!(
+                    sub,
+                    Some(Op::Sub {
+                        subject: "logs.>".into(),
+                        queue_group: Some("vector".into()),
+                        sid: "1".into(),
+                    })
+                );
+                transport.send(message("logs.web", "GET /"))
+            })
+            .and_then(|transport| transport.send(Op::Ping))
+            .and_then(|transport| transport.send(message("logs.db", "SELECT 1")))
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error));
+        let (pong, _transport) = rt.block_on(server).unwrap();
+        assert_eq!(pong, Some(Op::Pong));
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "GET /".into());
+        assert_eq!(log[&Atom::from("subject")], "logs.web".into());
+        let log = events[1].as_log();
+        assert_eq!(log[&event::MESSAGE], "SELECT 1".into());
+        assert_eq!(log[&Atom::from("subject")], "logs.db".into());
+    }
+}
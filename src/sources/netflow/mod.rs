@@ -0,0 +1,173 @@
+use crate::{
+    event::{self, Event, ValueKind},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use futures::{future, stream, sync::mpsc, Future, Sink, Stream};
+use parser::{FieldValue, Flow, FlowDecoder};
+use serde::{Deserialize, Serialize};
+use std::{io, net::SocketAddr};
+use string_cache::DefaultAtom as Atom;
+use tokio::{
+    codec::BytesCodec,
+    net::{UdpFramed, UdpSocket},
+};
+
+mod parser;
+
+/// Collects NetFlow v5, v9 and IPFIX flow records sent over UDP.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NetflowConfig {
+    pub address: SocketAddr,
+    pub host_key: Option<Atom>,
+    #[serde(default = "default_max_templates")]
+    pub max_templates: usize,
+}
+
+fn default_max_templates() -> usize {
+    10_000
+}
+
+impl NetflowConfig {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            host_key: None,
+            max_templates: default_max_templates(),
+        }
+    }
+}
+
+#[typetag::serde(name = "netflow")]
+impl SourceConfig for NetflowConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        let host_key = self.host_key.clone().unwrap_or(event::HOST.clone());
+        let decoder = FlowDecoder::new(self.max_templates);
+        Ok(netflow(self.address, host_key, decoder, out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn netflow(
+    address: SocketAddr,
+    host_key: Atom,
+    mut decoder: FlowDecoder,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let out = out.sink_map_err(|e| error!("error sending event: {:?}", e));
+
+    Box::new(
+        future::lazy(move || {
+            let socket = UdpSocket::bind(&address).expect("failed to bind to udp listener socket");
+
+            info!(message = "listening.", %address, r#type = "udp");
+
+            Ok(socket)
+        })
+        .and_then(move |socket| {
+            UdpFramed::new(socket, BytesCodec::new())
+                .map_err(|error: io::Error| error!(message = "error reading datagram.", %error))
+                .map(move |(packet, exporter)| {
+                    let flows = decoder
+                        .decode(exporter, &packet)
+                        .map_err(|error| {
+                            warn!(
+                                message = "failed to decode packet.",
+                                %exporter,
+                                %error,
+                                decode_errors_counter = 1,
+                            )
+                        })
+                        .unwrap_or_default();
+
+                    let host_key = host_key.clone();
+                    stream::iter_ok(
+                        flows
+                            .into_iter()
+                            .map(move |flow| flow_to_event(flow, &host_key, exporter)),
+                    )
+                })
+                .flatten()
+                .forward(out)
+                .map(|_| info!("finished sending"))
+        }),
+    )
+}
+
+/// Each flow record becomes an event with its fields, and the address of the
+/// exporter as the host.
+fn flow_to_event(flow: Flow, host_key: &Atom, exporter: SocketAddr) -> Event {
+    let mut event = Event::new_empty_log();
+    let log = event.as_mut_log();
+    log.insert_implicit(host_key.clone(), exporter.ip().to_string().into());
+
+    for (name, value) in flow {
+        let value = match value {
+            FieldValue::Integer(value) => ValueKind::Integer(value as i64),
+            FieldValue::Address(address) => address.to_string().into(),
+            FieldValue::Timestamp(timestamp) => timestamp.into(),
+            FieldValue::String(string) | FieldValue::Bytes(string) => string.into(),
+        };
+        log.insert_explicit(name.into(), value);
+    }
+
+    trace!(message = "Received one event.", ?event);
+    event
+}
+
+#[cfg(test)]
+mod test {
+    use super::NetflowConfig;
+    use crate::event;
+    use crate::test_util::{collect_n, next_addr};
+    use crate::topology::config::{GlobalOptions, SourceConfig};
+    use chrono::{TimeZone, Utc};
+    use futures::sync::mpsc;
+    use std::{net::UdpSocket, thread, time::Duration};
+    use string_cache::DefaultAtom as Atom;
+
+    #[test]
+    fn netflow_v5_events() {
+        let (tx, rx) = mpsc::channel(10);
+        let address = next_addr();
+        let server = NetflowConfig::new(address)
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        thread::sleep(Duration::from_millis(100));
+
+        let packet = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/netflow/v5.bin"
+        ));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(&[0, 1, 0, 0], address).unwrap();
+        socket.send_to(packet, address).unwrap();
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let log = events[0].as_log();
+
+        assert_eq!(log[&event::HOST], "127.0.0.1".into());
+        assert_eq!(
+            log[&event::TIMESTAMP],
+            Utc.timestamp(1570000000, 500_000_000).into()
+        );
+        assert_eq!(log[&Atom::from("src_addr")], "10.0.0.1".into());
+        assert_eq!(log[&Atom::from("dst_port")], 443.into());
+        assert_eq!(log[&Atom::from("bytes")], 1500.into());
+        assert!(log.is_structured());
+        assert_eq!(
+            events[1].as_log()[&Atom::from("dst_addr")],
+            "8.8.8.8".into()
+        );
+    }
+}
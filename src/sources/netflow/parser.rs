@@ -0,0 +1,766 @@
+use chrono::{DateTime, TimeZone, Utc};
+use snafu::Snafu;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+#[derive(Debug, Snafu, PartialEq)]
+pub enum ParseError {
+    #[snafu(display("Packet is truncated"))]
+    Truncated,
+    #[snafu(display("Unsupported version {}", version))]
+    UnsupportedVersion { version: u16 },
+    #[snafu(display("Invalid length of {} bytes", length))]
+    InvalidLength { length: u16 },
+    #[snafu(display("Template {} is invalid", id))]
+    InvalidTemplate { id: u16 },
+}
+
+/// A field of a flow record, which is turned into the matching `ValueKind`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Integer(u64),
+    Address(IpAddr),
+    Timestamp(DateTime<Utc>),
+    String(String),
+    /// Fields of unknown types, as hex.
+    Bytes(String),
+}
+
+pub type Flow = Vec<(Cow<'static, str>, FieldValue)>;
+
+/// Marks a variable length field in an IPFIX template.
+const VARIABLE_LENGTH: u16 = 65535;
+/// Set when an IPFIX field is followed by its private enterprise number.
+const ENTERPRISE_BIT: u16 = 0x8000;
+
+/// Decodes NetFlow v5, v9 and IPFIX packets, keeping the templates that v9
+/// and IPFIX exporters send to describe their flow records.
+pub struct FlowDecoder {
+    templates: HashMap<TemplateKey, Template>,
+    max_templates: usize,
+}
+
+/// Template ids are only unique to an exporter and the observation domain
+/// (or source id, in v9) it sends them for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct TemplateKey {
+    exporter: SocketAddr,
+    version: u16,
+    domain: u32,
+    id: u16,
+}
+
+#[derive(Debug, PartialEq)]
+struct Template {
+    fields: Vec<FieldSpec>,
+    /// Options records describe the exporter rather than flows.
+    options: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FieldSpec {
+    id: u16,
+    enterprise: Option<u32>,
+    length: u16,
+}
+
+/// Turns the relative times of flow records into timestamps.
+#[derive(Clone, Copy, Debug)]
+struct Clock {
+    export_ms: i64,
+    /// Milliseconds since the exporter booted, which IPFIX doesn't send.
+    uptime_ms: Option<u32>,
+}
+
+impl FlowDecoder {
+    pub fn new(max_templates: usize) -> Self {
+        FlowDecoder {
+            templates: HashMap::new(),
+            max_templates,
+        }
+    }
+
+    /// Decode the flow records of a packet sent by `exporter`. Records using
+    /// templates that haven't been received yet are dropped.
+    pub fn decode(&mut self, exporter: SocketAddr, packet: &[u8]) -> Result<Vec<Flow>, ParseError> {
+        let mut reader = Reader::new(packet);
+        match reader.u16()? {
+            5 => decode_v5(reader),
+            9 => self.decode_v9(exporter, reader),
+            10 => self.decode_ipfix(exporter, reader),
+            version => Err(ParseError::UnsupportedVersion { version }),
+        }
+    }
+
+    fn decode_v9(
+        &mut self,
+        exporter: SocketAddr,
+        mut reader: Reader,
+    ) -> Result<Vec<Flow>, ParseError> {
+        let _count = reader.u16()?;
+        let uptime_ms = reader.u32()?;
+        let secs = reader.u32()?;
+        let _sequence = reader.u32()?;
+        let source_id = reader.u32()?;
+
+        let clock = Clock {
+            export_ms: i64::from(secs) * 1000,
+            uptime_ms: Some(uptime_ms),
+        };
+        let header = vec![
+            ("flow_version".into(), FieldValue::Integer(9)),
+            ("source_id".into(), FieldValue::Integer(source_id.into())),
+            (
+                "timestamp".into(),
+                FieldValue::Timestamp(clock.export_time()),
+            ),
+        ];
+        self.decode_sets(exporter, 9, source_id, clock, &header, reader)
+    }
+
+    fn decode_ipfix(
+        &mut self,
+        exporter: SocketAddr,
+        mut reader: Reader,
+    ) -> Result<Vec<Flow>, ParseError> {
+        // The length covers the whole message, including the version and
+        // length already read.
+        let length = reader.u16()?;
+        if length < 16 || usize::from(length) - 4 > reader.remaining() {
+            return Err(ParseError::InvalidLength { length });
+        }
+        let mut reader = Reader::new(reader.take(usize::from(length) - 4)?);
+        let secs = reader.u32()?;
+        let _sequence = reader.u32()?;
+        let domain = reader.u32()?;
+
+        let clock = Clock {
+            export_ms: i64::from(secs) * 1000,
+            uptime_ms: None,
+        };
+        let header = vec![
+            ("flow_version".into(), FieldValue::Integer(10)),
+            (
+                "observation_domain_id".into(),
+                FieldValue::Integer(domain.into()),
+            ),
+            (
+                "timestamp".into(),
+                FieldValue::Timestamp(clock.export_time()),
+            ),
+        ];
+        self.decode_sets(exporter, 10, domain, clock, &header, reader)
+    }
+
+    /// Read the sets, or flowsets in v9, that follow the header. Each starts
+    /// with its id, telling templates and data apart, and its length.
+    fn decode_sets(
+        &mut self,
+        exporter: SocketAddr,
+        version: u16,
+        domain: u32,
+        clock: Clock,
+        header: &Flow,
+        mut reader: Reader,
+    ) -> Result<Vec<Flow>, ParseError> {
+        let key = |id| TemplateKey {
+            exporter,
+            version,
+            domain,
+            id,
+        };
+
+        let mut flows = Vec::new();
+        while reader.remaining() >= 4 {
+            let id = reader.u16()?;
+            let length = reader.u16()?;
+            if length < 4 {
+                return Err(ParseError::InvalidLength { length });
+            }
+            let mut set = Reader::new(reader.take(usize::from(length) - 4)?);
+
+            match (version, id) {
+                (9, 0) | (10, 2) => {
+                    while set.remaining() >= 4 {
+                        let id = set.u16()?;
+                        let field_count = set.u16()?;
+                        let fields = read_fields(&mut set, version, field_count)?;
+                        self.insert_template(key(id), fields, false)?;
+                    }
+                }
+                (9, 1) => {
+                    while set.remaining() >= 6 {
+                        let id = set.u16()?;
+                        let scope_length = set.u16()?;
+                        let option_length = set.u16()?;
+                        let field_count = (scope_length / 4) + (option_length / 4);
+                        let fields = read_fields(&mut set, version, field_count)?;
+                        self.insert_template(key(id), fields, true)?;
+                    }
+                }
+                (10, 3) => {
+                    while set.remaining() >= 4 {
+                        let id = set.u16()?;
+                        let field_count = set.u16()?;
+                        if field_count > 0 {
+                            let _scope_field_count = set.u16()?;
+                        }
+                        let fields = read_fields(&mut set, version, field_count)?;
+                        self.insert_template(key(id), fields, true)?;
+                    }
+                }
+                (_, id) if id >= 256 => match self.templates.get(&key(id)) {
+                    Some(template) if template.options => {
+                        trace!(message = "skipping options records.", template = id);
+                    }
+                    Some(template) => template.read_records(&mut set, clock, header, &mut flows)?,
+                    None => {
+                        warn!(
+                            message = "dropping records with an unknown template.",
+                            template = id,
+                            %exporter,
+                            unknown_template_counter = 1,
+                        );
+                    }
+                },
+                (_, id) => debug!(message = "skipping unknown set.", set = id),
+            }
+        }
+        Ok(flows)
+    }
+
+    /// Store a template, or withdraw it if it has no fields, which IPFIX
+    /// exporters do to reuse its id.
+    fn insert_template(
+        &mut self,
+        key: TemplateKey,
+        fields: Vec<FieldSpec>,
+        options: bool,
+    ) -> Result<(), ParseError> {
+        if key.id < 256 {
+            return Err(ParseError::InvalidTemplate { id: key.id });
+        }
+        if fields.is_empty() {
+            self.templates.remove(&key);
+            return Ok(());
+        }
+        if fields.iter().all(|field| field.length == 0) {
+            return Err(ParseError::InvalidTemplate { id: key.id });
+        }
+
+        if !self.templates.contains_key(&key) && self.templates.len() >= self.max_templates {
+            warn!(
+                message = "dropping template, too many templates are known.",
+                template = key.id,
+                exporter = %key.exporter,
+                max_templates = self.max_templates,
+            );
+            return Ok(());
+        }
+        self.templates.insert(key, Template { fields, options });
+        Ok(())
+    }
+}
+
+fn decode_v5(mut reader: Reader) -> Result<Vec<Flow>, ParseError> {
+    let count = reader.u16()?;
+    let uptime_ms = reader.u32()?;
+    let secs = reader.u32()?;
+    let nanos = reader.u32()?;
+    let _sequence = reader.u32()?;
+    let engine_type = reader.u8()?;
+    let engine_id = reader.u8()?;
+    // The top two bits are the sampling mode.
+    let sampling_interval = reader.u16()? & 0x3fff;
+
+    let clock = Clock {
+        export_ms: i64::from(secs) * 1000 + i64::from(nanos / 1_000_000),
+        uptime_ms: Some(uptime_ms),
+    };
+
+    let mut flows = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        let mut record = Reader::new(reader.take(48)?);
+        let src_addr = record.take(4)?;
+        let dst_addr = record.take(4)?;
+        let next_hop = record.take(4)?;
+        let input_interface = record.u16()?;
+        let output_interface = record.u16()?;
+        let packets = record.u32()?;
+        let bytes = record.u32()?;
+        let first = record.u32()?;
+        let last = record.u32()?;
+        let src_port = record.u16()?;
+        let dst_port = record.u16()?;
+        let _pad = record.u8()?;
+        let tcp_flags = record.u8()?;
+        let protocol = record.u8()?;
+        let tos = record.u8()?;
+        let src_as = record.u16()?;
+        let dst_as = record.u16()?;
+        let src_mask = record.u8()?;
+        let dst_mask = record.u8()?;
+
+        let mut flow: Flow = vec![
+            ("flow_version".into(), FieldValue::Integer(5)),
+            (
+                "engine_type".into(),
+                FieldValue::Integer(engine_type.into()),
+            ),
+            ("engine_id".into(), FieldValue::Integer(engine_id.into())),
+            (
+                "sampling_interval".into(),
+                FieldValue::Integer(sampling_interval.into()),
+            ),
+            (
+                "timestamp".into(),
+                FieldValue::Timestamp(clock.export_time()),
+            ),
+            ("src_addr".into(), FieldValue::Address(ipv4(src_addr))),
+            ("dst_addr".into(), FieldValue::Address(ipv4(dst_addr))),
+            ("next_hop".into(), FieldValue::Address(ipv4(next_hop))),
+            (
+                "input_interface".into(),
+                FieldValue::Integer(input_interface.into()),
+            ),
+            (
+                "output_interface".into(),
+                FieldValue::Integer(output_interface.into()),
+            ),
+            ("packets".into(), FieldValue::Integer(packets.into())),
+            ("bytes".into(), FieldValue::Integer(bytes.into())),
+            ("src_port".into(), FieldValue::Integer(src_port.into())),
+            ("dst_port".into(), FieldValue::Integer(dst_port.into())),
+            ("tcp_flags".into(), FieldValue::Integer(tcp_flags.into())),
+            ("protocol".into(), FieldValue::Integer(protocol.into())),
+            ("tos".into(), FieldValue::Integer(tos.into())),
+            ("src_as".into(), FieldValue::Integer(src_as.into())),
+            ("dst_as".into(), FieldValue::Integer(dst_as.into())),
+            ("src_mask".into(), FieldValue::Integer(src_mask.into())),
+            ("dst_mask".into(), FieldValue::Integer(dst_mask.into())),
+        ];
+        if let Some(start) = clock.at_uptime(first) {
+            flow.push(("flow_start".into(), FieldValue::Timestamp(start)));
+        }
+        if let Some(end) = clock.at_uptime(last) {
+            flow.push(("flow_end".into(), FieldValue::Timestamp(end)));
+        }
+        flows.push(flow);
+    }
+    Ok(flows)
+}
+
+fn read_fields(
+    reader: &mut Reader,
+    version: u16,
+    count: u16,
+) -> Result<Vec<FieldSpec>, ParseError> {
+    (0..count)
+        .map(|_| {
+            let id = reader.u16()?;
+            let length = reader.u16()?;
+            if version == 10 && id & ENTERPRISE_BIT != 0 {
+                Ok(FieldSpec {
+                    id: id & !ENTERPRISE_BIT,
+                    enterprise: Some(reader.u32()?),
+                    length,
+                })
+            } else {
+                Ok(FieldSpec {
+                    id,
+                    enterprise: None,
+                    length,
+                })
+            }
+        })
+        .collect()
+}
+
+impl Template {
+    fn read_records(
+        &self,
+        set: &mut Reader,
+        clock: Clock,
+        header: &Flow,
+        flows: &mut Vec<Flow>,
+    ) -> Result<(), ParseError> {
+        // Anything shorter than a record is padding.
+        let min_length: usize = self
+            .fields
+            .iter()
+            .map(|field| match field.length {
+                VARIABLE_LENGTH => 1,
+                length => usize::from(length),
+            })
+            .sum();
+
+        while set.remaining() >= min_length.max(1) {
+            let mut flow = header.clone();
+            for field in &self.fields {
+                let length = match field.length {
+                    VARIABLE_LENGTH => match set.u8()? {
+                        255 => set.u16()?,
+                        length => length.into(),
+                    },
+                    length => length,
+                };
+                let data = set.take(usize::from(length))?;
+                flow.push(field.decode(data, clock));
+            }
+            flows.push(flow);
+        }
+        Ok(())
+    }
+}
+
+/// How the value of a known field is decoded.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Integer,
+    Address,
+    /// Milliseconds since the exporter booted.
+    Uptime,
+    Seconds,
+    Milliseconds,
+    String,
+}
+
+/// Names and kinds of the common fields, by their IPFIX information element
+/// id, which NetFlow v9 shares.
+fn known_field(id: u16) -> Option<(&'static str, Kind)> {
+    Some(match id {
+        1 => ("bytes", Kind::Integer),
+        2 => ("packets", Kind::Integer),
+        4 => ("protocol", Kind::Integer),
+        5 => ("tos", Kind::Integer),
+        6 => ("tcp_flags", Kind::Integer),
+        7 => ("src_port", Kind::Integer),
+        8 => ("src_addr", Kind::Address),
+        9 => ("src_mask", Kind::Integer),
+        10 => ("input_interface", Kind::Integer),
+        11 => ("dst_port", Kind::Integer),
+        12 => ("dst_addr", Kind::Address),
+        13 => ("dst_mask", Kind::Integer),
+        14 => ("output_interface", Kind::Integer),
+        15 => ("next_hop", Kind::Address),
+        16 => ("src_as", Kind::Integer),
+        17 => ("dst_as", Kind::Integer),
+        21 => ("flow_end", Kind::Uptime),
+        22 => ("flow_start", Kind::Uptime),
+        27 => ("src_addr", Kind::Address),
+        28 => ("dst_addr", Kind::Address),
+        29 => ("src_mask", Kind::Integer),
+        30 => ("dst_mask", Kind::Integer),
+        32 => ("icmp_type", Kind::Integer),
+        60 => ("ip_version", Kind::Integer),
+        61 => ("direction", Kind::Integer),
+        62 => ("next_hop", Kind::Address),
+        96 => ("application_name", Kind::String),
+        150 => ("flow_start", Kind::Seconds),
+        151 => ("flow_end", Kind::Seconds),
+        152 => ("flow_start", Kind::Milliseconds),
+        153 => ("flow_end", Kind::Milliseconds),
+        _ => return None,
+    })
+}
+
+impl FieldSpec {
+    /// Decode a known field into its type, and anything else, including
+    /// known fields of an unexpected length, as `field_<id>`.
+    fn decode(&self, data: &[u8], clock: Clock) -> (Cow<'static, str>, FieldValue) {
+        if self.enterprise.is_none() {
+            if let Some((name, kind)) = known_field(self.id) {
+                if let Some(value) = kind.decode(data, clock) {
+                    return (name.into(), value);
+                }
+            }
+        }
+
+        let name = match self.enterprise {
+            Some(enterprise) => format!("field_{}_{}", enterprise, self.id),
+            None => format!("field_{}", self.id),
+        };
+        let value = match data.len() {
+            1..=8 => FieldValue::Integer(uint(data)),
+            _ => FieldValue::Bytes(data.iter().map(|byte| format!("{:02x}", byte)).collect()),
+        };
+        (name.into(), value)
+    }
+}
+
+impl Kind {
+    fn decode(self, data: &[u8], clock: Clock) -> Option<FieldValue> {
+        match (self, data.len()) {
+            (Kind::Integer, 1..=8) => Some(FieldValue::Integer(uint(data))),
+            (Kind::Address, 4) => Some(FieldValue::Address(ipv4(data))),
+            (Kind::Address, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                Some(FieldValue::Address(Ipv6Addr::from(octets).into()))
+            }
+            (Kind::Uptime, 4) => clock
+                .at_uptime(uint(data) as u32)
+                .map(FieldValue::Timestamp),
+            (Kind::Seconds, 4) => {
+                timestamp_millis(uint(data) as i64 * 1000).map(FieldValue::Timestamp)
+            }
+            (Kind::Milliseconds, 8) if uint(data) <= std::i64::MAX as u64 => {
+                timestamp_millis(uint(data) as i64).map(FieldValue::Timestamp)
+            }
+            (Kind::String, _) => Some(FieldValue::String(
+                String::from_utf8_lossy(data).into_owned(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Clock {
+    fn export_time(&self) -> DateTime<Utc> {
+        timestamp_millis(self.export_ms).expect("export time is in range")
+    }
+
+    /// Turn a time in milliseconds since the exporter booted into a
+    /// timestamp, relative to the time the packet was exported.
+    fn at_uptime(&self, ms: u32) -> Option<DateTime<Utc>> {
+        self.uptime_ms.and_then(|uptime_ms| {
+            timestamp_millis(self.export_ms - i64::from(uptime_ms) + i64::from(ms))
+        })
+    }
+}
+
+fn timestamp_millis(ms: i64) -> Option<DateTime<Utc>> {
+    if ms < 0 {
+        return None;
+    }
+    Utc.timestamp_opt(ms / 1000, (ms % 1000) as u32 * 1_000_000)
+        .single()
+}
+
+fn ipv4(data: &[u8]) -> IpAddr {
+    Ipv4Addr::new(data[0], data[1], data[2], data[3]).into()
+}
+
+/// Read a big endian unsigned integer of up to 8 bytes.
+fn uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |n, &byte| n << 8 | u64::from(byte))
+}
+
+/// Reads big endian integers from a packet, checking there are enough bytes
+/// left.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        if self.buf.len() < len {
+            return Err(ParseError::Truncated);
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ParseError> {
+        Ok(uint(self.take(2)?) as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, ParseError> {
+        Ok(uint(self.take(4)?) as u32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FieldValue, Flow, FlowDecoder, ParseError};
+    use chrono::{TimeZone, Utc};
+    use std::net::{IpAddr, SocketAddr};
+
+    const V5: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/netflow/v5.bin"
+    ));
+    const V9_TEMPLATES: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/netflow/v9_templates.bin"
+    ));
+    const V9_DATA: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/netflow/v9_data.bin"
+    ));
+    const IPFIX: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/netflow/ipfix.bin"
+    ));
+
+    fn exporter(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 100], port))
+    }
+
+    fn field<'a>(flow: &'a Flow, name: &str) -> &'a FieldValue {
+        &flow
+            .iter()
+            .find(|(field, _)| field == name)
+            .unwrap_or_else(|| panic!("no field {}", name))
+            .1
+    }
+
+    fn address(address: &str) -> FieldValue {
+        FieldValue::Address(address.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn netflow_decodes_v5() {
+        let flows = FlowDecoder::new(10).decode(exporter(2055), V5).unwrap();
+        assert_eq!(flows.len(), 2);
+
+        let flow = &flows[0];
+        assert_eq!(field(flow, "flow_version"), &FieldValue::Integer(5));
+        assert_eq!(field(flow, "sampling_interval"), &FieldValue::Integer(100));
+        assert_eq!(
+            field(flow, "timestamp"),
+            &FieldValue::Timestamp(Utc.timestamp(1570000000, 500_000_000))
+        );
+        assert_eq!(field(flow, "src_addr"), &address("10.0.0.1"));
+        assert_eq!(field(flow, "dst_addr"), &address("10.0.0.2"));
+        assert_eq!(field(flow, "next_hop"), &address("10.0.0.254"));
+        assert_eq!(field(flow, "src_port"), &FieldValue::Integer(51000));
+        assert_eq!(field(flow, "dst_port"), &FieldValue::Integer(443));
+        assert_eq!(field(flow, "protocol"), &FieldValue::Integer(6));
+        assert_eq!(field(flow, "tcp_flags"), &FieldValue::Integer(0x1b));
+        assert_eq!(field(flow, "packets"), &FieldValue::Integer(10));
+        assert_eq!(field(flow, "bytes"), &FieldValue::Integer(1500));
+        assert_eq!(field(flow, "src_as"), &FieldValue::Integer(64512));
+        // Exported 360s after boot, so a flow starting 300s after boot
+        // started a minute before the export.
+        assert_eq!(
+            field(flow, "flow_start"),
+            &FieldValue::Timestamp(Utc.timestamp(1569999940, 500_000_000))
+        );
+        assert_eq!(
+            field(flow, "flow_end"),
+            &FieldValue::Timestamp(Utc.timestamp(1569999990, 500_000_000))
+        );
+
+        assert_eq!(field(&flows[1], "dst_addr"), &address("8.8.8.8"));
+        assert_eq!(field(&flows[1], "protocol"), &FieldValue::Integer(17));
+    }
+
+    #[test]
+    fn netflow_decodes_v9_with_templates() {
+        let mut decoder = FlowDecoder::new(10);
+
+        // Data can't be decoded before its template arrives.
+        assert!(decoder.decode(exporter(2055), V9_DATA).unwrap().is_empty());
+
+        assert!(decoder
+            .decode(exporter(2055), V9_TEMPLATES)
+            .unwrap()
+            .is_empty());
+        assert_eq!(decoder.templates.len(), 2);
+
+        let flows = decoder.decode(exporter(2055), V9_DATA).unwrap();
+        assert_eq!(flows.len(), 2);
+
+        let flow = &flows[0];
+        assert_eq!(field(flow, "flow_version"), &FieldValue::Integer(9));
+        assert_eq!(field(flow, "source_id"), &FieldValue::Integer(7));
+        assert_eq!(field(flow, "src_addr"), &address("10.1.0.1"));
+        assert_eq!(field(flow, "dst_addr"), &address("10.1.0.2"));
+        assert_eq!(field(flow, "src_port"), &FieldValue::Integer(40000));
+        assert_eq!(field(flow, "dst_port"), &FieldValue::Integer(80));
+        assert_eq!(field(flow, "bytes"), &FieldValue::Integer(4200));
+        assert_eq!(field(flow, "packets"), &FieldValue::Integer(7));
+        assert_eq!(field(flow, "input_interface"), &FieldValue::Integer(5));
+        assert_eq!(field(flow, "field_234"), &FieldValue::Integer(1));
+        assert_eq!(
+            field(flow, "flow_start"),
+            &FieldValue::Timestamp(Utc.timestamp(1569999940, 0))
+        );
+        assert_eq!(
+            field(flow, "flow_end"),
+            &FieldValue::Timestamp(Utc.timestamp(1569999990, 0))
+        );
+        assert_eq!(field(&flows[1], "dst_addr"), &address("1.1.1.1"));
+
+        // Templates are only used for the exporter that sent them.
+        assert!(decoder.decode(exporter(2056), V9_DATA).unwrap().is_empty());
+    }
+
+    #[test]
+    fn netflow_decodes_ipfix() {
+        let flows = FlowDecoder::new(10).decode(exporter(4739), IPFIX).unwrap();
+        assert_eq!(flows.len(), 2);
+
+        let flow = &flows[0];
+        assert_eq!(field(flow, "flow_version"), &FieldValue::Integer(10));
+        assert_eq!(
+            field(flow, "observation_domain_id"),
+            &FieldValue::Integer(42)
+        );
+        assert_eq!(
+            field(flow, "timestamp"),
+            &FieldValue::Timestamp(Utc.timestamp(1570000000, 0))
+        );
+        assert_eq!(field(flow, "src_addr"), &address("2001:db8::1"));
+        assert_eq!(field(flow, "dst_addr"), &address("2001:db8::2"));
+        assert_eq!(field(flow, "dst_port"), &FieldValue::Integer(443));
+        assert_eq!(field(flow, "bytes"), &FieldValue::Integer(123456));
+        assert_eq!(field(flow, "packets"), &FieldValue::Integer(100));
+        assert_eq!(
+            field(flow, "flow_start"),
+            &FieldValue::Timestamp(Utc.timestamp(1569999990, 123_000_000))
+        );
+        assert_eq!(field(flow, "field_29305_1"), &FieldValue::Integer(7));
+        assert_eq!(
+            field(flow, "application_name"),
+            &FieldValue::String("https".into())
+        );
+        assert_eq!(
+            field(&flows[1], "application_name"),
+            &FieldValue::String("dns".into())
+        );
+    }
+
+    #[test]
+    fn netflow_limits_templates() {
+        let mut decoder = FlowDecoder::new(1);
+        decoder.decode(exporter(2055), V9_TEMPLATES).unwrap();
+        assert_eq!(decoder.templates.len(), 1);
+    }
+
+    #[test]
+    fn netflow_rejects_invalid_packets() {
+        let mut decoder = FlowDecoder::new(10);
+        assert_eq!(
+            decoder.decode(exporter(2055), &[0, 7, 0, 0]),
+            Err(ParseError::UnsupportedVersion { version: 7 })
+        );
+        assert_eq!(
+            decoder.decode(exporter(2055), &V5[..100]),
+            Err(ParseError::Truncated)
+        );
+        assert_eq!(
+            decoder.decode(exporter(4739), &IPFIX[..IPFIX.len() - 1]),
+            Err(ParseError::InvalidLength {
+                length: IPFIX.len() as u16
+            })
+        );
+    }
+}
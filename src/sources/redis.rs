@@ -0,0 +1,351 @@
+use super::util::{reconnect, SessionError};
+use crate::{
+    event::Event,
+    redis::{self, command, request, unexpected, ConnectionConfig, Connector, Transport, Value},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use bytes::Bytes;
+use futures::{
+    future::{self, Either, Loop},
+    stream,
+    sync::mpsc,
+    Future, Sink, Stream,
+};
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::io;
+use string_cache::DefaultAtom as Atom;
+
+/// How many seconds `BLPOP` blocks for before it is sent again, so that the
+/// connection doesn't stay idle for long.
+const BLPOP_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("at least one key must be given"))]
+    NoKeys,
+}
+
+/// Pops events from Redis lists, or receives those published to channels.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedisSourceConfig {
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+    /// The lists to pop from, or the channels or patterns to subscribe to.
+    pub keys: Vec<String>,
+    #[serde(default = "default_method")]
+    pub method: Method,
+    pub key_field: Option<Atom>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    Blpop,
+    Subscribe,
+    Psubscribe,
+}
+
+impl Method {
+    fn command(self) -> &'static str {
+        match self {
+            Method::Blpop => "BLPOP",
+            Method::Subscribe => "SUBSCRIBE",
+            Method::Psubscribe => "PSUBSCRIBE",
+        }
+    }
+}
+
+fn default_method() -> Method {
+    Method::Blpop
+}
+
+impl RedisSourceConfig {
+    pub fn new(connection: ConnectionConfig, keys: Vec<String>) -> Self {
+        Self {
+            connection,
+            keys,
+            method: default_method(),
+            key_field: None,
+        }
+    }
+}
+
+#[typetag::serde(name = "redis")]
+impl SourceConfig for RedisSourceConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        if self.keys.is_empty() {
+            return Err(Box::new(BuildError::NoKeys));
+        }
+        let connector = self.connection.build()?;
+        Ok(redis_source(connector, self.clone(), out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn redis_source(
+    connector: Connector,
+    config: RedisSourceConfig,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let mut args = vec![config.method.command().to_string()];
+    args.extend(config.keys.iter().cloned());
+    if config.method == Method::Blpop {
+        args.push(BLPOP_TIMEOUT_SECS.to_string());
+    }
+    let command = command(args);
+
+    let addr = connector.addr();
+    Box::new(reconnect(
+        addr,
+        move || connector.connect(),
+        move |transport| {
+            let key_field = config.key_field.clone();
+            let out = out.clone();
+            match config.method {
+                Method::Blpop => Either::A(pop(transport, command.clone(), key_field, out)),
+                _ => Either::B(subscribe(transport, command.clone(), key_field, out)),
+            }
+        },
+    ))
+}
+
+/// Pop the lists one element at a time, until the connection is lost.
+fn pop(
+    transport: Transport,
+    blpop: Value,
+    key_field: Option<Atom>,
+    out: mpsc::Sender<Event>,
+) -> impl Future<Item = (), Error = SessionError<redis::Error>> {
+    info!(message = "popping lists.");
+    future::loop_fn((transport, out), move |(transport, out)| {
+        let key_field = key_field.clone();
+        request(transport, blpop.clone())
+            .map_err(SessionError::Lost)
+            .and_then(move |(reply, transport)| {
+                let element = match &reply {
+                    // Popping timed out.
+                    Value::Nil => return Either::A(future::ok(Loop::Continue((transport, out)))),
+                    Value::Array(values) => match &values[..] {
+                        [Value::Data(key), Value::Data(payload)] => {
+                            Some((key.clone(), payload.clone()))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match element {
+                    Some((key, payload)) => Either::B(
+                        out.send(to_event(key, payload, &key_field))
+                            .map_err(|_| SessionError::OutputClosed)
+                            .map(move |out| Loop::Continue((transport, out))),
+                    ),
+                    None => Either::A(future::err(SessionError::Lost(unexpected(Some(reply))))),
+                }
+            })
+    })
+}
+
+/// Forward the messages of the channels subscribed to, until the connection is
+/// lost.
+fn subscribe(
+    transport: Transport,
+    subscribe: Value,
+    key_field: Option<Atom>,
+    out: mpsc::Sender<Event>,
+) -> impl Future<Item = (), Error = SessionError<redis::Error>> {
+    transport
+        .send(subscribe)
+        .map_err(io_error)
+        .and_then(|transport| {
+            transport
+                .map_err(io_error)
+                .chain(stream::once(Err(SessionError::Lost(redis::Error::Closed))))
+                .fold(out, move |out, reply| {
+                    let message = match &reply {
+                        Value::Array(values) => match &values[..] {
+                            [Value::Data(kind), Value::Data(channel), Value::Data(payload)]
+                                if kind == "message" =>
+                            {
+                                Some((channel.clone(), payload.clone()))
+                            }
+                            // Messages matching a pattern start with it.
+                            [Value::Data(kind), _, Value::Data(channel), Value::Data(payload)]
+                                if kind == "pmessage" =>
+                            {
+                                Some((channel.clone(), payload.clone()))
+                            }
+                            // Subscriptions are confirmed one key at a time.
+                            [Value::Data(kind), Value::Data(key), Value::Integer(_)]
+                                if kind == "subscribe" || kind == "psubscribe" =>
+                            {
+                                info!(
+                                    message = "subscribed.",
+                                    key = %String::from_utf8_lossy(key)
+                                );
+                                return Either::A(future::ok(out));
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match message {
+                        Some((channel, payload)) => Either::B(
+                            out.send(to_event(channel, payload, &key_field))
+                                .map_err(|_| SessionError::OutputClosed),
+                        ),
+                        None => Either::A(future::err(SessionError::Lost(unexpected(Some(reply))))),
+                    }
+                })
+        })
+        .map(|_| ())
+}
+
+fn io_error(source: io::Error) -> SessionError<redis::Error> {
+    SessionError::Lost(redis::Error::Io { source })
+}
+
+fn to_event(key: Bytes, payload: Bytes, key_field: &Option<Atom>) -> Event {
+    let mut event = Event::from(payload);
+    if let Some(key_field) = key_field {
+        event
+            .as_mut_log()
+            .insert_implicit(key_field.clone(), key.into());
+    }
+    event
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Method, RedisSourceConfig};
+    use crate::{
+        event,
+        redis::{command, ConnectionConfig, RespCodec, Value},
+        test_util::{collect_n, next_addr},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use futures::{sync::mpsc, Future, Sink, Stream};
+    use string_cache::DefaultAtom as Atom;
+    use tokio::{codec::Framed, net::TcpListener};
+
+    fn data(values: &[&str]) -> Value {
+        command(values.to_vec())
+    }
+
+    fn blpop() -> Value {
+        data(&["BLPOP", "logs:web", "logs:db", "30"])
+    }
+
+    #[test]
+    fn redis_source_pops_lists() {
+        let address = next_addr();
+        let listener = TcpListener::bind(&address).unwrap();
+
+        let mut connection = ConnectionConfig::new(address.to_string());
+        connection.database = Some(2);
+        let mut config =
+            RedisSourceConfig::new(connection, vec!["logs:web".into(), "logs:db".into()]);
+        config.key_field = Some("key".into());
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(socket, _)| {
+                Framed::new(socket.unwrap(), RespCodec::new())
+                    .into_future()
+                    .map_err(|(error, _)| error)
+            })
+            .and_then(|(select, transport)| {
+                assert_eq!(select, Some(data(&["SELECT", "2"])));
+                transport.send(Value::Status("OK".into()))
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(command, transport)| {
+                assert_eq!(command, Some(blpop()));
+                transport.send(data(&["logs:db", "SELECT 1"]))
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(command, transport)| {
+                assert_eq!(command, Some(blpop()));
+                transport.send(Value::Nil)
+            })
+            .and_then(|transport| transport.into_future().map_err(|(error, _)| error))
+            .and_then(|(command, transport)| {
+                assert_eq!(command, Some(blpop()));
+                transport.send(data(&["logs:web", "GET /"]))
+            });
+        let _transport = rt.block_on(server).unwrap();
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "SELECT 1".into());
+        assert_eq!(log[&Atom::from("key")], "logs:db".into());
+        let log = events[1].as_log();
+        assert_eq!(log[&event::MESSAGE], "GET /".into());
+        assert_eq!(log[&Atom::from("key")], "logs:web".into());
+    }
+
+    #[test]
+    fn redis_source_subscribes_to_patterns() {
+        let address = next_addr();
+        let listener = TcpListener::bind(&address).unwrap();
+
+        let mut config = RedisSourceConfig::new(
+            ConnectionConfig::new(address.to_string()),
+            vec!["logs.*".into()],
+        );
+        config.method = Method::Psubscribe;
+        config.key_field = Some("channel".into());
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(socket, _)| {
+                Framed::new(socket.unwrap(), RespCodec::new())
+                    .into_future()
+                    .map_err(|(error, _)| error)
+            })
+            .and_then(|(subscribe, transport)| {
+                assert_eq!(subscribe, Some(data(&["PSUBSCRIBE", "logs.*"])));
+                transport.send(Value::Array(vec![
+                    Value::Data("psubscribe".into()),
+                    Value::Data("logs.*".into()),
+                    Value::Integer(1),
+                ]))
+            })
+            .and_then(|transport| {
+                transport.send(data(&["pmessage", "logs.*", "logs.web", "GET /"]))
+            });
+        let _transport = rt.block_on(server).unwrap();
+
+        let events = rt.block_on(collect_n(rx, 1)).ok().unwrap();
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "GET /".into());
+        assert_eq!(log[&Atom::from("channel")], "logs.web".into());
+    }
+}
@@ -0,0 +1,160 @@
+//! The machine readable page of `mod_status`, requested with `?auto`, which
+//! reads like:
+//!
+//! ```text
+//! localhost
+//! ServerVersion: Apache/2.4.41 (Unix)
+//! Total Accesses: 131
+//! Total kBytes: 138
+//! CPULoad: .0233
+//! ServerUptimeSeconds: 1234
+//! BusyWorkers: 1
+//! IdleWorkers: 74
+//! ConnsTotal: 1
+//! ConnsAsyncWriting: 0
+//! ConnsAsyncKeepAlive: 0
+//! ConnsAsyncClosing: 0
+//! Scoreboard: __W___K....
+//! ```
+//!
+//! Only the workers and scoreboard are always there, the rest depending on
+//! `ExtendedStatus` and the MPM in use.
+
+use super::{parse_value, ParseError, Sample};
+use std::collections::HashMap;
+
+/// The states of the scoreboard, one character per worker slot.
+const SCOREBOARD: [(char, &str); 11] = [
+    ('_', "waiting"),
+    ('S', "starting"),
+    ('R', "reading"),
+    ('W', "sending"),
+    ('K', "keepalive"),
+    ('D', "dns_lookup"),
+    ('C', "closing"),
+    ('L', "logging"),
+    ('G', "finishing"),
+    ('I', "idle_cleanup"),
+    ('.', "open"),
+];
+
+pub fn parse(page: &str) -> Result<Vec<Sample>, ParseError> {
+    let fields = page
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            Some((parts.next()?.trim(), parts.next()?.trim()))
+        })
+        .collect::<HashMap<_, _>>();
+    let value = |field: &'static str| parse_value(field, fields.get(field).cloned());
+    let optional = |field: &'static str| match fields.get(field) {
+        Some(_) => value(field).map(Some),
+        None => Ok(None),
+    };
+
+    let mut samples = vec![
+        Sample::gauge("apache_workers", Some("busy"), value("BusyWorkers")?),
+        Sample::gauge("apache_workers", Some("idle"), value("IdleWorkers")?),
+    ];
+
+    if let Some(accesses) = optional("Total Accesses")? {
+        samples.push(Sample::counter("apache_requests_total", accesses));
+    }
+    if let Some(kbytes) = optional("Total kBytes")? {
+        samples.push(Sample::counter("apache_sent_bytes_total", kbytes * 1024.0));
+    }
+    if let Some(load) = optional("CPULoad")? {
+        samples.push(Sample::gauge("apache_cpu_load", None, load));
+    }
+    if let Some(uptime) = optional("ServerUptimeSeconds")? {
+        samples.push(Sample::gauge("apache_uptime_seconds", None, uptime));
+    }
+
+    let connections = [
+        ("ConnsTotal", "total"),
+        ("ConnsAsyncWriting", "writing"),
+        ("ConnsAsyncKeepAlive", "keepalive"),
+        ("ConnsAsyncClosing", "closing"),
+    ];
+    for &(field, state) in connections.iter() {
+        if let Some(connections) = optional(field)? {
+            samples.push(Sample::gauge(
+                "apache_connections",
+                Some(state),
+                connections,
+            ));
+        }
+    }
+
+    let scoreboard = fields.get("Scoreboard").ok_or(ParseError::Missing {
+        field: "Scoreboard",
+    })?;
+    for &(slot, state) in SCOREBOARD.iter() {
+        let count = scoreboard.chars().filter(|c| *c == slot).count();
+        samples.push(Sample::gauge(
+            "apache_scoreboard",
+            Some(state),
+            count as f64,
+        ));
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use crate::sources::server_status::{ParseError, Sample};
+
+    #[test]
+    fn apache_parses_status() {
+        let page = "localhost\n\
+                    ServerVersion: Apache/2.4.41 (Unix)\n\
+                    Server Built: Aug 14 2019 00:35:10\n\
+                    Total Accesses: 131\n\
+                    Total kBytes: 138\n\
+                    CPULoad: .0233\n\
+                    ServerUptimeSeconds: 1234\n\
+                    BusyWorkers: 1\n\
+                    IdleWorkers: 74\n\
+                    ConnsTotal: 2\n\
+                    ConnsAsyncWriting: 0\n\
+                    ConnsAsyncKeepAlive: 1\n\
+                    ConnsAsyncClosing: 0\n\
+                    Scoreboard: __W___K....\n";
+
+        let samples = parse(page).unwrap();
+        for sample in &[
+            Sample::gauge("apache_workers", Some("busy"), 1.0),
+            Sample::gauge("apache_workers", Some("idle"), 74.0),
+            Sample::counter("apache_requests_total", 131.0),
+            Sample::counter("apache_sent_bytes_total", 141_312.0),
+            Sample::gauge("apache_cpu_load", None, 0.0233),
+            Sample::gauge("apache_uptime_seconds", None, 1234.0),
+            Sample::gauge("apache_connections", Some("total"), 2.0),
+            Sample::gauge("apache_connections", Some("keepalive"), 1.0),
+            Sample::gauge("apache_scoreboard", Some("waiting"), 5.0),
+            Sample::gauge("apache_scoreboard", Some("sending"), 1.0),
+            Sample::gauge("apache_scoreboard", Some("keepalive"), 1.0),
+            Sample::gauge("apache_scoreboard", Some("open"), 4.0),
+            Sample::gauge("apache_scoreboard", Some("reading"), 0.0),
+        ] {
+            assert!(samples.contains(sample), "missing {:?}", sample);
+        }
+        assert_eq!(samples.len(), 21);
+    }
+
+    #[test]
+    fn apache_parses_minimal_status() {
+        let samples = parse("BusyWorkers: 1\nIdleWorkers: 4\nScoreboard: _W___\n").unwrap();
+        assert_eq!(samples.len(), 13);
+    }
+
+    #[test]
+    fn apache_rejects_other_pages() {
+        match parse("<html><body><h1>Apache Server Status</h1></body></html>") {
+            Err(ParseError::Missing { field }) => assert_eq!(field, "BusyWorkers"),
+            other => panic!("expected a missing field, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{
+    event::{Event, Metric},
+    sinks::util::http::HttpService,
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use chrono::Utc;
+use futures::{future, stream, sync::mpsc, Future, Sink, Stream};
+use http::{Method, StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::timer::{Interval, Timeout};
+use tower::Service;
+
+mod apache;
+mod nginx;
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("at least one endpoint must be given"))]
+    NoEndpoints,
+    #[snafu(display("invalid endpoint {:?}: {}", endpoint, source))]
+    InvalidEndpoint {
+        endpoint: String,
+        source: http::uri::InvalidUri,
+    },
+}
+
+#[derive(Debug, Snafu)]
+pub enum ParseError {
+    #[snafu(display("missing {}", field))]
+    Missing { field: &'static str },
+    #[snafu(display("invalid {}: {:?}", field, value))]
+    InvalidValue { field: &'static str, value: String },
+}
+
+#[derive(Debug, Snafu)]
+enum ScrapeError {
+    #[snafu(display("request failed: {}", source))]
+    Request { source: hyper::Error },
+    #[snafu(display("request timed out"))]
+    TimedOut,
+    #[snafu(display("server responded with {}", status))]
+    UnexpectedStatus { status: StatusCode },
+    #[snafu(display("invalid status page: {}", source))]
+    InvalidPage { source: ParseError },
+}
+
+/// Scrapes the status pages of nginx or Apache servers on an interval,
+/// emitting their connections, requests and workers as metrics.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ServerStatusConfig {
+    pub server: Server,
+    pub endpoints: Vec<String>,
+    #[serde(default = "default_scrape_interval_secs")]
+    pub scrape_interval_secs: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Server {
+    /// The page of the `stub_status` module.
+    Nginx,
+    /// The machine readable page of `mod_status`.
+    Apache,
+}
+
+impl Server {
+    fn parse(self, page: &str) -> Result<Vec<Sample>, ParseError> {
+        match self {
+            Server::Nginx => nginx::parse(page),
+            Server::Apache => apache::parse(page),
+        }
+    }
+
+    fn up_metric(self) -> &'static str {
+        match self {
+            Server::Nginx => "nginx_up",
+            Server::Apache => "apache_up",
+        }
+    }
+}
+
+fn default_scrape_interval_secs() -> u64 {
+    15
+}
+
+impl ServerStatusConfig {
+    pub fn new(server: Server, endpoints: Vec<String>) -> Self {
+        Self {
+            server,
+            endpoints,
+            scrape_interval_secs: default_scrape_interval_secs(),
+        }
+    }
+}
+
+#[typetag::serde(name = "server_status")]
+impl SourceConfig for ServerStatusConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        if self.endpoints.is_empty() {
+            return Err(Box::new(BuildError::NoEndpoints));
+        }
+        let interval = Duration::from_secs(self.scrape_interval_secs);
+        let scrapers = self
+            .endpoints
+            .iter()
+            .map(|endpoint| Scraper::new(self.server, endpoint, interval))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(server_status(scrapers, interval, out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Metric
+    }
+}
+
+fn server_status(
+    scrapers: Vec<Scraper>,
+    interval: Duration,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let out = out.sink_map_err(|e| error!("error sending metric: {:?}", e));
+
+    Box::new(
+        Interval::new(Instant::now(), interval)
+            .map_err(|error| error!(message = "timer error.", %error))
+            .fold((scrapers, out), |(scrapers, out), _| {
+                future::join_all(scrapers.into_iter().map(Scraper::scrape)).and_then(|scraped| {
+                    let (scrapers, events): (Vec<_>, Vec<_>) = scraped.into_iter().unzip();
+                    out.send_all(stream::iter_ok(events.into_iter().flatten()))
+                        .map(|(out, _)| (scrapers, out))
+                })
+            })
+            .map(|_| info!("finished sending")),
+    )
+}
+
+/// A value read from a status page. Counters are totals since the server
+/// started, and are sent as increments since the previous scrape.
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    name: &'static str,
+    state: Option<&'static str>,
+    kind: Kind,
+    value: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+impl Sample {
+    fn counter(name: &'static str, value: f64) -> Self {
+        Self {
+            name,
+            state: None,
+            kind: Kind::Counter,
+            value,
+        }
+    }
+
+    fn gauge(name: &'static str, state: Option<&'static str>, value: f64) -> Self {
+        Self {
+            name,
+            state,
+            kind: Kind::Gauge,
+            value,
+        }
+    }
+}
+
+/// Parse the value of a field, which is missing when `None`.
+fn parse_value(field: &'static str, value: Option<&str>) -> Result<f64, ParseError> {
+    let value = value.ok_or(ParseError::Missing { field })?;
+    value.parse().map_err(|_| ParseError::InvalidValue {
+        field,
+        value: value.into(),
+    })
+}
+
+struct Scraper {
+    server: Server,
+    endpoint: String,
+    service: HttpService,
+    timeout: Duration,
+    /// The counters of the previous scrape.
+    totals: HashMap<(&'static str, Option<&'static str>), f64>,
+}
+
+impl Scraper {
+    fn new(server: Server, endpoint: &str, timeout: Duration) -> Result<Self, BuildError> {
+        // The page of `mod_status` is only machine readable with `?auto`.
+        let uri = match server {
+            Server::Apache if !endpoint.contains('?') => format!("{}?auto", endpoint),
+            _ => endpoint.to_string(),
+        };
+        let uri = uri.parse::<Uri>().context(InvalidEndpoint { endpoint })?;
+
+        let service = HttpService::new(move |body| {
+            let mut builder = hyper::Request::builder();
+            builder.method(Method::GET);
+            builder.uri(uri.clone());
+            builder.body(body).unwrap()
+        });
+
+        Ok(Self {
+            server,
+            endpoint: endpoint.to_string(),
+            service,
+            timeout,
+            totals: HashMap::new(),
+        })
+    }
+
+    /// Scrape the page once, resolving with the metrics read from it and
+    /// whether the server is up. Failures are logged, so that the other
+    /// endpoints keep being scraped.
+    fn scrape(mut self) -> impl Future<Item = (Self, Vec<Event>), Error = ()> {
+        let server = self.server;
+        Timeout::new(self.service.call(Vec::new()), self.timeout)
+            .map_err(|error| match error.into_inner() {
+                Some(source) => ScrapeError::Request { source },
+                None => ScrapeError::TimedOut,
+            })
+            .and_then(move |response| {
+                if !response.status().is_success() {
+                    return Err(ScrapeError::UnexpectedStatus {
+                        status: response.status(),
+                    });
+                }
+                let page = String::from_utf8_lossy(response.body());
+                server.parse(&page).context(InvalidPage)
+            })
+            .then(move |result| {
+                let (mut samples, up) = match result {
+                    Ok(samples) => (samples, 1.0),
+                    Err(error) => {
+                        warn!(
+                            message = "failed to scrape status page.",
+                            endpoint = self.endpoint.as_str(),
+                            %error
+                        );
+                        (Vec::new(), 0.0)
+                    }
+                };
+                samples.push(Sample::gauge(server.up_metric(), None, up));
+                let events = self.events(samples);
+                Ok((self, events))
+            })
+    }
+
+    fn events(&mut self, samples: Vec<Sample>) -> Vec<Event> {
+        let timestamp = Some(Utc::now());
+        samples
+            .into_iter()
+            .map(|sample| {
+                let mut tags = HashMap::new();
+                tags.insert("endpoint".to_string(), self.endpoint.clone());
+                if let Some(state) = sample.state {
+                    tags.insert("state".to_string(), state.to_string());
+                }
+                let name = sample.name.to_string();
+                let tags = Some(tags);
+
+                let metric = match sample.kind {
+                    Kind::Gauge => Metric::Gauge {
+                        name,
+                        val: sample.value,
+                        direction: None,
+                        timestamp,
+                        tags,
+                    },
+                    Kind::Counter => {
+                        let key = (sample.name, sample.state);
+                        let val = match self.totals.insert(key, sample.value) {
+                            Some(previous) if previous <= sample.value => sample.value - previous,
+                            // Totals going down mean the server restarted, and
+                            // counted from zero since.
+                            _ => sample.value,
+                        };
+                        Metric::Counter {
+                            name,
+                            val,
+                            timestamp,
+                            tags,
+                        }
+                    }
+                };
+                Event::Metric(metric)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sample, Scraper, Server, ServerStatusConfig};
+    use crate::{
+        event::{Event, Metric},
+        test_util::{collect_n, next_addr},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use futures::{sync::mpsc, Future};
+    use hyper::{service::service_fn_ok, Body, Response, Server as HyperServer};
+    use std::time::Duration;
+
+    fn value(event: &Event) -> (String, f64) {
+        match event.as_metric() {
+            Metric::Counter { name, val, .. } | Metric::Gauge { name, val, .. } => {
+                (name.clone(), *val)
+            }
+            metric => panic!("unexpected metric {:?}", metric),
+        }
+    }
+
+    #[test]
+    fn server_status_sends_counter_increments() {
+        let mut scraper =
+            Scraper::new(Server::Nginx, "http://localhost/", Duration::from_secs(1)).unwrap();
+
+        let requests = |total| vec![Sample::counter("nginx_http_requests_total", total)];
+        let increments = [10.0, 25.0, 25.0, 5.0]
+            .iter()
+            .map(|total| value(&scraper.events(requests(*total))[0]).1)
+            .collect::<Vec<_>>();
+
+        // The server restarted before the last scrape.
+        assert_eq!(increments, vec![10.0, 15.0, 0.0, 5.0]);
+    }
+
+    #[test]
+    fn server_status_scrapes_nginx() {
+        let address = next_addr();
+        let server = HyperServer::bind(&address)
+            .serve(|| {
+                service_fn_ok(|_| {
+                    Response::new(Body::from(
+                        "Active connections: 291 \n\
+                         server accepts handled requests\n \
+                         16630948 16630948 31070465 \n\
+                         Reading: 6 Writing: 179 Waiting: 106 \n",
+                    ))
+                })
+            })
+            .map_err(|error| panic!("server error: {}", error));
+
+        let endpoint = format!("http://{}/nginx_status", address);
+        let config = ServerStatusConfig::new(Server::Nginx, vec![endpoint.clone()]);
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        rt.spawn(source);
+
+        let events = rt.block_on(collect_n(rx, 8)).ok().unwrap();
+        rt.shutdown_now();
+
+        let values = events.iter().map(value).collect::<Vec<_>>();
+        assert!(values.contains(&("nginx_up".into(), 1.0)));
+        assert!(values.contains(&("nginx_http_requests_total".into(), 31_070_465.0)));
+
+        let tags = events[0].as_metric().tags().clone().unwrap();
+        assert_eq!(tags["endpoint"], endpoint);
+        assert_eq!(tags["state"], "active");
+    }
+}
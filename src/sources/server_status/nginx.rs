@@ -0,0 +1,93 @@
+//! The page of the nginx `stub_status` module, which reads like:
+//!
+//! ```text
+//! Active connections: 291
+//! server accepts handled requests
+//!  16630948 16630948 31070465
+//! Reading: 6 Writing: 179 Waiting: 106
+//! ```
+
+use super::{parse_value, ParseError, Sample};
+
+pub fn parse(page: &str) -> Result<Vec<Sample>, ParseError> {
+    let words = page.split_whitespace().collect::<Vec<_>>();
+    // The value `offset` words after `label`.
+    let value = |field: &'static str, label: &str, offset: usize| {
+        let position = words.iter().position(|word| *word == label);
+        parse_value(field, position.and_then(|i| words.get(i + offset).cloned()))
+    };
+
+    Ok(vec![
+        Sample::gauge(
+            "nginx_connections",
+            Some("active"),
+            value("active connections", "connections:", 1)?,
+        ),
+        Sample::gauge(
+            "nginx_connections",
+            Some("reading"),
+            value("reading connections", "Reading:", 1)?,
+        ),
+        Sample::gauge(
+            "nginx_connections",
+            Some("writing"),
+            value("writing connections", "Writing:", 1)?,
+        ),
+        Sample::gauge(
+            "nginx_connections",
+            Some("waiting"),
+            value("waiting connections", "Waiting:", 1)?,
+        ),
+        Sample::counter(
+            "nginx_connections_accepted_total",
+            value("accepted connections", "requests", 1)?,
+        ),
+        Sample::counter(
+            "nginx_connections_handled_total",
+            value("handled connections", "requests", 2)?,
+        ),
+        Sample::counter(
+            "nginx_http_requests_total",
+            value("requests", "requests", 3)?,
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use crate::sources::server_status::{ParseError, Sample};
+
+    #[test]
+    fn nginx_parses_stub_status() {
+        let page = "Active connections: 291 \n\
+                    server accepts handled requests\n \
+                    16630948 16630947 31070465 \n\
+                    Reading: 6 Writing: 179 Waiting: 106 \n";
+
+        assert_eq!(
+            parse(page).unwrap(),
+            vec![
+                Sample::gauge("nginx_connections", Some("active"), 291.0),
+                Sample::gauge("nginx_connections", Some("reading"), 6.0),
+                Sample::gauge("nginx_connections", Some("writing"), 179.0),
+                Sample::gauge("nginx_connections", Some("waiting"), 106.0),
+                Sample::counter("nginx_connections_accepted_total", 16_630_948.0),
+                Sample::counter("nginx_connections_handled_total", 16_630_947.0),
+                Sample::counter("nginx_http_requests_total", 31_070_465.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn nginx_rejects_other_pages() {
+        match parse("<html><body>Welcome to nginx!</body></html>") {
+            Err(ParseError::Missing { field }) => assert_eq!(field, "active connections"),
+            other => panic!("expected a missing field, got {:?}", other),
+        }
+        match parse("Active connections: many") {
+            Err(ParseError::InvalidValue { value, .. }) => assert_eq!(value, "many"),
+            other => panic!("expected an invalid value, got {:?}", other),
+        }
+    }
+}
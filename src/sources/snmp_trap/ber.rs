@@ -0,0 +1,182 @@
+//! Just enough of the Basic Encoding Rules of ASN.1 to read SNMP messages.
+
+use super::message::ParseError;
+
+pub const INTEGER: u8 = 0x02;
+pub const OCTET_STRING: u8 = 0x04;
+pub const NULL: u8 = 0x05;
+pub const OBJECT_IDENTIFIER: u8 = 0x06;
+pub const SEQUENCE: u8 = 0x30;
+
+// The application types defined by SNMP.
+pub const IP_ADDRESS: u8 = 0x40;
+pub const COUNTER32: u8 = 0x41;
+pub const GAUGE32: u8 = 0x42;
+pub const TIMETICKS: u8 = 0x43;
+pub const OPAQUE: u8 = 0x44;
+pub const COUNTER64: u8 = 0x46;
+
+// The exceptions that can stand in for a value.
+pub const NO_SUCH_OBJECT: u8 = 0x80;
+pub const NO_SUCH_INSTANCE: u8 = 0x81;
+pub const END_OF_MIB_VIEW: u8 = 0x82;
+
+/// A tag, the value it applies to, and the offset of that value in the
+/// whole message.
+#[derive(Clone, Copy, Debug)]
+pub struct Tlv<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+    pub offset: usize,
+}
+
+/// Reads consecutive values, such as the contents of a sequence.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, offset: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn read(&mut self) -> Result<Tlv<'a>, ParseError> {
+        let tag = *self.buf.first().ok_or(ParseError::Truncated)?;
+        // SNMP only uses tag numbers that fit in a single byte.
+        if tag & 0x1f == 0x1f {
+            return Err(ParseError::InvalidTag { tag });
+        }
+
+        let first = *self.buf.get(1).ok_or(ParseError::Truncated)?;
+        let (length, header) = if first < 0x80 {
+            (usize::from(first), 2)
+        } else {
+            // The long form, as the indefinite form isn't allowed.
+            let count = usize::from(first & 0x7f);
+            if count == 0 || count > 4 {
+                return Err(ParseError::InvalidLength);
+            }
+            let bytes = self.buf.get(2..2 + count).ok_or(ParseError::Truncated)?;
+            let length = bytes
+                .iter()
+                .fold(0, |length, &byte| length << 8 | usize::from(byte));
+            (length, 2 + count)
+        };
+
+        let end = header
+            .checked_add(length)
+            .ok_or(ParseError::InvalidLength)?;
+        let value = self.buf.get(header..end).ok_or(ParseError::Truncated)?;
+        let tlv = Tlv {
+            tag,
+            value,
+            offset: self.offset + header,
+        };
+        self.buf = &self.buf[end..];
+        self.offset += end;
+        Ok(tlv)
+    }
+
+    pub fn expect(&mut self, tag: u8) -> Result<Tlv<'a>, ParseError> {
+        let tlv = self.read()?;
+        if tlv.tag != tag {
+            return Err(ParseError::UnexpectedTag {
+                expected: tag,
+                found: tlv.tag,
+            });
+        }
+        Ok(tlv)
+    }
+
+    pub fn integer(&mut self) -> Result<i64, ParseError> {
+        self.expect(INTEGER)?.integer()
+    }
+
+    pub fn octet_string(&mut self) -> Result<&'a [u8], ParseError> {
+        Ok(self.expect(OCTET_STRING)?.value)
+    }
+
+    pub fn oid(&mut self) -> Result<String, ParseError> {
+        self.expect(OBJECT_IDENTIFIER)?.oid()
+    }
+
+    pub fn sequence(&mut self) -> Result<Reader<'a>, ParseError> {
+        Ok(self.expect(SEQUENCE)?.reader())
+    }
+}
+
+impl<'a> Tlv<'a> {
+    /// Read the values this one is made of.
+    pub fn reader(&self) -> Reader<'a> {
+        Reader {
+            buf: self.value,
+            offset: self.offset,
+        }
+    }
+
+    /// A two's complement signed integer.
+    pub fn integer(&self) -> Result<i64, ParseError> {
+        if self.value.is_empty() || self.value.len() > 8 {
+            return Err(ParseError::InvalidInteger);
+        }
+        let sign = if self.value[0] & 0x80 != 0 { -1 } else { 0 };
+        Ok(self
+            .value
+            .iter()
+            .fold(sign, |n, &byte| n << 8 | i64::from(byte)))
+    }
+
+    /// An unsigned integer, like counters, which needs a leading zero byte
+    /// when its top bit is set.
+    pub fn unsigned(&self) -> Result<u64, ParseError> {
+        let value = if self.value.len() == 9 && self.value[0] == 0 {
+            &self.value[1..]
+        } else {
+            self.value
+        };
+        if value.is_empty() || value.len() > 8 {
+            return Err(ParseError::InvalidInteger);
+        }
+        Ok(value.iter().fold(0, |n, &byte| n << 8 | u64::from(byte)))
+    }
+
+    /// An object identifier, in its dotted form.
+    pub fn oid(&self) -> Result<String, ParseError> {
+        let mut subids = Vec::new();
+        let mut subid: u64 = 0;
+        let mut pending = false;
+        for &byte in self.value {
+            if subid > std::u64::MAX >> 7 {
+                return Err(ParseError::InvalidOid);
+            }
+            subid = subid << 7 | u64::from(byte & 0x7f);
+            pending = byte & 0x80 != 0;
+            if !pending {
+                subids.push(subid);
+                subid = 0;
+            }
+        }
+        if subids.is_empty() || pending {
+            return Err(ParseError::InvalidOid);
+        }
+
+        // The first two arcs share the first sub-identifier.
+        let (first, second) = match subids[0] {
+            n if n < 40 => (0, n),
+            n if n < 80 => (1, n - 40),
+            n => (2, n - 80),
+        };
+        let mut oid = format!("{}.{}", first, second);
+        for subid in &subids[1..] {
+            oid.push('.');
+            oid.push_str(&subid.to_string());
+        }
+        Ok(oid)
+    }
+}
@@ -0,0 +1,463 @@
+use super::{
+    ber::{
+        Reader, Tlv, COUNTER32, COUNTER64, END_OF_MIB_VIEW, GAUGE32, INTEGER, IP_ADDRESS,
+        NO_SUCH_INSTANCE, NO_SUCH_OBJECT, NULL, OBJECT_IDENTIFIER, OCTET_STRING, OPAQUE, TIMETICKS,
+    },
+    usm::Users,
+};
+use snafu::Snafu;
+use std::{net::Ipv4Addr, time::Instant};
+
+#[derive(Debug, Snafu)]
+pub enum ParseError {
+    #[snafu(display("Message is truncated"))]
+    Truncated,
+    #[snafu(display("Invalid tag {:#04x}", tag))]
+    InvalidTag { tag: u8 },
+    #[snafu(display("Invalid length"))]
+    InvalidLength,
+    #[snafu(display("Expected tag {:#04x}, found {:#04x}", expected, found))]
+    UnexpectedTag { expected: u8, found: u8 },
+    #[snafu(display("Invalid integer"))]
+    InvalidInteger,
+    #[snafu(display("Invalid object identifier"))]
+    InvalidOid,
+    #[snafu(display("Unsupported SNMP version {}", version))]
+    UnsupportedVersion { version: i64 },
+    #[snafu(display("Unsupported PDU type {:#04x}, only traps are supported", tag))]
+    UnsupportedPdu { tag: u8 },
+    #[snafu(display("Trap has no snmpTrapOID.0"))]
+    MissingTrapOid,
+    #[snafu(display("Unsupported security model {}", model))]
+    UnsupportedSecurityModel { model: i64 },
+    #[snafu(display("Invalid message flags"))]
+    InvalidFlags,
+    #[snafu(display("Encrypted messages are not supported"))]
+    Encrypted,
+    #[snafu(display("Unknown user {:?}", user))]
+    UnknownUser { user: String },
+    #[snafu(display("User {:?} sent a message with the wrong security level", user))]
+    WrongSecurityLevel { user: String },
+    #[snafu(display("Authentication failed for user {:?}", user))]
+    AuthenticationFailed { user: String },
+    #[snafu(display("Could not authenticate message: {}", source))]
+    Crypto { source: openssl::error::ErrorStack },
+    #[snafu(display("Message from engine {} is outside the time window", engine_id))]
+    NotInTimeWindow { engine_id: String },
+}
+
+const TRAP_V1: u8 = 0xa4;
+const TRAP_V2: u8 = 0xa7;
+
+const SYS_UPTIME: &str = "1.3.6.1.2.1.1.3.0";
+const SNMP_TRAP_OID: &str = "1.3.6.1.6.3.1.1.4.1.0";
+const SNMP_TRAP_ENTERPRISE: &str = "1.3.6.1.6.3.1.1.4.3.0";
+const SNMP_TRAP_ADDRESS: &str = "1.3.6.1.6.3.18.1.3.0";
+/// The parent of the generic traps, such as `coldStart` and `linkDown`.
+const SNMP_TRAPS: &str = "1.3.6.1.6.3.1.1.5";
+
+/// The message flags of SNMPv3.
+const FLAG_AUTH: u8 = 0x01;
+const FLAG_PRIV: u8 = 0x02;
+/// The user-based security model.
+const USM: i64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Version {
+    V1,
+    V2c,
+    V3,
+}
+
+impl Version {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::V1 => "1",
+            Version::V2c => "2c",
+            Version::V3 => "3",
+        }
+    }
+}
+
+/// A trap of any version. Both `enterprise` and `trap_oid` are filled in for
+/// every version, converted as described in RFC 3584.
+#[derive(Debug, PartialEq)]
+pub struct Trap {
+    pub version: Version,
+    pub community: Option<Vec<u8>>,
+    pub user: Option<Vec<u8>>,
+    pub engine_id: Option<Vec<u8>>,
+    pub enterprise: String,
+    pub trap_oid: String,
+    pub agent_address: Option<Ipv4Addr>,
+    pub generic_trap: Option<i64>,
+    pub specific_trap: Option<i64>,
+    pub uptime: Option<u64>,
+    pub request_id: Option<i64>,
+    pub varbinds: Vec<VarBind>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VarBind {
+    pub oid: String,
+    pub value: VarValue,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VarValue {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    Oid(String),
+    IpAddress(Ipv4Addr),
+    Counter32(u64),
+    Gauge32(u64),
+    TimeTicks(u64),
+    Opaque(Vec<u8>),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl VarValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            VarValue::Integer(_) => "integer",
+            VarValue::OctetString(_) => "octet_string",
+            VarValue::Null => "null",
+            VarValue::Oid(_) => "oid",
+            VarValue::IpAddress(_) => "ip_address",
+            VarValue::Counter32(_) => "counter32",
+            VarValue::Gauge32(_) => "gauge32",
+            VarValue::TimeTicks(_) => "timeticks",
+            VarValue::Opaque(_) => "opaque",
+            VarValue::Counter64(_) => "counter64",
+            VarValue::NoSuchObject => "no_such_object",
+            VarValue::NoSuchInstance => "no_such_instance",
+            VarValue::EndOfMibView => "end_of_mib_view",
+        }
+    }
+
+    fn decode(tlv: Tlv) -> Result<Self, ParseError> {
+        Ok(match tlv.tag {
+            INTEGER => VarValue::Integer(tlv.integer()?),
+            OCTET_STRING => VarValue::OctetString(tlv.value.to_vec()),
+            NULL => VarValue::Null,
+            OBJECT_IDENTIFIER => VarValue::Oid(tlv.oid()?),
+            IP_ADDRESS => VarValue::IpAddress(ip_address(tlv)?),
+            COUNTER32 => VarValue::Counter32(tlv.unsigned()?),
+            GAUGE32 => VarValue::Gauge32(tlv.unsigned()?),
+            TIMETICKS => VarValue::TimeTicks(tlv.unsigned()?),
+            OPAQUE => VarValue::Opaque(tlv.value.to_vec()),
+            COUNTER64 => VarValue::Counter64(tlv.unsigned()?),
+            NO_SUCH_OBJECT => VarValue::NoSuchObject,
+            NO_SUCH_INSTANCE => VarValue::NoSuchInstance,
+            END_OF_MIB_VIEW => VarValue::EndOfMibView,
+            tag => return Err(ParseError::InvalidTag { tag }),
+        })
+    }
+}
+
+/// Parse a trap, authenticating SNMPv3 messages as one of `users`.
+pub fn parse_message(message: &[u8], users: &mut Users) -> Result<Trap, ParseError> {
+    let mut reader = Reader::new(message);
+    let mut msg = reader.sequence()?;
+    match msg.integer()? {
+        0 => {
+            let community = msg.octet_string()?.to_vec();
+            let pdu = msg.read()?;
+            if pdu.tag != TRAP_V1 {
+                return Err(ParseError::UnsupportedPdu { tag: pdu.tag });
+            }
+            let mut trap = parse_v1_pdu(pdu)?;
+            trap.community = Some(community);
+            Ok(trap)
+        }
+        1 => {
+            let community = msg.octet_string()?.to_vec();
+            let mut trap = parse_v2_pdu(Version::V2c, msg.read()?)?;
+            trap.community = Some(community);
+            Ok(trap)
+        }
+        3 => parse_v3(message, msg, users),
+        version => Err(ParseError::UnsupportedVersion { version }),
+    }
+}
+
+fn parse_v1_pdu(pdu: Tlv) -> Result<Trap, ParseError> {
+    let mut pdu = pdu.reader();
+    let enterprise = pdu.oid()?;
+    let agent_address = ip_address(pdu.read()?)?;
+    let generic_trap = pdu.integer()?;
+    let specific_trap = pdu.integer()?;
+    let uptime = pdu.read()?.unsigned()?;
+    let varbinds = read_varbinds(pdu.sequence()?)?;
+
+    let trap_oid = match generic_trap {
+        0..=5 => format!("{}.{}", SNMP_TRAPS, generic_trap + 1),
+        _ => format!("{}.0.{}", enterprise, specific_trap),
+    };
+
+    Ok(Trap {
+        version: Version::V1,
+        community: None,
+        user: None,
+        engine_id: None,
+        enterprise,
+        trap_oid,
+        agent_address: Some(agent_address),
+        generic_trap: Some(generic_trap),
+        specific_trap: Some(specific_trap),
+        uptime: Some(uptime),
+        request_id: None,
+        varbinds,
+    })
+}
+
+/// SNMPv2 traps carry the uptime and trap OID as their first two variable
+/// bindings, which are taken out of `varbinds`.
+fn parse_v2_pdu(version: Version, pdu: Tlv) -> Result<Trap, ParseError> {
+    if pdu.tag != TRAP_V2 {
+        return Err(ParseError::UnsupportedPdu { tag: pdu.tag });
+    }
+    let mut pdu = pdu.reader();
+    let request_id = pdu.integer()?;
+    let _error_status = pdu.integer()?;
+    let _error_index = pdu.integer()?;
+
+    let mut uptime = None;
+    let mut trap_oid = None;
+    let mut varbinds = read_varbinds(pdu.sequence()?)?;
+    varbinds.retain(|varbind| match (varbind.oid.as_str(), &varbind.value) {
+        (SYS_UPTIME, VarValue::TimeTicks(ticks)) => {
+            uptime = Some(*ticks);
+            false
+        }
+        (SNMP_TRAP_OID, VarValue::Oid(oid)) => {
+            trap_oid = Some(oid.clone());
+            false
+        }
+        _ => true,
+    });
+    let trap_oid = trap_oid.ok_or(ParseError::MissingTrapOid)?;
+
+    let find = |oid: &str| varbinds.iter().find(|varbind| varbind.oid == oid);
+    let agent_address = find(SNMP_TRAP_ADDRESS).and_then(|varbind| match varbind.value {
+        VarValue::IpAddress(address) => Some(address),
+        _ => None,
+    });
+    let trap_enterprise = find(SNMP_TRAP_ENTERPRISE).and_then(|varbind| match &varbind.value {
+        VarValue::Oid(oid) => Some(oid.clone()),
+        _ => None,
+    });
+
+    Ok(Trap {
+        version,
+        community: None,
+        user: None,
+        engine_id: None,
+        enterprise: v2_enterprise(&trap_oid, trap_enterprise),
+        trap_oid,
+        agent_address,
+        generic_trap: None,
+        specific_trap: None,
+        uptime,
+        request_id: Some(request_id),
+        varbinds,
+    })
+}
+
+/// The enterprise of a generic trap is given by `snmpTrapEnterprise.0`, and
+/// that of others is the parent of their OID, less a trailing zero.
+fn v2_enterprise(trap_oid: &str, trap_enterprise: Option<String>) -> String {
+    if trap_oid.starts_with(SNMP_TRAPS) && trap_oid[SNMP_TRAPS.len()..].starts_with('.') {
+        return trap_enterprise.unwrap_or_else(|| SNMP_TRAPS.into());
+    }
+    let parent = trap_oid.rsplitn(2, '.').nth(1).unwrap_or(trap_oid);
+    if parent.ends_with(".0") {
+        parent[..parent.len() - 2].into()
+    } else {
+        parent.into()
+    }
+}
+
+fn parse_v3(message: &[u8], mut msg: Reader, users: &mut Users) -> Result<Trap, ParseError> {
+    let mut global = msg.sequence()?;
+    let _id = global.integer()?;
+    let _max_size = global.integer()?;
+    let flags = match global.octet_string()? {
+        [flags] => *flags,
+        _ => return Err(ParseError::InvalidFlags),
+    };
+    let model = global.integer()?;
+    if model != USM {
+        return Err(ParseError::UnsupportedSecurityModel { model });
+    }
+
+    let mut params = msg.expect(OCTET_STRING)?.reader().sequence()?;
+    let engine_id = params.octet_string()?;
+    let engine_boots = params.integer()?;
+    let engine_time = params.integer()?;
+    let user = params.octet_string()?;
+    let auth_params = params.expect(OCTET_STRING)?;
+    let _priv_params = params.octet_string()?;
+
+    if flags & FLAG_PRIV != 0 {
+        return Err(ParseError::Encrypted);
+    }
+    users.authenticate(
+        message,
+        user,
+        engine_id,
+        flags & FLAG_AUTH != 0,
+        auth_params,
+    )?;
+    if flags & FLAG_AUTH != 0 {
+        users.check_time(engine_id, engine_boots, engine_time, Instant::now())?;
+    }
+
+    let mut scoped_pdu = msg.sequence()?;
+    let _context_engine_id = scoped_pdu.octet_string()?;
+    let _context_name = scoped_pdu.octet_string()?;
+    let mut trap = parse_v2_pdu(Version::V3, scoped_pdu.read()?)?;
+    trap.user = Some(user.to_vec());
+    trap.engine_id = Some(engine_id.to_vec());
+    Ok(trap)
+}
+
+fn read_varbinds(mut list: Reader) -> Result<Vec<VarBind>, ParseError> {
+    let mut varbinds = Vec::new();
+    while !list.is_empty() {
+        let mut varbind = list.sequence()?;
+        let oid = varbind.oid()?;
+        let value = VarValue::decode(varbind.read()?)?;
+        varbinds.push(VarBind { oid, value });
+    }
+    Ok(varbinds)
+}
+
+fn ip_address(tlv: Tlv) -> Result<Ipv4Addr, ParseError> {
+    if tlv.tag != IP_ADDRESS {
+        return Err(ParseError::UnexpectedTag {
+            expected: IP_ADDRESS,
+            found: tlv.tag,
+        });
+    }
+    match tlv.value {
+        [a, b, c, d] => Ok(Ipv4Addr::new(*a, *b, *c, *d)),
+        _ => Err(ParseError::InvalidLength),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_message, ParseError, VarBind, VarValue, Version};
+    use crate::sources::snmp_trap::usm::{AuthProtocol, UserConfig, Users};
+    use std::net::Ipv4Addr;
+
+    const V1: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/snmp_trap/v1.bin"
+    ));
+    const V2C: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/snmp_trap/v2c.bin"
+    ));
+    const V3_AUTH_SHA: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/snmp_trap/v3_auth_sha.bin"
+    ));
+
+    fn users(password: &str) -> Users {
+        Users::new(&[UserConfig {
+            name: "vector".into(),
+            auth_protocol: Some(AuthProtocol::Sha),
+            auth_password: Some(password.into()),
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn snmp_trap_parses_v1() {
+        let trap = parse_message(V1, &mut users("maplesyrup")).unwrap();
+        assert_eq!(trap.version, Version::V1);
+        assert_eq!(trap.community, Some(b"public".to_vec()));
+        assert_eq!(trap.enterprise, "1.3.6.1.4.1.8072.2.3.1");
+        assert_eq!(trap.trap_oid, "1.3.6.1.4.1.8072.2.3.1.0.17");
+        assert_eq!(trap.agent_address, Some(Ipv4Addr::new(192, 0, 2, 10)));
+        assert_eq!(trap.generic_trap, Some(6));
+        assert_eq!(trap.specific_trap, Some(17));
+        assert_eq!(trap.uptime, Some(12345));
+        assert_eq!(
+            trap.varbinds,
+            vec![
+                VarBind {
+                    oid: "1.3.6.1.4.1.8072.2.3.2.1".into(),
+                    value: VarValue::Integer(-42),
+                },
+                VarBind {
+                    oid: "1.3.6.1.2.1.1.5.0".into(),
+                    value: VarValue::OctetString(b"router-1".to_vec()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn snmp_trap_parses_v2c() {
+        let trap = parse_message(V2C, &mut users("maplesyrup")).unwrap();
+        assert_eq!(trap.version, Version::V2c);
+        assert_eq!(trap.trap_oid, "1.3.6.1.6.3.1.1.5.3");
+        assert_eq!(trap.enterprise, "1.3.6.1.6.3.1.1.5");
+        assert_eq!(trap.agent_address, Some(Ipv4Addr::new(198, 51, 100, 7)));
+        assert_eq!(trap.uptime, Some(54321));
+        assert_eq!(trap.request_id, Some(1234));
+        assert_eq!(trap.varbinds.len(), 5);
+        assert_eq!(trap.varbinds[2].value, VarValue::Counter64((1 << 63) + 5));
+    }
+
+    #[test]
+    fn snmp_trap_authenticates_v3() {
+        let mut known = users("maplesyrup");
+        let trap = parse_message(V3_AUTH_SHA, &mut known).unwrap();
+        assert_eq!(trap.version, Version::V3);
+        assert_eq!(trap.user, Some(b"vector".to_vec()));
+        assert_eq!(trap.request_id, Some(99));
+        assert_eq!(trap.trap_oid, "1.3.6.1.6.3.1.1.5.3");
+        // Still within the time window of its engine.
+        assert!(parse_message(V3_AUTH_SHA, &mut known).is_ok());
+
+        match parse_message(V3_AUTH_SHA, &mut users("pancakes!")) {
+            Err(ParseError::AuthenticationFailed { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut tampered = V3_AUTH_SHA.to_vec();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        match parse_message(&tampered, &mut users("maplesyrup")) {
+            Err(ParseError::AuthenticationFailed { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        match parse_message(V3_AUTH_SHA, &mut Users::new(&[]).unwrap()) {
+            Err(ParseError::UnknownUser { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn snmp_trap_rejects_garbage() {
+        let mut users = users("maplesyrup");
+        for length in 0..V2C.len() {
+            assert!(parse_message(&V2C[..length], &mut users).is_err());
+        }
+        let mut get = V2C.to_vec();
+        get[14] = 0xa0;
+        match parse_message(&get, &mut users) {
+            Err(ParseError::UnsupportedPdu { tag: 0xa0 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{
+    event::{self, Event, ValueKind},
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use chrono::Utc;
+use futures::{future, sync::mpsc, Future, Sink, Stream};
+use message::{parse_message, Trap, VarValue};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::{
+    codec::BytesCodec,
+    net::{UdpFramed, UdpSocket},
+};
+use usm::{UserConfig, Users};
+
+mod ber;
+mod message;
+mod usm;
+
+/// Receives SNMP traps, of version 1, 2c and 3, over UDP.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SnmpTrapConfig {
+    pub address: SocketAddr,
+    pub host_key: Option<Atom>,
+    /// The communities version 1 and 2c traps are accepted from, or any if
+    /// empty.
+    #[serde(default)]
+    pub communities: Vec<String>,
+    /// The users version 3 traps are accepted from.
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+}
+
+impl SnmpTrapConfig {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            host_key: None,
+            communities: Vec::new(),
+            users: Vec::new(),
+        }
+    }
+}
+
+#[typetag::serde(name = "snmp_trap")]
+impl SourceConfig for SnmpTrapConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        let host_key = self.host_key.clone().unwrap_or(event::HOST.clone());
+        let users = Users::new(&self.users)?;
+        Ok(snmp_trap(
+            self.address,
+            host_key,
+            self.communities.clone(),
+            users,
+            out,
+        ))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn snmp_trap(
+    address: SocketAddr,
+    host_key: Atom,
+    communities: Vec<String>,
+    mut users: Users,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let out = out.sink_map_err(|e| error!("error sending event: {:?}", e));
+
+    Box::new(
+        future::lazy(move || {
+            let socket = UdpSocket::bind(&address).expect("failed to bind to udp listener socket");
+
+            info!(message = "listening.", %address, r#type = "udp");
+
+            Ok(socket)
+        })
+        .and_then(move |socket| {
+            UdpFramed::new(socket, BytesCodec::new())
+                .map_err(|error: io::Error| error!(message = "error reading datagram.", %error))
+                .filter_map(move |(packet, sender)| {
+                    let trap = parse_message(&packet, &mut users)
+                        .map_err(|error| {
+                            warn!(
+                                message = "failed to parse trap.",
+                                %sender,
+                                %error,
+                                decode_errors_counter = 1,
+                            )
+                        })
+                        .ok()?;
+
+                    if let Some(community) = &trap.community {
+                        let allowed = communities.is_empty()
+                            || communities
+                                .iter()
+                                .any(|allowed| allowed.as_bytes() == &community[..]);
+                        if !allowed {
+                            warn!(
+                                message = "dropping trap from unknown community.",
+                                %sender,
+                                community = %String::from_utf8_lossy(community),
+                                unknown_community_counter = 1,
+                            );
+                            return None;
+                        }
+                    }
+
+                    Some(trap_to_event(trap, &host_key, sender))
+                })
+                .forward(out)
+                .map(|_| info!("finished sending"))
+        }),
+    )
+}
+
+/// Traps become events with their variable bindings as a `varbinds` array
+/// of `oid`, `type` and `value`.
+fn trap_to_event(trap: Trap, host_key: &Atom, sender: SocketAddr) -> Event {
+    let mut event = Event::new_empty_log();
+    let log = event.as_mut_log();
+    log.insert_implicit(event::TIMESTAMP.clone(), Utc::now().into());
+    log.insert_implicit(host_key.clone(), sender.ip().to_string().into());
+
+    log.insert_explicit("version".into(), trap.version.as_str().into());
+    if let Some(community) = trap.community {
+        log.insert_explicit("community".into(), string_or_hex(community));
+    }
+    if let Some(user) = trap.user {
+        log.insert_explicit("user".into(), string_or_hex(user));
+    }
+    if let Some(engine_id) = trap.engine_id {
+        log.insert_explicit("engine_id".into(), hex(&engine_id).into());
+    }
+    log.insert_explicit("enterprise".into(), trap.enterprise.into());
+    log.insert_explicit("trap_oid".into(), trap.trap_oid.into());
+    // Traps that don't name their agent are assumed to come from it.
+    let agent_address = trap.agent_address.map_or(sender.ip(), IpAddr::from);
+    log.insert_explicit("agent_address".into(), agent_address.to_string().into());
+    if let Some(generic_trap) = trap.generic_trap {
+        log.insert_explicit("generic_trap".into(), generic_trap.into());
+    }
+    if let Some(specific_trap) = trap.specific_trap {
+        log.insert_explicit("specific_trap".into(), specific_trap.into());
+    }
+    if let Some(uptime) = trap.uptime {
+        log.insert_explicit("uptime".into(), unsigned(uptime));
+    }
+    if let Some(request_id) = trap.request_id {
+        log.insert_explicit("request_id".into(), request_id.into());
+    }
+
+    for (index, varbind) in trap.varbinds.into_iter().enumerate() {
+        let prefix = format!("varbinds[{}]", index);
+        log.insert_explicit(format!("{}.oid", prefix).into(), varbind.oid.into());
+        log.insert_explicit(
+            format!("{}.type", prefix).into(),
+            varbind.value.type_name().into(),
+        );
+        log.insert_explicit(format!("{}.value", prefix).into(), value(varbind.value));
+    }
+
+    trace!(message = "Received one event.", ?event);
+    event
+}
+
+fn value(value: VarValue) -> ValueKind {
+    match value {
+        VarValue::Integer(integer) => integer.into(),
+        VarValue::OctetString(bytes) => string_or_hex(bytes),
+        VarValue::Oid(oid) => oid.into(),
+        VarValue::IpAddress(address) => address.to_string().into(),
+        VarValue::Counter32(integer)
+        | VarValue::Gauge32(integer)
+        | VarValue::TimeTicks(integer)
+        | VarValue::Counter64(integer) => unsigned(integer),
+        VarValue::Opaque(bytes) => hex(&bytes).into(),
+        VarValue::Null
+        | VarValue::NoSuchObject
+        | VarValue::NoSuchInstance
+        | VarValue::EndOfMibView => "".into(),
+    }
+}
+
+/// Counters that don't fit an `i64` are kept as strings.
+fn unsigned(integer: u64) -> ValueKind {
+    if integer <= std::i64::MAX as u64 {
+        ValueKind::Integer(integer as i64)
+    } else {
+        integer.to_string().into()
+    }
+}
+
+/// Octet strings hold both text and binary data, such as MAC addresses,
+/// which is turned into hex.
+fn string_or_hex(bytes: Vec<u8>) -> ValueKind {
+    match String::from_utf8(bytes) {
+        Ok(string)
+            if !string
+                .chars()
+                .any(|c| c.is_control() && !c.is_ascii_whitespace()) =>
+        {
+            string.into()
+        }
+        Ok(string) => hex(string.as_bytes()).into(),
+        Err(error) => hex(error.as_bytes()).into(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::SnmpTrapConfig;
+    use crate::event;
+    use crate::test_util::{collect_n, next_addr};
+    use crate::topology::config::{GlobalOptions, SourceConfig};
+    use futures::sync::mpsc;
+    use std::{net::UdpSocket, thread, time::Duration};
+    use string_cache::DefaultAtom as Atom;
+
+    const V1: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/snmp_trap/v1.bin"
+    ));
+    const V2C: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/snmp_trap/v2c.bin"
+    ));
+
+    #[test]
+    fn snmp_trap_events() {
+        let (tx, rx) = mpsc::channel(10);
+        let address = next_addr();
+        let mut config = SnmpTrapConfig::new(address);
+        config.communities = vec!["public".into()];
+        let server = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        thread::sleep(Duration::from_millis(100));
+
+        let mut private = V2C.to_vec();
+        private[8..14].copy_from_slice(b"secret");
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for packet in &[&private[..], b"not a trap", V1, V2C] {
+            socket.send_to(packet, address).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+
+        let log = events[0].as_log();
+        assert_eq!(log[&event::HOST], "127.0.0.1".into());
+        assert_eq!(log[&Atom::from("version")], "1".into());
+        assert_eq!(log[&Atom::from("community")], "public".into());
+        assert_eq!(
+            log[&Atom::from("enterprise")],
+            "1.3.6.1.4.1.8072.2.3.1".into()
+        );
+        assert_eq!(
+            log[&Atom::from("trap_oid")],
+            "1.3.6.1.4.1.8072.2.3.1.0.17".into()
+        );
+        assert_eq!(log[&Atom::from("agent_address")], "192.0.2.10".into());
+        assert_eq!(log[&Atom::from("specific_trap")], 17.into());
+        assert_eq!(log[&Atom::from("uptime")], 12345.into());
+        assert_eq!(log[&Atom::from("varbinds[0].value")], (-42).into());
+        assert_eq!(log[&Atom::from("varbinds[1].type")], "octet_string".into());
+        assert_eq!(log[&Atom::from("varbinds[1].value")], "router-1".into());
+
+        let log = events[1].as_log();
+        assert_eq!(log[&Atom::from("version")], "2c".into());
+        assert_eq!(log[&Atom::from("trap_oid")], "1.3.6.1.6.3.1.1.5.3".into());
+        assert_eq!(log[&Atom::from("enterprise")], "1.3.6.1.6.3.1.1.5".into());
+        assert_eq!(log[&Atom::from("agent_address")], "198.51.100.7".into());
+        assert_eq!(log[&Atom::from("uptime")], 54321.into());
+        assert_eq!(log[&Atom::from("request_id")], 1234.into());
+        assert_eq!(
+            log[&Atom::from("varbinds[0].oid")],
+            "1.3.6.1.2.1.2.2.1.1.2".into()
+        );
+        assert_eq!(log[&Atom::from("varbinds[1].value")], "eth1".into());
+        assert_eq!(log[&Atom::from("varbinds[2].type")], "counter64".into());
+        assert_eq!(
+            log[&Atom::from("varbinds[2].value")],
+            "9223372036854775813".into()
+        );
+        assert_eq!(log[&Atom::from("varbinds[3].value")], "001a2b3c4d5e".into());
+    }
+}
@@ -0,0 +1,725 @@
+use super::util::{Decoding, DecodingConfig};
+use crate::{
+    event::Event,
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use bytes::BytesMut;
+use futures::{future, sink::Wait, sync::mpsc, Future, Sink};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::RecvTimeoutError,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::codec::Decoder;
+use tracing::dispatcher;
+
+/// The file, inside the data directory, listing the files whose post-action is
+/// pending.
+const CHECKPOINTS_FILE: &str = "checkpoints.json";
+
+/// How much of a file is read at a time.
+const READ_CHUNK_BYTES: usize = 8 * 1024;
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("{} suffix must not be empty", option))]
+    EmptySuffix { option: &'static str },
+    #[snafu(display("files can't be moved into the spool directory they are read from"))]
+    MoveIntoSpool,
+}
+
+#[derive(Debug, Snafu)]
+enum SpoolError {
+    #[snafu(display("could not read {:?}: {}", path, source))]
+    ReadFile { path: PathBuf, source: io::Error },
+    #[snafu(display("could not split {:?} into frames: {}", path, source))]
+    SplitFile { path: PathBuf, source: io::Error },
+    #[snafu(display("could not {} {:?}: {}", action, path, source))]
+    PostActionFailed {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display("could not save checkpoints to {:?}: {}", path, source))]
+    SaveCheckpoints { path: PathBuf, source: io::Error },
+    #[snafu(display("output of the source is closed"))]
+    OutputClosed,
+}
+
+/// Ingests whole files dropped into a spool directory, once each, then deletes
+/// or moves them out of the way.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolConfig {
+    pub directory: PathBuf,
+    pub data_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub ready: ReadyConfig,
+    #[serde(default)]
+    pub post_action: PostAction,
+    #[serde(default = "default_file_key")]
+    pub file_key: Option<Atom>,
+    #[serde(default = "default_max_line_bytes")]
+    pub max_line_bytes: usize,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    #[serde(default)]
+    pub decoding: DecodingConfig,
+}
+
+/// When a file is done being written, and ready to be ingested.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ReadyConfig {
+    /// Once its size and modification time stayed the same for `settle_ms`.
+    Stable {
+        #[serde(default = "default_settle_ms")]
+        settle_ms: u64,
+    },
+    /// Once a marker file, named after it with `suffix` appended, exists.
+    Marker {
+        #[serde(default = "default_marker_suffix")]
+        suffix: String,
+    },
+}
+
+impl Default for ReadyConfig {
+    fn default() -> Self {
+        ReadyConfig::Stable {
+            settle_ms: default_settle_ms(),
+        }
+    }
+}
+
+/// What is done with a file once its events were sent.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PostAction {
+    Delete,
+    Move { directory: PathBuf },
+    Rename { suffix: String },
+}
+
+impl Default for PostAction {
+    fn default() -> Self {
+        PostAction::Delete
+    }
+}
+
+fn default_settle_ms() -> u64 {
+    5000
+}
+
+fn default_marker_suffix() -> String {
+    ".done".into()
+}
+
+fn default_file_key() -> Option<Atom> {
+    Some("file".into())
+}
+
+/// Files are often a single large frame, such as a JSON document, so the
+/// limit is much higher than that of sources reading streams.
+fn default_max_line_bytes() -> usize {
+    bytesize::mib(10u64) as usize
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+impl SpoolConfig {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            data_dir: None,
+            ready: ReadyConfig::default(),
+            post_action: PostAction::default(),
+            file_key: default_file_key(),
+            max_line_bytes: default_max_line_bytes(),
+            poll_interval_ms: default_poll_interval_ms(),
+            decoding: DecodingConfig::default(),
+        }
+    }
+}
+
+#[typetag::serde(name = "spool")]
+impl SourceConfig for SpoolConfig {
+    fn build(
+        &self,
+        name: &str,
+        globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        match (&self.ready, &self.post_action) {
+            (ReadyConfig::Marker { suffix }, _) if suffix.is_empty() => {
+                return Err(Box::new(BuildError::EmptySuffix { option: "marker" }))
+            }
+            (_, PostAction::Rename { suffix }) if suffix.is_empty() => {
+                return Err(Box::new(BuildError::EmptySuffix { option: "rename" }))
+            }
+            (_, PostAction::Move { directory }) if *directory == self.directory => {
+                return Err(Box::new(BuildError::MoveIntoSpool))
+            }
+            _ => (),
+        }
+        let decoding = self.decoding.build(self.max_line_bytes)?;
+
+        // Like the file source, each source keeps its checkpoints in a
+        // subdirectory named after it.
+        let data_dir = globals.resolve_and_make_data_subdir(self.data_dir.as_ref(), name)?;
+        let checkpoints = Checkpoints::load(data_dir.join(CHECKPOINTS_FILE));
+
+        Ok(spool_source(self, decoding, checkpoints, out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+}
+
+fn spool_source(
+    config: &SpoolConfig,
+    decoding: Decoding,
+    checkpoints: Checkpoints,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel::<()>();
+
+    let spool = Spool {
+        directory: config.directory.clone(),
+        ready: config.ready.clone(),
+        post_action: config.post_action.clone(),
+        file_key: config.file_key.clone(),
+        decoding,
+        checkpoints,
+        seen: HashMap::new(),
+        failed: HashMap::new(),
+    };
+    let poll_interval = Duration::from_millis(config.poll_interval_ms);
+
+    Box::new(future::lazy(move || {
+        info!(message = "Watching spool directory.", directory = ?spool.directory);
+
+        // Files are read with blocking calls, like the file source does.
+        let span = info_span!("spool");
+        let dispatcher = dispatcher::get_default(|d| d.clone());
+        thread::spawn(move || {
+            let dispatcher = dispatcher;
+            dispatcher::with_default(&dispatcher, || {
+                span.in_scope(|| spool.run(out.wait(), poll_interval, shutdown_rx))
+            });
+        });
+
+        // Dropping shutdown_tx is how we signal to the spool that it's time to
+        // shut down, so it needs to be held onto until the future we return is
+        // dropped.
+        future::empty().inspect(|_| drop(shutdown_tx))
+    }))
+}
+
+/// What identifies a version of a file, so that a file written again under the
+/// same name isn't mistaken for the one before.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+struct Fingerprint {
+    len: u64,
+    modified: SystemTime,
+}
+
+impl Fingerprint {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+}
+
+/// The files whose events were all sent, but whose post-action isn't done
+/// yet. They are saved before the post-action, so that a file is never
+/// ingested twice when Vector stops in between, the post-action being done on
+/// restart instead.
+struct Checkpoints {
+    path: PathBuf,
+    pending: HashMap<PathBuf, Fingerprint>,
+}
+
+impl Checkpoints {
+    fn load(path: PathBuf) -> Self {
+        let pending = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|error| {
+                error!(message = "invalid checkpoints; ignoring them.", ?path, %error);
+                HashMap::new()
+            }),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(error) => {
+                error!(message = "could not read checkpoints; ignoring them.", ?path, %error);
+                HashMap::new()
+            }
+        };
+        Self { path, pending }
+    }
+
+    /// Write the checkpoints to a temporary file renamed over the previous
+    /// ones, so that they are never left half written.
+    fn save(&self) -> Result<(), SpoolError> {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let contents = serde_json::to_vec(&self.pending).expect("checkpoints are serializable");
+
+        File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(&contents)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp, &self.path))
+            .context(SaveCheckpoints { path: &self.path })
+    }
+}
+
+struct Spool {
+    directory: PathBuf,
+    ready: ReadyConfig,
+    post_action: PostAction,
+    file_key: Option<Atom>,
+    decoding: Decoding,
+    checkpoints: Checkpoints,
+    /// The files waiting to settle, with how they last looked and since when.
+    seen: HashMap<PathBuf, (Fingerprint, Instant)>,
+    /// The files which couldn't be split into frames, left in place until
+    /// they change.
+    failed: HashMap<PathBuf, Fingerprint>,
+}
+
+impl Spool {
+    fn run(
+        mut self,
+        mut out: Wait<mpsc::Sender<Event>>,
+        poll_interval: Duration,
+        shutdown: std::sync::mpsc::Receiver<()>,
+    ) {
+        loop {
+            self.finish_pending();
+
+            for path in self.ready_files() {
+                match self.ingest(&path, &mut out) {
+                    Ok(()) => (),
+                    Err(SpoolError::OutputClosed) => return,
+                    Err(error) => error!(message = "failed to ingest file.", %error),
+                }
+            }
+
+            match shutdown.recv_timeout(poll_interval) {
+                Err(RecvTimeoutError::Timeout) => (),
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Do the post-actions still pending, from before a restart or after they
+    /// failed. Files that changed since aren't the ones that were ingested,
+    /// and are left alone.
+    fn finish_pending(&mut self) {
+        let pending = self
+            .checkpoints
+            .pending
+            .iter()
+            .map(|(path, fingerprint)| (path.clone(), fingerprint.clone()))
+            .collect::<Vec<_>>();
+
+        for (path, fingerprint) in pending {
+            if Fingerprint::of(&path).ok().as_ref() == Some(&fingerprint) {
+                if let Err(error) = self.post_action(&path) {
+                    error!(message = "post-action failed; retrying later.", %error);
+                    continue;
+                }
+            }
+            self.checkpoints.pending.remove(&path);
+            if let Err(error) = self.checkpoints.save() {
+                error!(message = "failed to save checkpoints.", %error);
+            }
+        }
+    }
+
+    /// List the files of the spool directory which are done being written,
+    /// oldest first.
+    fn ready_files(&mut self) -> Vec<PathBuf> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                error!(message = "could not list spool directory.", directory = ?self.directory, %error);
+                return Vec::new();
+            }
+        };
+
+        let now = Instant::now();
+        let mut seen = HashMap::new();
+        let mut failed = HashMap::new();
+        let mut ready = Vec::new();
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            if !self.is_candidate(&path) {
+                continue;
+            }
+            let fingerprint = match Fingerprint::of(&path) {
+                Ok(fingerprint) => fingerprint,
+                // The file is gone already.
+                Err(_) => continue,
+            };
+            if self.failed.get(&path) == Some(&fingerprint) {
+                failed.insert(path, fingerprint);
+                continue;
+            }
+
+            let is_ready = match &self.ready {
+                ReadyConfig::Marker { suffix } => with_suffix(&path, suffix).exists(),
+                ReadyConfig::Stable { settle_ms } => {
+                    let since = match self.seen.get(&path) {
+                        Some((previous, since)) if *previous == fingerprint => *since,
+                        _ => now,
+                    };
+                    seen.insert(path.clone(), (fingerprint.clone(), since));
+                    now.duration_since(since) >= Duration::from_millis(*settle_ms)
+                }
+            };
+            if is_ready {
+                ready.push((fingerprint.modified, path));
+            }
+        }
+        self.seen = seen;
+        self.failed = failed;
+
+        ready.sort();
+        ready.into_iter().map(|(_, path)| path).collect()
+    }
+
+    /// Whether a path is a file to ingest, rather than a directory, a hidden
+    /// file still being written, or one of the files the source creates.
+    fn is_candidate(&self, path: &Path) -> bool {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        if name.starts_with('.') || !path.is_file() {
+            return false;
+        }
+        if let ReadyConfig::Marker { suffix } = &self.ready {
+            if name.ends_with(suffix.as_str()) {
+                return false;
+            }
+        }
+        if let PostAction::Rename { suffix } = &self.post_action {
+            if name.ends_with(suffix.as_str()) {
+                return false;
+            }
+        }
+        // The post-action of the file failed, and is retried instead.
+        !self.checkpoints.pending.contains_key(path)
+    }
+
+    /// Send the events of a whole file as it is read, then checkpoint it
+    /// before doing its post-action. A file that can't be read to the end is
+    /// ingested again on the next poll, sending the events read before the
+    /// error again. One that can't be split into frames is left in place
+    /// without its post-action, and only ingested again once it changes.
+    fn ingest(
+        &mut self,
+        path: &Path,
+        out: &mut Wait<mpsc::Sender<Event>>,
+    ) -> Result<(), SpoolError> {
+        let fingerprint = Fingerprint::of(path).context(ReadFile { path })?;
+        let mut file = File::open(path).context(ReadFile { path })?;
+        debug!(message = "ingesting file.", ?path, bytes = fingerprint.len);
+
+        let name = path.to_string_lossy().into_owned();
+        let mut framer = self.decoding.framer();
+        let mut buf = BytesMut::new();
+        let mut chunk = vec![0; READ_CHUNK_BYTES];
+        loop {
+            let read = match file.read(&mut chunk) {
+                Ok(read) => read,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error).context(ReadFile { path }),
+            };
+            buf.extend_from_slice(&chunk[..read]);
+
+            loop {
+                let frame = if read == 0 {
+                    framer.decode_eof(&mut buf)
+                } else {
+                    framer.decode(&mut buf)
+                };
+                let frame = match frame {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(error) => {
+                        warn!(
+                            message = "failed to split file into frames.",
+                            ?path,
+                            %error,
+                            decode_errors_counter = 1,
+                        );
+                        out.flush().map_err(|_| SpoolError::OutputClosed)?;
+                        self.failed.insert(path.to_path_buf(), fingerprint);
+                        return Err(error).context(SplitFile { path });
+                    }
+                };
+                if let Some(mut event) = self.decoding.decode(frame) {
+                    if let Some(file_key) = &self.file_key {
+                        event
+                            .as_mut_log()
+                            .insert_implicit(file_key.clone(), name.clone().into());
+                    }
+                    out.send(event).map_err(|_| SpoolError::OutputClosed)?;
+                }
+            }
+
+            if read == 0 {
+                break;
+            }
+        }
+        out.flush().map_err(|_| SpoolError::OutputClosed)?;
+
+        self.checkpoints
+            .pending
+            .insert(path.to_path_buf(), fingerprint);
+        self.checkpoints.save()?;
+
+        self.post_action(path)?;
+        self.checkpoints.pending.remove(path);
+        self.checkpoints.save()
+    }
+
+    fn post_action(&self, path: &Path) -> Result<(), SpoolError> {
+        match &self.post_action {
+            PostAction::Delete => fs::remove_file(path).context(PostActionFailed {
+                action: "delete",
+                path,
+            })?,
+            PostAction::Move { directory } => {
+                let target = directory.join(path.file_name().unwrap_or_default());
+                // Renaming fails across file systems, where the file is copied
+                // instead.
+                fs::rename(path, &target)
+                    .or_else(|_| fs::copy(path, &target).and_then(|_| fs::remove_file(path)))
+                    .context(PostActionFailed {
+                        action: "move",
+                        path,
+                    })?
+            }
+            PostAction::Rename { suffix } => {
+                fs::rename(path, with_suffix(path, suffix)).context(PostActionFailed {
+                    action: "rename",
+                    path,
+                })?
+            }
+        }
+
+        if let ReadyConfig::Marker { suffix } = &self.ready {
+            match fs::remove_file(with_suffix(path, suffix)) {
+                Err(ref error) if error.kind() != io::ErrorKind::NotFound => {
+                    warn!(message = "could not delete marker file.", ?path, %error)
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Checkpoints, Fingerprint, PostAction, ReadyConfig, SpoolConfig, CHECKPOINTS_FILE};
+    use crate::{
+        event,
+        test_util::{block_on, collect_n, wait_for},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use futures::{sync::mpsc, Stream};
+    use std::{collections::HashMap, fs, path::Path, thread, time::Duration};
+    use string_cache::DefaultAtom as Atom;
+    use tempfile::tempdir;
+
+    fn config(directory: &Path, data_dir: &Path) -> SpoolConfig {
+        let mut config = SpoolConfig::new(directory.to_path_buf());
+        config.data_dir = Some(data_dir.to_path_buf());
+        config.ready = ReadyConfig::Stable { settle_ms: 0 };
+        config.poll_interval_ms = 10;
+        config
+    }
+
+    #[test]
+    fn spool_ingests_settled_files() {
+        let spool = tempdir().unwrap();
+        let data_dir = tempdir().unwrap();
+        let path = spool.path().join("batch.log");
+        fs::write(&path, "first line\nsecond line").unwrap();
+        fs::write(spool.path().join(".batch.log.partial"), "still writing\n").unwrap();
+
+        let (tx, rx) = mpsc::channel(10);
+        let source = config(spool.path(), data_dir.path())
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let file = path.to_string_lossy().into_owned();
+        assert_eq!(events[0].as_log()[&event::MESSAGE], "first line".into());
+        assert_eq!(events[0].as_log()[&Atom::from("file")], file.into());
+        assert_eq!(events[1].as_log()[&event::MESSAGE], "second line".into());
+
+        wait_for(|| !path.exists());
+        assert!(spool.path().join(".batch.log.partial").exists());
+    }
+
+    #[test]
+    fn spool_splits_lines_across_chunks() {
+        let spool = tempdir().unwrap();
+        let data_dir = tempdir().unwrap();
+        let lines: Vec<_> = (0..3000).map(|i| format!("line {}", i)).collect();
+        fs::write(spool.path().join("batch.log"), lines.join("\n")).unwrap();
+
+        let (tx, rx) = mpsc::channel(10);
+        let source = config(spool.path(), data_dir.path())
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        let events = rt.block_on(collect_n(rx, lines.len())).ok().unwrap();
+        let messages: Vec<_> = events
+            .iter()
+            .map(|event| event.as_log()[&event::MESSAGE].to_string_lossy())
+            .collect();
+        assert_eq!(messages, lines);
+    }
+
+    #[test]
+    fn spool_waits_for_markers() {
+        let spool = tempdir().unwrap();
+        let data_dir = tempdir().unwrap();
+        let path = spool.path().join("batch.log");
+        fs::write(&path, "only line\n").unwrap();
+
+        let mut config = config(spool.path(), data_dir.path());
+        config.ready = ReadyConfig::Marker {
+            suffix: ".done".into(),
+        };
+        config.post_action = PostAction::Rename {
+            suffix: ".ingested".into(),
+        };
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(path.exists());
+        fs::write(spool.path().join("batch.log.done"), "").unwrap();
+
+        let events = rt.block_on(collect_n(rx, 1)).ok().unwrap();
+        assert_eq!(events[0].as_log()[&event::MESSAGE], "only line".into());
+
+        wait_for(|| spool.path().join("batch.log.ingested").exists());
+        assert!(!path.exists());
+        assert!(!spool.path().join("batch.log.done").exists());
+    }
+
+    #[test]
+    fn spool_finishes_pending_post_actions() {
+        let spool = tempdir().unwrap();
+        let done = tempdir().unwrap();
+        let data_dir = tempdir().unwrap();
+        let path = spool.path().join("batch.log");
+        fs::write(&path, "already sent\n").unwrap();
+
+        // Vector stopped after the file was ingested, but before it was moved.
+        let subdir = data_dir.path().join("default");
+        fs::create_dir(&subdir).unwrap();
+        let mut checkpoints = Checkpoints {
+            path: subdir.join(CHECKPOINTS_FILE),
+            pending: HashMap::new(),
+        };
+        checkpoints
+            .pending
+            .insert(path.clone(), Fingerprint::of(&path).unwrap());
+        checkpoints.save().unwrap();
+
+        let mut config = config(spool.path(), data_dir.path());
+        config.post_action = PostAction::Move {
+            directory: done.path().to_path_buf(),
+        };
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        wait_for(|| fs::read_to_string(subdir.join(CHECKPOINTS_FILE)).unwrap() == "{}");
+        assert!(done.path().join("batch.log").exists());
+        assert!(!path.exists());
+
+        // Shutting down the source closes the output, without any event sent.
+        rt.shutdown_now();
+        assert_eq!(block_on(rx.collect()).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn spool_leaves_files_it_cannot_split() {
+        let spool = tempdir().unwrap();
+        let data_dir = tempdir().unwrap();
+        let path = spool.path().join("batch.log");
+        fs::write(&path, "5 first6 second bad").unwrap();
+
+        let mut config = config(spool.path(), data_dir.path());
+        config.decoding = toml::from_str(r#"framing = "octet_counting""#).unwrap();
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(source);
+
+        // A file ingested after the bad one shows it was polled past.
+        let later = spool.path().join("later.log");
+        thread::sleep(Duration::from_millis(100));
+        fs::write(&later, "4 next").unwrap();
+        wait_for(|| !later.exists());
+        thread::sleep(Duration::from_millis(100));
+        assert!(path.exists());
+
+        rt.shutdown_now();
+        let events = block_on(rx.collect()).unwrap();
+        let messages: Vec<_> = events
+            .iter()
+            .map(|event| event.as_log()[&event::MESSAGE].to_string_lossy())
+            .collect();
+        assert_eq!(messages, vec!["first", "second", "next"]);
+    }
+}
@@ -1,11 +1,12 @@
+use super::util::{DecodingConfig, LineAgg, MultilineConfig};
 use crate::{
     event::{self, Event},
     topology::config::{DataType, GlobalOptions, SourceConfig},
 };
 use bytes::Bytes;
-use codec::BytesDelimitedCodec;
 use futures::{future, sync::mpsc, Future, Sink, Stream};
 use serde::{Deserialize, Serialize};
+use string_cache::DefaultAtom as Atom;
 use tokio::{
     codec::FramedRead,
     io::{stdin, AsyncRead},
@@ -17,6 +18,8 @@ pub struct StdinConfig {
     #[serde(default = "default_max_length")]
     pub max_length: usize,
     pub host_key: Option<String>,
+    pub multiline: Option<MultilineConfig>,
+    pub decoding: DecodingConfig,
 }
 
 impl Default for StdinConfig {
@@ -24,6 +27,8 @@ impl Default for StdinConfig {
         StdinConfig {
             max_length: default_max_length(),
             host_key: None,
+            multiline: None,
+            decoding: DecodingConfig::default(),
         }
     }
 }
@@ -40,6 +45,11 @@ impl SourceConfig for StdinConfig {
         _globals: &GlobalOptions,
         out: mpsc::Sender<Event>,
     ) -> Result<super::Source, crate::Error> {
+        if let Some(ref multiline) = self.multiline {
+            multiline.build()?;
+        }
+        self.decoding.build(self.max_length)?;
+
         Ok(stdin_source(stdin(), self.clone(), out))
     }
 
@@ -58,26 +68,40 @@ where
         let host_key = config.host_key.clone().unwrap_or(event::HOST.to_string());
         let hostname = hostname::get_hostname();
 
-        let source = FramedRead::new(
-            stream,
-            BytesDelimitedCodec::new_with_max_length(b'\n', config.max_length),
-        )
-        .map(move |line| create_event(line, &host_key, &hostname))
-        .map_err(|e| error!("error reading line: {:?}", e))
-        .forward(out.sink_map_err(|e| error!("Error sending in sink {}", e)))
-        .map(|_| info!("finished sending"));
+        let decoding = config.decoding.build(config.max_length).unwrap(); // validated in build
+
+        let lines = FramedRead::new(stream, decoding.framer())
+            .map_err(|e| error!("error reading line: {:?}", e));
+
+        let lines: Box<dyn Stream<Item = Bytes, Error = ()> + Send> = match config.multiline {
+            Some(multiline) => Box::new(
+                LineAgg::new(
+                    lines.map(|line| (line, ())),
+                    multiline.build().unwrap(), // validated in build
+                )
+                .map(|(line, _)| line),
+            ),
+            None => Box::new(lines),
+        };
+
+        let source = lines
+            .filter_map(move |line| decoding.decode(line))
+            .map(move |event| create_event(event, &host_key, &hostname))
+            .forward(out.sink_map_err(|e| error!("Error sending in sink {}", e)))
+            .map(|_| info!("finished sending"));
 
         source
     }))
 }
 
-fn create_event(line: Bytes, host_key: &String, hostname: &Option<String>) -> Event {
-    let mut event = Event::from(line);
-
+fn create_event(mut event: Event, host_key: &String, hostname: &Option<String>) -> Event {
+    // Codecs like syslog may already have found the host.
     if let Some(hostname) = &hostname {
-        event
-            .as_mut_log()
-            .insert_implicit(host_key.clone().into(), hostname.clone().into());
+        let host_key = Atom::from(host_key.as_str());
+        let log = event.as_mut_log();
+        if log.get(&host_key).is_none() {
+            log.insert_implicit(host_key, hostname.clone().into());
+        }
     }
 
     event
@@ -86,7 +110,7 @@ fn create_event(line: Bytes, host_key: &String, hostname: &Option<String>) -> Ev
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::event;
+    use crate::{event, sources::util::Mode};
     use futures::sync::mpsc;
     use futures::Async::*;
     use std::io::Cursor;
@@ -98,7 +122,7 @@ mod tests {
         let host_key = "host".to_string();
         let hostname = Some("Some.Machine".to_string());
 
-        let event = create_event(line, &host_key, &hostname);
+        let event = create_event(Event::from(line), &host_key, &hostname);
         let log = event.into_log();
 
         assert_eq!(log[&"host".into()], "Some.Machine".into());
@@ -135,4 +159,33 @@ mod tests {
         assert!(event.is_ready());
         assert_eq!(Ready(None), event);
     }
+
+    #[test]
+    fn stdin_aggregates_multiline() {
+        let (tx, rx) = mpsc::channel(10);
+        let config = StdinConfig {
+            multiline: Some(MultilineConfig {
+                start_pattern: r"\\$".into(),
+                condition_pattern: r"\\$".into(),
+                mode: Mode::ContinuePast,
+                timeout_ms: 1000,
+                max_lines: None,
+            }),
+            ..StdinConfig::default()
+        };
+        let buf = Cursor::new(String::from("hello \\\nworld\nagain\n"));
+
+        let mut rt = Runtime::new().unwrap();
+        let source = stdin_source(buf, config, tx);
+
+        rt.block_on(source).unwrap();
+
+        let messages = rt
+            .block_on(rx.collect())
+            .unwrap()
+            .into_iter()
+            .map(|event| event.as_log()[&event::MESSAGE].to_string_lossy())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["hello \\\nworld", "again"]);
+    }
 }
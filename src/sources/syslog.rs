@@ -210,6 +210,20 @@ fn event_from_str(
         bytes = &field::display(line.len())
     );
 
+    let event = parse_event(host_key, default_host, line);
+    if event.is_none() {
+        warn!("Problem parsing incoming message, check syslog format");
+    }
+    event
+}
+
+/// Parse an RFC 5424 message into an event, with the hostname stored under
+/// `host_key`, falling back to `default_host` if the message has none.
+pub(crate) fn parse_event(
+    host_key: &str,
+    default_host: Option<Bytes>,
+    line: &str,
+) -> Option<Event> {
     let line = line.trim();
     syslog_rfc5424::parse_message(line)
         .map(|parsed| {
@@ -218,11 +232,11 @@ fn event_from_str(
             if let Some(host) = &parsed.hostname {
                 event
                     .as_mut_log()
-                    .insert_implicit(host_key.clone().into(), host.clone().into());
+                    .insert_implicit(host_key.into(), host.clone().into());
             } else if let Some(default_host) = default_host {
                 event
                     .as_mut_log()
-                    .insert_implicit(host_key.clone().into(), default_host.into());
+                    .insert_implicit(host_key.into(), default_host.into());
             }
 
             let timestamp = parsed
@@ -242,7 +256,6 @@ fn event_from_str(
 
             event
         })
-        .map_err(|_| warn!("Problem parsing incoming message, check syslog format"))
         .ok()
 }
 
@@ -1,14 +1,17 @@
-use super::util::TcpSource;
+use super::util::{
+    Decoding, DecodingConfig, Framer, LineAgg, LineAggConfig, MultilineConfig, TcpSource,
+};
 use crate::{
     event::{self, Event},
     topology::config::{DataType, GlobalOptions, SourceConfig},
 };
 use bytes::Bytes;
-use codec::{self, BytesDelimitedCodec};
-use futures::sync::mpsc;
+use futures::{sync::mpsc, Future, Sink, Stream};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use stream_cancel::StreamExt;
 use string_cache::DefaultAtom as Atom;
+use tokio::codec::FramedRead;
 use tracing::field;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -20,6 +23,9 @@ pub struct TcpConfig {
     #[serde(default = "default_shutdown_timeout_secs")]
     pub shutdown_timeout_secs: u64,
     pub host_key: Option<Atom>,
+    pub multiline: Option<MultilineConfig>,
+    #[serde(default)]
+    pub decoding: DecodingConfig,
 }
 
 fn default_max_length() -> usize {
@@ -37,6 +43,8 @@ impl TcpConfig {
             max_length: default_max_length(),
             host_key: None,
             shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            multiline: None,
+            decoding: DecodingConfig::default(),
         }
     }
 }
@@ -51,6 +59,8 @@ impl SourceConfig for TcpConfig {
     ) -> Result<super::Source, crate::Error> {
         let tcp = RawTcpSource {
             config: self.clone(),
+            decoding: self.decoding.build(self.max_length)?,
+            multiline: self.multiline.as_ref().map(|m| m.build()).transpose()?,
         };
         tcp.run(self.address, self.shutdown_timeout_secs, out)
     }
@@ -63,17 +73,57 @@ impl SourceConfig for TcpConfig {
 #[derive(Debug, Clone)]
 struct RawTcpSource {
     config: TcpConfig,
+    decoding: Decoding,
+    multiline: Option<LineAggConfig>,
 }
 
 impl TcpSource for RawTcpSource {
-    type Decoder = BytesDelimitedCodec;
+    type Decoder = Framer;
 
     fn decoder(&self) -> Self::Decoder {
-        BytesDelimitedCodec::new_with_max_length(b'\n', self.config.max_length)
+        self.decoding.framer()
+    }
+
+    // Overridden to aggregate multiline messages, separately for each
+    // connection, before building events out of them.
+    fn run(
+        self,
+        addr: SocketAddr,
+        shutdown_timeout_secs: u64,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, crate::Error> {
+        let out = out.sink_map_err(|e| error!("error sending event: {:?}", e));
+
+        super::util::listen(
+            addr,
+            shutdown_timeout_secs,
+            move |socket, host, shutdown| {
+                let source = self.clone();
+                let out = out.clone();
+
+                let lines = FramedRead::new(socket, source.decoder())
+                    .take_until(shutdown)
+                    .map_err(|error| warn!(message = "connection error.", %error));
+
+                let lines: Box<dyn Stream<Item = Bytes, Error = ()> + Send> =
+                    match source.multiline.clone() {
+                        Some(multiline) => Box::new(
+                            LineAgg::new(lines.map(|line| (line, ())), multiline)
+                                .map(|(line, _)| line),
+                        ),
+                        None => Box::new(lines),
+                    };
+
+                lines
+                    .filter_map(move |line| source.build_event(line, host.clone()))
+                    .forward(out)
+                    .map(|_| debug!("connection closed."))
+            },
+        )
     }
 
     fn build_event(&self, frame: Bytes, host: Option<Bytes>) -> Option<Event> {
-        let mut event = Event::from(frame);
+        let mut event = self.decoding.decode(frame)?;
 
         let host_key = if let Some(key) = &self.config.host_key {
             key
@@ -81,10 +131,12 @@ impl TcpSource for RawTcpSource {
             &event::HOST
         };
 
+        // Codecs like syslog may already have found the host in the frame.
         if let Some(host) = host {
-            event
-                .as_mut_log()
-                .insert_implicit(host_key.clone(), host.into());
+            let log = event.as_mut_log();
+            if log.get(host_key).is_none() {
+                log.insert_implicit(host_key.clone(), host.into());
+            }
         }
 
         trace!(
@@ -103,6 +155,7 @@ mod test {
     use crate::topology::config::{GlobalOptions, SourceConfig};
     use futures::sync::mpsc;
     use futures::Stream;
+    use string_cache::DefaultAtom as Atom;
 
     #[test]
     fn tcp_it_includes_host() {
@@ -178,4 +231,42 @@ mod test {
             "more short".into()
         );
     }
+
+    #[test]
+    fn tcp_decodes_json() {
+        let (tx, rx) = mpsc::channel(10);
+
+        let addr = next_addr();
+
+        let config: TcpConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            [decoding]
+            framing = "character_delimited"
+            delimiter = ";"
+            codec = "json"
+            "#,
+            addr
+        ))
+        .unwrap();
+
+        let server = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        wait_for_tcp(addr);
+
+        let lines = vec![r#"{"message":"one","status":200};nope;{"message":"two"}"#.to_owned()];
+        rt.block_on(send_lines(addr, lines.into_iter())).unwrap();
+
+        let (event, rx) = block_on(rx.into_future()).unwrap();
+        let event = event.unwrap();
+        assert_eq!(event.as_log()[&event::MESSAGE], "one".into());
+        assert_eq!(event.as_log()[&Atom::from("status")], 200.into());
+        assert_eq!(event.as_log()[&event::HOST], "127.0.0.1".into());
+
+        let (event, _rx) = block_on(rx.into_future()).unwrap();
+        assert_eq!(event.unwrap().as_log()[&event::MESSAGE], "two".into());
+    }
 }
@@ -1,28 +1,41 @@
+use super::util::{Decoding, DecodingConfig};
 use crate::{
     event::{self, Event},
     topology::config::{DataType, GlobalOptions, SourceConfig},
 };
-use bytes::Bytes;
-use codec::BytesDelimitedCodec;
-use futures::{future, sync::mpsc, Future, Sink, Stream};
+use bytes::BytesMut;
+use futures::{future, stream, sync::mpsc, Future, Sink, Stream};
 use serde::{Deserialize, Serialize};
 use std::{io, net::SocketAddr};
 use string_cache::DefaultAtom as Atom;
-use tokio::net::udp::{UdpFramed, UdpSocket};
+use tokio::{
+    codec::BytesCodec,
+    net::udp::{UdpFramed, UdpSocket},
+};
 
 /// UDP processes messages per packet, where messages are separated by newline.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct UdpConfig {
     pub address: SocketAddr,
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
     pub host_key: Option<Atom>,
+    #[serde(default)]
+    pub decoding: DecodingConfig,
+}
+
+fn default_max_length() -> usize {
+    bytesize::kib(100u64) as usize
 }
 
 impl UdpConfig {
     pub fn new(address: SocketAddr) -> Self {
         Self {
             address,
+            max_length: default_max_length(),
             host_key: None,
+            decoding: DecodingConfig::default(),
         }
     }
 }
@@ -36,7 +49,8 @@ impl SourceConfig for UdpConfig {
         out: mpsc::Sender<Event>,
     ) -> Result<super::Source, crate::Error> {
         let host_key = self.host_key.clone().unwrap_or(event::HOST.clone());
-        Ok(udp(self.address, host_key, out))
+        let decoding = self.decoding.build(self.max_length)?;
+        Ok(udp(self.address, host_key, decoding, out))
     }
 
     fn output_type(&self) -> DataType {
@@ -44,7 +58,12 @@ impl SourceConfig for UdpConfig {
     }
 }
 
-pub fn udp(address: SocketAddr, host_key: Atom, out: mpsc::Sender<Event>) -> super::Source {
+pub fn udp(
+    address: SocketAddr,
+    host_key: Atom,
+    decoding: Decoding,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
     let out = out.sink_map_err(|e| error!("error sending event: {:?}", e));
 
     Box::new(
@@ -56,22 +75,28 @@ pub fn udp(address: SocketAddr, host_key: Atom, out: mpsc::Sender<Event>) -> sup
             Ok(socket)
         })
         .and_then(move |socket| {
-            let host_key = host_key.clone();
-            // UDP processes messages per packet, where messages are separated by newline.
-            // And stretch to end of packet.
-            UdpFramed::with_decode(socket, BytesDelimitedCodec::new(b'\n'), true)
-                .map(move |(line, addr): (Bytes, _)| {
-                    let mut event = Event::from(line);
-
-                    event
-                        .as_mut_log()
-                        .insert_implicit(host_key.clone().into(), addr.to_string().into());
-
-                    trace!(message = "Received one event.", ?event);
-                    event
-                })
-                // Error from Decoder or UdpSocket
+            // Each packet is split into frames on its own, with the end of the
+            // packet ending the last frame, so a bad packet only loses itself.
+            UdpFramed::with_decode(socket, BytesCodec::new(), true)
+                // Error from UdpSocket
                 .map_err(|error: io::Error| error!(message = "error reading datagram.", %error))
+                .map(move |(packet, addr): (BytesMut, _)| {
+                    let host_key = host_key.clone();
+                    let events = decoding.decode_message(packet.freeze()).into_iter().map(
+                        move |mut event| {
+                            // Codecs like syslog may already have found the host.
+                            let log = event.as_mut_log();
+                            if log.get(&host_key).is_none() {
+                                log.insert_implicit(host_key.clone(), addr.to_string().into());
+                            }
+
+                            trace!(message = "Received one event.", ?event);
+                            event
+                        },
+                    );
+                    stream::iter_ok(events)
+                })
+                .flatten()
                 .forward(out)
                 // Done with listening and sending
                 .map(|_| ())
@@ -83,6 +108,7 @@ pub fn udp(address: SocketAddr, host_key: Atom, out: mpsc::Sender<Event>) -> sup
 mod test {
     use super::UdpConfig;
     use crate::event;
+    use crate::sources::util::Codec;
     use crate::test_util::{collect_n, next_addr};
     use crate::topology::config::{GlobalOptions, SourceConfig};
     use futures::sync::mpsc;
@@ -185,4 +211,32 @@ mod test {
         assert_eq!(events[0].as_log()[&event::HOST], format!("{}", from).into());
     }
 
+    #[test]
+    fn udp_decodes_syslog() {
+        let (tx, rx) = mpsc::channel(2);
+
+        let address = next_addr();
+        let mut config = UdpConfig::new(address);
+        config.decoding.codec = Some(Codec::Syslog);
+        let server = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        thread::sleep(Duration::from_millis(100));
+
+        send_lines(
+            address,
+            vec![
+                "<34>1 2003-10-11T22:14:15.003Z mymachine su - ID47 - 'su root' failed\nnot syslog",
+            ],
+        );
+        let events = rt.block_on(collect_n(rx, 1)).ok().unwrap();
+
+        assert_eq!(
+            events[0].as_log()[&event::MESSAGE],
+            "'su root' failed".into()
+        );
+        assert_eq!(events[0].as_log()[&event::HOST], "mymachine".into());
+    }
 }
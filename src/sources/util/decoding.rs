@@ -0,0 +1,365 @@
+use crate::{
+    event::{self, proto, Event},
+    sources::syslog,
+    transforms::json_parser,
+};
+use bytes::{Bytes, BytesMut};
+use chrono::Utc;
+use codec::{BytesDelimitedCodec, OctetCountingCodec};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::io;
+use tokio::codec::{Decoder, LengthDelimitedCodec};
+
+#[derive(Debug, Snafu)]
+pub enum BuildError {
+    #[snafu(display("A `delimiter` is required for character delimited framing"))]
+    MissingDelimiter,
+    #[snafu(display("Delimiter {:?} is not a single byte character", delimiter))]
+    InvalidDelimiter { delimiter: char },
+}
+
+#[derive(Debug, Snafu)]
+pub enum DecodeError {
+    #[snafu(display("Frame is not valid UTF-8: {}", source))]
+    InvalidUtf8 { source: std::str::Utf8Error },
+    #[snafu(display("Frame is not valid JSON: {}", source))]
+    InvalidJson { source: serde_json::Error },
+    #[snafu(display("Frame is not a JSON object"))]
+    NotAnObject,
+    #[snafu(display("Frame is not a valid RFC 5424 syslog message"))]
+    InvalidSyslog,
+    #[snafu(display("Frame is not a valid protobuf event: {}", source))]
+    InvalidProtobuf { source: prost::DecodeError },
+    #[snafu(display("Frame does not hold an event"))]
+    MissingEvent,
+    #[snafu(display("Frame is not a log event"))]
+    NotALog,
+}
+
+/// How a source splits the bytes it reads into frames, and turns each frame
+/// into an event. By default, frames are lines which become the `message`
+/// field of the events.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DecodingConfig {
+    pub framing: Option<Framing>,
+    pub delimiter: Option<char>,
+    pub codec: Option<Codec>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    NewlineDelimited,
+    CharacterDelimited,
+    LengthDelimited,
+    OctetCounting,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Bytes,
+    Json,
+    Syslog,
+    Protobuf,
+}
+
+impl DecodingConfig {
+    /// Validate the options, with frames limited to `max_length` bytes.
+    pub fn build(&self, max_length: usize) -> Result<Decoding, BuildError> {
+        let framing = self.framing.unwrap_or(Framing::NewlineDelimited);
+        let delimiter = match (framing, self.delimiter) {
+            (Framing::CharacterDelimited, None) => return Err(BuildError::MissingDelimiter),
+            (Framing::CharacterDelimited, Some(delimiter)) if !delimiter.is_ascii() => {
+                return Err(BuildError::InvalidDelimiter { delimiter })
+            }
+            (Framing::CharacterDelimited, Some(delimiter)) => delimiter as u8,
+            _ => b'\n',
+        };
+
+        Ok(Decoding {
+            framing,
+            delimiter,
+            max_length,
+            codec: self.codec.unwrap_or(Codec::Bytes),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Decoding {
+    framing: Framing,
+    delimiter: u8,
+    max_length: usize,
+    codec: Codec,
+}
+
+impl Decoding {
+    /// A decoder splitting a stream of bytes into frames.
+    pub fn framer(&self) -> Framer {
+        match self.framing {
+            Framing::NewlineDelimited | Framing::CharacterDelimited => Framer::Delimited(
+                BytesDelimitedCodec::new_with_max_length(self.delimiter, self.max_length),
+            ),
+            Framing::LengthDelimited => {
+                let mut codec = LengthDelimitedCodec::new();
+                codec.set_max_frame_length(self.max_length);
+                Framer::LengthDelimited(codec)
+            }
+            Framing::OctetCounting => {
+                Framer::OctetCounting(OctetCountingCodec::new_with_max_length(self.max_length))
+            }
+        }
+    }
+
+    /// Turn a frame into an event. Frames that can't be decoded are logged,
+    /// counted and dropped.
+    pub fn decode(&self, frame: Bytes) -> Option<Event> {
+        self.codec
+            .decode(frame)
+            .map_err(|error| {
+                warn!(
+                    message = "failed to decode frame.",
+                    codec = ?self.codec,
+                    %error,
+                    decode_errors_counter = 1,
+                )
+            })
+            .ok()
+    }
+
+    /// Split a self-contained message, such as a datagram, into frames and
+    /// decode each of them.
+    pub fn decode_message(&self, message: Bytes) -> Vec<Event> {
+        let mut framer = self.framer();
+        let mut buf = BytesMut::from(message);
+        let mut events = Vec::new();
+        loop {
+            match framer.decode_eof(&mut buf) {
+                Ok(Some(frame)) => events.extend(self.decode(frame)),
+                Ok(None) => break,
+                Err(error) => {
+                    warn!(
+                        message = "failed to split message into frames.",
+                        framing = ?self.framing,
+                        %error,
+                        decode_errors_counter = 1,
+                    );
+                    break;
+                }
+            }
+        }
+        events
+    }
+}
+
+impl Codec {
+    fn decode(self, frame: Bytes) -> Result<Event, DecodeError> {
+        match self {
+            Codec::Bytes => Ok(Event::from(frame)),
+            Codec::Json => {
+                let value = serde_json::from_slice(&frame).context(InvalidJson)?;
+                let object = match value {
+                    Value::Object(object) => object,
+                    _ => return Err(DecodeError::NotAnObject),
+                };
+
+                let mut event = Event::new_empty_log();
+                event
+                    .as_mut_log()
+                    .insert_implicit(event::TIMESTAMP.clone(), Utc::now().into());
+                for (name, value) in object {
+                    json_parser::insert(&mut event, name, value);
+                }
+                Ok(event)
+            }
+            Codec::Syslog => {
+                let line = std::str::from_utf8(&frame).context(InvalidUtf8)?;
+                syslog::parse_event(&event::HOST, None, line).context(InvalidSyslog)
+            }
+            Codec::Protobuf => {
+                let wrapper = proto::EventWrapper::decode(frame).context(InvalidProtobuf)?;
+                if wrapper.event.is_none() {
+                    return Err(DecodeError::MissingEvent);
+                }
+                match Event::from(wrapper) {
+                    event @ Event::Log(_) => Ok(event),
+                    _ => Err(DecodeError::NotALog),
+                }
+            }
+        }
+    }
+}
+
+/// Splits bytes into frames, in any of the ways supported by `Framing`.
+pub enum Framer {
+    Delimited(BytesDelimitedCodec),
+    LengthDelimited(LengthDelimitedCodec),
+    OctetCounting(OctetCountingCodec),
+}
+
+impl Decoder for Framer {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Bytes>, io::Error> {
+        match self {
+            Framer::Delimited(codec) => codec.decode(buf),
+            Framer::LengthDelimited(codec) => Ok(codec.decode(buf)?.map(BytesMut::freeze)),
+            Framer::OctetCounting(codec) => codec.decode(buf),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Bytes>, io::Error> {
+        match self {
+            Framer::Delimited(codec) => codec.decode_eof(buf),
+            Framer::LengthDelimited(codec) => Ok(codec.decode_eof(buf)?.map(BytesMut::freeze)),
+            Framer::OctetCounting(codec) => codec.decode_eof(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Codec, DecodingConfig, Framing};
+    use crate::event::{self, proto, Event};
+    use bytes::{BufMut, Bytes, BytesMut};
+    use prost::Message;
+    use string_cache::DefaultAtom as Atom;
+
+    fn decode(config: DecodingConfig, message: &[u8]) -> Vec<Event> {
+        config
+            .build(1024)
+            .unwrap()
+            .decode_message(Bytes::from(message))
+    }
+
+    fn message(event: &Event) -> String {
+        event.as_log()[&event::MESSAGE].to_string_lossy()
+    }
+
+    #[test]
+    fn decoding_defaults_to_lines() {
+        let events = decode(DecodingConfig::default(), b"one\ntwo\nthree");
+        let messages: Vec<_> = events.iter().map(message).collect();
+        assert_eq!(messages, vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn decoding_splits_frames() {
+        let config = DecodingConfig {
+            framing: Some(Framing::CharacterDelimited),
+            delimiter: Some(','),
+            ..Default::default()
+        };
+        let events = decode(config, b"one,two");
+        assert_eq!(
+            events.iter().map(message).collect::<Vec<_>>(),
+            ["one", "two"]
+        );
+
+        let config = DecodingConfig {
+            framing: Some(Framing::OctetCounting),
+            ..Default::default()
+        };
+        let events = decode(config, b"7 one\ntwo5 three");
+        assert_eq!(
+            events.iter().map(message).collect::<Vec<_>>(),
+            ["one\ntwo", "three"]
+        );
+
+        let config = DecodingConfig {
+            framing: Some(Framing::LengthDelimited),
+            ..Default::default()
+        };
+        let mut buf = BytesMut::with_capacity(32);
+        buf.put_u32_be(3);
+        buf.put("one");
+        buf.put_u32_be(5);
+        buf.put("t\nwo\n");
+        let events = decode(config, &buf);
+        assert_eq!(
+            events.iter().map(message).collect::<Vec<_>>(),
+            ["one", "t\nwo\n"]
+        );
+    }
+
+    #[test]
+    fn decoding_validates_delimiter() {
+        let config = DecodingConfig {
+            framing: Some(Framing::CharacterDelimited),
+            ..Default::default()
+        };
+        assert!(config.build(1024).is_err());
+
+        let config = DecodingConfig {
+            delimiter: Some('é'),
+            ..config
+        };
+        assert!(config.build(1024).is_err());
+    }
+
+    #[test]
+    fn decoding_parses_json() {
+        let config = DecodingConfig {
+            codec: Some(Codec::Json),
+            ..Default::default()
+        };
+        let events = decode(
+            config,
+            b"{\"message\":\"hi\",\"status\":200,\"user\":{\"id\":\"a\"}}\nnot json\n[1]",
+        );
+
+        assert_eq!(events.len(), 1);
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "hi".into());
+        assert_eq!(log[&Atom::from("status")], 200.into());
+        assert_eq!(log[&Atom::from("user.id")], "a".into());
+        assert!(log.get(&event::TIMESTAMP).is_some());
+    }
+
+    #[test]
+    fn decoding_parses_syslog() {
+        let config = DecodingConfig {
+            framing: Some(Framing::OctetCounting),
+            codec: Some(Codec::Syslog),
+            ..Default::default()
+        };
+        let line =
+            "<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - 'su root' failed";
+        let events = decode(config, format!("{} {}", line.len(), line).as_bytes());
+
+        assert_eq!(events.len(), 1);
+        let log = events[0].as_log();
+        assert_eq!(log[&event::MESSAGE], "'su root' failed".into());
+        assert_eq!(log[&event::HOST], "mymachine.example.com".into());
+        assert_eq!(log[&Atom::from("appname")], "su".into());
+    }
+
+    #[test]
+    fn decoding_parses_protobuf() {
+        let config = DecodingConfig {
+            framing: Some(Framing::LengthDelimited),
+            codec: Some(Codec::Protobuf),
+            ..Default::default()
+        };
+        let mut event = Event::from("hello");
+        event
+            .as_mut_log()
+            .insert_explicit("foo".into(), "bar".into());
+
+        let wrapper = proto::EventWrapper::from(event);
+        let mut buf = BytesMut::with_capacity(wrapper.encoded_len() + 4);
+        buf.put_u32_be(wrapper.encoded_len() as u32);
+        wrapper.encode(&mut buf).unwrap();
+
+        let events = decode(config, &buf);
+        assert_eq!(events.len(), 1);
+        assert_eq!(message(&events[0]), "hello");
+        assert_eq!(events[0].as_log()[&Atom::from("foo")], "bar".into());
+    }
+}
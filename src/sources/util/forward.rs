@@ -0,0 +1,29 @@
+use crate::event::Event;
+use futures::{stream, sync::mpsc, Future, Sink, Stream};
+
+/// Send the events of each message to `out`, and only once they have all been
+/// accepted, hand the message to `store`. Messages without any events are
+/// stored right away, so they don't hold back later offsets.
+pub fn forward_and_store<S, M, F>(
+    messages: S,
+    out: mpsc::Sender<Event>,
+    store: F,
+) -> impl Future<Item = (), Error = ()>
+where
+    S: Stream<Item = (Vec<Event>, M), Error = ()>,
+    F: FnMut(M),
+{
+    messages
+        .fold((out, store), |(out, mut store), (events, message)| {
+            // Not `send_all`, as that closes `out` once it is done.
+            let sent = stream::iter_ok(events).fold(out, |out, event| {
+                out.send(event)
+                    .map_err(|e| error!(message = "Error sending to sink", error = ?e))
+            });
+            sent.map(move |out| {
+                store(message);
+                (out, store)
+            })
+        })
+        .map(|_| ())
+}
@@ -1,3 +1,11 @@
+mod decoding;
+mod forward;
+mod multiline;
+mod reconnect;
 mod tcp;
 
-pub use tcp::TcpSource;
+pub use decoding::{Codec, Decoding, DecodingConfig, Framer};
+pub use forward::forward_and_store;
+pub use multiline::{LineAgg, LineAggConfig, Mode, MultilineConfig};
+pub use reconnect::{reconnect, SessionError};
+pub use tcp::{listen, ShutdownSignal, TcpSource};
@@ -0,0 +1,345 @@
+use bytes::{Bytes, BytesMut};
+use futures::{Async, Poll, Stream};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::Duration;
+use tokio::timer::{delay_queue, DelayQueue};
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("start_pattern {:?} is not a valid regex: {}", pattern, source))]
+    InvalidStartPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    #[snafu(display("condition_pattern {:?} is not a valid regex: {}", pattern, source))]
+    InvalidConditionPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MultilineConfig {
+    pub start_pattern: String,
+    pub condition_pattern: String,
+    pub mode: Mode,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    pub max_lines: Option<usize>,
+}
+
+/// How `condition_pattern` decides which lines belong to an aggregated
+/// message once `start_pattern` has started one.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// All consecutive lines matching the condition are included.
+    ContinueThrough,
+    /// All consecutive lines matching the condition, plus the first line that
+    /// doesn't, are included.
+    ContinuePast,
+    /// All consecutive lines not matching the condition are included.
+    HaltBefore,
+    /// All consecutive lines not matching the condition, plus the first line
+    /// that does, are included.
+    HaltWith,
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+impl MultilineConfig {
+    /// The behavior of the legacy `message_start_indicator` option, which
+    /// starts a new message whenever a line matches `indicator`.
+    pub fn from_start_indicator(indicator: String, timeout_ms: u64) -> Self {
+        Self {
+            start_pattern: String::new(),
+            condition_pattern: indicator,
+            mode: Mode::HaltBefore,
+            timeout_ms,
+            max_lines: None,
+        }
+    }
+
+    pub fn build(&self) -> Result<LineAggConfig, crate::Error> {
+        let start_pattern =
+            Regex::new(&self.start_pattern).with_context(|| InvalidStartPattern {
+                pattern: self.start_pattern.clone(),
+            })?;
+        let condition_pattern =
+            Regex::new(&self.condition_pattern).with_context(|| InvalidConditionPattern {
+                pattern: self.condition_pattern.clone(),
+            })?;
+
+        Ok(LineAggConfig {
+            start_pattern,
+            condition_pattern,
+            mode: self.mode,
+            timeout: Duration::from_millis(self.timeout_ms),
+            max_lines: self.max_lines,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LineAggConfig {
+    start_pattern: Regex,
+    condition_pattern: Regex,
+    mode: Mode,
+    timeout: Duration,
+    max_lines: Option<usize>,
+}
+
+/// Aggregates the lines of `inner` into multiline messages, keeping a separate
+/// message in progress for each key (such as the file a line was read from).
+///
+/// A line matching `start_pattern` starts a new message, and subsequent lines
+/// with the same key are added to it according to the `mode`. Lines that are
+/// not part of a message are passed through as they are. A message is flushed
+/// once the `timeout` since it was started has passed, or once it reaches
+/// `max_lines`.
+pub struct LineAgg<T, K> {
+    inner: T,
+    config: LineAggConfig,
+    buffers: HashMap<K, Aggregate>,
+    timeouts: DelayQueue<K>,
+    ready: VecDeque<(Bytes, K)>,
+    draining: bool,
+}
+
+struct Aggregate {
+    lines: BytesMut,
+    num_lines: usize,
+    timeout: delay_queue::Key,
+}
+
+impl<T, K> LineAgg<T, K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new(inner: T, config: LineAggConfig) -> Self {
+        Self {
+            inner,
+            config,
+            buffers: HashMap::new(),
+            timeouts: DelayQueue::new(),
+            ready: VecDeque::new(),
+            draining: false,
+        }
+    }
+
+    fn handle_line(&mut self, line: Bytes, key: K) {
+        let line = match self.buffers.entry(key.clone()) {
+            Entry::Occupied(mut entry) => {
+                let condition_matched = self.config.condition_pattern.is_match(&line);
+                let (include, flush) = match (self.config.mode, condition_matched) {
+                    (Mode::ContinueThrough, true) => (true, false),
+                    (Mode::ContinueThrough, false) => (false, true),
+                    (Mode::ContinuePast, true) => (true, false),
+                    (Mode::ContinuePast, false) => (true, true),
+                    (Mode::HaltBefore, false) => (true, false),
+                    (Mode::HaltBefore, true) => (false, true),
+                    (Mode::HaltWith, false) => (true, false),
+                    (Mode::HaltWith, true) => (true, true),
+                };
+
+                let line = if include {
+                    let aggregate = entry.get_mut();
+                    aggregate.lines.extend_from_slice(b"\n");
+                    aggregate.lines.extend_from_slice(&line);
+                    aggregate.num_lines += 1;
+                    None
+                } else {
+                    Some(line)
+                };
+
+                let at_max_lines = self
+                    .config
+                    .max_lines
+                    .map_or(false, |max| entry.get().num_lines >= max);
+                if flush || at_max_lines {
+                    let (key, aggregate) = entry.remove_entry();
+                    self.timeouts.remove(&aggregate.timeout);
+                    self.ready.push_back((aggregate.lines.freeze(), key));
+                }
+
+                match line {
+                    Some(line) => line,
+                    None => return,
+                }
+            }
+            Entry::Vacant(_) => line,
+        };
+
+        // There is no message in progress for this key, so the line either
+        // starts one or is passed through on its own.
+        if self.config.start_pattern.is_match(&line) && self.config.max_lines != Some(1) {
+            let timeout = self.timeouts.insert(key.clone(), self.config.timeout);
+            self.buffers.insert(
+                key,
+                Aggregate {
+                    lines: line.into(),
+                    num_lines: 1,
+                    timeout,
+                },
+            );
+        } else {
+            self.ready.push_back((line, key));
+        }
+    }
+}
+
+impl<T, K> Stream for LineAgg<T, K>
+where
+    T: Stream<Item = (Bytes, K), Error = ()>,
+    K: Hash + Eq + Clone,
+{
+    type Item = (Bytes, K);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(item) = self.ready.pop_front() {
+                return Ok(Async::Ready(Some(item)));
+            }
+
+            if self.draining {
+                return Ok(Async::Ready(None));
+            }
+
+            // check for keys that have hit their timeout
+            while let Ok(Async::Ready(Some(expired))) = self.timeouts.poll() {
+                let key = expired.into_inner();
+                if let Some(aggregate) = self.buffers.remove(&key) {
+                    self.ready.push_back((aggregate.lines.freeze(), key));
+                }
+            }
+
+            match self.inner.poll()? {
+                Async::Ready(Some((line, key))) => self.handle_line(line, key),
+                Async::Ready(None) => {
+                    // start flushing all existing data, stop polling inner
+                    self.draining = true;
+                    let buffered = self
+                        .buffers
+                        .drain()
+                        .map(|(key, aggregate)| (aggregate.lines.freeze(), key));
+                    self.ready.extend(buffered);
+                }
+                Async::NotReady => {
+                    if self.ready.is_empty() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mode, MultilineConfig};
+    use bytes::Bytes;
+    use futures::{stream, Future, Stream};
+
+    fn aggregate(config: MultilineConfig, lines: &[&str]) -> Vec<String> {
+        let config = config.build().unwrap();
+        let lines = lines
+            .iter()
+            .map(|line| (Bytes::from(*line), ()))
+            .collect::<Vec<_>>();
+
+        let agg = super::LineAgg::new(stream::iter_ok(lines), config);
+        let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+        rt.block_on(agg.collect())
+            .unwrap()
+            .into_iter()
+            .map(|(line, _)| String::from_utf8(line.to_vec()).unwrap())
+            .collect()
+    }
+
+    fn config(start_pattern: &str, condition_pattern: &str, mode: Mode) -> MultilineConfig {
+        MultilineConfig {
+            start_pattern: start_pattern.into(),
+            condition_pattern: condition_pattern.into(),
+            mode,
+            timeout_ms: 1000,
+            max_lines: None,
+        }
+    }
+
+    #[test]
+    fn multiline_continue_through() {
+        let config = config(r"^[^\s]", r"^[\s]+at", Mode::ContinueThrough);
+        let lines = [
+            "Exception in thread \"main\" java.lang.NullPointerException",
+            "        at com.example.Book.getTitle(Book.java:16)",
+            "        at com.example.Author.getBookTitles(Author.java:25)",
+            "some other line",
+        ];
+
+        assert_eq!(
+            aggregate(config, &lines),
+            vec![
+                "Exception in thread \"main\" java.lang.NullPointerException\n        at com.example.Book.getTitle(Book.java:16)\n        at com.example.Author.getBookTitles(Author.java:25)",
+                "some other line",
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_continue_past() {
+        let config = config(r"\\$", r"\\$", Mode::ContinuePast);
+        let lines = ["first \\", "second \\", "third", "fourth"];
+
+        assert_eq!(
+            aggregate(config, &lines),
+            vec!["first \\\nsecond \\\nthird", "fourth"]
+        );
+    }
+
+    #[test]
+    fn multiline_halt_before() {
+        let config = config("", r"^\d{4}-", Mode::HaltBefore);
+        let lines = [
+            "2019-01-01 Traceback (most recent call last):",
+            "  File \"main.py\", line 1, in <module>",
+            "ZeroDivisionError: division by zero",
+            "2019-01-02 next",
+        ];
+
+        assert_eq!(
+            aggregate(config, &lines),
+            vec![
+                "2019-01-01 Traceback (most recent call last):\n  File \"main.py\", line 1, in <module>\nZeroDivisionError: division by zero",
+                "2019-01-02 next",
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_halt_with() {
+        let config = config("^BEGIN", "^END", Mode::HaltWith);
+        let lines = ["outside", "BEGIN", "body", "END", "outside again"];
+
+        assert_eq!(
+            aggregate(config, &lines),
+            vec!["outside", "BEGIN\nbody\nEND", "outside again"]
+        );
+    }
+
+    #[test]
+    fn multiline_max_lines() {
+        let mut config = config("", "^INFO", Mode::HaltBefore);
+        config.max_lines = Some(2);
+        let lines = ["INFO one", "a", "b", "c"];
+
+        assert_eq!(aggregate(config, &lines), vec!["INFO one\na", "b\nc"]);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::sinks::util::reconnect::fresh_backoff;
+use futures::{
+    future::{self, Either, Loop},
+    Future,
+};
+use std::{fmt, net::SocketAddr, time::Instant};
+use tokio::timer::Delay;
+
+/// Why a session with a server ended.
+#[derive(Debug)]
+pub enum SessionError<E> {
+    /// The connection failed, so another one is made.
+    Lost(E),
+    /// Nothing is left to send events to, so there is no point in going on.
+    OutputClosed,
+}
+
+impl<E> From<E> for SessionError<E> {
+    fn from(error: E) -> Self {
+        SessionError::Lost(error)
+    }
+}
+
+/// Run a session over each connection made to a server, connecting again
+/// whenever the connection can't be made or a session ends, after a delay
+/// that grows until a connection is made, and stopping once the output of the
+/// source is closed.
+pub fn reconnect<C, F, S, R, E>(
+    addr: SocketAddr,
+    connect: C,
+    session: S,
+) -> impl Future<Item = (), Error = ()>
+where
+    C: FnMut() -> F,
+    F: Future,
+    F::Error: fmt::Display,
+    S: FnMut(F::Item) -> R,
+    R: Future<Item = (), Error = SessionError<E>>,
+    E: fmt::Display,
+{
+    future::loop_fn(
+        (connect, session, fresh_backoff()),
+        move |(mut connect, mut session, mut backoff)| {
+            debug!(message = "connecting.", %addr);
+            connect()
+                .then(move |result| match result {
+                    Ok(connection) => {
+                        backoff = fresh_backoff();
+                        let ended = session(connection);
+                        Either::A(ended.then(move |result| Ok((result, connect, session, backoff))))
+                    }
+                    Err(error) => {
+                        error!(message = "unable to connect.", %addr, %error);
+                        Either::B(future::ok((Ok(()), connect, session, backoff)))
+                    }
+                })
+                .and_then(move |(result, connect, session, mut backoff)| {
+                    match result {
+                        Err(SessionError::OutputClosed) => {
+                            return Either::A(future::ok(Loop::Break(())))
+                        }
+                        Err(SessionError::Lost(error)) => {
+                            error!(message = "connection lost.", %addr, %error)
+                        }
+                        Ok(()) => (),
+                    }
+                    let delay = backoff.next().unwrap();
+                    Either::B(
+                        Delay::new(Instant::now() + delay)
+                            .then(move |_| Ok(Loop::Continue((connect, session, backoff)))),
+                    )
+                })
+        },
+    )
+}
@@ -9,12 +9,16 @@ use std::{
 use stream_cancel::{StreamExt, Tripwire};
 use tokio::{
     codec::{Decoder, FramedRead},
-    net::TcpListener,
+    net::{TcpListener, TcpStream},
     timer,
 };
 use tracing::field;
 use tracing_futures::Instrument;
 
+/// Resolves once the source is shutting down and a connection has been
+/// given `shutdown_timeout_secs` to finish up.
+pub type ShutdownSignal = Box<dyn Future<Item = (), Error = ()> + Send>;
+
 pub trait TcpSource: Clone + Send + 'static {
     type Decoder: Decoder<Error = io::Error> + Send + 'static;
 
@@ -34,48 +38,84 @@ pub trait TcpSource: Clone + Send + 'static {
     ) -> Result<crate::sources::Source, crate::Error> {
         let out = out.sink_map_err(|e| error!("error sending event: {:?}", e));
 
-        let source = future::lazy(move || {
-            let listener = match TcpListener::bind(&addr) {
-                Ok(listener) => listener,
-                Err(err) => {
-                    error!("Failed to bind to listener socket: {}", err);
-                    return future::Either::B(future::err(()));
-                }
-            };
-
-            info!(
-                message = "listening.",
-                addr = field::display(listener.local_addr().unwrap_or(addr))
-            );
-
-            let (trigger, tripwire) = Tripwire::new();
-            let tripwire = tripwire
-                .and_then(move |_| {
-                    timer::Delay::new(Instant::now() + Duration::from_secs(shutdown_timeout_secs))
-                        .map_err(|err| panic!("Timer error: {:?}", err))
-                })
-                .shared();
-
-            let future = listener
-                .incoming()
-                .map_err(|error| {
-                    error!(
-                        message = "failed to accept socket",
-                        %error
-                    )
-                })
-                .for_each(move |socket| {
-                    let peer_addr = socket.peer_addr().ok().map(|s| s.ip().to_string());
-
-                    let span = if let Some(addr) = &peer_addr {
-                        info_span!("connection", peer_addr = field::display(addr))
-                    } else {
-                        info_span!("connection")
-                    };
-
-                    let host = peer_addr.map(Bytes::from);
-
-                    let tripwire = tripwire
+        listen(
+            addr,
+            shutdown_timeout_secs,
+            move |socket, host, shutdown| {
+                let source = self.clone();
+                let out = out.clone();
+
+                let events_in = FramedRead::new(socket, source.decoder())
+                    .take_until(shutdown)
+                    .filter_map(move |frame| {
+                        let host = host.clone();
+                        source.build_event(frame, host)
+                    })
+                    .map_err(|error| warn!(message = "connection error.", %error));
+
+                events_in.forward(out).map(|_| debug!("connection closed."))
+            },
+        )
+    }
+}
+
+/// Listen for TCP connections on `addr`, spawning the future returned by
+/// `handler` for each accepted socket.
+///
+/// The handler is given the peer's address (if known) and a signal it should
+/// use to stop reading once the source is shut down.
+pub fn listen<H, F>(
+    addr: SocketAddr,
+    shutdown_timeout_secs: u64,
+    handler: H,
+) -> Result<crate::sources::Source, crate::Error>
+where
+    H: Fn(TcpStream, Option<Bytes>, ShutdownSignal) -> F + Send + 'static,
+    F: Future<Item = (), Error = ()> + Send + 'static,
+{
+    let source = future::lazy(move || {
+        let listener = match TcpListener::bind(&addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to bind to listener socket: {}", err);
+                return future::Either::B(future::err(()));
+            }
+        };
+
+        info!(
+            message = "listening.",
+            addr = field::display(listener.local_addr().unwrap_or(addr))
+        );
+
+        let (trigger, tripwire) = Tripwire::new();
+        let tripwire = tripwire
+            .and_then(move |_| {
+                timer::Delay::new(Instant::now() + Duration::from_secs(shutdown_timeout_secs))
+                    .map_err(|err| panic!("Timer error: {:?}", err))
+            })
+            .shared();
+
+        let future = listener
+            .incoming()
+            .map_err(|error| {
+                error!(
+                    message = "failed to accept socket",
+                    %error
+                )
+            })
+            .for_each(move |socket| {
+                let peer_addr = socket.peer_addr().ok().map(|s| s.ip().to_string());
+
+                let span = if let Some(addr) = &peer_addr {
+                    info_span!("connection", peer_addr = field::display(addr))
+                } else {
+                    info_span!("connection")
+                };
+
+                let host = peer_addr.map(Bytes::from);
+
+                let tripwire: ShutdownSignal = Box::new(
+                    tripwire
                         .clone()
                         .map(move |_| {
                             info!(
@@ -83,33 +123,22 @@ pub trait TcpSource: Clone + Send + 'static {
                                 shutdown_timeout_secs
                             )
                         })
-                        .map_err(|_| ());
+                        .map_err(|_| ()),
+                );
 
-                    let source = self.clone();
-                    span.in_scope(|| {
-                        debug!("accepted a new socket.");
+                span.in_scope(|| {
+                    debug!("accepted a new socket.");
 
-                        let out = out.clone();
+                    let handler = handler(socket, host, tripwire);
 
-                        let events_in = FramedRead::new(socket, source.decoder())
-                            .take_until(tripwire)
-                            .filter_map(move |frame| {
-                                let host = host.clone();
-                                source.build_event(frame, host)
-                            })
-                            .map_err(|error| warn!(message = "connection error.", %error));
+                    tokio::spawn(handler.instrument(span.clone()));
+                });
 
-                        let handler = events_in.forward(out).map(|_| debug!("connection closed."));
+                Ok(())
+            })
+            .inspect(|_| trigger.cancel());
+        future::Either::A(future)
+    });
 
-                        tokio::spawn(handler.instrument(span.clone()));
-                    });
-
-                    Ok(())
-                })
-                .inspect(|_| trigger.cancel());
-            future::Either::A(future)
-        });
-
-        Ok(Box::new(source))
-    }
+    Ok(Box::new(source))
 }
@@ -1,15 +1,21 @@
-use super::util::TcpSource;
 use crate::{
     event::proto,
+    sinks::vector::PROTOCOL_VERSION,
     topology::config::{DataType, GlobalOptions, SourceConfig},
     Event,
 };
 use bytes::{Bytes, BytesMut};
-use futures::sync::mpsc;
+use flate2::read::GzDecoder;
+use futures::{future, stream, stream::SplitSink, sync::mpsc, Future, Sink, Stream};
 use prost::Message;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
-use tokio::codec::LengthDelimitedCodec;
+use snafu::{ResultExt, Snafu};
+use std::{io::Read, net::SocketAddr};
+use stream_cancel::StreamExt;
+use tokio::{
+    codec::{Framed, LengthDelimitedCodec},
+    net::TcpStream,
+};
 use tracing::field;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -41,8 +47,21 @@ impl SourceConfig for VectorConfig {
         _globals: &GlobalOptions,
         out: mpsc::Sender<Event>,
     ) -> Result<super::Source, crate::Error> {
-        let vector = VectorSource;
-        vector.run(self.address, self.shutdown_timeout_secs, out)
+        super::util::listen(
+            self.address,
+            self.shutdown_timeout_secs,
+            move |socket, _host, shutdown| {
+                let (writer, reader) = Framed::new(socket, LengthDelimitedCodec::new()).split();
+
+                reader
+                    .take_until(shutdown)
+                    .map_err(|error| warn!(message = "connection error.", %error))
+                    .fold((out.clone(), writer), |(out, writer), frame| {
+                        handle_frame(frame, out, writer)
+                    })
+                    .map(|_| debug!("connection closed."))
+            },
+        )
     }
 
     fn output_type(&self) -> DataType {
@@ -50,47 +69,155 @@ impl SourceConfig for VectorConfig {
     }
 }
 
-#[derive(Debug, Clone)]
-struct VectorSource;
+#[derive(Debug, Snafu)]
+enum BatchError {
+    #[snafu(display("Could not decompress batch: {}", source))]
+    DecompressError { source: std::io::Error },
+    #[snafu(display("Could not decode batch: {}", source))]
+    DecodeError { source: prost::DecodeError },
+}
 
-impl TcpSource for VectorSource {
-    type Decoder = LengthDelimitedCodec;
+type ResponseWriter = SplitSink<Framed<TcpStream, LengthDelimitedCodec>>;
+type FrameFuture = Box<dyn Future<Item = (mpsc::Sender<Event>, ResponseWriter), Error = ()> + Send>;
 
-    fn decoder(&self) -> Self::Decoder {
-        LengthDelimitedCodec::new()
-    }
+/// Handle one frame sent by a `vector` sink. Batches are only acknowledged
+/// once all of their events have been handed off to the topology. Frames from
+/// sinks that predate the batched protocol contain a single `EventWrapper`
+/// and don't expect a response.
+fn handle_frame(frame: BytesMut, out: mpsc::Sender<Event>, writer: ResponseWriter) -> FrameFuture {
+    let request = match proto::Request::decode(&frame[..]) {
+        Ok(request) => request,
+        Err(error) => {
+            error!(
+                message = "failed to parse protobuf message.",
+                %error,
+                decode_errors_counter = 1
+            );
+            return Box::new(future::ok((out, writer)));
+        }
+    };
+
+    match request.message {
+        Some(proto::request::Message::Hello(hello)) => {
+            if hello.version != PROTOCOL_VERSION {
+                warn!(
+                    message = "peer speaks a different protocol version.",
+                    version = hello.version
+                );
+            }
 
-    fn build_event(&self, frame: BytesMut, _host: Option<Bytes>) -> Option<Event> {
-        match proto::EventWrapper::decode(frame).map(Event::from) {
+            let response = encode_response(proto::response::Message::Hello(proto::Hello {
+                version: PROTOCOL_VERSION,
+            }));
+            Box::new(
+                writer
+                    .send(response)
+                    .map(move |writer| (out, writer))
+                    .map_err(|error| warn!(message = "error writing response.", %error)),
+            )
+        }
+        Some(proto::request::Message::Batch(batch)) => {
+            let id = batch.id;
+            let events = match decode_batch(batch) {
+                Ok(events) => {
+                    trace!(message = "received batch.", id = id, events = events.len());
+                    events
+                }
+                Err(error) => {
+                    // The batch would fail the same way if it was sent again,
+                    // so it is acknowledged all the same, for the sink not to
+                    // wait on it.
+                    error!(
+                        message = "dropping batch.",
+                        id = id,
+                        %error,
+                        decode_errors_counter = 1
+                    );
+                    Vec::new()
+                }
+            };
+
+            let ack = encode_response(proto::response::Message::Ack(proto::Ack { id }));
+            Box::new(
+                out.send_all(stream::iter_ok::<_, mpsc::SendError<Event>>(events))
+                    .map_err(|e| error!("error sending event: {:?}", e))
+                    .and_then(move |(out, _)| {
+                        writer
+                            .send(ack)
+                            .map(move |writer| (out, writer))
+                            .map_err(|error| warn!(message = "error writing response.", %error))
+                    }),
+            )
+        }
+        None => match proto::EventWrapper::decode(frame).map(Event::from) {
             Ok(event) => {
                 trace!(
                     message = "Received one event.",
                     event = field::debug(&event)
                 );
-                Some(event)
+                Box::new(
+                    out.send(event)
+                        .map(move |out| (out, writer))
+                        .map_err(|e| error!("error sending event: {:?}", e)),
+                )
             }
-            Err(e) => {
-                error!("failed to parse protobuf message: {:?}", e);
-                None
+            Err(error) => {
+                error!(
+                    message = "failed to parse protobuf message.",
+                    %error,
+                    decode_errors_counter = 1
+                );
+                Box::new(future::ok((out, writer)))
             }
-        }
+        },
     }
 }
 
+fn decode_batch(batch: proto::Batch) -> Result<Vec<Event>, BatchError> {
+    let payload = match batch.compression() {
+        proto::batch::Compression::None => batch.payload,
+        proto::batch::Compression::Gzip => {
+            let mut payload = Vec::new();
+            GzDecoder::new(&batch.payload[..])
+                .read_to_end(&mut payload)
+                .context(DecompressError)?;
+            payload
+        }
+    };
+
+    let batch = proto::EventBatch::decode(&payload[..]).context(DecodeError)?;
+    Ok(batch.events.into_iter().map(Event::from).collect())
+}
+
+fn encode_response(message: proto::response::Message) -> Bytes {
+    let response = proto::Response {
+        message: Some(message),
+    };
+    let mut out = BytesMut::with_capacity(response.encoded_len());
+    response.encode(&mut out).unwrap();
+    out.freeze()
+}
+
 #[cfg(test)]
 mod test {
     use super::VectorConfig;
     use crate::{
         buffers::Acker,
-        sinks::vector::vector,
+        event::proto,
+        sinks::{
+            util::Compression,
+            vector::{vector, VectorSinkConfig},
+        },
         test_util::{next_addr, wait_for_tcp, CollectCurrent},
         topology::config::{GlobalOptions, SourceConfig},
         Event,
     };
+    use bytes::BytesMut;
     use futures::{stream, sync::mpsc, Future, Sink};
+    use prost::Message;
+    use tokio::codec::{Framed, FramedWrite, LengthDelimitedCodec};
 
-    #[test]
-    fn tcp_it_works_with_vector_sink() {
+    fn roundtrip_events(compression: Compression) {
         let (tx, rx) = mpsc::channel(100);
 
         let addr = next_addr();
@@ -101,7 +228,12 @@ mod test {
         rt.spawn(server);
         wait_for_tcp(addr);
 
-        let sink = vector("NONE".into(), addr, Acker::Null);
+        let mut config = VectorSinkConfig::new(addr.to_string());
+        config.batch_size = Some(3);
+        config.compression = Some(compression);
+
+        let (acker, ack_counter) = Acker::new_for_testing();
+        let sink = vector(&config, addr, acker);
         let events = vec![
             Event::from("test"),
             Event::from("events"),
@@ -113,9 +245,95 @@ mod test {
             Event::from("source"),
         ];
 
+        // The sink only completes once every batch has been acknowledged.
         let _ = rt
             .block_on(sink.send_all(stream::iter_ok(events.clone().into_iter())))
             .unwrap();
+        assert_eq!(
+            ack_counter.load(std::sync::atomic::Ordering::Relaxed),
+            events.len()
+        );
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let (_, output) = CollectCurrent::new(rx).wait().unwrap();
+        assert_eq!(events, output);
+    }
+
+    #[test]
+    fn tcp_it_works_with_vector_sink() {
+        roundtrip_events(Compression::None);
+    }
+
+    #[test]
+    fn tcp_it_works_with_compressed_batches() {
+        roundtrip_events(Compression::Gzip);
+    }
+
+    #[test]
+    fn tcp_acknowledges_undecodable_batches() {
+        let (tx, _rx) = mpsc::channel(100);
+
+        let addr = next_addr();
+        let server = VectorConfig::new(addr.clone())
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        wait_for_tcp(addr);
+
+        let request = proto::Request {
+            message: Some(proto::request::Message::Batch(proto::Batch {
+                id: 7,
+                compression: proto::batch::Compression::Gzip as i32,
+                payload: b"not gzip".to_vec(),
+            })),
+        };
+        let mut frame = BytesMut::with_capacity(request.encoded_len());
+        request.encode(&mut frame).unwrap();
+
+        let exchange = tokio::net::TcpStream::connect(&addr).and_then(|socket| {
+            Framed::new(socket, LengthDelimitedCodec::new())
+                .send(frame.freeze())
+                .and_then(|framed| framed.into_future().map_err(|(error, _)| error))
+        });
+        let (response, _) = rt.block_on(exchange).unwrap();
+
+        let response = proto::Response::decode(response.unwrap()).unwrap();
+        assert_eq!(
+            response.message,
+            Some(proto::response::Message::Ack(proto::Ack { id: 7 }))
+        );
+    }
+
+    #[test]
+    fn tcp_it_accepts_legacy_frames() {
+        let (tx, rx) = mpsc::channel(100);
+
+        let addr = next_addr();
+        let server = VectorConfig::new(addr.clone())
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        wait_for_tcp(addr);
+
+        let events = vec![Event::from("before"), Event::from("batching")];
+        let frames = events
+            .clone()
+            .into_iter()
+            .map(|event| {
+                let wrapper = proto::EventWrapper::from(event);
+                let mut out = BytesMut::with_capacity(wrapper.encoded_len());
+                wrapper.encode(&mut out).unwrap();
+                out.freeze()
+            })
+            .collect::<Vec<_>>();
+
+        let send = tokio::net::TcpStream::connect(&addr).and_then(|socket| {
+            FramedWrite::new(socket, LengthDelimitedCodec::new()).send_all(stream::iter_ok(frames))
+        });
+        let _ = rt.block_on(send).unwrap();
 
         std::thread::sleep(std::time::Duration::from_millis(50));
 
@@ -96,7 +96,7 @@ impl Transform for JsonParser {
     }
 }
 
-fn insert(event: &mut Event, name: String, value: Value) {
+pub(crate) fn insert(event: &mut Event, name: String, value: Value) {
     match value {
         Value::String(string) => {
             event